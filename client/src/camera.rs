@@ -0,0 +1,237 @@
+// Orbit camera: the eye circles a target point at a distance set by the zoom
+// level, and every control (zoom, panning and orbiting) moves this one model
+use serde::{Deserialize, Serialize};
+
+/// Eye distance from the target at zoom 1
+const BASE_DISTANCE: f32 = 10.0;
+
+/// Vertical field of view
+const FOV_DEGREES: f32 = 45.0;
+
+/// Highest elevation above or below the target, short of the poles where
+/// the up vector would flip
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// No foreshortening, for measuring shapes such as an edge-on disk
+    Orthographic,
+}
+
+/// Everything that places the camera, as saved in a bookmark. The eye sits
+/// `yaw` and `pitch` around the target at the zoom level's distance.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub target: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub zoom: f32,
+    #[serde(default)]
+    pub projection: Projection,
+}
+
+pub struct Camera {
+    target: [f32; 3],
+    /// Rotation of the eye about the vertical axis, 0 looking down -z
+    yaw: f32,
+    /// Elevation of the eye above the target's horizontal plane
+    pitch: f32,
+    zoom: f32,
+    projection: Projection,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Camera {
+            target: [0.0; 3],
+            yaw: 0.0,
+            pitch: 0.0,
+            zoom: 1.0,
+            projection: Projection::Perspective,
+        }
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            target: self.target,
+            yaw: self.yaw,
+            pitch: self.pitch,
+            zoom: self.zoom,
+            projection: self.projection,
+        }
+    }
+
+    /// Jump to a saved pose, rejecting one with non-finite values or a zoom
+    /// that is not positive
+    pub fn set_pose(&mut self, pose: &CameraPose) -> Result<(), String> {
+        let valid = pose.target.iter().all(|v| v.is_finite())
+            && pose.yaw.is_finite()
+            && pose.pitch.is_finite()
+            && pose.zoom.is_finite()
+            && pose.zoom > 0.0;
+        if !valid {
+            return Err(format!("Invalid camera pose: {:?}", pose));
+        }
+        self.target = pose.target;
+        self.yaw = pose.yaw % std::f32::consts::TAU;
+        self.pitch = pose.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        self.zoom = pose.zoom;
+        self.projection = pose.projection;
+        Ok(())
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Zooming moves the eye closer; invalid levels are ignored
+    pub fn set_zoom(&mut self, zoom: f32) {
+        if zoom.is_finite() && zoom > 0.0 {
+            self.zoom = zoom;
+        }
+    }
+
+    fn distance(&self) -> f32 {
+        BASE_DISTANCE / self.zoom
+    }
+
+    /// Move the target across the screen; a step covers more ground the
+    /// further out the camera is
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let scale = 2.0 / self.zoom;
+        let (right, up) = self.screen_axes();
+        for axis in 0..3 {
+            self.target[axis] += (right[axis] * dx + up[axis] * dy) * scale;
+        }
+    }
+
+    /// Circle the target by the given angles in radians
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Back to looking down the z axis at the origin; the zoom level is
+    /// the slider's and stays
+    pub fn reset(&mut self) {
+        self.target = [0.0; 3];
+        self.yaw = 0.0;
+        self.pitch = 0.0;
+    }
+
+    fn eye(&self) -> [f32; 3] {
+        let distance = self.distance();
+        [
+            self.target[0] + distance * self.pitch.cos() * self.yaw.sin(),
+            self.target[1] + distance * self.pitch.sin(),
+            self.target[2] + distance * self.pitch.cos() * self.yaw.cos(),
+        ]
+    }
+
+    /// Right and up directions of the screen in world space
+    fn screen_axes(&self) -> ([f32; 3], [f32; 3]) {
+        let eye = self.eye();
+        let forward = normalize([
+            self.target[0] - eye[0],
+            self.target[1] - eye[1],
+            self.target[2] - eye[2],
+        ]);
+        let right = normalize(cross(forward, [0.0, 1.0, 0.0]));
+        (right, cross(right, forward))
+    }
+
+    /// Projection whose clip planes follow the eye distance, so zooming
+    /// far out does not cut the scene off. The orthographic view shows the
+    /// same extent at the target as the perspective one.
+    pub fn projection(&self, aspect: f32) -> [f32; 16] {
+        let distance = self.distance();
+        let (near, far) = (distance * 0.01, distance * 100.0);
+        let f = 1.0 / (FOV_DEGREES.to_radians() / 2.0).tan();
+        if self.projection == Projection::Orthographic {
+            // Depth covers `far` on both sides of the eye: without
+            // perspective, particles behind it are still in view
+            let half_height = distance / f;
+            return [
+                1.0 / (half_height * aspect),
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0 / half_height,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                -1.0 / far,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+            ];
+        }
+        [
+            f / aspect,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            f,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            (far + near) / (near - far),
+            -1.0,
+            0.0,
+            0.0,
+            (2.0 * far * near) / (near - far),
+            0.0,
+        ]
+    }
+
+    /// Look-at view matrix from the eye to the target
+    pub fn view(&self) -> [f32; 16] {
+        let eye = self.eye();
+        let (s, u) = self.screen_axes();
+        let f = cross(u, s);
+
+        [
+            s[0],
+            u[0],
+            -f[0],
+            0.0,
+            s[1],
+            u[1],
+            -f[1],
+            0.0,
+            s[2],
+            u[2],
+            -f[2],
+            0.0,
+            -dot(s, eye),
+            -dot(u, eye),
+            dot(f, eye),
+            1.0,
+        ]
+    }
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / len, v[1] / len, v[2] / len]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
@@ -0,0 +1,187 @@
+// Text the client shows to people: connection status, errors and HUD labels,
+// looked up by key in per-locale catalogs so sites embedding the demo can
+// translate it. English is built in and fills in any key a catalog lacks;
+// pages add languages or override strings with `add_catalog`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// Locale used until the page picks one, and the fallback for missing keys
+const DEFAULT_LOCALE: &str = "en";
+
+const EN: &[(&str, &str)] = &[
+    ("status.connecting", "Connecting to server..."),
+    ("status.connected", "Connected to server"),
+    (
+        "status.disconnected",
+        "Disconnected from server - Reconnecting in 3s...",
+    ),
+    ("status.server", "Server"),
+    ("error.server", "Server Error: {message}"),
+    (
+        "notice.particles_trimmed",
+        "Frames took {time} ms, over the {target} ms budget: particles reduced from {from} to {to}",
+    ),
+    (
+        "notice.particles_raised",
+        "Frames take {time} ms, well under the {target} ms budget: the next reset uses {to} particles",
+    ),
+    ("hud.fps", "Server FPS"),
+    ("hud.compute_time", "Compute Time"),
+    ("hud.particles", "Particles"),
+    ("hud.sim_time", "Sim Time"),
+    ("hud.cpu_usage", "CPU Usage"),
+    ("hud.substeps", "Sub-steps"),
+    ("hud.units", "Units"),
+    ("hud.tree", "Tree"),
+    ("hud.energy", "Energy"),
+    (
+        "hud.energy_legend",
+        "(kinetic solid, potential dashed, between groups dotted)",
+    ),
+    ("hud.frame_buffer", "Frame Buffer"),
+];
+
+const ES: &[(&str, &str)] = &[
+    ("status.connecting", "Conectando con el servidor..."),
+    ("status.connected", "Conectado al servidor"),
+    (
+        "status.disconnected",
+        "Desconectado del servidor - Reconectando en 3 s...",
+    ),
+    ("status.server", "Servidor"),
+    ("error.server", "Error del servidor: {message}"),
+    (
+        "notice.particles_trimmed",
+        "Los fotogramas tardaron {time} ms, más que el límite de {target} ms: partículas reducidas de {from} a {to}",
+    ),
+    (
+        "notice.particles_raised",
+        "Los fotogramas tardan {time} ms, muy por debajo del límite de {target} ms: el próximo reinicio usará {to} partículas",
+    ),
+    ("hud.fps", "FPS del servidor"),
+    ("hud.compute_time", "Tiempo de cálculo"),
+    ("hud.particles", "Partículas"),
+    ("hud.sim_time", "Tiempo simulado"),
+    ("hud.cpu_usage", "Uso de CPU"),
+    ("hud.substeps", "Subpasos"),
+    ("hud.units", "Unidades"),
+    ("hud.tree", "Árbol"),
+    ("hud.energy", "Energía"),
+    (
+        "hud.energy_legend",
+        "(cinética continua, potencial discontinua, entre grupos punteada)",
+    ),
+    ("hud.frame_buffer", "Búfer de fotogramas"),
+];
+
+/// Catalogs compiled into the client
+const BUILT_IN: &[(&str, &[(&str, &str)])] = &[("en", EN), ("es", ES)];
+
+struct Catalogs {
+    locale: String,
+    /// Catalogs the page added, by locale
+    added: HashMap<String, HashMap<String, String>>,
+}
+
+thread_local! {
+    static CATALOGS: RefCell<Catalogs> = RefCell::new(Catalogs {
+        locale: DEFAULT_LOCALE.to_string(),
+        added: HashMap::new(),
+    });
+}
+
+/// Lower-case BCP 47 tag, e.g. "pt-BR" becomes "pt-br"
+fn normalize(tag: &str) -> String {
+    tag.trim().replace('_', "-").to_lowercase()
+}
+
+fn built_in(locale: &str, key: &str) -> Option<&'static str> {
+    BUILT_IN
+        .iter()
+        .find(|(tag, _)| *tag == locale)
+        .and_then(|(_, entries)| entries.iter().find(|(k, _)| *k == key))
+        .map(|(_, text)| *text)
+}
+
+/// `key` in the current locale, then its language without the region,
+/// then English. Unknown keys come back as themselves.
+pub fn text(key: &str) -> String {
+    CATALOGS.with(|catalogs| {
+        let catalogs = catalogs.borrow();
+        let language = catalogs.locale.split('-').next().unwrap_or_default();
+        let found = [catalogs.locale.as_str(), language, DEFAULT_LOCALE]
+            .into_iter()
+            .find_map(|locale| {
+                catalogs
+                    .added
+                    .get(locale)
+                    .and_then(|entries| entries.get(key).cloned())
+                    .or_else(|| built_in(locale, key).map(str::to_string))
+            })
+            .unwrap_or_else(|| key.to_string());
+        found
+    })
+}
+
+/// `key` with each `{name}` placeholder replaced by its value in `args`
+pub fn format(key: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(text(key), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// Choose the locale, e.g. from `navigator.language`. A region without a
+/// catalog of its own falls back to its language. Returns the normalized tag.
+#[wasm_bindgen]
+pub fn set_locale(tag: String) -> String {
+    let locale = normalize(&tag);
+    CATALOGS.with(|catalogs| catalogs.borrow_mut().locale = locale.clone());
+    locale
+}
+
+/// The current locale
+#[wasm_bindgen]
+pub fn locale() -> String {
+    CATALOGS.with(|catalogs| catalogs.borrow().locale.clone())
+}
+
+/// Locales with a built-in or added catalog
+#[wasm_bindgen]
+pub fn available_locales() -> Vec<String> {
+    CATALOGS.with(|catalogs| {
+        let catalogs = catalogs.borrow();
+        let mut locales: Vec<String> = BUILT_IN
+            .iter()
+            .map(|(tag, _)| tag.to_string())
+            .chain(catalogs.added.keys().cloned())
+            .collect();
+        locales.sort();
+        locales.dedup();
+        locales
+    })
+}
+
+/// Add strings for `tag` from a JSON object of key to text, e.g.
+/// `{"status.connected": "Verbunden"}`. They take precedence over the
+/// built-in catalog of that locale.
+#[wasm_bindgen]
+pub fn add_catalog(tag: String, json: String) -> Result<(), JsValue> {
+    let entries: HashMap<String, String> = serde_json::from_str(&json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid catalog: {}", e)))?;
+    CATALOGS.with(|catalogs| {
+        catalogs
+            .borrow_mut()
+            .added
+            .entry(normalize(&tag))
+            .or_default()
+            .extend(entries);
+    });
+    Ok(())
+}
+
+/// `key` in the current locale, for the page's own labels
+#[wasm_bindgen]
+pub fn translate(key: String) -> String {
+    text(&key)
+}
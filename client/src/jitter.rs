@@ -0,0 +1,98 @@
+// Jitter buffer for incoming state frames. Frames arrive unevenly over the
+// network; holding a couple back and playing them out at the average arrival
+// interval keeps motion smooth, and easing the playout rate hides the
+// backlog after a hiccup instead of jumping ahead.
+use n_body_shared::SimulationState;
+use std::collections::VecDeque;
+
+/// Frames held back before playout starts
+pub const DEFAULT_TARGET_DEPTH: usize = 2;
+/// Beyond this the oldest frames are dropped; catching up would take too long
+const MAX_DEPTH: usize = 8;
+/// Fastest catch-up, as a multiple of the normal playout rate
+const MAX_CATCH_UP: f64 = 1.5;
+/// Gaps longer than this are outages, not jitter, and do not count towards
+/// the arrival interval
+const MAX_GAP_MS: f64 = 1000.0;
+
+pub struct JitterBuffer {
+    frames: VecDeque<SimulationState>,
+    target_depth: usize,
+    /// Smoothed time between arriving frames
+    interval_ms: f64,
+    last_arrival_ms: Option<f64>,
+    /// When the next frame is due, `None` while (re)filling the buffer
+    next_play_ms: Option<f64>,
+}
+
+impl JitterBuffer {
+    pub fn new(target_depth: usize, interval_ms: f64) -> Self {
+        JitterBuffer {
+            frames: VecDeque::with_capacity(MAX_DEPTH),
+            target_depth: target_depth.clamp(1, MAX_DEPTH),
+            interval_ms,
+            last_arrival_ms: None,
+            next_play_ms: None,
+        }
+    }
+
+    /// Frames waiting to be shown
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn push(&mut self, state: SimulationState, now_ms: f64) {
+        if let Some(last) = self.last_arrival_ms {
+            let gap = now_ms - last;
+            if gap > 0.0 && gap < MAX_GAP_MS {
+                self.interval_ms = 0.9 * self.interval_ms + 0.1 * gap;
+            }
+        }
+        self.last_arrival_ms = Some(now_ms);
+
+        // A reset starts a new timeline; the old frames are stale
+        if self
+            .frames
+            .back()
+            .is_some_and(|last| state.frame_number < last.frame_number)
+        {
+            self.frames.clear();
+        }
+        self.frames.push_back(state);
+        while self.frames.len() > MAX_DEPTH {
+            self.frames.pop_front();
+        }
+    }
+
+    /// The frame to show at `now_ms`, if a new one is due
+    pub fn poll(&mut self, now_ms: f64) -> Option<SimulationState> {
+        let due = match self.next_play_ms {
+            Some(due) => due,
+            None if self.frames.len() >= self.target_depth => now_ms,
+            None => return None,
+        };
+        if now_ms < due {
+            return None;
+        }
+
+        let Some(frame) = self.frames.pop_front() else {
+            // Ran dry: keep the last frame on screen and refill
+            self.next_play_ms = None;
+            return None;
+        };
+
+        // Play faster while behind and a little slower when nearly empty,
+        // so the depth drifts back to the target without visible jumps
+        let excess = self.frames.len() as f64 - self.target_depth as f64;
+        let rate = if excess > 0.0 {
+            (1.0 + 0.1 * excess).min(MAX_CATCH_UP)
+        } else if excess < -1.0 {
+            0.9
+        } else {
+            1.0
+        };
+        // Never owe more than one frame, or a stall would be followed by a burst
+        self.next_play_ms = Some((due + self.interval_ms / rate).max(now_ms - self.interval_ms));
+        Some(frame)
+    }
+}
@@ -1,9 +1,19 @@
-use n_body_shared::{ClientMessage, ServerMessage, SimulationConfig, SimulationState};
+use n_body_shared::{
+    wire, Accessibility, ClientMessage, ForceSolver, Integrator, Morphology, PhysicsConfig, Role,
+    Scenario, ServerMessage, SimulationState, StateDiff, ThetaTuning, UnitSystem, ViewConfig,
+    CRATE_VERSION, DEFAULT_THETA, PROTOCOL_VERSION,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{console, ErrorEvent, HtmlCanvasElement, MessageEvent, WebSocket};
+use web_sys::{console, BinaryType, ErrorEvent, HtmlCanvasElement, MessageEvent, WebSocket};
 
+mod camera;
+mod i18n;
+mod jitter;
+mod lod;
 mod renderer;
+use camera::{CameraPose, Projection};
+use jitter::{JitterBuffer, DEFAULT_TARGET_DEPTH};
 use renderer::Renderer;
 
 #[wasm_bindgen]
@@ -12,7 +22,18 @@ pub struct Client {
     renderer: Renderer,
     canvas: HtmlCanvasElement,
     current_state: Option<SimulationState>,
-    config: SimulationConfig,
+    frames: JitterBuffer,
+    /// Newest state received, which `StateDelta` frames build on
+    latest_state: Option<SimulationState>,
+    /// Gallery snapshot shown in place of the live run until `resume_live`
+    frozen: Option<SimulationState>,
+    config: PhysicsConfig,
+    /// Id of the last config update sent; its `Ack` decides what the
+    /// controls show, older ones having been overtaken by it
+    last_config_id: u64,
+    view: ViewConfig,
+    role: Role,
+    units: UnitSystem,
 }
 
 #[wasm_bindgen]
@@ -22,24 +43,44 @@ impl Client {
         console::log_1(&format!("Connecting to server: {}", server_url).into());
 
         let ws = WebSocket::new(&server_url)?;
+        // Binary state frames arrive as bytes to hand straight to wasm
+        ws.set_binary_type(BinaryType::Arraybuffer);
 
         let renderer = Renderer::new(&canvas)?;
 
-        let config = SimulationConfig {
+        let config = PhysicsConfig {
             particle_count: 3000,
             time_step: 0.01,
+            steps_per_update: 1,
             gravity_strength: 1.0,
-            visual_fps: 30,
-            zoom_level: 1.0,
             debug: false,
+            periodic_box: None,
+            solver: ForceSolver::Direct,
+            theta: DEFAULT_THETA,
+            theta_tuning: ThetaTuning::Off,
+            integrator: Integrator::SymplecticEuler,
+            speed_of_light: None,
+            scenario: Scenario::GalaxyCollision,
+            morphologies: [Morphology::Spiral; 2],
+            galaxies: Vec::new(),
+            seed: 0,
         };
 
+        let view = ViewConfig::default();
+
         Ok(Client {
             ws,
             renderer,
             canvas,
             current_state: None,
+            frames: JitterBuffer::new(DEFAULT_TARGET_DEPTH, 1000.0 / view.visual_fps as f64),
+            latest_state: None,
+            frozen: None,
             config,
+            last_config_id: 0,
+            view,
+            role: Role::Controller,
+            units: UnitSystem::default(),
         })
     }
 
@@ -59,7 +100,11 @@ impl Client {
             let window = web_sys::window().unwrap();
             if let Some(handler) = window.get("updateConnectionStatus") {
                 if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
-                    let _ = function.call1(&JsValue::NULL, &JsValue::from_bool(true));
+                    let _ = function.call2(
+                        &JsValue::NULL,
+                        &JsValue::from_bool(true),
+                        &JsValue::from_str(&i18n::text("status.connected")),
+                    );
                 }
             }
         }) as Box<dyn FnMut()>);
@@ -79,6 +124,15 @@ impl Client {
                         let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&message));
                     }
                 }
+            } else if let Ok(buffer) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                // State frames in the binary encoding
+                let window = web_sys::window().unwrap();
+                if let Some(handler) = window.get("handleWebSocketBinary") {
+                    if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                        let bytes = js_sys::Uint8Array::new(&buffer);
+                        let _ = function.call1(&JsValue::NULL, &bytes);
+                    }
+                }
             }
         }) as Box<dyn FnMut(MessageEvent)>);
         ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
@@ -98,7 +152,11 @@ impl Client {
             let window = web_sys::window().unwrap();
             if let Some(handler) = window.get("updateConnectionStatus") {
                 if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
-                    let _ = function.call1(&JsValue::NULL, &JsValue::from_bool(false));
+                    let _ = function.call2(
+                        &JsValue::NULL,
+                        &JsValue::from_bool(false),
+                        &JsValue::from_str(&i18n::text("status.disconnected")),
+                    );
                 }
             }
         }) as Box<dyn FnMut()>);
@@ -108,23 +166,183 @@ impl Client {
         Ok(())
     }
 
+    /// A binary state frame from a session connected with `encoding=bincode`
+    pub fn handle_binary(&mut self, bytes: &[u8]) {
+        match wire::decode_bincode(bytes) {
+            Ok(wire::BinaryMessage::State(state)) => {
+                let state = SimulationState::from(state);
+                if self.config.debug {
+                    console::log_1(
+                        &format!(
+                            "Received binary state: {} particles in {} bytes, frame {}",
+                            state.len(),
+                            bytes.len(),
+                            state.frame_number
+                        )
+                        .into(),
+                    );
+                }
+                self.receive_state(state);
+            }
+            Ok(wire::BinaryMessage::StateDiff(diff)) => self.receive_diff(diff),
+            Ok(wire::BinaryMessage::QuantizedState(state)) => {
+                self.receive_state(SimulationState::from(state))
+            }
+            Err(e) => console::error_1(&e.into()),
+        }
+    }
+
+    /// Rebuild a frame from the changes since the last state received
+    fn receive_diff(&mut self, diff: StateDiff) {
+        // The server always sends a keyframe before any diff
+        let Some(mut state) = self.latest_state.clone() else {
+            console::error_1(&"State diff received before any state".into());
+            self.request_state();
+            return;
+        };
+        if let Err(e) = diff.apply(&mut state) {
+            console::error_1(&e.into());
+            self.request_state();
+            return;
+        }
+        if self.config.debug {
+            console::log_1(
+                &format!(
+                    "Received diff: {} particles, frame {}",
+                    state.len(),
+                    diff.frame_number
+                )
+                .into(),
+            );
+        }
+        self.receive_state(state);
+    }
+
     pub fn handle_message(&mut self, message: String) {
         match serde_json::from_str::<ServerMessage>(&message) {
             Ok(msg) => match msg {
+                ServerMessage::Welcome(welcome) => {
+                    console::log_1(
+                        &format!(
+                            "Session {} (resumed: {}, role: {:?})",
+                            welcome.session_token, welcome.resumed, welcome.role
+                        )
+                        .into(),
+                    );
+                    self.role = welcome.role;
+                    self.view = welcome.settings;
+                    self.renderer.set_accessibility(&self.view.accessibility);
+
+                    // Let JavaScript keep the token (and the server's public
+                    // websocket URL) so a reload can resume the session
+                    let window = web_sys::window().unwrap();
+                    if let Some(handler) = window.get("applyView") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let view_json = serde_json::to_string(&self.view).unwrap();
+                            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&view_json));
+                        }
+                    }
+                    if let Some(handler) = window.get("storeSessionToken") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let websocket_url = welcome
+                                .websocket_url
+                                .as_deref()
+                                .map_or(JsValue::NULL, JsValue::from_str);
+                            let _ = function.call2(
+                                &JsValue::NULL,
+                                &JsValue::from_str(&welcome.session_token),
+                                &websocket_url,
+                            );
+                        }
+                    }
+
+                    // Physical scales let the page label values in real units
+                    self.units = welcome.units;
+                    if let Some(handler) = window.get("applyUnits") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let units_json = serde_json::to_string(&self.units).unwrap();
+                            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&units_json));
+                        }
+                    }
+
+                    // Spectators get their simulation controls disabled
+                    if let Some(handler) = window.get("applyRole") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let role = format!("{:?}", welcome.role);
+                            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&role));
+                        }
+                    }
+                }
                 ServerMessage::State(state) => {
                     if self.config.debug {
                         console::log_1(
                             &format!(
-                                "Received state: {} particles, frame {}, sim_time {:.2}s",
-                                state.particles.len(),
+                                "Received state: {} particles, frame {}, sim_time {:.2}s{}",
+                                state.len(),
                                 state.frame_number,
-                                state.sim_time
+                                state.sim_time,
+                                if state.interpolated {
+                                    " (interpolated)"
+                                } else {
+                                    ""
+                                }
+                            )
+                            .into(),
+                        );
+                    }
+                    self.receive_state(state);
+                }
+                ServerMessage::StateDelta(delta) => {
+                    // The server always sends a full frame before any delta
+                    let Some(mut state) = self.latest_state.clone() else {
+                        console::error_1(&"State delta received before any state".into());
+                        self.request_state();
+                        return;
+                    };
+                    if let Err(e) = delta.apply(&mut state) {
+                        console::error_1(&e.into());
+                        self.request_state();
+                        return;
+                    }
+                    if self.config.debug {
+                        console::log_1(
+                            &format!(
+                                "Received delta: {} of {} particles moved, frame {}",
+                                delta.indices.len(),
+                                state.len(),
+                                delta.frame_number
+                            )
+                            .into(),
+                        );
+                    }
+                    self.receive_state(state);
+                }
+                ServerMessage::StateDiff(diff) => self.receive_diff(diff),
+                ServerMessage::Paused {
+                    frame_number,
+                    sim_time,
+                } => {
+                    // The last frame stays on screen; nothing to redraw
+                    if self.config.debug {
+                        console::log_1(
+                            &format!("Paused at frame {}, sim_time {:.2}", frame_number, sim_time)
+                                .into(),
+                        );
+                    }
+                }
+                ServerMessage::StatsSchema(schema) => {
+                    // The page shows a fixed set of stats; the schema is for
+                    // dashboards that chart whatever the server reports
+                    if self.config.debug {
+                        console::log_1(
+                            &format!(
+                                "Stats schema v{}: {} metrics",
+                                schema.version,
+                                schema.metrics.len()
                             )
                             .into(),
                         );
                     }
-                    self.current_state = Some(state);
-                    self.render();
                 }
                 ServerMessage::Stats(stats) => {
                     // Stats are handled by JavaScript for UI updates
@@ -138,51 +356,196 @@ impl Client {
                         .call1(&JsValue::NULL, &JsValue::from_str(&stats_json))
                         .unwrap();
                 }
-                ServerMessage::Config(config) => {
-                    console::log_1(
-                        &format!(
-                            "Received config: {} particles, debug: {}",
-                            config.particle_count, config.debug
-                        )
-                        .into(),
-                    );
-                    self.config = config.clone();
-
-                    // Enable debug logging if requested
-                    if config.debug {
-                        console::log_1(
-                            &"Debug mode enabled - verbose client logging active".into(),
-                        );
+                ServerMessage::Scenarios { scenarios } => {
+                    // The page builds its scenario picker from the list
+                    let window = web_sys::window().unwrap();
+                    if let Some(handler) = window.get("applyScenarios") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let scenarios_json = serde_json::to_string(&scenarios).unwrap();
+                            let _ =
+                                function.call1(&JsValue::NULL, &JsValue::from_str(&scenarios_json));
+                        }
+                    }
+                }
+                ServerMessage::Energy { samples } => {
+                    // The page keeps the series and plots it
+                    let window = web_sys::window().unwrap();
+                    if let Some(handler) = window.get("applyEnergy") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let samples_json = serde_json::to_string(&samples).unwrap();
+                            let _ =
+                                function.call1(&JsValue::NULL, &JsValue::from_str(&samples_json));
+                        }
+                    }
+                }
+                ServerMessage::Config(config) => self.apply_config(config),
+                ServerMessage::Ack {
+                    id,
+                    applied_config,
+                    error,
+                } => {
+                    if id != self.last_config_id {
+                        return;
+                    }
+                    // On rejection too, so the controls go back to the
+                    // settings still in effect
+                    self.apply_config(applied_config);
+                    if let Some(message) = error {
+                        console::error_1(&format!("Config update rejected: {}", message).into());
+                        let window = web_sys::window().unwrap();
+                        let _ = window.alert_with_message(&i18n::format(
+                            "error.server",
+                            &[("message", &message)],
+                        ));
+                    }
+                }
+                ServerMessage::Recording(status) => {
+                    // The page shows whether the run is being recorded
+                    let window = web_sys::window().unwrap();
+                    if let Some(handler) = window.get("applyRecording") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let status_json = serde_json::to_string(&status).unwrap();
+                            let _ =
+                                function.call1(&JsValue::NULL, &JsValue::from_str(&status_json));
+                        }
+                    }
+                }
+                ServerMessage::Snapshot(info) => {
+                    let window = web_sys::window().unwrap();
+                    if let Some(handler) = window.get("applySnapshot") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let info_json = serde_json::to_string(&info).unwrap();
+                            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&info_json));
+                        }
                     }
+                }
+                ServerMessage::ParticlesScaled(scaling) => {
+                    let key = if scaling.immediate {
+                        "notice.particles_trimmed"
+                    } else {
+                        "notice.particles_raised"
+                    };
+                    let notice = i18n::format(
+                        key,
+                        &[
+                            ("time", &format!("{:.1}", scaling.computation_time_ms)),
+                            ("target", &format!("{:.1}", scaling.target_ms)),
+                            ("from", &scaling.from.to_string()),
+                            ("to", &scaling.to.to_string()),
+                        ],
+                    );
+                    console::warn_1(&notice.clone().into());
+                    self.config.particle_count = scaling.to;
 
-                    // Update UI elements via JavaScript
+                    // Keep the particle count control in step with the server
                     let window = web_sys::window().unwrap();
                     if let Some(update_ui) = window.get("updateUIFromConfig") {
                         if let Some(function) = update_ui.dyn_ref::<js_sys::Function>() {
-                            let config_json = serde_json::to_string(&config).unwrap();
+                            let config_json = serde_json::to_string(&self.config).unwrap();
                             let _ =
                                 function.call1(&JsValue::NULL, &JsValue::from_str(&config_json));
                         }
                     }
+                    if let Some(handler) = window.get("showNotice") {
+                        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+                            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&notice));
+                        }
+                    }
+                }
+                ServerMessage::Reload {
+                    protocol_version,
+                    server_version,
+                } => {
+                    console::warn_1(
+                        &format!(
+                            "Server {} speaks protocol {}, this client ({}) speaks {}",
+                            server_version, protocol_version, CRATE_VERSION, PROTOCOL_VERSION
+                        )
+                        .into(),
+                    );
+                    request_reload(&server_version);
                 }
                 ServerMessage::Error { message } => {
                     console::error_1(&format!("Server error: {}", message).into());
 
                     // Show error to user via alert
                     let window = web_sys::window().unwrap();
-                    let _ = window.alert_with_message(&format!("Server Error: {}", message));
+                    let _ = window.alert_with_message(&i18n::format(
+                        "error.server",
+                        &[("message", &message)],
+                    ));
                 }
             },
             Err(e) => {
+                // A newer server may send messages this build cannot parse,
+                // but its reload request always keeps the same shape
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(&message) {
+                    if value["type"] == "Reload" {
+                        let server_version = value["server_version"].as_str().unwrap_or("");
+                        request_reload(server_version);
+                        return;
+                    }
+                }
                 console::error_1(&format!("Failed to parse server message: {}", e).into());
             }
         }
     }
 
-    fn render(&self) {
-        if let Some(state) = &self.current_state {
-            console::log_1(&format!("Rendering {} particles", state.particles.len()).into());
-            self.renderer.render(&state.particles);
+    /// Play out buffered frames; call once per animation frame with the
+    /// `requestAnimationFrame` timestamp
+    pub fn animate(&mut self, now_ms: f64) {
+        if let Some(state) = self.frames.poll(now_ms) {
+            self.current_state = Some(state);
+            self.render();
+        } else if let Some(frozen) = &self.frozen {
+            // Redrawn every frame so the camera can still move around it
+            self.renderer.render(frozen);
+        }
+    }
+
+    /// Show a gallery snapshot (the JSON from `/api/gallery/{id}`) as a
+    /// frozen frame; live frames keep arriving but are not drawn
+    pub fn show_snapshot(&mut self, json: String) -> Result<(), JsValue> {
+        let state: SimulationState = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid snapshot: {}", e)))?;
+        state
+            .validate()
+            .map_err(|e| JsValue::from_str(&format!("Invalid snapshot: {}", e)))?;
+        self.frozen = Some(state);
+        self.render();
+        Ok(())
+    }
+
+    /// Back from a gallery snapshot to the live run
+    pub fn resume_live(&mut self) {
+        self.frozen = None;
+        self.render();
+    }
+
+    /// Frames waiting in the jitter buffer, for the debug overlay
+    pub fn buffer_depth(&self) -> usize {
+        self.frames.depth()
+    }
+
+    fn receive_state(&mut self, state: SimulationState) {
+        if let Err(e) = state.validate() {
+            console::error_1(&e.into());
+            return;
+        }
+        self.latest_state = Some(state.clone());
+        if self.current_state.is_none() {
+            // Show the first frame straight away, then buffer
+            self.current_state = Some(state);
+            self.render();
+        } else {
+            self.frames.push(state, now_ms());
+        }
+    }
+
+    fn render(&mut self) {
+        if let Some(state) = self.frozen.as_ref().or(self.current_state.as_ref()) {
+            console::log_1(&format!("Rendering {} particles", state.len()).into());
+            self.renderer.render(state);
         }
     }
 
@@ -215,6 +578,16 @@ impl Client {
         }
     }
 
+    /// Physics steps the server integrates per frame it sends
+    pub fn set_steps_per_update(&mut self, steps: u32) {
+        self.config.steps_per_update = steps;
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update simulation speed: WebSocket not connected".into());
+        }
+    }
+
     pub fn set_gravity_strength(&mut self, strength: f32) {
         self.config.gravity_strength = strength;
         if self.is_connected() {
@@ -224,33 +597,222 @@ impl Client {
         }
     }
 
+    /// Frame rate the server streams to this client alone
     pub fn set_visual_fps(&mut self, fps: u32) {
-        self.config.visual_fps = fps;
+        self.view.visual_fps = fps;
         if self.is_connected() {
-            self.send_config_update();
+            self.send_view_update();
         } else {
             console::log_1(&"Cannot update visual FPS: WebSocket not connected".into());
         }
     }
 
-    pub fn set_zoom_level(&mut self, zoom: f32) {
-        self.config.zoom_level = zoom;
-        self.renderer.set_zoom(zoom);
+    /// Most particles the server streams to this client, a representative
+    /// subset when the simulation has more; 0 streams them all
+    pub fn set_max_streamed_particles(&mut self, max: usize) {
+        self.view.max_streamed_particles = (max > 0).then_some(max);
+        if self.is_connected() {
+            self.send_view_update();
+        } else {
+            console::log_1(&"Cannot update streamed particles: WebSocket not connected".into());
+        }
+    }
+
+    /// Palette, reduced motion and large text as JSON, e.g.
+    /// `{"palette": "ColorblindSafe", "reduced_motion": true, "large_text": false}`.
+    /// Kept with the session's view settings, so they survive a reload.
+    pub fn set_accessibility(&mut self, json: String) -> Result<(), JsValue> {
+        let accessibility: Accessibility = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid accessibility settings: {}", e)))?;
+        self.renderer.set_accessibility(&accessibility);
+        self.view.accessibility = accessibility;
+        if self.is_connected() {
+            self.send_view_update();
+        } else {
+            console::log_1(&"Cannot save accessibility settings: WebSocket not connected".into());
+        }
+        Ok(())
+    }
+
+    pub fn set_solver(&mut self, solver: String) {
+        self.config.solver = match solver.as_str() {
+            "BarnesHut" => ForceSolver::BarnesHut,
+            _ => ForceSolver::Direct,
+        };
         if self.is_connected() {
             self.send_config_update();
         } else {
-            console::log_1(&"Cannot update zoom level: WebSocket not connected".into());
+            console::log_1(&"Cannot update solver: WebSocket not connected".into());
         }
     }
 
+    pub fn set_theta(&mut self, theta: f32) {
+        self.config.theta = theta;
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update theta: WebSocket not connected".into());
+        }
+    }
+
+    /// Let the server tune theta: "FrameTime" with a budget in ms,
+    /// "ForceError" with a mean relative error such as 0.01, or "Off"
+    pub fn set_theta_tuning(&mut self, mode: String, target: f32) {
+        self.config.theta_tuning = match mode.as_str() {
+            "FrameTime" => ThetaTuning::FrameTime { target_ms: target },
+            "ForceError" => ThetaTuning::ForceError { target },
+            _ => ThetaTuning::Off,
+        };
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update theta tuning: WebSocket not connected".into());
+        }
+    }
+
+    pub fn set_integrator(&mut self, integrator: String) {
+        self.config.integrator = match integrator.as_str() {
+            "Leapfrog" => Integrator::Leapfrog,
+            "Yoshida4" => Integrator::Yoshida4,
+            _ => Integrator::SymplecticEuler,
+        };
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update integrator: WebSocket not connected".into());
+        }
+    }
+
+    /// Switch scenarios by name, e.g. "SolarSystem", "file:<name>" or
+    /// "import:<name>"; the server rebuilds the particles even when the
+    /// scenario is already selected
+    pub fn set_scenario(&mut self, scenario: String) {
+        match scenario.parse() {
+            Ok(parsed) => self.config.scenario = parsed,
+            Err(e) => {
+                console::error_1(&JsValue::from_str(&e));
+                return;
+            }
+        }
+        if self.role < Role::Controller {
+            return;
+        }
+        if self.is_connected() {
+            let msg = ClientMessage::SetScenario { scenario };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send scenario: {:?}", e).into());
+                }
+            }
+        } else {
+            console::log_1(&"Cannot update scenario: WebSocket not connected".into());
+        }
+    }
+
+    /// Ask the server for a fresh scenario list, e.g. after an import
+    pub fn list_scenarios(&self) {
+        if !self.is_connected() {
+            return;
+        }
+        if let Ok(json) = serde_json::to_string(&ClientMessage::ListScenarios) {
+            if let Err(e) = self.ws.send_with_str(&json) {
+                console::error_1(&format!("Failed to request scenarios: {:?}", e).into());
+            }
+        }
+    }
+
+    /// Ask the server for the current frame in full, e.g. when a delta or
+    /// diff does not fit the last state received
+    pub fn request_state(&self) {
+        if !self.is_connected() {
+            return;
+        }
+        if let Ok(json) = serde_json::to_string(&ClientMessage::RequestState) {
+            if let Err(e) = self.ws.send_with_str(&json) {
+                console::error_1(&format!("Failed to request state: {:?}", e).into());
+            }
+        }
+    }
+
+    /// Choose the colliding galaxy shapes as "First,Second", e.g. "Spiral,Elliptical"
+    pub fn set_morphologies(&mut self, pair: String) {
+        let parse = |name: &str| match name.trim() {
+            "Elliptical" => Morphology::Elliptical,
+            "Composite" => Morphology::Composite,
+            _ => Morphology::Spiral,
+        };
+        let mut names = pair.split(',');
+        self.config.morphologies = [
+            parse(names.next().unwrap_or_default()),
+            parse(names.next().unwrap_or_default()),
+        ];
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update galaxies: WebSocket not connected".into());
+        }
+    }
+
+    /// Stage a custom collision from a JSON array of galaxy specs, e.g.
+    /// `[{"count": 2000, "center": [-5, 0, 0], "velocity": [0.5, 0, 0]}, ...]`.
+    /// An empty array restores the default pair.
+    pub fn set_galaxies(&mut self, json: String) -> Result<(), JsValue> {
+        self.config.galaxies = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid galaxies: {}", e)))?;
+        if self.is_connected() {
+            self.send_config_update();
+        } else {
+            console::log_1(&"Cannot update galaxies: WebSocket not connected".into());
+        }
+        Ok(())
+    }
+
+    /// Zoom is a view setting of this client alone and never reaches the
+    /// server
+    pub fn set_zoom_level(&mut self, zoom: f32) {
+        self.renderer.set_zoom(zoom);
+    }
+
     pub fn move_camera(&mut self, dx: f32, dy: f32) {
         self.renderer.move_camera(dx, dy);
     }
 
+    /// Circle the camera around what it looks at, by angles in radians
+    pub fn orbit_camera(&mut self, yaw: f32, pitch: f32) {
+        self.renderer.orbit_camera(yaw, pitch);
+    }
+
     pub fn reset_camera(&mut self) {
         self.renderer.reset_camera();
     }
 
+    /// The camera's target, orbit angles, zoom and projection as JSON, e.g.
+    /// `{"target": [0, 0, 0], "yaw": 0, "pitch": 1.2, "zoom": 2, "projection": "Orthographic"}`,
+    /// for saving as a bookmark
+    pub fn camera_pose(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.renderer.camera_pose())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize camera: {}", e)))
+    }
+
+    /// Move the camera to a pose from `camera_pose`. Other fields, such as a
+    /// bookmark's name, are ignored.
+    pub fn set_camera_pose(&mut self, json: String) -> Result<(), JsValue> {
+        let pose: CameraPose = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid camera pose: {}", e)))?;
+        self.renderer
+            .set_camera_pose(&pose)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// "Perspective" or "Orthographic"
+    pub fn set_projection(&mut self, name: String) {
+        let projection = match name.as_str() {
+            "Orthographic" => Projection::Orthographic,
+            _ => Projection::Perspective,
+        };
+        self.renderer.set_projection(projection);
+    }
+
     fn is_connected(&self) -> bool {
         self.ws.ready_state() == WebSocket::OPEN
     }
@@ -290,9 +852,144 @@ impl Client {
         }
     }
 
-    fn send_config_update(&self) {
+    /// Advance the paused simulation by `count` physics steps
+    pub fn step(&self, count: u32) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::Step { count };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send step: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    /// Negate every velocity so the run retraces its steps
+    pub fn reverse_time(&self) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::ReverseTime;
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send reverse time: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    /// Jump back to frame `frame` of the run, or of a replay
+    pub fn seek(&self, frame: u32) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::Seek {
+                frame: frame as u64,
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send seek: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    /// Start recording every `interval`th frame on the server, its
+    /// configured interval when 0
+    pub fn start_recording(&self, interval: u32) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::StartRecording {
+                interval: (interval > 0).then_some(interval as u64),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send start recording: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    pub fn stop_recording(&self) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::StopRecording;
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send stop recording: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    /// Save the run on the server as snapshot `name`, named after the time
+    /// when empty
+    pub fn save_snapshot(&self, name: String) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::SaveSnapshot {
+                name: (!name.is_empty()).then_some(name),
+            };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send save snapshot: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    /// Continue the run from snapshot `name` saved on the server
+    pub fn load_snapshot(&self, name: String) {
+        if self.ws.ready_state() == WebSocket::OPEN {
+            let msg = ClientMessage::LoadSnapshot { name };
+            if let Ok(json) = serde_json::to_string(&msg) {
+                if let Err(e) = self.ws.send_with_str(&json) {
+                    console::error_1(&format!("Failed to send load snapshot: {:?}", e).into());
+                }
+            }
+        }
+    }
+
+    fn send_view_update(&self) {
+        let msg = ClientMessage::UpdateView(self.view.clone());
+        if let Ok(json) = serde_json::to_string(&msg) {
+            if let Err(e) = self.ws.send_with_str(&json) {
+                console::error_1(&format!("Failed to send view update: {:?}", e).into());
+            }
+        }
+    }
+
+    /// Take over settings from the server and show them in the controls
+    fn apply_config(&mut self, config: PhysicsConfig) {
+        console::log_1(
+            &format!(
+                "Received config: {} particles, debug: {}",
+                config.particle_count, config.debug
+            )
+            .into(),
+        );
+        self.renderer.set_periodic_box(config.periodic_box);
+
+        // Enable debug logging if requested
+        if config.debug {
+            console::log_1(&"Debug mode enabled - verbose client logging active".into());
+        }
+
+        // Update UI elements via JavaScript
+        let window = web_sys::window().unwrap();
+        if let Some(update_ui) = window.get("updateUIFromConfig") {
+            if let Some(function) = update_ui.dyn_ref::<js_sys::Function>() {
+                let config_json = serde_json::to_string(&config).unwrap();
+                let _ = function.call1(&JsValue::NULL, &JsValue::from_str(&config_json));
+            }
+        }
+        self.config = config;
+    }
+
+    fn send_config_update(&mut self) {
+        if self.role < Role::Controller {
+            // Only controllers change the shared simulation
+            return;
+        }
         if self.ws.ready_state() == WebSocket::OPEN {
-            let msg = ClientMessage::UpdateConfig(self.config.clone());
+            self.last_config_id += 1;
+            let msg = ClientMessage::UpdateConfig {
+                id: Some(self.last_config_id),
+                config: self.config.clone(),
+            };
             if let Ok(json) = serde_json::to_string(&msg) {
                 if let Err(e) = self.ws.send_with_str(&json) {
                     console::error_1(&format!("Failed to send config update: {:?}", e).into());
@@ -302,6 +999,38 @@ impl Client {
     }
 }
 
+/// Milliseconds on the same clock as `requestAnimationFrame` timestamps
+fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0.0, |performance| performance.now())
+}
+
+/// Wire protocol version of this client build, sent when connecting
+#[wasm_bindgen]
+pub fn protocol_version() -> u32 {
+    PROTOCOL_VERSION
+}
+
+/// Crate version of this client build
+#[wasm_bindgen]
+pub fn client_version() -> String {
+    CRATE_VERSION.to_string()
+}
+
+/// Ask the page to reload, bypassing cached assets, to pick up the client
+/// build matching `server_version`
+fn request_reload(server_version: &str) {
+    let window = web_sys::window().unwrap();
+    if let Some(handler) = window.get("reloadForVersion") {
+        if let Some(function) = handler.dyn_ref::<js_sys::Function>() {
+            let _ = function.call1(&JsValue::NULL, &JsValue::from_str(server_version));
+            return;
+        }
+    }
+    let _ = window.location().reload();
+}
+
 #[wasm_bindgen(start)]
 pub fn main() {
     console::log_1(&"N-Body client WASM module loaded".into());
@@ -0,0 +1,92 @@
+// Level of detail for very large particle counts. Once there are more
+// particles than screen cells, many of them land on the same few pixels and
+// drawing each one only costs upload time and overdraw. Binning them by
+// screen position and drawing one brighter point per occupied cell keeps the
+// picture while bounding the work by the size of the canvas.
+use n_body_shared::SimulationState;
+
+/// Side of a screen cell in pixels; half the drawn point size so clustered
+/// points still overlap into a continuous glow
+pub const CELL_PIXELS: f32 = 4.0;
+/// Brightest a cluster gets, as a multiple of its mean colour
+const MAX_BOOST: f32 = 4.0;
+
+#[derive(Clone, Copy, Default)]
+struct Bin {
+    count: u32,
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Number of cells covering a `width` x `height` canvas
+pub fn cell_count(width: f32, height: f32) -> usize {
+    ((width / CELL_PIXELS).ceil() * (height / CELL_PIXELS).ceil()) as usize
+}
+
+/// Vertex data with the particles merged per screen cell: positions are the
+/// cell's mean, colours the mean of `color` of the particle index,
+/// brightened with the number of particles if `boost`. Particles behind the
+/// camera or off screen are dropped. `mvp` is the column-major projection ×
+/// view matrix.
+pub fn cluster(
+    state: &SimulationState,
+    color: impl Fn(usize) -> [u8; 4],
+    mvp: &[f32; 16],
+    (width, height): (f32, f32),
+    boost: bool,
+) -> (Vec<f32>, Vec<u8>) {
+    let columns = (width / CELL_PIXELS).ceil().max(1.0) as usize;
+    let rows = (height / CELL_PIXELS).ceil().max(1.0) as usize;
+    let mut bins = vec![Bin::default(); columns * rows];
+    let mut occupied = Vec::new();
+
+    for (particle, position) in state.positions.chunks_exact(3).enumerate() {
+        let [x, y, z] = [position[0], position[1], position[2]];
+        let w = mvp[3] * x + mvp[7] * y + mvp[11] * z + mvp[15];
+        if w <= 0.0 {
+            continue;
+        }
+        let ndc_x = (mvp[0] * x + mvp[4] * y + mvp[8] * z + mvp[12]) / w;
+        let ndc_y = (mvp[1] * x + mvp[5] * y + mvp[9] * z + mvp[13]) / w;
+        if !(-1.0..1.0).contains(&ndc_x) || !(-1.0..1.0).contains(&ndc_y) {
+            continue;
+        }
+        let column = ((ndc_x + 1.0) * 0.5 * columns as f32) as usize;
+        let row = ((ndc_y + 1.0) * 0.5 * rows as f32) as usize;
+        let index = row.min(rows - 1) * columns + column.min(columns - 1);
+
+        let bin = &mut bins[index];
+        if bin.count == 0 {
+            occupied.push(index);
+        }
+        bin.count += 1;
+        for (sum, value) in bin.position.iter_mut().zip([x, y, z]) {
+            *sum += value;
+        }
+        for (sum, value) in bin.color.iter_mut().zip(color(particle)) {
+            *sum += value as f32;
+        }
+    }
+
+    let mut positions = Vec::with_capacity(occupied.len() * 3);
+    let mut colors = Vec::with_capacity(occupied.len() * 4);
+    for index in occupied {
+        let bin = &bins[index];
+        let count = bin.count as f32;
+        let boost = if boost {
+            count.sqrt().min(MAX_BOOST)
+        } else {
+            1.0
+        };
+        positions.extend(bin.position.map(|sum| sum / count));
+        let [r, g, b, a] = bin.color.map(|sum| sum / count);
+        let channel = |value: f32| value.min(255.0).round() as u8;
+        colors.extend([
+            channel(r * boost),
+            channel(g * boost),
+            channel(b * boost),
+            channel(a),
+        ]);
+    }
+    (positions, colors)
+}
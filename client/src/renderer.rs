@@ -1,23 +1,50 @@
-use n_body_shared::Particle;
+use n_body_shared::{color_byte, Accessibility, Palette, SimulationState, MAX_PARTICLES};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
+
+use crate::camera::{Camera, CameraPose, Projection};
+use crate::lod;
 use web_sys::{
-    HtmlCanvasElement, WebGlBuffer, WebGlProgram, WebGlRenderingContext as GL, WebGlShader,
-    WebGlUniformLocation,
+    HtmlCanvasElement, OesVertexArrayObject, WebGlBuffer, WebGlProgram,
+    WebGlRenderingContext as GL, WebGlShader, WebGlUniformLocation, WebGlVertexArrayObject,
 };
 
+/// Attribute setup of the particles and the box outline, recorded once in
+/// vertex array objects so drawing only has to bind them
+struct VertexArrays {
+    ext: OesVertexArrayObject,
+    particles: WebGlVertexArrayObject,
+    outline: WebGlVertexArrayObject,
+}
+
 pub struct Renderer {
     gl: GL,
-    program: WebGlProgram,
+    /// `None` without OES_vertex_array_object; attributes are then set up
+    /// before every draw
+    vertex_arrays: Option<VertexArrays>,
+    a_position: u32,
+    a_color: u32,
     position_buffer: WebGlBuffer,
     color_buffer: WebGlBuffer,
+    box_buffer: WebGlBuffer,
+    box_color_buffer: WebGlBuffer,
     u_projection: WebGlUniformLocation,
     u_view: WebGlUniformLocation,
+    u_lines: WebGlUniformLocation,
+    u_flat: WebGlUniformLocation,
+    accessibility: Accessibility,
+    /// Matrices last sent to the shader; they only change with the camera
+    uniforms: Option<([f32; 16], [f32; 16])>,
+    /// Points the particle buffers have room for; they are allocated once
+    /// and only grow, so frames are uploaded into them in place
+    capacity: usize,
+    /// Colours currently in the color buffer, to skip re-uploading them
+    /// when they have not changed, which is nearly every frame
+    uploaded_colors: Vec<u8>,
+    periodic_box: Option<f32>,
     width: f32,
     height: f32,
-    zoom: f32,
-    camera_x: f32,
-    camera_y: f32,
+    camera: Camera,
 }
 
 impl Renderer {
@@ -47,6 +74,10 @@ impl Renderer {
             .create_buffer()
             .ok_or("Failed to create position buffer")?;
         let color_buffer = gl.create_buffer().ok_or("Failed to create color buffer")?;
+        let box_buffer = gl.create_buffer().ok_or("Failed to create box buffer")?;
+        let box_color_buffer = gl
+            .create_buffer()
+            .ok_or("Failed to create box color buffer")?;
 
         // Get uniform locations
         let u_projection = gl
@@ -55,22 +86,88 @@ impl Renderer {
         let u_view = gl
             .get_uniform_location(&program, "u_view")
             .ok_or("Failed to get u_view")?;
-
-        Ok(Renderer {
+        let u_lines = gl
+            .get_uniform_location(&program, "u_lines")
+            .ok_or("Failed to get u_lines")?;
+        let u_flat = gl
+            .get_uniform_location(&program, "u_flat")
+            .ok_or("Failed to get u_flat")?;
+        let a_position = gl.get_attrib_location(&program, "a_position") as u32;
+        let a_color = gl.get_attrib_location(&program, "a_color") as u32;
+
+        let mut renderer = Renderer {
             gl,
-            program,
+            vertex_arrays: None,
+            a_position,
+            a_color,
             position_buffer,
             color_buffer,
+            box_buffer,
+            box_color_buffer,
             u_projection,
             u_view,
+            u_lines,
+            u_flat,
+            accessibility: Accessibility::default(),
+            uniforms: None,
+            capacity: 0,
+            uploaded_colors: Vec::new(),
+            periodic_box: None,
             width: canvas.width() as f32,
             height: canvas.height() as f32,
-            zoom: 1.0,
-            camera_x: 0.0,
-            camera_y: 0.0,
+            camera: Camera::new(),
+        };
+        renderer.vertex_arrays = renderer.create_vertex_arrays();
+        Ok(renderer)
+    }
+
+    /// Record the attribute setup in vertex array objects, if supported
+    fn create_vertex_arrays(&self) -> Option<VertexArrays> {
+        let ext = self
+            .gl
+            .get_extension("OES_vertex_array_object")
+            .ok()
+            .flatten()?
+            .unchecked_into::<OesVertexArrayObject>();
+        let record = |position: &WebGlBuffer, color: &WebGlBuffer| {
+            let vao = ext.create_vertex_array_oes()?;
+            ext.bind_vertex_array_oes(Some(&vao));
+            self.bind_attributes(position, color);
+            ext.bind_vertex_array_oes(None);
+            Some(vao)
+        };
+        let particles = record(&self.position_buffer, &self.color_buffer)?;
+        let outline = record(&self.box_buffer, &self.box_color_buffer)?;
+        Some(VertexArrays {
+            ext,
+            particles,
+            outline,
         })
     }
 
+    /// Point the position and color attributes at a pair of buffers
+    fn bind_attributes(&self, position: &WebGlBuffer, color: &WebGlBuffer) {
+        self.gl.bind_buffer(GL::ARRAY_BUFFER, Some(position));
+        self.gl
+            .vertex_attrib_pointer_with_i32(self.a_position, 3, GL::FLOAT, false, 0, 0);
+        self.gl.enable_vertex_attrib_array(self.a_position);
+
+        self.gl.bind_buffer(GL::ARRAY_BUFFER, Some(color));
+        self.gl
+            .vertex_attrib_pointer_with_i32(self.a_color, 4, GL::UNSIGNED_BYTE, true, 0, 0);
+        self.gl.enable_vertex_attrib_array(self.a_color);
+    }
+
+    /// Use the particle or the outline buffers for the next draw
+    fn use_buffers(&self, outline: bool) {
+        match (&self.vertex_arrays, outline) {
+            (Some(arrays), false) => arrays.ext.bind_vertex_array_oes(Some(&arrays.particles)),
+            (Some(arrays), true) => arrays.ext.bind_vertex_array_oes(Some(&arrays.outline)),
+            (None, false) => self.bind_attributes(&self.position_buffer, &self.color_buffer),
+            (None, true) => self.bind_attributes(&self.box_buffer, &self.box_color_buffer),
+        }
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.width = width as f32;
         self.height = height as f32;
@@ -78,100 +175,193 @@ impl Renderer {
     }
 
     pub fn set_zoom(&mut self, zoom: f32) {
-        self.zoom = zoom;
+        self.camera.set_zoom(zoom);
+    }
+
+    /// Set the periodic box outline to draw, uploading its edges once
+    pub fn set_periodic_box(&mut self, box_size: Option<f32>) {
+        if self.periodic_box == box_size {
+            return;
+        }
+        self.periodic_box = box_size;
+
+        if let Some(size) = box_size {
+            let edges = box_edges(size * 0.5);
+            let colors: Vec<u8> = (0..edges.len() / 3)
+                .flat_map(|_| [77, 77, 102, 255])
+                .collect();
+
+            self.gl
+                .bind_buffer(GL::ARRAY_BUFFER, Some(&self.box_buffer));
+            unsafe {
+                let edges_array = js_sys::Float32Array::view(&edges);
+                self.gl.buffer_data_with_array_buffer_view(
+                    GL::ARRAY_BUFFER,
+                    &edges_array,
+                    GL::STATIC_DRAW,
+                );
+            }
+
+            self.gl
+                .bind_buffer(GL::ARRAY_BUFFER, Some(&self.box_color_buffer));
+            unsafe {
+                let colors_array = js_sys::Uint8Array::view(&colors);
+                self.gl.buffer_data_with_array_buffer_view(
+                    GL::ARRAY_BUFFER,
+                    &colors_array,
+                    GL::STATIC_DRAW,
+                );
+            }
+        }
     }
 
     pub fn move_camera(&mut self, dx: f32, dy: f32) {
-        // Movement speed scales with zoom level for intuitive control
-        let movement_scale = 2.0 / self.zoom;
-        self.camera_x += dx * movement_scale;
-        self.camera_y += dy * movement_scale;
+        self.camera.pan(dx, dy);
+    }
+
+    pub fn orbit_camera(&mut self, yaw: f32, pitch: f32) {
+        self.camera.orbit(yaw, pitch);
     }
 
     pub fn reset_camera(&mut self) {
-        self.camera_x = 0.0;
-        self.camera_y = 0.0;
+        self.camera.reset();
     }
 
-    pub fn render(&self, particles: &[Particle]) {
-        // Clear
-        self.gl.clear_color(0.0, 0.0, 0.0, 1.0);
-        self.gl.clear(GL::COLOR_BUFFER_BIT);
+    pub fn camera_pose(&self) -> CameraPose {
+        self.camera.pose()
+    }
 
-        // Prepare particle data
-        let mut positions = Vec::with_capacity(particles.len() * 3);
-        let mut colors = Vec::with_capacity(particles.len() * 4);
+    pub fn set_camera_pose(&mut self, pose: &CameraPose) -> Result<(), String> {
+        self.camera.set_pose(pose)
+    }
 
-        for particle in particles {
-            positions.push(particle.position.x);
-            positions.push(particle.position.y);
-            positions.push(particle.position.z);
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.camera.set_projection(projection);
+    }
 
-            colors.extend_from_slice(&particle.color);
+    /// Switch palette and, for reduced motion, draw solid points blended
+    /// over each other rather than glowing points that add up
+    pub fn set_accessibility(&mut self, accessibility: &Accessibility) {
+        if accessibility.reduced_motion {
+            self.gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
+        } else {
+            self.gl.blend_func(GL::SRC_ALPHA, GL::ONE);
         }
-
-        // Update position buffer
         self.gl
-            .bind_buffer(GL::ARRAY_BUFFER, Some(&self.position_buffer));
-        unsafe {
-            let positions_array = js_sys::Float32Array::view(&positions);
-            self.gl.buffer_data_with_array_buffer_view(
+            .uniform1i(Some(&self.u_flat), accessibility.reduced_motion as i32);
+        self.accessibility = accessibility.clone();
+        // Colors have to be uploaded again in the new palette
+        self.uploaded_colors.clear();
+    }
+
+    /// Make room for `points` in the particle buffers, allocating for at
+    /// least the server's default particle limit and doubling from there
+    fn reserve(&mut self, points: usize) {
+        if points <= self.capacity {
+            return;
+        }
+        let capacity = points.max(MAX_PARTICLES).max(self.capacity * 2);
+        // Three float coordinates and four color bytes per point
+        let point_bytes = [
+            (&self.position_buffer, 3 * std::mem::size_of::<f32>()),
+            (&self.color_buffer, 4),
+        ];
+        for (buffer, bytes) in point_bytes {
+            self.gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+            self.gl.buffer_data_with_i32(
                 GL::ARRAY_BUFFER,
-                &positions_array,
+                (capacity * bytes) as i32,
                 GL::DYNAMIC_DRAW,
             );
         }
+        self.capacity = capacity;
+        // The new color buffer is empty
+        self.uploaded_colors.clear();
+    }
 
-        // Update color buffer
+    pub fn render(&mut self, state: &SimulationState) {
+        // Clear
+        self.gl.clear_color(0.0, 0.0, 0.0, 1.0);
+        self.gl.clear(GL::COLOR_BUFFER_BIT);
+
+        let projection = self.camera.projection(self.width / self.height);
+        let view = self.camera.view();
+
+        // Prepare particle data, merging particles that share screen cells
+        // once there are more of them than cells. Otherwise the state's own
+        // arrays are uploaded, with colors only rebuilt for another palette.
+        let palette = self.accessibility.palette;
+        let clustered;
+        let recolored: Vec<u8>;
+        let (positions, colors): (&[f32], &[u8]) =
+            if state.len() > lod::cell_count(self.width, self.height) {
+                clustered = lod::cluster(
+                    state,
+                    |index| particle_color(state, index, palette),
+                    &multiply(&projection, &view),
+                    (self.width, self.height),
+                    !self.accessibility.reduced_motion,
+                );
+                (&clustered.0, &clustered.1)
+            } else if palette == Palette::Natural {
+                (&state.positions, &state.colors)
+            } else {
+                recolored = (0..state.len())
+                    .flat_map(|index| particle_color(state, index, palette))
+                    .collect();
+                (&state.positions, &recolored)
+            };
+
+        self.reserve(positions.len() / 3);
+
+        // Update position buffer in place
         self.gl
-            .bind_buffer(GL::ARRAY_BUFFER, Some(&self.color_buffer));
+            .bind_buffer(GL::ARRAY_BUFFER, Some(&self.position_buffer));
         unsafe {
-            let colors_array = js_sys::Float32Array::view(&colors);
-            self.gl.buffer_data_with_array_buffer_view(
+            let positions_array = js_sys::Float32Array::view(positions);
+            self.gl.buffer_sub_data_with_i32_and_array_buffer_view(
                 GL::ARRAY_BUFFER,
-                &colors_array,
-                GL::DYNAMIC_DRAW,
+                0,
+                &positions_array,
             );
         }
 
-        // Set up attributes
-        let position_attrib = self.gl.get_attrib_location(&self.program, "a_position") as u32;
-        self.gl
-            .bind_buffer(GL::ARRAY_BUFFER, Some(&self.position_buffer));
-        self.gl
-            .vertex_attrib_pointer_with_i32(position_attrib, 3, GL::FLOAT, false, 0, 0);
-        self.gl.enable_vertex_attrib_array(position_attrib);
-
-        let color_attrib = self.gl.get_attrib_location(&self.program, "a_color") as u32;
-        self.gl
-            .bind_buffer(GL::ARRAY_BUFFER, Some(&self.color_buffer));
-        self.gl
-            .vertex_attrib_pointer_with_i32(color_attrib, 4, GL::FLOAT, false, 0, 0);
-        self.gl.enable_vertex_attrib_array(color_attrib);
-
-        // Set uniforms
-        let aspect = self.width / self.height;
-        let fov = 45.0_f32.to_radians();
-        let near = 0.1;
-        let far = 100.0;
+        // Colors only change on a reset, or every frame while clustering
+        if colors != self.uploaded_colors {
+            self.gl
+                .bind_buffer(GL::ARRAY_BUFFER, Some(&self.color_buffer));
+            unsafe {
+                let colors_array = js_sys::Uint8Array::view(colors);
+                self.gl.buffer_sub_data_with_i32_and_array_buffer_view(
+                    GL::ARRAY_BUFFER,
+                    0,
+                    &colors_array,
+                );
+            }
+            self.uploaded_colors = colors.to_vec();
+        }
 
-        let projection = self.perspective_matrix(fov, aspect, near, far);
-        self.gl
-            .uniform_matrix4fv_with_f32_array(Some(&self.u_projection), false, &projection);
-
-        // Apply zoom by adjusting camera distance and position
-        // Start with a closer initial view (was 20.0, now 10.0 for better initial scale)
-        let camera_distance = 10.0 / self.zoom;
-        let view = self.look_at_matrix(
-            [self.camera_x, self.camera_y, camera_distance], // eye (zoomed and positioned)
-            [self.camera_x, self.camera_y, 0.0],             // center (follows camera)
-            [0.0, 1.0, 0.0],                                 // up
-        );
-        self.gl
-            .uniform_matrix4fv_with_f32_array(Some(&self.u_view), false, &view);
+        // Set uniforms, unless the camera has not moved
+        if self.uniforms != Some((projection, view)) {
+            self.gl
+                .uniform_matrix4fv_with_f32_array(Some(&self.u_projection), false, &projection);
+            self.gl
+                .uniform_matrix4fv_with_f32_array(Some(&self.u_view), false, &view);
+            self.uniforms = Some((projection, view));
+        }
 
         // Draw particles as points
-        self.gl.draw_arrays(GL::POINTS, 0, particles.len() as i32);
+        self.use_buffers(false);
+        self.gl
+            .draw_arrays(GL::POINTS, 0, (positions.len() / 3) as i32);
+
+        // Outline the periodic box so wrap-around is visible
+        if self.periodic_box.is_some() {
+            self.use_buffers(true);
+            self.gl.uniform1i(Some(&self.u_lines), 1);
+            self.gl.draw_arrays(GL::LINES, 0, 24);
+            self.gl.uniform1i(Some(&self.u_lines), 0);
+        }
     }
 
     fn compile_shader(gl: &GL, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
@@ -219,68 +409,85 @@ impl Renderer {
                 .unwrap_or_else(|| String::from("Unknown error creating program object")))
         }
     }
-
-    fn perspective_matrix(&self, fov: f32, aspect: f32, near: f32, far: f32) -> [f32; 16] {
-        let f = 1.0 / (fov / 2.0).tan();
-        [
-            f / aspect,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            f,
-            0.0,
-            0.0,
-            0.0,
-            0.0,
-            (far + near) / (near - far),
-            -1.0,
-            0.0,
-            0.0,
-            (2.0 * far * near) / (near - far),
-            0.0,
-        ]
-    }
-
-    fn look_at_matrix(&self, eye: [f32; 3], center: [f32; 3], up: [f32; 3]) -> [f32; 16] {
-        let f = normalize([center[0] - eye[0], center[1] - eye[1], center[2] - eye[2]]);
-        let s = normalize(cross(f, up));
-        let u = cross(s, f);
-
-        [
-            s[0],
-            u[0],
-            -f[0],
-            0.0,
-            s[1],
-            u[1],
-            -f[1],
-            0.0,
-            s[2],
-            u[2],
-            -f[2],
-            0.0,
-            -dot(s, eye),
-            -dot(u, eye),
-            dot(f, eye),
-            1.0,
-        ]
-    }
 }
 
-fn normalize(v: [f32; 3]) -> [f32; 3] {
-    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
-    [v[0] / len, v[1] / len, v[2] / len]
+/// Okabe-Ito colors, told apart with any kind of color vision
+const COLORBLIND_SAFE: [[f32; 3]; 7] = [
+    [0.902, 0.624, 0.0],
+    [0.337, 0.706, 0.914],
+    [0.0, 0.620, 0.451],
+    [0.941, 0.894, 0.259],
+    [0.0, 0.447, 0.698],
+    [0.835, 0.369, 0.0],
+    [0.800, 0.475, 0.655],
+];
+
+const HIGH_CONTRAST: [[f32; 3]; 6] = [
+    [1.0, 1.0, 1.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0],
+    [1.0, 0.5, 0.0],
+];
+
+/// Particle `index`'s color in `palette`: its group's hue, at the
+/// brightness of its own color unless the palette is high contrast
+fn particle_color(state: &SimulationState, index: usize, palette: Palette) -> [u8; 4] {
+    let color = state.color(index);
+    let [r, g, b, _] = color.map(|channel| channel as f32 / 255.0);
+    let group = state.group(index) as usize;
+    let (hue, brightness) = match palette {
+        Palette::Natural => return color,
+        Palette::ColorblindSafe => (
+            COLORBLIND_SAFE[group % COLORBLIND_SAFE.len()],
+            (r.max(g).max(b) * 1.2).min(1.0),
+        ),
+        Palette::HighContrast => (HIGH_CONTRAST[group % HIGH_CONTRAST.len()], 1.0),
+    };
+    let [r, g, b] = hue.map(|channel| color_byte(channel * brightness));
+    [r, g, b, color[3]]
 }
 
-fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
-    [
-        a[1] * b[2] - a[2] * b[1],
-        a[2] * b[0] - a[0] * b[2],
-        a[0] * b[1] - a[1] * b[0],
-    ]
+/// Line-list vertices for the 12 edges of a cube with half-width `h`
+fn box_edges(h: f32) -> Vec<f32> {
+    let corners = [
+        [-h, -h, -h],
+        [h, -h, -h],
+        [h, h, -h],
+        [-h, h, -h],
+        [-h, -h, h],
+        [h, -h, h],
+        [h, h, h],
+        [-h, h, h],
+    ];
+    let edges = [
+        (0, 1),
+        (1, 2),
+        (2, 3),
+        (3, 0),
+        (4, 5),
+        (5, 6),
+        (6, 7),
+        (7, 4),
+        (0, 4),
+        (1, 5),
+        (2, 6),
+        (3, 7),
+    ];
+
+    edges
+        .iter()
+        .flat_map(|&(a, b)| corners[a].into_iter().chain(corners[b]))
+        .collect()
 }
 
-fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
-    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+/// Product of two column-major 4×4 matrices
+fn multiply(a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    let mut product = [0.0; 16];
+    for (index, value) in product.iter_mut().enumerate() {
+        let (column, row) = (index / 4, index % 4);
+        *value = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+    }
+    product
 }
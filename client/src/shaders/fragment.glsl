@@ -1,13 +1,22 @@
 precision mediump float;
 
+uniform bool u_lines;
+// Solid points instead of the soft glow, for reduced motion
+uniform bool u_flat;
+
 varying vec4 v_color;
 
 void main() {
+    if (u_lines) {
+        gl_FragColor = v_color;
+        return;
+    }
+
     vec2 coord = gl_PointCoord - vec2(0.5);
     if (length(coord) > 0.5) {
         discard;
     }
     
-    float intensity = 1.0 - length(coord) * 2.0;
+    float intensity = u_flat ? 1.0 : 1.0 - length(coord) * 2.0;
     gl_FragColor = v_color * intensity;
 }
\ No newline at end of file
@@ -0,0 +1,18 @@
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let header = Path::new(&crate_dir).join("include").join("n_body.h");
+
+    // Regenerate the C header from the exported functions and types
+    match cbindgen::generate(&crate_dir) {
+        Ok(bindings) => {
+            bindings.write_to_file(header);
+        }
+        Err(e) => println!("cargo:warning=Failed to generate C header: {}", e),
+    }
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
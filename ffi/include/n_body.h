@@ -0,0 +1,115 @@
+#ifndef N_BODY_H
+#define N_BODY_H
+
+/* Generated by cbindgen from ffi/src/lib.rs - do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Call succeeded
+#define NBODY_OK 0
+
+// A required pointer argument was null
+#define NBODY_ERROR_NULL -1
+
+// The configuration was rejected; see `nbody_last_error`
+#define NBODY_ERROR_INVALID -2
+
+#define NBODY_SOLVER_DIRECT 0
+
+#define NBODY_SOLVER_BARNES_HUT 1
+
+#define NBODY_INTEGRATOR_SYMPLECTIC_EULER 0
+
+#define NBODY_INTEGRATOR_LEAPFROG 1
+
+#define NBODY_INTEGRATOR_YOSHIDA4 2
+
+// Opaque simulation handle
+typedef struct NBodySimulation NBodySimulation;
+
+// Physics settings. `periodic_box` <= 0 means open boundaries and
+// `speed_of_light` <= 0 disables the post-Newtonian correction.
+typedef struct NBodyConfig {
+  uintptr_t particle_count;
+  float time_step;
+  float gravity_strength;
+  // One of the `NBODY_SOLVER_*` constants
+  uint32_t solver;
+  float theta;
+  // One of the `NBODY_INTEGRATOR_*` constants
+  uint32_t integrator;
+  float periodic_box;
+  float speed_of_light;
+  // Seed for the initial conditions; changing it regenerates them
+  uint64_t seed;
+} NBodyConfig;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a simulation of `particle_count` particles with the default galaxy
+// collision. Returns null if the count exceeds the supported maximum. Free
+// the handle with `nbody_destroy`.
+struct NBodySimulation *nbody_create(uintptr_t particle_count);
+
+// Free a simulation created by `nbody_create`.
+//
+// # Safety
+// `sim` must be null or a handle from `nbody_create` not yet destroyed.
+void nbody_destroy(struct NBodySimulation *sim);
+
+// Advance the simulation by `steps` time steps.
+//
+// # Safety
+// `sim` must be null or a live handle from `nbody_create`.
+int32_t nbody_step(struct NBodySimulation *sim, uint32_t steps);
+
+// Number of particles currently simulated, or 0 for a null handle.
+//
+// # Safety
+// `sim` must be null or a live handle from `nbody_create`.
+uintptr_t nbody_particle_count(const struct NBodySimulation *sim);
+
+// Elapsed simulation time, or 0 for a null handle.
+//
+// # Safety
+// `sim` must be null or a live handle from `nbody_create`.
+float nbody_sim_time(const struct NBodySimulation *sim);
+
+// Copy particle positions into `out` as consecutive x, y, z floats, for at
+// most `capacity` particles. Returns the number of particles written.
+//
+// # Safety
+// `sim` must be null or a live handle, and `out` must be null or point to
+// at least `capacity * 3` writable floats.
+uintptr_t nbody_get_positions(const struct NBodySimulation *sim, float *out, uintptr_t capacity);
+
+// Read the current physics settings into `out`.
+//
+// # Safety
+// `sim` must be null or a live handle and `out` null or writable.
+int32_t nbody_get_config(const struct NBodySimulation *sim, struct NBodyConfig *out);
+
+// Apply new physics settings. Changing the particle count regenerates the
+// initial conditions. On failure the previous settings stay in effect.
+//
+// # Safety
+// `sim` must be null or a live handle and `config` null or readable.
+int32_t nbody_set_config(struct NBodySimulation *sim, const struct NBodyConfig *config);
+
+// Message describing the last failed call on this handle, or null. The
+// string stays valid until the next call that fails.
+//
+// # Safety
+// `sim` must be null or a live handle from `nbody_create`.
+const char *nbody_last_error(const struct NBodySimulation *sim);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* N_BODY_H */
@@ -0,0 +1,237 @@
+//! Stable C ABI over the n-body physics core, for embedding the simulation in
+//! C/C++ visualization tools and game engines. The matching header is
+//! generated into `include/n_body.h` on every build.
+
+use n_body_server::config::Config;
+use n_body_server::simulation::Simulation;
+use n_body_shared::{ForceSolver, Integrator, MAX_PARTICLES};
+use std::ffi::{c_char, CString};
+use std::ptr;
+
+/// Call succeeded
+pub const NBODY_OK: i32 = 0;
+/// A required pointer argument was null
+pub const NBODY_ERROR_NULL: i32 = -1;
+/// The configuration was rejected; see `nbody_last_error`
+pub const NBODY_ERROR_INVALID: i32 = -2;
+
+pub const NBODY_SOLVER_DIRECT: u32 = 0;
+pub const NBODY_SOLVER_BARNES_HUT: u32 = 1;
+
+pub const NBODY_INTEGRATOR_SYMPLECTIC_EULER: u32 = 0;
+pub const NBODY_INTEGRATOR_LEAPFROG: u32 = 1;
+pub const NBODY_INTEGRATOR_YOSHIDA4: u32 = 2;
+
+/// Opaque simulation handle
+pub struct NBodySimulation {
+    simulation: Simulation,
+    last_error: Option<CString>,
+}
+
+/// Physics settings. `periodic_box` <= 0 means open boundaries and
+/// `speed_of_light` <= 0 disables the post-Newtonian correction.
+#[repr(C)]
+pub struct NBodyConfig {
+    pub particle_count: usize,
+    pub time_step: f32,
+    pub gravity_strength: f32,
+    /// One of the `NBODY_SOLVER_*` constants
+    pub solver: u32,
+    pub theta: f32,
+    /// One of the `NBODY_INTEGRATOR_*` constants
+    pub integrator: u32,
+    pub periodic_box: f32,
+    pub speed_of_light: f32,
+    /// Seed for the initial conditions; changing it regenerates them
+    pub seed: u64,
+}
+
+/// Create a simulation of `particle_count` particles with the default galaxy
+/// collision. Returns null if the count exceeds the supported maximum. Free
+/// the handle with `nbody_destroy`.
+#[no_mangle]
+pub extern "C" fn nbody_create(particle_count: usize) -> *mut NBodySimulation {
+    if particle_count > MAX_PARTICLES {
+        return ptr::null_mut();
+    }
+
+    let mut sim_config = Config::default().simulation;
+    sim_config.default_particles = particle_count;
+
+    Box::into_raw(Box::new(NBodySimulation {
+        simulation: Simulation::new(&sim_config, false),
+        last_error: None,
+    }))
+}
+
+/// Free a simulation created by `nbody_create`.
+///
+/// # Safety
+/// `sim` must be null or a handle from `nbody_create` not yet destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_destroy(sim: *mut NBodySimulation) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+/// Advance the simulation by `steps` time steps.
+///
+/// # Safety
+/// `sim` must be null or a live handle from `nbody_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_step(sim: *mut NBodySimulation, steps: u32) -> i32 {
+    let Some(sim) = sim.as_mut() else {
+        return NBODY_ERROR_NULL;
+    };
+    for _ in 0..steps {
+        sim.simulation.step();
+    }
+    NBODY_OK
+}
+
+/// Number of particles currently simulated, or 0 for a null handle.
+///
+/// # Safety
+/// `sim` must be null or a live handle from `nbody_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_particle_count(sim: *const NBodySimulation) -> usize {
+    sim.as_ref()
+        .map_or(0, |sim| sim.simulation.particles().len())
+}
+
+/// Elapsed simulation time, or 0 for a null handle.
+///
+/// # Safety
+/// `sim` must be null or a live handle from `nbody_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_sim_time(sim: *const NBodySimulation) -> f32 {
+    sim.as_ref().map_or(0.0, |sim| sim.simulation.sim_time())
+}
+
+/// Copy particle positions into `out` as consecutive x, y, z floats, for at
+/// most `capacity` particles. Returns the number of particles written.
+///
+/// # Safety
+/// `sim` must be null or a live handle, and `out` must be null or point to
+/// at least `capacity * 3` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_get_positions(
+    sim: *const NBodySimulation,
+    out: *mut f32,
+    capacity: usize,
+) -> usize {
+    let Some(sim) = sim.as_ref() else {
+        return 0;
+    };
+    if out.is_null() {
+        return 0;
+    }
+
+    let particles = sim.simulation.particles();
+    let count = particles.len().min(capacity);
+    let out = std::slice::from_raw_parts_mut(out, count * 3);
+    for (xyz, particle) in out.chunks_exact_mut(3).zip(particles) {
+        xyz[0] = particle.position.x;
+        xyz[1] = particle.position.y;
+        xyz[2] = particle.position.z;
+    }
+    count
+}
+
+/// Read the current physics settings into `out`.
+///
+/// # Safety
+/// `sim` must be null or a live handle and `out` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_get_config(
+    sim: *const NBodySimulation,
+    out: *mut NBodyConfig,
+) -> i32 {
+    let (Some(sim), Some(out)) = (sim.as_ref(), out.as_mut()) else {
+        return NBODY_ERROR_NULL;
+    };
+
+    let config = sim.simulation.get_config();
+    *out = NBodyConfig {
+        particle_count: config.particle_count,
+        time_step: config.time_step,
+        gravity_strength: config.gravity_strength,
+        solver: match config.solver {
+            ForceSolver::Direct => NBODY_SOLVER_DIRECT,
+            ForceSolver::BarnesHut => NBODY_SOLVER_BARNES_HUT,
+        },
+        theta: config.theta,
+        integrator: match config.integrator {
+            Integrator::SymplecticEuler => NBODY_INTEGRATOR_SYMPLECTIC_EULER,
+            Integrator::Leapfrog => NBODY_INTEGRATOR_LEAPFROG,
+            Integrator::Yoshida4 => NBODY_INTEGRATOR_YOSHIDA4,
+        },
+        periodic_box: config.periodic_box.unwrap_or(0.0),
+        speed_of_light: config.speed_of_light.unwrap_or(0.0),
+        seed: config.seed,
+    };
+    NBODY_OK
+}
+
+/// Apply new physics settings. Changing the particle count regenerates the
+/// initial conditions. On failure the previous settings stay in effect.
+///
+/// # Safety
+/// `sim` must be null or a live handle and `config` null or readable.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_set_config(
+    sim: *mut NBodySimulation,
+    config: *const NBodyConfig,
+) -> i32 {
+    let (Some(sim), Some(config)) = (sim.as_mut(), config.as_ref()) else {
+        return NBODY_ERROR_NULL;
+    };
+
+    let solver = match config.solver {
+        NBODY_SOLVER_DIRECT => ForceSolver::Direct,
+        NBODY_SOLVER_BARNES_HUT => ForceSolver::BarnesHut,
+        other => return sim.fail(format!("Unknown solver {}", other)),
+    };
+    let integrator = match config.integrator {
+        NBODY_INTEGRATOR_SYMPLECTIC_EULER => Integrator::SymplecticEuler,
+        NBODY_INTEGRATOR_LEAPFROG => Integrator::Leapfrog,
+        NBODY_INTEGRATOR_YOSHIDA4 => Integrator::Yoshida4,
+        other => return sim.fail(format!("Unknown integrator {}", other)),
+    };
+
+    let mut updated = sim.simulation.get_config().clone();
+    updated.particle_count = config.particle_count;
+    updated.time_step = config.time_step;
+    updated.gravity_strength = config.gravity_strength;
+    updated.solver = solver;
+    updated.theta = config.theta;
+    updated.integrator = integrator;
+    updated.periodic_box = (config.periodic_box > 0.0).then_some(config.periodic_box);
+    updated.speed_of_light = (config.speed_of_light > 0.0).then_some(config.speed_of_light);
+    updated.seed = config.seed;
+
+    match sim.simulation.update_config(updated) {
+        Ok(()) => NBODY_OK,
+        Err(message) => sim.fail(message),
+    }
+}
+
+/// Message describing the last failed call on this handle, or null. The
+/// string stays valid until the next call that fails.
+///
+/// # Safety
+/// `sim` must be null or a live handle from `nbody_create`.
+#[no_mangle]
+pub unsafe extern "C" fn nbody_last_error(sim: *const NBodySimulation) -> *const c_char {
+    sim.as_ref()
+        .and_then(|sim| sim.last_error.as_ref())
+        .map_or(ptr::null(), |message| message.as_ptr())
+}
+
+impl NBodySimulation {
+    fn fail(&mut self, message: String) -> i32 {
+        self.last_error = CString::new(message).ok();
+        NBODY_ERROR_INVALID
+    }
+}
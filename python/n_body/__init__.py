@@ -0,0 +1,10 @@
+"""N-body galaxy simulation for notebooks.
+
+``Simulation`` steps the physics and hands out particles as numpy arrays;
+``Simulation.frames`` iterates over positions, and ``n_body.widget.show``
+plays a simulation in a Jupyter canvas.
+"""
+
+from ._native import Frames, Simulation, render
+
+__all__ = ["Frames", "Simulation", "render"]
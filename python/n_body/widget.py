@@ -0,0 +1,27 @@
+"""Play a simulation in a Jupyter notebook through an ipycanvas widget."""
+
+import threading
+import time
+
+from ipycanvas import Canvas
+
+from ._native import render
+
+
+def show(simulation, steps_per_frame=1, frames=None, width=400, height=400,
+         extent=10.0, fps=30):
+    """Return a canvas that draws ``simulation`` as it runs.
+
+    Frames are computed on a background thread, so the cell finishes at once
+    and the notebook stays usable; ``frames`` limits how many are drawn.
+    """
+    canvas = Canvas(width=width, height=height)
+
+    def play():
+        for positions in simulation.frames(steps_per_frame, frames):
+            started = time.monotonic()
+            canvas.put_image_data(render(positions, width, height, extent), 0, 0)
+            time.sleep(max(0.0, 1 / fps - (time.monotonic() - started)))
+
+    threading.Thread(target=play, daemon=True).start()
+    return canvas
@@ -0,0 +1,202 @@
+//! Python bindings to the n-body physics core, for stepping a simulation from
+//! notebooks and reading its particles as numpy arrays. Built with maturin as
+//! the native half of the `n_body` package; `n_body.widget` draws the frames
+//! into a Jupyter canvas.
+
+use n_body_server::config::Config;
+use n_body_server::simulation::Simulation as Core;
+use n_body_shared::{Particle, MAX_PARTICLES};
+use numpy::ndarray::{Array2, Array3};
+use numpy::{IntoPyArray, PyArray2, PyArray3, PyReadonlyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Brightness a single particle adds to its pixel, so dense regions glow
+const SPLAT: u8 = 96;
+
+/// A simulation of the default galaxy collision. The core sits behind a
+/// mutex rather than Python's borrow tracking, so reading it from a notebook
+/// while a widget steps it on another thread waits for the step instead of
+/// raising "Already mutably borrowed".
+#[pyclass(module = "n_body", frozen)]
+struct Simulation {
+    simulation: Mutex<Core>,
+}
+
+impl Simulation {
+    fn core(&self) -> MutexGuard<'_, Core> {
+        self.simulation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[pymethods]
+impl Simulation {
+    #[new]
+    #[pyo3(signature = (particles = 2000, seed = 0))]
+    fn new(particles: usize, seed: u64) -> PyResult<Self> {
+        if particles > MAX_PARTICLES {
+            return Err(PyValueError::new_err(format!(
+                "At most {} particles are supported",
+                MAX_PARTICLES
+            )));
+        }
+        let mut sim_config = Config::default().simulation;
+        sim_config.default_particles = particles;
+        sim_config.seed = seed;
+        Ok(Simulation {
+            simulation: Mutex::new(Core::new(&sim_config, false)),
+        })
+    }
+
+    /// Advance by `steps` time steps, letting other Python threads run
+    #[pyo3(signature = (steps = 1))]
+    fn step(&self, py: Python<'_>, steps: u32) {
+        // Locked without the GIL, so other threads' reads wait here rather
+        // than holding the GIL while this step waits for them
+        py.detach(|| {
+            let mut simulation = self.core();
+            for _ in 0..steps {
+                simulation.step();
+            }
+        });
+    }
+
+    /// Start over from new initial conditions
+    fn reset(&self, py: Python<'_>) {
+        py.detach(|| self.core().reset());
+    }
+
+    /// Positions as an (N, 3) float32 array
+    fn positions<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f32>> {
+        let values = py.detach(|| vectors(self.core().particles(), |p| p.position.coords.into()));
+        to_array(py, 3, values)
+    }
+
+    /// Velocities as an (N, 3) float32 array
+    fn velocities<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f32>> {
+        let values = py.detach(|| vectors(self.core().particles(), |p| p.velocity.into()));
+        to_array(py, 3, values)
+    }
+
+    /// RGBA colors in [0, 1] as an (N, 4) float32 array
+    fn colors<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<f32>> {
+        let values = py.detach(|| {
+            let simulation = self.core();
+            simulation
+                .particles()
+                .iter()
+                .flat_map(|p| p.color)
+                .collect()
+        });
+        to_array(py, 4, values)
+    }
+
+    #[getter]
+    fn particle_count(&self, py: Python<'_>) -> usize {
+        py.detach(|| self.core().particles().len())
+    }
+
+    #[getter]
+    fn frame_number(&self, py: Python<'_>) -> u64 {
+        py.detach(|| self.core().frame_number())
+    }
+
+    #[getter]
+    fn sim_time(&self, py: Python<'_>) -> f32 {
+        py.detach(|| self.core().sim_time())
+    }
+
+    /// Iterate over the positions after every `steps_per_frame` steps, for
+    /// `count` frames or without end
+    #[pyo3(signature = (steps_per_frame = 1, count = None))]
+    fn frames(slf: Py<Self>, steps_per_frame: u32, count: Option<u64>) -> Frames {
+        Frames {
+            simulation: slf,
+            steps_per_frame,
+            remaining: count,
+        }
+    }
+}
+
+/// Positions of a simulation frame after frame, from `Simulation.frames`
+#[pyclass(module = "n_body")]
+struct Frames {
+    simulation: Py<Simulation>,
+    steps_per_frame: u32,
+    remaining: Option<u64>,
+}
+
+#[pymethods]
+impl Frames {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> Option<Bound<'py, PyArray2<f32>>> {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.checked_sub(1)?;
+        }
+        let simulation = self.simulation.get();
+        simulation.step(py, self.steps_per_frame);
+        Some(simulation.positions(py))
+    }
+}
+
+/// Draw `positions` seen from above into a (height, width, 4) RGBA image,
+/// `extent` units either side of the origin, ready for a canvas widget
+#[pyfunction]
+#[pyo3(signature = (positions, width = 400, height = 400, extent = 10.0))]
+fn render<'py>(
+    py: Python<'py>,
+    positions: PyReadonlyArray2<'py, f32>,
+    width: usize,
+    height: usize,
+    extent: f32,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let positions = positions.as_array();
+    if positions.ncols() < 2 {
+        return Err(PyValueError::new_err("Positions need x and y columns"));
+    }
+    if extent <= 0.0 {
+        return Err(PyValueError::new_err("Extent must be positive"));
+    }
+
+    let mut image = Array3::<u8>::zeros((height, width, 4));
+    image.slice_mut(numpy::ndarray::s![.., .., 3]).fill(255);
+    let scale = width.min(height) as f32 / (2.0 * extent);
+    for position in positions.rows() {
+        let x = width as f32 / 2.0 + position[0] * scale;
+        let y = height as f32 / 2.0 - position[1] * scale;
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            continue;
+        }
+        let (row, column) = (y as usize, x as usize);
+        for channel in 0..3 {
+            let value = &mut image[[row, column, channel]];
+            *value = value.saturating_add(SPLAT);
+        }
+    }
+    Ok(image.into_pyarray(py))
+}
+
+fn vectors(particles: &[Particle], vector: impl Fn(&Particle) -> [f32; 3]) -> Vec<f32> {
+    particles.iter().flat_map(vector).collect()
+}
+
+/// `values` as rows of `columns` floats each
+fn to_array(py: Python<'_>, columns: usize, values: Vec<f32>) -> Bound<'_, PyArray2<f32>> {
+    Array2::from_shape_vec((values.len() / columns, columns), values)
+        .expect("whole rows")
+        .into_pyarray(py)
+}
+
+#[pymodule]
+fn _native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Simulation>()?;
+    m.add_class::<Frames>()?;
+    m.add_function(wrap_pyfunction!(render, m)?)?;
+    Ok(())
+}
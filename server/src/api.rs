@@ -0,0 +1,389 @@
+// Plain HTTP endpoints for dashboards, scripts and curl users
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::stream;
+use log::info;
+use n_body_shared::{Role, Scenario, StatsSchema};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::import::{self, ImportFormat};
+use crate::jobs::JobRequest;
+use crate::scenarios;
+use crate::snapshot::Snapshot;
+use crate::spec;
+use crate::watchdog::HealthStatus;
+use crate::AppState;
+
+/// Largest initial-condition file accepted by `POST /api/import/{name}`
+pub const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Metrics carried by the stats JSON with their units, for dashboards that
+/// build their charts from it: `GET /api/stats/schema`
+pub async fn stats_schema() -> HttpResponse {
+    HttpResponse::Ok().json(StatsSchema::current())
+}
+
+/// OpenAPI description of these endpoints, generated from the types they
+/// read and write: `GET /api/spec`
+pub async fn openapi_spec() -> HttpResponse {
+    HttpResponse::Ok().json(spec::openapi())
+}
+
+/// AsyncAPI description of the websocket protocol: `GET /api/spec/websocket`
+pub async fn asyncapi_spec() -> HttpResponse {
+    HttpResponse::Ok().json(spec::asyncapi())
+}
+
+/// A page for trying the endpoints and websocket messages from the specs in
+/// a browser: `GET /api/playground`
+pub async fn playground() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(include_str!("../../www/playground.html"))
+}
+
+/// Built-in scenarios, scenario files and imported particle sets with their
+/// descriptions and particle counts, for scenario pickers: `GET /api/scenarios`
+pub async fn list_scenarios(data: web::Data<AppState>) -> HttpResponse {
+    let dir = PathBuf::from(&data.config().simulation.scenarios_dir);
+    // Scenario scripts run to be counted; keep them off the event loop
+    match web::block(move || scenarios::catalog(&dir)).await {
+        Ok(scenarios) => HttpResponse::Ok().json(scenarios),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Time-lapse snapshots of the live run, oldest first: `GET /api/gallery`
+pub async fn list_gallery(data: web::Data<AppState>) -> HttpResponse {
+    match data.simulation().lock() {
+        Ok(sim) => HttpResponse::Ok().json(sim.gallery().entries()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// One snapshot as a state frame (the JSON of a `State` message without its
+/// `type`): `GET /api/gallery/{id}`
+pub async fn gallery_entry(data: web::Data<AppState>, id: web::Path<u64>) -> HttpResponse {
+    let state = match data.simulation().lock() {
+        Ok(sim) => sim.gallery().state(id.into_inner()),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    match state {
+        Some(state) => HttpResponse::Ok().json(state),
+        None => {
+            HttpResponse::NotFound().body("No such gallery entry; it may have been thinned out")
+        }
+    }
+}
+
+/// Energy partition samples of the current run, oldest first: `GET /api/energy`
+pub async fn energy_history(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.energy().history())
+}
+
+/// Frame rate, particle count, clients, traffic and watchdog stalls of the
+/// main simulation for Prometheus: `GET /metrics`
+pub async fn metrics(data: web::Data<AppState>) -> HttpResponse {
+    let frame = data.latest().load_full();
+    HttpResponse::Ok()
+        .content_type(crate::metrics::CONTENT_TYPE)
+        .body(data.metrics().render(&frame, data.watchdog()))
+}
+
+/// Watchdog view of the main simulation for liveness probes: `GET /health`.
+/// 503 once it is hung past the watchdog timeout, so an orchestrator
+/// restarts the server; a shorter stall still answers 200.
+pub async fn health(data: web::Data<AppState>) -> HttpResponse {
+    let health = data.watchdog().health();
+    match health.status {
+        HealthStatus::Hung => HttpResponse::ServiceUnavailable().json(health),
+        HealthStatus::Healthy | HealthStatus::Stalled => HttpResponse::Ok().json(health),
+    }
+}
+
+/// Server-sent events feed of the stats JSON, one `data:` event every
+/// `stats_frequency` simulation frames. Try `curl -N host/api/stats/stream`.
+pub async fn stats_stream(data: web::Data<AppState>) -> HttpResponse {
+    let latest = data.latest().clone();
+    // Following the stats keeps the simulation stepping like a viewer does
+    let attendance = data.presence().join();
+    let config = data.config();
+    let every = config.simulation.stats_frequency.max(1);
+    let units = config.unit_system();
+    let time_precision = config.units.time_precision;
+
+    // Poll at the physics rate so no stats boundary is skipped
+    let mut interval = tokio::time::interval(Duration::from_millis(
+        config.simulation.update_rate_ms.max(1),
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let events = stream::unfold(
+        (interval, None, attendance),
+        move |(mut interval, mut last_sent, attendance)| {
+            let latest = latest.clone();
+            async move {
+                loop {
+                    interval.tick().await;
+                    let frame = latest.load();
+                    let period = frame.stats.frame_number / every;
+                    if last_sent == Some(period) {
+                        continue;
+                    }
+                    last_sent = Some(period);
+                    let mut stats = frame.stats.clone();
+                    stats.sim_time_display = units.format_time(stats.sim_time, time_precision);
+
+                    let json = serde_json::to_string(&stats).ok()?;
+                    let event = web::Bytes::from(format!("data: {}\n\n", json));
+                    return Some((
+                        Ok::<_, actix_web::Error>(event),
+                        (interval, last_sent, attendance),
+                    ));
+                }
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // Keep reverse proxies such as nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events)
+}
+
+fn jobs_disabled() -> HttpResponse {
+    HttpResponse::NotFound().body("Jobs are disabled on this server")
+}
+
+/// `POST /api/jobs`: queue a headless run, e.g.
+/// `{"scenario": "Plummer", "particle_count": 2000, "duration": 10.0}`
+pub async fn submit_job(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    request: web::Json<JobRequest>,
+) -> HttpResponse {
+    if data.role_for(&req) < Role::Controller {
+        return HttpResponse::Forbidden().body("Submitting jobs requires control access");
+    }
+    let Some(jobs) = data.jobs() else {
+        return jobs_disabled();
+    };
+    match jobs.submit(request.into_inner()) {
+        Ok(id) => HttpResponse::Accepted().json(jobs.status(id)),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+pub async fn list_jobs(data: web::Data<AppState>) -> HttpResponse {
+    match data.jobs() {
+        Some(jobs) => HttpResponse::Ok().json(jobs.list()),
+        None => jobs_disabled(),
+    }
+}
+
+pub async fn job_status(data: web::Data<AppState>, id: web::Path<u64>) -> HttpResponse {
+    let Some(jobs) = data.jobs() else {
+        return jobs_disabled();
+    };
+    match jobs.status(id.into_inner()) {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body("No such job"),
+    }
+}
+
+pub async fn cancel_job(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<u64>,
+) -> HttpResponse {
+    if data.role_for(&req) < Role::Controller {
+        return HttpResponse::Forbidden().body("Cancelling jobs requires control access");
+    }
+    let Some(jobs) = data.jobs() else {
+        return jobs_disabled();
+    };
+    match jobs.cancel(id.into_inner()) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::NotFound().body(e),
+    }
+}
+
+/// Download one of a job's result files: `config.json`, `log.csv` or a
+/// `snapshot-NNNN.json`
+pub async fn job_file(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(u64, String)>,
+) -> HttpResponse {
+    let Some(jobs) = data.jobs() else {
+        return jobs_disabled();
+    };
+    let (id, name) = path.into_inner();
+    let Some(path) = jobs.file_path(id, &name) else {
+        return HttpResponse::NotFound().body("No such file");
+    };
+    match actix_files::NamedFile::open_async(&path).await {
+        Ok(file) => file.into_response(&req),
+        Err(e) => HttpResponse::InternalServerError().body(format!(
+            "Failed to open {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ImportQuery {
+    #[serde(default = "default_import_format")]
+    format: ImportFormat,
+}
+
+fn default_import_format() -> ImportFormat {
+    ImportFormat::Csv
+}
+
+/// Reply to a successful import
+#[derive(Serialize, JsonSchema)]
+pub struct Imported {
+    pub name: String,
+    pub particles: usize,
+}
+
+/// `POST /api/import/{name}?format=csv|gadget`: store the body as an import
+/// file in the scenarios directory and switch the simulation to it, e.g.
+/// `curl --data-binary @ics.csv host/api/import/my_ics`
+pub async fn import_particles(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+) -> HttpResponse {
+    if data.role_for(&req) < Role::Controller {
+        return HttpResponse::Forbidden().body("Importing particles requires control access");
+    }
+    let name = name.into_inner();
+    let format = query.format;
+    let dir = PathBuf::from(&data.config().simulation.scenarios_dir);
+
+    // Parsing a large snapshot takes a while; keep it off the event loop
+    let stored = web::block({
+        let name = name.clone();
+        move || -> Result<usize, String> {
+            let path = import::path_for(&dir, &name, format)?;
+            let count = format.parse(&body)?.len();
+            std::fs::create_dir_all(&dir)
+                .and_then(|_| std::fs::write(&path, &body))
+                .map_err(|e| format!("Failed to store {}: {}", path.display(), e))?;
+            // An older upload in another format would take precedence
+            for other in [ImportFormat::Csv, ImportFormat::Gadget] {
+                if other != format {
+                    let _ = std::fs::remove_file(import::path_for(&dir, &name, other)?);
+                }
+            }
+            Ok(count)
+        }
+    })
+    .await;
+    let count = match stored {
+        Ok(Ok(count)) => count,
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(e),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    let mut sim = match data.simulation().lock() {
+        Ok(sim) => sim,
+        Err(_) => return HttpResponse::InternalServerError().body("Simulation lock poisoned"),
+    };
+    // Selecting the same name again reloads the new contents
+    match sim.set_scenario(Scenario::Import(name.clone())) {
+        Ok(()) => {
+            info!("Imported {} particles as '{}'", count, name);
+            HttpResponse::Ok().json(Imported {
+                name,
+                particles: count,
+            })
+        }
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SnapshotQuery {
+    /// Name to save under; one made from the time when omitted
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// `POST /api/snapshots?name=<name>`: save the main simulation to the
+/// snapshots directory, e.g. before a restart
+pub async fn save_snapshot(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<SnapshotQuery>,
+) -> HttpResponse {
+    if data.role_for(&req) < Role::Controller {
+        return HttpResponse::Forbidden().body("Saving snapshots requires control access");
+    }
+    let simulation = data.simulation().clone();
+    let dir = PathBuf::from(&data.config().simulation.snapshots_dir);
+    let name = query.into_inner().name;
+
+    // Writing a large run takes a while; keep it off the event loop
+    let saved = web::block(move || {
+        let snapshot = simulation
+            .lock()
+            .map_err(|_| "Simulation lock poisoned".to_string())?
+            .snapshot(name)?;
+        snapshot.save(&dir)?;
+        Ok::<_, String>(snapshot.info)
+    })
+    .await;
+    match saved {
+        Ok(Ok(info)) => {
+            info!("Saved snapshot {}", info.name);
+            HttpResponse::Created().json(info)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// `POST /api/snapshots/{name}/load`: continue the main simulation from a
+/// saved snapshot
+pub async fn load_snapshot(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+) -> HttpResponse {
+    if data.role_for(&req) < Role::Controller {
+        return HttpResponse::Forbidden().body("Loading snapshots requires control access");
+    }
+    let simulation = data.simulation().clone();
+    let dir = PathBuf::from(&data.config().simulation.snapshots_dir);
+    let name = name.into_inner();
+
+    let loaded = web::block(move || {
+        let snapshot = Snapshot::load(&dir, &name)?;
+        let info = snapshot.info.clone();
+        simulation
+            .lock()
+            .map_err(|_| "Simulation lock poisoned".to_string())?
+            .restore(snapshot)?;
+        Ok::<_, String>(info)
+    })
+    .await;
+    match loaded {
+        Ok(Ok(info)) => {
+            info!("Loaded snapshot {}", info.name);
+            HttpResponse::Ok().json(info)
+        }
+        Ok(Err(e)) => HttpResponse::BadRequest().body(e),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
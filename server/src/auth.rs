@@ -0,0 +1,226 @@
+use actix_web::HttpRequest;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use n_body_shared::Role;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AuthConfig;
+
+/// A way of recognising who is connecting. Providers return the role their
+/// credentials grant, or `None` if the request carries nothing they accept.
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn authenticate(&self, req: &HttpRequest) -> Option<Role>;
+}
+
+/// Trusts an identity header set by a reverse proxy that has already
+/// authenticated the user. Only enable this when the proxy strips any
+/// client-supplied copy of the header.
+pub struct ProxyHeaderAuth {
+    header: String,
+}
+
+impl AuthProvider for ProxyHeaderAuth {
+    fn name(&self) -> &'static str {
+        "proxy-header"
+    }
+
+    fn authenticate(&self, req: &HttpRequest) -> Option<Role> {
+        req.headers()
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .filter(|user| !user.trim().is_empty())
+            .map(|_| Role::Controller)
+    }
+}
+
+/// Accepts HS256-signed JWTs. The optional `role` claim can downgrade a token
+/// to viewer access; `exp` is honoured when present.
+pub struct JwtAuth {
+    secret: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    exp: Option<u64>,
+    role: Option<Role>,
+}
+
+impl JwtAuth {
+    fn verify(&self, token: &str) -> Option<JwtClaims> {
+        let (signing_input, signature) = token.rsplit_once('.')?;
+        let (header, payload) = signing_input.split_once('.')?;
+
+        let header: JwtHeader =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        if header.alg != "HS256" {
+            return None;
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).ok()?;
+        mac.update(signing_input.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?)
+            .ok()?;
+
+        let claims: JwtClaims =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        if let Some(exp) = claims.exp {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            if exp <= now {
+                return None;
+            }
+        }
+
+        Some(claims)
+    }
+}
+
+impl AuthProvider for JwtAuth {
+    fn name(&self) -> &'static str {
+        "jwt"
+    }
+
+    fn authenticate(&self, req: &HttpRequest) -> Option<Role> {
+        let token = presented_token(req)?;
+        self.verify(&token)
+            .map(|claims| claims.role.unwrap_or(Role::Controller))
+    }
+}
+
+/// Grants control to requests presenting one of the configured API keys
+pub struct ApiKeyAuth {
+    keys: Vec<String>,
+}
+
+impl AuthProvider for ApiKeyAuth {
+    fn name(&self) -> &'static str {
+        "api-key"
+    }
+
+    fn authenticate(&self, req: &HttpRequest) -> Option<Role> {
+        let token = presented_token(req)?;
+        self.keys
+            .iter()
+            .any(|key| constant_time_eq(key, &token))
+            .then_some(Role::Controller)
+    }
+}
+
+/// Compare without revealing through the time taken how much of a guessed
+/// key was right
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// Runs the configured providers in order and decides each connection's role
+pub struct Authenticator {
+    providers: Vec<Box<dyn AuthProvider>>,
+}
+
+impl Authenticator {
+    pub fn from_config(config: &AuthConfig) -> Self {
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+
+        if let Some(header) = &config.proxy_header {
+            providers.push(Box::new(ProxyHeaderAuth {
+                header: header.clone(),
+            }));
+        }
+        if let Some(secret) = &config.jwt_secret {
+            providers.push(Box::new(JwtAuth {
+                secret: secret.as_bytes().to_vec(),
+            }));
+        }
+        if !config.api_keys.is_empty() {
+            providers.push(Box::new(ApiKeyAuth {
+                keys: config.api_keys.clone(),
+            }));
+        }
+
+        for provider in &providers {
+            log::info!("Auth provider enabled: {}", provider.name());
+        }
+
+        Authenticator { providers }
+    }
+
+    /// With no providers configured everyone may control the simulation, as
+    /// before auth existed; otherwise unauthenticated clients only spectate
+    pub fn role_for(&self, req: &HttpRequest) -> Role {
+        if self.providers.is_empty() {
+            return Role::Controller;
+        }
+
+        self.authenticated_role(req).unwrap_or(Role::Viewer)
+    }
+
+    /// Role granted by a provider that actually recognised the request
+    pub fn authenticated_role(&self, req: &HttpRequest) -> Option<Role> {
+        self.providers
+            .iter()
+            .filter_map(|provider| provider.authenticate(req))
+            .max()
+    }
+}
+
+/// Credentials from `Authorization: Bearer`, `X-Api-Key`, or on websocket
+/// upgrades a `token` query parameter, since browsers can't set headers there.
+/// Other requests must use a header so keys don't end up in URLs and logs.
+fn presented_token(req: &HttpRequest) -> Option<String> {
+    let headers = req.headers();
+
+    if let Some(bearer) = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim().to_string());
+    }
+
+    if let Some(key) = headers
+        .get("X-Api-Key")
+        .and_then(|value| value.to_str().ok())
+    {
+        return Some(key.trim().to_string());
+    }
+
+    let upgrade = headers
+        .get("Upgrade")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return None;
+    }
+
+    actix_web::web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.get("token").cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_compares_whole_keys() {
+        assert!(constant_time_eq("a-long-key", "a-long-key"));
+        assert!(constant_time_eq("", ""));
+        assert!(!constant_time_eq("a-long-key", "a-long-kez"));
+        assert!(!constant_time_eq("a-long-key", "a-long-ke"));
+        assert!(!constant_time_eq("a-long-key", "a-long-key-"));
+        assert!(!constant_time_eq("", "a"));
+    }
+}
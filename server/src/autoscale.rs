@@ -0,0 +1,206 @@
+// Automatic particle count: watches the physics time per frame and trims
+// particles when frames stay over budget, or raises the count for the next
+// reset when they stay well under it
+use n_body_shared::{ForceSolver, Particle, ParticleScaling};
+
+use crate::config::AutoScaleConfig;
+
+/// Safety margin on the estimated count, so one change is usually enough
+const MARGIN: f32 = 0.9;
+
+/// Bounds on one change: trims remove at least a tenth and at most half of
+/// the particles, growth at most doubles the count
+const MIN_TRIM: f32 = 0.5;
+const MAX_TRIM: f32 = 0.9;
+const MAX_GROWTH: f32 = 2.0;
+
+pub struct AutoScale {
+    config: AutoScaleConfig,
+    /// Consecutive frames over the target, and their total time
+    slow_frames: u32,
+    slow_ms: f32,
+    /// Consecutive frames under `headroom` of the target, and their total time
+    fast_frames: u32,
+    fast_ms: f32,
+}
+
+/// What to do about the particle count after a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    /// Remove particles now, keeping this many
+    Trim(usize),
+    /// Generate this many at the next reset
+    Grow(usize),
+}
+
+impl AutoScale {
+    pub fn new(config: AutoScaleConfig) -> Self {
+        AutoScale {
+            config,
+            slow_frames: 0,
+            slow_ms: 0.0,
+            fast_frames: 0,
+            fast_ms: 0.0,
+        }
+    }
+
+    /// Record a frame of `particles` that took `frame_time_ms`. `next_count`
+    /// is what the next reset generates, and `ceiling` the most it may be
+    /// raised to.
+    pub fn observe(
+        &mut self,
+        frame_time_ms: f32,
+        particles: usize,
+        next_count: usize,
+        ceiling: usize,
+        solver: ForceSolver,
+    ) -> Option<(Decision, f32)> {
+        let target = self.config.target_ms;
+        if frame_time_ms > target {
+            self.slow_frames += 1;
+            self.slow_ms += frame_time_ms;
+            self.fast_frames = 0;
+            self.fast_ms = 0.0;
+        } else if frame_time_ms < target * self.config.headroom {
+            self.fast_frames += 1;
+            self.fast_ms += frame_time_ms;
+            self.slow_frames = 0;
+            self.slow_ms = 0.0;
+        } else {
+            self.slow_frames = 0;
+            self.slow_ms = 0.0;
+            self.fast_frames = 0;
+            self.fast_ms = 0.0;
+        }
+
+        if self.slow_frames >= self.config.frames {
+            let mean = self.slow_ms / self.slow_frames as f32;
+            self.slow_frames = 0;
+            self.slow_ms = 0.0;
+            if particles <= self.config.min_particles {
+                return None;
+            }
+            let factor = (count_ratio(target / mean, solver) * MARGIN).clamp(MIN_TRIM, MAX_TRIM);
+            let count = ((particles as f32 * factor) as usize).max(self.config.min_particles);
+            return Some((Decision::Trim(count), mean));
+        }
+
+        if self.fast_frames >= self.config.frames {
+            let mean = self.fast_ms / self.fast_frames as f32;
+            self.fast_frames = 0;
+            self.fast_ms = 0.0;
+            let factor =
+                (count_ratio(target / mean.max(f32::EPSILON), solver) * MARGIN).min(MAX_GROWTH);
+            let count = ((particles as f32 * factor) as usize).min(ceiling);
+            // Already raised that far, or nothing to gain
+            if count <= next_count {
+                return None;
+            }
+            return Some((Decision::Grow(count), mean));
+        }
+
+        None
+    }
+
+    /// The notice for clients about `decision` on a simulation of `from`
+    /// particles
+    pub fn scaling(&self, decision: Decision, from: usize, mean_ms: f32) -> ParticleScaling {
+        let (to, immediate) = match decision {
+            Decision::Trim(count) => (count, true),
+            Decision::Grow(count) => (count, false),
+        };
+        ParticleScaling {
+            from,
+            to,
+            computation_time_ms: mean_ms,
+            target_ms: self.config.target_ms,
+            immediate,
+        }
+    }
+}
+
+/// Change of particle count that changes the frame time by `time_ratio`.
+/// The direct sum costs N², a tree about N log N, taken as N here.
+fn count_ratio(time_ratio: f32, solver: ForceSolver) -> f32 {
+    match solver {
+        ForceSolver::Direct => time_ratio.sqrt(),
+        ForceSolver::BarnesHut => time_ratio,
+    }
+}
+
+/// Keep `count` particles spread evenly through the list, plus every pinned
+/// one, and hand the mass of the removed particles to the kept ones so
+/// gravity keeps its overall strength
+pub fn thin(particles: &mut Vec<Particle>, count: usize) {
+    let (fixed, free): (Vec<Particle>, Vec<Particle>) =
+        particles.drain(..).partition(|particle| particle.fixed);
+    let keep = count.saturating_sub(fixed.len()).min(free.len());
+    if keep == 0 {
+        *particles = fixed;
+        return;
+    }
+
+    let total: f32 = free.iter().map(|particle| particle.mass).sum();
+    let stride = free.len() as f64 / keep as f64;
+    let mut kept: Vec<Particle> = (0..keep)
+        .map(|index| free[(index as f64 * stride) as usize].clone())
+        .collect();
+    let kept_mass: f32 = kept.iter().map(|particle| particle.mass).sum();
+    if kept_mass > 0.0 {
+        let scale = total / kept_mass;
+        for particle in &mut kept {
+            particle.mass *= scale;
+        }
+    }
+
+    *particles = kept;
+    particles.extend(fixed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+
+    fn particle(mass: f32, fixed: bool) -> Particle {
+        Particle {
+            position: Point3::origin(),
+            velocity: Vector3::zeros(),
+            mass,
+            color: [1.0; 4],
+            fixed,
+            group: 0,
+        }
+    }
+
+    fn total_mass(particles: &[Particle]) -> f32 {
+        particles.iter().map(|particle| particle.mass).sum()
+    }
+
+    #[test]
+    fn thin_conserves_mass() {
+        let mut particles: Vec<Particle> = (0..1000)
+            .map(|i| particle(1.0 + (i % 7) as f32, false))
+            .collect();
+        particles.push(particle(500.0, true));
+        let before = total_mass(&particles);
+
+        thin(&mut particles, 101);
+
+        assert_eq!(particles.len(), 101);
+        assert!((total_mass(&particles) - before).abs() <= before * 1e-5);
+    }
+
+    #[test]
+    fn thin_keeps_pinned_particles_as_they_are() {
+        let mut particles: Vec<Particle> = (0..100).map(|_| particle(1.0, false)).collect();
+        particles.push(particle(500.0, true));
+
+        thin(&mut particles, 11);
+
+        let pinned: Vec<&Particle> = particles.iter().filter(|p| p.fixed).collect();
+        assert_eq!(pinned.len(), 1);
+        assert_eq!(pinned[0].mass, 500.0);
+        assert!((total_mass(&particles) - 600.0).abs() < 1e-3);
+    }
+}
@@ -1,33 +1,1260 @@
+use n_body_shared::{
+    ForceSolver, GalaxySpec, Integrator, MassFunction, Morphology, Scenario, ThetaTuning,
+    UnitSystem, DEFAULT_THETA,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
+/// Where the server reads its settings from, relative to its working
+/// directory
+pub const CONFIG_PATH: &str = "config.toml";
+
+/// Prefix of the environment variables overriding settings, e.g.
+/// `N_BODY_SIMULATION__UPDATE_RATE_MS=50` for `[simulation] update_rate_ms`
+pub const ENV_PREFIX: &str = "N_BODY_";
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub simulation: SimulationConfig,
     pub websocket: WebSocketConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub demo: DemoConfig,
+    #[serde(default)]
+    pub units: UnitsConfig,
+    #[serde(default)]
+    pub udp: UdpConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub rooms: RoomsConfig,
+    #[serde(default)]
+    pub distributed: DistributedConfig,
+    /// Simulations that run for the server's lifetime besides the main one,
+    /// reached at `/ws/{name}`. Each table overrides `[simulation]` keys,
+    /// e.g. its own `default_particles`, `max_particles` and `update_rate_ms`.
+    #[serde(default)]
+    pub simulations: BTreeMap<String, toml::Table>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
     pub port: u16,
+    /// IPv4 or IPv6 address; `::` listens dual-stack on both
     pub host: String,
     #[serde(default)]
     pub debug: bool,
+    /// Externally visible URL of the page, e.g. `https://example.com/nbody`
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// Believe X-Forwarded-For/-Proto/-Host headers from a reverse proxy
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
+    /// Reverse proxies in front of the server that each append to
+    /// X-Forwarded-For; the client is the address the outermost one added
+    #[serde(default = "default_trusted_proxy_hops")]
+    pub trusted_proxy_hops: usize,
+    /// Origins of other sites' pages allowed to call the API and open
+    /// websockets, e.g. `https://example.com`; `*` allows any origin. Empty
+    /// keeps the server to its own page.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// PEM certificate chain; with `tls_key`, serves https:// and wss://
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    /// PEM private key of `tls_cert`
+    #[serde(default)]
+    pub tls_key: Option<String>,
+    /// Apply edits of config.toml to the running server: update rate,
+    /// stats frequency, heartbeat and control intervals, and the scenarios'
+    /// settings. Anything else still takes a restart.
+    #[serde(default = "default_watch_config")]
+    pub watch_config: bool,
+}
+
+fn default_watch_config() -> bool {
+    true
+}
+
+fn default_trusted_proxy_hops() -> usize {
+    1
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SimulationConfig {
     pub default_particles: usize,
     pub update_rate_ms: u64,
+    /// Physics steps integrated per published frame; raising it fast-forwards
+    /// the run without sending frames more often
+    #[serde(default = "default_steps_per_update")]
+    pub steps_per_update: u32,
     pub stats_frequency: u64,
+    /// Initial gravitational constant G in simulation units
+    #[serde(default = "default_gravity_strength")]
+    pub gravity_strength: f32,
+    /// Run in a cubic periodic box of this side length
+    #[serde(default)]
+    pub periodic_box: Option<f32>,
+    /// Particle cap below the shared MAX_PARTICLES
+    #[serde(default)]
+    pub max_particles: Option<usize>,
+    #[serde(default)]
+    pub solver: ForceSolver,
+    #[serde(default = "default_theta")]
+    pub theta: f32,
+    /// Adjust theta at runtime to a frame-time or force-error budget
+    #[serde(default)]
+    pub theta_tuning: ThetaTuning,
+    #[serde(default)]
+    pub integrator: Integrator,
+    /// Enable the 1PN relativistic correction with this speed of light
+    #[serde(default)]
+    pub speed_of_light: Option<f32>,
+    /// Re-sort particles along a Morton (Z-order) curve every this many
+    /// frames for memory locality; 0 disables sorting
+    #[serde(default = "default_sort_interval")]
+    pub sort_interval: u64,
+    /// Stream kinematically interpolated frames between physics steps when a
+    /// client asks for more visual FPS than the physics update rate
+    #[serde(default = "default_interpolate_frames")]
+    pub interpolate_frames: bool,
+    /// Stop stepping while no websocket client, stats stream or UDP
+    /// broadcaster follows the simulation, and carry on when one connects
+    #[serde(default = "default_pause_when_idle")]
+    pub pause_when_idle: bool,
+    /// Kahan-compensated force accumulation in the direct solver; more
+    /// accurate for large particle counts at some cost in throughput
+    #[serde(default)]
+    pub compensated_summation: bool,
+    /// Sub-step a frame when any two particles come closer than this
+    #[serde(default)]
+    pub encounter_distance: Option<f32>,
+    /// Upper bound on sub-steps per frame during close encounters
+    #[serde(default = "default_max_substeps")]
+    pub max_substeps: u32,
+    /// Pin an immovable attractor of this mass at the origin on every reset
+    #[serde(default)]
+    pub central_attractor_mass: Option<f32>,
+    /// Initial conditions generated on every reset, e.g. `"Plummer"` or
+    /// `{ File = "three_way" }` for a file in `scenarios_dir`
+    #[serde(default)]
+    pub scenario: Scenario,
+    /// Directory holding scenario definition files (`<name>.toml` or
+    /// `<name>.json`)
+    #[serde(default = "default_scenarios_dir")]
+    pub scenarios_dir: String,
+    /// Directory snapshots are saved to and loaded from (`<name>.json`)
+    #[serde(default = "default_snapshots_dir")]
+    pub snapshots_dir: String,
+    /// Globular cluster for the `GlobularCluster` scenario
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+    /// Impact that made the galaxy of the `RingGalaxy` scenario
+    #[serde(default)]
+    pub ring: RingConfig,
+    /// Starting cloud of the `ColdCollapse` scenario
+    #[serde(default)]
+    pub cold_collapse: ColdCollapseConfig,
+    /// Star and disk of the `ProtoplanetaryDisk` scenario
+    #[serde(default)]
+    pub protoplanetary: ProtoplanetaryConfig,
+    /// Multiple systems of the `BinaryCluster` scenario
+    #[serde(default)]
+    pub binaries: BinaryConfig,
+    /// Time-lapse snapshots of the run served at `/api/gallery`
+    #[serde(default)]
+    pub gallery: GalleryConfig,
+    /// Recent keyframes of the run that `Seek` jumps back to
+    #[serde(default)]
+    pub rewind: RewindConfig,
+    /// Fit the particle count to the frame-time budget of this machine
+    #[serde(default)]
+    pub auto_scale: AutoScaleConfig,
+    /// Per-group kinetic and potential energy streamed to clients
+    #[serde(default)]
+    pub energy: EnergyConfig,
+    /// Every Nth frame of the run written to disk for later analysis
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    /// Shapes of the two colliding galaxies, e.g. `["Spiral", "Elliptical"]`
+    #[serde(default)]
+    pub morphologies: [Morphology; 2],
+    /// Random velocities of the two default galaxies, as a fraction of the
+    /// local circular speed; 0 gives perfectly cold orbits
+    #[serde(default)]
+    pub dispersion: f32,
+    /// Masses of the two default galaxies' particles: `"Ramp"` or an initial
+    /// mass function, `"Salpeter"` or `"Kroupa"`
+    #[serde(default)]
+    pub mass_function: MassFunction,
+    /// Custom galaxy collision as `[[simulation.galaxies]]` tables; replaces
+    /// the default pair and `morphologies` when present
+    #[serde(default)]
+    pub galaxies: Vec<GalaxySpec>,
+    /// Seed for the random initial conditions
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl SimulationConfig {
+    /// Copy the settings a running simulation can change from `reloaded`:
+    /// its pace and the scenarios' settings, used from the next reset
+    pub fn reload_from(&mut self, reloaded: &SimulationConfig) {
+        self.update_rate_ms = reloaded.update_rate_ms;
+        self.stats_frequency = reloaded.stats_frequency;
+        self.central_attractor_mass = reloaded.central_attractor_mass;
+        self.cluster = reloaded.cluster.clone();
+        self.ring = reloaded.ring.clone();
+        self.cold_collapse = reloaded.cold_collapse.clone();
+        self.protoplanetary = reloaded.protoplanetary.clone();
+        self.binaries = reloaded.binaries.clone();
+        self.dispersion = reloaded.dispersion;
+        self.mass_function = reloaded.mass_function;
+    }
+}
+
+fn default_gravity_strength() -> f32 {
+    1.0
+}
+
+fn default_theta() -> f32 {
+    DEFAULT_THETA
+}
+
+fn default_steps_per_update() -> u32 {
+    1
+}
+
+fn default_sort_interval() -> u64 {
+    64
+}
+
+fn default_interpolate_frames() -> bool {
+    true
+}
+
+fn default_pause_when_idle() -> bool {
+    true
+}
+
+fn default_max_substeps() -> u32 {
+    16
+}
+
+fn default_scenarios_dir() -> String {
+    "scenarios".to_string()
+}
+
+fn default_snapshots_dir() -> String {
+    "snapshots".to_string()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct WebSocketConfig {
     pub heartbeat_interval_sec: u64,
     pub client_timeout_sec: u64,
+    /// How long a disconnected client's session can still be resumed
+    #[serde(default = "default_session_ttl_sec")]
+    pub session_ttl_sec: u64,
+    /// Stream only the particles that moved farther than this since the
+    /// client last saw them; unset always sends full frames. Suits scenes
+    /// that are mostly pinned or static. JSON streams only.
+    #[serde(default)]
+    pub dirty_threshold: Option<f32>,
+    /// Fraction of moved particles above which a full frame is sent instead
+    #[serde(default = "default_dirty_max_fraction")]
+    pub dirty_max_fraction: f32,
+    /// Send a full keyframe every this many frames and, in between, each
+    /// particle's change of position and velocity as 16-bit steps; unset
+    /// always sends full frames. Takes precedence over `dirty_threshold`.
+    /// JSON and bincode streams only.
+    #[serde(default)]
+    pub keyframe_interval: Option<u32>,
+    /// Least time between one client's config updates, resets and scenario
+    /// switches, so a slider dragged at input rate can't keep a shared
+    /// simulation restarting. Quicker updates are merged into the latest
+    /// one; quicker resets are refused. 0 disables the limit.
+    #[serde(default = "default_control_interval_ms")]
+    pub control_interval_ms: u64,
+}
+
+fn default_session_ttl_sec() -> u64 {
+    600
+}
+
+fn default_control_interval_ms() -> u64 {
+    100
+}
+
+fn default_dirty_max_fraction() -> f32 {
+    0.25
+}
+
+/// Authentication providers; when none are configured every client may
+/// control the simulation
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AuthConfig {
+    /// Identity header set by an authenticating reverse proxy
+    #[serde(default)]
+    pub proxy_header: Option<String>,
+    /// Shared secret for HS256-signed JWTs
+    #[serde(default)]
+    pub jwt_secret: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+}
+
+/// King model globular cluster orbiting an elliptical host galaxy
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClusterConfig {
+    /// log10(tidal radius / core radius); about 0.5 for loose clusters and
+    /// up to 2.5 for core-collapsed ones
+    #[serde(default = "default_cluster_concentration")]
+    pub concentration: f32,
+    /// Outer cutoff radius; defaults to the Jacobi radius set by the host's tide
+    #[serde(default)]
+    pub tidal_radius: Option<f32>,
+    /// Distance of the circular orbit from the host's centre
+    #[serde(default = "default_cluster_orbit_radius")]
+    pub orbit_radius: f32,
+    /// Cluster mass as a fraction of the host galaxy's mass
+    #[serde(default = "default_cluster_mass_fraction")]
+    pub mass_fraction: f32,
+}
+
+fn default_cluster_concentration() -> f32 {
+    1.5
+}
+
+fn default_cluster_orbit_radius() -> f32 {
+    6.0
+}
+
+fn default_cluster_mass_fraction() -> f32 {
+    0.02
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            concentration: default_cluster_concentration(),
+            tidal_radius: None,
+            orbit_radius: default_cluster_orbit_radius(),
+            mass_fraction: default_cluster_mass_fraction(),
+        }
+    }
+}
+
+/// The collision behind the `RingGalaxy` scenario
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RingConfig {
+    /// Companion mass as a fraction of the target galaxy's mass
+    #[serde(default = "default_ring_companion_mass_fraction")]
+    pub companion_mass_fraction: f32,
+    /// Virial radius of the companion, which also softens the impact near
+    /// the centre of the disk
+    #[serde(default = "default_ring_companion_radius")]
+    pub companion_radius: f32,
+    /// Speed at which the companion passed through the disk
+    #[serde(default = "default_ring_impact_speed")]
+    pub impact_speed: f32,
+    /// Time since the impact; the ring forms and then moves outward
+    #[serde(default = "default_ring_age")]
+    pub age: f32,
+}
+
+fn default_ring_companion_mass_fraction() -> f32 {
+    0.25
+}
+
+fn default_ring_companion_radius() -> f32 {
+    0.5
+}
+
+fn default_ring_impact_speed() -> f32 {
+    4.0
+}
+
+fn default_ring_age() -> f32 {
+    1.5
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        RingConfig {
+            companion_mass_fraction: default_ring_companion_mass_fraction(),
+            companion_radius: default_ring_companion_radius(),
+            impact_speed: default_ring_impact_speed(),
+            age: default_ring_age(),
+        }
+    }
+}
+
+/// Shape of the cold collapse cloud
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollapseShape {
+    #[default]
+    Sphere,
+    Cube,
+}
+
+/// Uniform cloud of unit mass for the `ColdCollapse` scenario
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColdCollapseConfig {
+    #[serde(default)]
+    pub shape: CollapseShape,
+    /// Radius of the sphere, or half the side of the cube
+    #[serde(default = "default_collapse_extent")]
+    pub extent: f32,
+    /// Virial ratio 2T/|W| of the random starting velocities: 0 starts
+    /// perfectly cold, 1 in virial equilibrium
+    #[serde(default)]
+    pub temperature: f32,
+}
+
+fn default_collapse_extent() -> f32 {
+    2.0
+}
+
+impl Default for ColdCollapseConfig {
+    fn default() -> Self {
+        ColdCollapseConfig {
+            shape: CollapseShape::default(),
+            extent: default_collapse_extent(),
+            temperature: 0.0,
+        }
+    }
+}
+
+/// Thin Keplerian disk of low-mass particles around a star, for the
+/// `ProtoplanetaryDisk` scenario and the `disk` scenario file generator
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProtoplanetaryConfig {
+    #[serde(default = "default_star_mass")]
+    pub star_mass: f32,
+    /// Total mass of the disk particles; keep it small next to the star
+    #[serde(default = "default_disk_mass")]
+    pub disk_mass: f32,
+    /// Range of semi-major axes, with equal numbers of orbits per unit radius
+    #[serde(default = "default_disk_inner_radius")]
+    pub inner_radius: f32,
+    #[serde(default = "default_disk_outer_radius")]
+    pub outer_radius: f32,
+    /// Spread of orbital eccentricities (Rayleigh scale); inclinations
+    /// spread half as much, in radians
+    #[serde(default = "default_disk_eccentricity")]
+    pub eccentricity: f32,
+}
+
+fn default_star_mass() -> f32 {
+    1.0
+}
+
+fn default_disk_mass() -> f32 {
+    0.001
+}
+
+fn default_disk_inner_radius() -> f32 {
+    1.0
+}
+
+fn default_disk_outer_radius() -> f32 {
+    3.0
+}
+
+fn default_disk_eccentricity() -> f32 {
+    0.02
+}
+
+impl Default for ProtoplanetaryConfig {
+    fn default() -> Self {
+        ProtoplanetaryConfig {
+            star_mass: default_star_mass(),
+            disk_mass: default_disk_mass(),
+            inner_radius: default_disk_inner_radius(),
+            outer_radius: default_disk_outer_radius(),
+            eccentricity: default_disk_eccentricity(),
+        }
+    }
+}
+
+impl ProtoplanetaryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |what: &str, value: f32| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be positive, got {}", what, value))
+            }
+        };
+        positive("star_mass", self.star_mass)?;
+        positive("inner_radius", self.inner_radius)?;
+        if self.disk_mass.is_nan() || self.disk_mass < 0.0 {
+            return Err(format!(
+                "disk_mass must not be negative, got {}",
+                self.disk_mass
+            ));
+        }
+        if self.outer_radius.is_nan() || self.outer_radius < self.inner_radius {
+            return Err(format!(
+                "outer_radius {} is inside inner_radius {}",
+                self.outer_radius, self.inner_radius
+            ));
+        }
+        if !(0.0..1.0).contains(&self.eccentricity) {
+            return Err(format!(
+                "eccentricity must be in [0, 1), got {}",
+                self.eccentricity
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Star cluster seeded with binaries and hierarchical triples, for the
+/// `BinaryCluster` scenario and the `binaries` scenario file generator
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BinaryConfig {
+    /// Total mass and virial radius of the Plummer cluster the systems
+    /// orbit in
+    #[serde(default = "default_star_mass")]
+    pub cluster_mass: f32,
+    #[serde(default = "default_binary_cluster_radius")]
+    pub cluster_radius: f32,
+    /// Shares of the systems that are binaries and hierarchical triples;
+    /// the rest are single stars
+    #[serde(default = "default_binary_fraction")]
+    pub binary_fraction: f32,
+    #[serde(default = "default_triple_fraction")]
+    pub triple_fraction: f32,
+    /// Range of inner semi-major axes, drawn evenly in log a (Öpik's law);
+    /// set both ends equal for a single value
+    #[serde(default = "default_min_semi_major_axis")]
+    pub min_semi_major_axis: f32,
+    #[serde(default = "default_max_semi_major_axis")]
+    pub max_semi_major_axis: f32,
+    /// Range of inner eccentricities, drawn uniformly
+    #[serde(default)]
+    pub min_eccentricity: f32,
+    #[serde(default = "default_max_eccentricity")]
+    pub max_eccentricity: f32,
+    /// Outer orbit of triples: its semi-major axis as a multiple of the
+    /// inner one, and its eccentricity
+    #[serde(default = "default_outer_ratio")]
+    pub outer_ratio: f32,
+    #[serde(default = "default_outer_eccentricity")]
+    pub outer_eccentricity: f32,
+}
+
+fn default_binary_cluster_radius() -> f32 {
+    1.0
+}
+
+fn default_binary_fraction() -> f32 {
+    0.3
+}
+
+fn default_triple_fraction() -> f32 {
+    0.05
+}
+
+fn default_min_semi_major_axis() -> f32 {
+    0.002
+}
+
+fn default_max_semi_major_axis() -> f32 {
+    0.05
+}
+
+fn default_max_eccentricity() -> f32 {
+    0.7
+}
+
+fn default_outer_ratio() -> f32 {
+    10.0
+}
+
+fn default_outer_eccentricity() -> f32 {
+    0.2
+}
+
+impl Default for BinaryConfig {
+    fn default() -> Self {
+        BinaryConfig {
+            cluster_mass: default_star_mass(),
+            cluster_radius: default_binary_cluster_radius(),
+            binary_fraction: default_binary_fraction(),
+            triple_fraction: default_triple_fraction(),
+            min_semi_major_axis: default_min_semi_major_axis(),
+            max_semi_major_axis: default_max_semi_major_axis(),
+            min_eccentricity: 0.0,
+            max_eccentricity: default_max_eccentricity(),
+            outer_ratio: default_outer_ratio(),
+            outer_eccentricity: default_outer_eccentricity(),
+        }
+    }
+}
+
+impl BinaryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |what: &str, value: f32| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be positive, got {}", what, value))
+            }
+        };
+        let eccentricity = |what: &str, value: f32| {
+            if (0.0..1.0).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("{} must be in [0, 1), got {}", what, value))
+            }
+        };
+        positive("cluster_mass", self.cluster_mass)?;
+        positive("cluster_radius", self.cluster_radius)?;
+        positive("min_semi_major_axis", self.min_semi_major_axis)?;
+        eccentricity("min_eccentricity", self.min_eccentricity)?;
+        eccentricity("max_eccentricity", self.max_eccentricity)?;
+        eccentricity("outer_eccentricity", self.outer_eccentricity)?;
+        if !(self.binary_fraction >= 0.0
+            && self.triple_fraction >= 0.0
+            && self.binary_fraction + self.triple_fraction <= 1.0)
+        {
+            return Err(format!(
+                "binary_fraction {} and triple_fraction {} must be fractions adding up to at most 1",
+                self.binary_fraction, self.triple_fraction
+            ));
+        }
+        if self.max_semi_major_axis.is_nan() || self.max_semi_major_axis < self.min_semi_major_axis
+        {
+            return Err(format!(
+                "max_semi_major_axis {} is below min_semi_major_axis {}",
+                self.max_semi_major_axis, self.min_semi_major_axis
+            ));
+        }
+        if self.max_eccentricity < self.min_eccentricity {
+            return Err(format!(
+                "max_eccentricity {} is below min_eccentricity {}",
+                self.max_eccentricity, self.min_eccentricity
+            ));
+        }
+        // Closer than this the outer star tears the inner binary apart
+        // within a few orbits (Mardling & Aarseth's limit is ~3 for
+        // circular orbits)
+        if self.outer_ratio.is_nan() || self.outer_ratio < 3.0 {
+            return Err(format!(
+                "outer_ratio must be at least 3 for stable triples, got {}",
+                self.outer_ratio
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Downsampled snapshots of the live run, kept in memory as a visual history
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GalleryConfig {
+    #[serde(default = "default_gallery_enabled")]
+    pub enabled: bool,
+    /// Simulation time between snapshots
+    #[serde(default = "default_gallery_interval")]
+    pub interval: f32,
+    /// Larger runs are thinned to about this many particles per snapshot
+    #[serde(default = "default_gallery_max_particles")]
+    pub max_particles: usize,
+    /// Once full, every other snapshot is dropped and the interval doubles,
+    /// so the gallery always spans the whole run
+    #[serde(default = "default_gallery_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_gallery_enabled() -> bool {
+    true
+}
+
+fn default_gallery_interval() -> f32 {
+    10.0
+}
+
+fn default_gallery_max_particles() -> usize {
+    2000
+}
+
+fn default_gallery_max_entries() -> usize {
+    64
+}
+
+/// Full copies of the recent run, kept in memory to jump back to
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RewindConfig {
+    #[serde(default = "default_rewind_enabled")]
+    pub enabled: bool,
+    /// Frames between keyframes
+    #[serde(default = "default_rewind_interval")]
+    pub interval: u64,
+    /// Keyframes kept; the oldest is dropped for each new one
+    #[serde(default = "default_rewind_keyframes")]
+    pub keyframes: usize,
+    /// Memory the keyframes may take up together; large runs keep fewer
+    #[serde(default = "default_rewind_max_mb")]
+    pub max_mb: usize,
+}
+
+fn default_rewind_enabled() -> bool {
+    true
+}
+
+fn default_rewind_interval() -> u64 {
+    30
+}
+
+fn default_rewind_keyframes() -> usize {
+    60
+}
+
+fn default_rewind_max_mb() -> usize {
+    256
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig {
+            enabled: default_rewind_enabled(),
+            interval: default_rewind_interval(),
+            keyframes: default_rewind_keyframes(),
+            max_mb: default_rewind_max_mb(),
+        }
+    }
+}
+
+impl Default for GalleryConfig {
+    fn default() -> Self {
+        GalleryConfig {
+            enabled: default_gallery_enabled(),
+            interval: default_gallery_interval(),
+            max_particles: default_gallery_max_particles(),
+            max_entries: default_gallery_max_entries(),
+        }
+    }
+}
+
+/// Particle count that follows the physics time per frame: sustained slow
+/// frames trim particles from the running simulation, sustained fast ones
+/// raise the count used at the next reset, up to the count last asked for
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AutoScaleConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Physics time per frame to stay under
+    #[serde(default = "default_auto_scale_target_ms")]
+    pub target_ms: f32,
+    /// Consecutive frames over the target, or under it by `headroom`,
+    /// before the count changes
+    #[serde(default = "default_auto_scale_frames")]
+    pub frames: u32,
+    /// Fraction of the target frames must stay under to add particles back
+    #[serde(default = "default_auto_scale_headroom")]
+    pub headroom: f32,
+    /// Never trim below this many particles
+    #[serde(default = "default_auto_scale_min_particles")]
+    pub min_particles: usize,
+}
+
+fn default_auto_scale_target_ms() -> f32 {
+    25.0
+}
+
+fn default_auto_scale_frames() -> u32 {
+    30
+}
+
+fn default_auto_scale_headroom() -> f32 {
+    0.5
+}
+
+fn default_auto_scale_min_particles() -> usize {
+    500
+}
+
+impl Default for AutoScaleConfig {
+    fn default() -> Self {
+        AutoScaleConfig {
+            enabled: false,
+            target_ms: default_auto_scale_target_ms(),
+            frames: default_auto_scale_frames(),
+            headroom: default_auto_scale_headroom(),
+            min_particles: default_auto_scale_min_particles(),
+        }
+    }
+}
+
+impl AutoScaleConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.target_ms.is_finite() && self.target_ms > 0.0) {
+            return Err(format!(
+                "Auto-scale target must be positive, got {} ms",
+                self.target_ms
+            ));
+        }
+        if !(self.headroom > 0.0 && self.headroom < 1.0) {
+            return Err(format!(
+                "Auto-scale headroom must be between 0 and 1, got {}",
+                self.headroom
+            ));
+        }
+        if self.frames == 0 {
+            return Err("Auto-scale needs at least one frame".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Energy partition between particle groups, measured off the physics loop
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EnergyConfig {
+    #[serde(default = "default_energy_enabled")]
+    pub enabled: bool,
+    /// Frames between samples; each one costs O(N²)
+    #[serde(default = "default_energy_interval")]
+    pub interval: u64,
+    /// Larger runs are not measured
+    #[serde(default = "default_energy_max_particles")]
+    pub max_particles: usize,
+    /// Samples kept for clients that connect later
+    #[serde(default = "default_energy_history")]
+    pub history: usize,
+}
+
+fn default_energy_enabled() -> bool {
+    true
+}
+
+fn default_energy_interval() -> u64 {
+    30
+}
+
+fn default_energy_max_particles() -> usize {
+    20_000
+}
+
+fn default_energy_history() -> usize {
+    1000
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        EnergyConfig {
+            enabled: default_energy_enabled(),
+            interval: default_energy_interval(),
+            max_particles: default_energy_max_particles(),
+            history: default_energy_history(),
+        }
+    }
+}
+
+/// Recording of the live run to a file per recording, started here or by a
+/// controller's `StartRecording`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordingConfig {
+    /// Start recording as soon as the simulation starts
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where recordings are written
+    #[serde(default = "default_recording_dir")]
+    pub dir: String,
+    /// Published frames between recorded ones
+    #[serde(default = "default_recording_interval")]
+    pub interval: u64,
+}
+
+fn default_recording_dir() -> String {
+    "recordings".to_string()
+}
+
+fn default_recording_interval() -> u64 {
+    10
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            enabled: false,
+            dir: default_recording_dir(),
+            interval: default_recording_interval(),
+        }
+    }
+}
+
+/// Binary position frames over UDP for game engines (see docs/udp.md)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UdpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Destination `host:port`; a broadcast address such as
+    /// `255.255.255.255:7777` reaches every listener on the LAN
+    #[serde(default = "default_udp_target")]
+    pub target: String,
+    /// Maximum frames per second sent
+    #[serde(default = "default_udp_rate_hz")]
+    pub rate_hz: u32,
+    /// Packet size limit; keep under the path MTU to avoid IP fragmentation
+    #[serde(default = "default_udp_max_packet_bytes")]
+    pub max_packet_bytes: usize,
+}
+
+fn default_udp_target() -> String {
+    "127.0.0.1:7777".to_string()
+}
+
+fn default_udp_rate_hz() -> u32 {
+    30
+}
+
+fn default_udp_max_packet_bytes() -> usize {
+    1400
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            enabled: false,
+            target: default_udp_target(),
+            rate_hz: default_udp_rate_hz(),
+            max_packet_bytes: default_udp_max_packet_bytes(),
+        }
+    }
+}
+
+/// Payload shape for webhook notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// `{"event", "message", "frame_number", "sim_time"}` for custom receivers
+    #[default]
+    Json,
+    /// Slack incoming webhook, `{"text": ...}`
+    Slack,
+    /// Discord webhook, `{"content": ...}`
+    Discord,
+}
+
+/// HTTP POST notifications about notable simulation events, so long
+/// unattended runs can raise an alert. Each detector is off until its
+/// threshold is set; watchdog stalls are always reported.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhookConfig {
+    /// Endpoints to POST to; none disables webhooks
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Report once when simulation time reaches this value
+    #[serde(default)]
+    pub run_duration: Option<f32>,
+    /// Report once when total energy drifts from its first measurement
+    /// after a reset by more than this fraction, e.g. 0.01 for 1%; skipped in
+    /// a periodic box
+    #[serde(default)]
+    pub energy_drift_threshold: Option<f64>,
+    /// Report when two particles come within this distance of each other
+    #[serde(default)]
+    pub merger_distance: Option<f32>,
+    /// Seconds between checks for finished runs and mergers
+    #[serde(default = "default_webhook_check_interval_sec")]
+    pub check_interval_sec: u64,
+    /// Seconds between energy measurements, which cost O(N²)
+    #[serde(default = "default_webhook_energy_interval_sec")]
+    pub energy_interval_sec: u64,
+}
+
+fn default_webhook_check_interval_sec() -> u64 {
+    5
+}
+
+fn default_webhook_energy_interval_sec() -> u64 {
+    60
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            urls: Vec::new(),
+            format: WebhookFormat::default(),
+            run_duration: None,
+            energy_drift_threshold: None,
+            merger_distance: None,
+            check_interval_sec: default_webhook_check_interval_sec(),
+            energy_interval_sec: default_webhook_energy_interval_sec(),
+        }
+    }
+}
+
+/// Headless batch simulations submitted through `POST /api/jobs`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobsConfig {
+    #[serde(default = "default_jobs_enabled")]
+    pub enabled: bool,
+    /// Where each job's results are written, in a `job-<id>` subdirectory
+    #[serde(default = "default_jobs_dir")]
+    pub dir: String,
+    /// Jobs that may be queued or running at once
+    #[serde(default = "default_jobs_max_pending")]
+    pub max_pending: usize,
+    /// Longest job accepted, in physics steps
+    #[serde(default = "default_jobs_max_steps")]
+    pub max_steps: u64,
+    /// Threads reserved for jobs, taken from the live simulation's pool; by
+    /// default a quarter of the CPUs
+    #[serde(default)]
+    pub threads: Option<usize>,
+    /// Jobs run at the same time, sharing the job threads
+    #[serde(default = "default_jobs_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_jobs_enabled() -> bool {
+    true
+}
+
+fn default_jobs_dir() -> String {
+    "jobs".to_string()
+}
+
+fn default_jobs_max_pending() -> usize {
+    8
+}
+
+fn default_jobs_concurrency() -> usize {
+    1
+}
+
+fn default_jobs_max_steps() -> u64 {
+    1_000_000
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            enabled: default_jobs_enabled(),
+            dir: default_jobs_dir(),
+            max_pending: default_jobs_max_pending(),
+            max_steps: default_jobs_max_steps(),
+            threads: None,
+            concurrency: default_jobs_concurrency(),
+        }
+    }
+}
+
+/// Simulations of their own for clients connecting to `/ws/{room}`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoomsConfig {
+    #[serde(default = "default_rooms_enabled")]
+    pub enabled: bool,
+    /// Rooms that may exist at once besides the main simulation; each one
+    /// steps its own particles
+    #[serde(default = "default_rooms_max_rooms")]
+    pub max_rooms: usize,
+    /// Seconds a room is kept after its last client leaves, so a reload
+    /// finds the experiment where it was
+    #[serde(default = "default_rooms_idle_timeout_sec")]
+    pub idle_timeout_sec: u64,
+}
+
+fn default_rooms_enabled() -> bool {
+    true
+}
+
+fn default_rooms_max_rooms() -> usize {
+    8
+}
+
+fn default_rooms_idle_timeout_sec() -> u64 {
+    300
+}
+
+impl Default for RoomsConfig {
+    fn default() -> Self {
+        RoomsConfig {
+            enabled: default_rooms_enabled(),
+            max_rooms: default_rooms_max_rooms(),
+            idle_timeout_sec: default_rooms_idle_timeout_sec(),
+        }
+    }
+}
+
+/// Part a server process plays in a simulation spread over several machines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// A standalone server
+    #[default]
+    Off,
+    /// Serves the clients and splits the main simulation's force
+    /// computation between itself and the workers
+    Frontend,
+    /// Only computes forces for a frontend; no HTTP or websocket server
+    Worker,
+}
+
+/// Domain decomposition of the main simulation across server processes:
+/// each node computes the forces on the particles of one spatial region
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DistributedConfig {
+    #[serde(default)]
+    pub role: NodeRole,
+    /// Address a worker accepts its frontend on
+    #[serde(default = "default_distributed_listen")]
+    pub listen: String,
+    /// Shared secret every request from the frontend carries; workers turn
+    /// away requests without it. Required by both roles.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Addresses of a frontend's workers, e.g. `"10.0.0.2:4100"`
+    #[serde(default)]
+    pub workers: Vec<String>,
+    /// Particles of other regions this close to a node's region are sent to
+    /// it exactly, in simulation length units
+    #[serde(default = "default_distributed_halo_width")]
+    pub halo_width: f32,
+    /// Particles farther away are merged into one mass per cell of this size
+    #[serde(default = "default_distributed_cell_size")]
+    pub cell_size: f32,
+    /// Seconds to wait for a worker's forces before computing them locally
+    #[serde(default = "default_distributed_timeout_sec")]
+    pub timeout_sec: u64,
+}
+
+fn default_distributed_listen() -> String {
+    "127.0.0.1:4100".to_string()
+}
+
+fn default_distributed_halo_width() -> f32 {
+    2.0
+}
+
+fn default_distributed_cell_size() -> f32 {
+    0.5
+}
+
+fn default_distributed_timeout_sec() -> u64 {
+    5
+}
+
+impl Default for DistributedConfig {
+    fn default() -> Self {
+        DistributedConfig {
+            role: NodeRole::default(),
+            listen: default_distributed_listen(),
+            key: None,
+            workers: Vec::new(),
+            halo_width: default_distributed_halo_width(),
+            cell_size: default_distributed_cell_size(),
+            timeout_sec: default_distributed_timeout_sec(),
+        }
+    }
+}
+
+/// What one simulation unit of length and mass means physically; the time
+/// unit is derived from these and the simulation's gravity strength
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnitsConfig {
+    #[serde(default = "default_length_kpc")]
+    pub length_kpc: f64,
+    #[serde(default = "default_mass_msun")]
+    pub mass_msun: f64,
+    /// Decimal places when displaying simulation time
+    #[serde(default = "default_time_precision")]
+    pub time_precision: usize,
+}
+
+fn default_length_kpc() -> f64 {
+    1.0
+}
+
+fn default_mass_msun() -> f64 {
+    1.0e10
+}
+
+fn default_time_precision() -> usize {
+    1
+}
+
+impl Default for UnitsConfig {
+    fn default() -> Self {
+        UnitsConfig {
+            length_kpc: default_length_kpc(),
+            mass_msun: default_mass_msun(),
+            time_precision: default_time_precision(),
+        }
+    }
+}
+
+/// Public demo preset: one switch that makes the server safe to expose on the
+/// open internet. Visitors only spectate unless an auth provider grants them
+/// control, the particle count is capped, the simulation periodically resets
+/// itself and HTTP requests are rate limited per client IP.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DemoConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_demo_max_particles")]
+    pub max_particles: usize,
+    #[serde(default = "default_demo_auto_reset_sec")]
+    pub auto_reset_sec: u64,
+    #[serde(default = "default_demo_requests_per_minute")]
+    pub requests_per_minute: u32,
+}
+
+fn default_demo_max_particles() -> usize {
+    5000
+}
+
+fn default_demo_auto_reset_sec() -> u64 {
+    300
+}
+
+fn default_demo_requests_per_minute() -> u32 {
+    120
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        DemoConfig {
+            enabled: false,
+            max_particles: default_demo_max_particles(),
+            auto_reset_sec: default_demo_auto_reset_sec(),
+            requests_per_minute: default_demo_requests_per_minute(),
+        }
+    }
+}
+
+/// Lower the particle limit of `simulation` to at most `cap`
+fn cap_particles(simulation: &mut SimulationConfig, cap: usize) {
+    let cap = simulation.max_particles.map_or(cap, |max| max.min(cap));
+    simulation.max_particles = Some(cap);
+    simulation.default_particles = simulation.default_particles.min(cap);
+}
+
+/// Overlay `overrides` on `base`, merging nested tables key by key
+fn merge_tables(base: &mut toml::Table, overrides: &toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(value)) => {
+                merge_tables(base, value)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
 }
 
 impl Default for Config {
@@ -37,66 +1264,360 @@ impl Default for Config {
                 port: 4000,
                 host: "0.0.0.0".to_string(),
                 debug: false,
+                public_url: None,
+                trust_proxy_headers: false,
+                trusted_proxy_hops: default_trusted_proxy_hops(),
+                allowed_origins: Vec::new(),
+                tls_cert: None,
+                tls_key: None,
+                watch_config: default_watch_config(),
             },
             simulation: SimulationConfig {
                 default_particles: 1000,
                 update_rate_ms: 33, // ~30 FPS
+                steps_per_update: default_steps_per_update(),
                 stats_frequency: 30,
+                gravity_strength: default_gravity_strength(),
+                periodic_box: None,
+                max_particles: None,
+                solver: ForceSolver::default(),
+                theta: DEFAULT_THETA,
+                theta_tuning: ThetaTuning::default(),
+                integrator: Integrator::default(),
+                speed_of_light: None,
+                sort_interval: default_sort_interval(),
+                interpolate_frames: default_interpolate_frames(),
+                pause_when_idle: default_pause_when_idle(),
+                compensated_summation: false,
+                encounter_distance: None,
+                max_substeps: default_max_substeps(),
+                central_attractor_mass: None,
+                scenario: Scenario::default(),
+                scenarios_dir: default_scenarios_dir(),
+                snapshots_dir: default_snapshots_dir(),
+                cluster: ClusterConfig::default(),
+                ring: RingConfig::default(),
+                cold_collapse: ColdCollapseConfig::default(),
+                protoplanetary: ProtoplanetaryConfig::default(),
+                binaries: BinaryConfig::default(),
+                gallery: GalleryConfig::default(),
+                rewind: RewindConfig::default(),
+                auto_scale: AutoScaleConfig::default(),
+                energy: EnergyConfig::default(),
+                recording: RecordingConfig::default(),
+                morphologies: Default::default(),
+                dispersion: 0.0,
+                mass_function: MassFunction::default(),
+                galaxies: Vec::new(),
+                seed: 0,
             },
             websocket: WebSocketConfig {
                 heartbeat_interval_sec: 5,
                 client_timeout_sec: 10,
+                session_ttl_sec: default_session_ttl_sec(),
+                dirty_threshold: None,
+                dirty_max_fraction: default_dirty_max_fraction(),
+                keyframe_interval: None,
+                control_interval_ms: default_control_interval_ms(),
             },
+            auth: AuthConfig::default(),
+            demo: DemoConfig::default(),
+            units: UnitsConfig::default(),
+            udp: UdpConfig::default(),
+            webhooks: WebhookConfig::default(),
+            jobs: JobsConfig::default(),
+            rooms: RoomsConfig::default(),
+            distributed: DistributedConfig::default(),
+            simulations: BTreeMap::new(),
         }
     }
 }
 
 impl Config {
+    /// Tighten simulation limits when the public demo preset is enabled
+    pub fn apply_demo_preset(&mut self) {
+        if !self.demo.enabled {
+            return;
+        }
+
+        cap_particles(&mut self.simulation, self.demo.max_particles);
+        // Visitors only watch, so rooms of their own would only cost CPU
+        self.rooms.enabled = false;
+    }
+
+    /// Settings of the simulation `[simulations.<name>]` describes: the
+    /// `[simulation]` table with its overrides, capped like the main
+    /// simulation in demo mode
+    pub fn named_simulation(&self, name: &str) -> Result<SimulationConfig, String> {
+        let overrides = self
+            .simulations
+            .get(name)
+            .ok_or_else(|| format!("No simulation named '{}'", name))?;
+        let mut table = toml::Table::try_from(&self.simulation)
+            .map_err(|e| format!("Failed to serialize [simulation]: {}", e))?;
+        merge_tables(&mut table, overrides);
+        let mut simulation: SimulationConfig = table
+            .try_into()
+            .map_err(|e| format!("Invalid [simulations.{}]: {}", name, e))?;
+        if self.demo.enabled {
+            cap_particles(&mut simulation, self.demo.max_particles);
+        }
+        Ok(simulation)
+    }
+
+    /// Physical units of the simulation, with G at its configured initial value
+    pub fn unit_system(&self) -> UnitSystem {
+        UnitSystem::from_scales(
+            self.units.length_kpc,
+            self.units.mass_msun,
+            self.simulation.gravity_strength as f64,
+        )
+    }
+
+    /// Copy the settings a running server can change from `reloaded`
+    pub fn reload_from(&mut self, reloaded: &Config) {
+        self.simulation.reload_from(&reloaded.simulation);
+        self.websocket.heartbeat_interval_sec = reloaded.websocket.heartbeat_interval_sec;
+        self.websocket.client_timeout_sec = reloaded.websocket.client_timeout_sec;
+        self.websocket.control_interval_ms = reloaded.websocket.control_interval_ms;
+        // Only read through `named_simulation`, which rooms reload from too
+        self.simulations = reloaded.simulations.clone();
+    }
+
+    /// Read and parse the settings in `path`, with environment overrides
+    pub fn read(path: &str) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let table: toml::Table =
+            toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+        Self::with_env(table).map_err(|e| format!("Invalid settings in {}: {}", path, e))
+    }
+
+    /// The settings in `table` with every `N_BODY_<TABLE>__<KEY>`
+    /// environment variable laid over them, so the environment takes
+    /// precedence over config.toml, which takes precedence over defaults
+    fn with_env(table: toml::Table) -> Result<Self, String> {
+        Self::with_vars(table, std::env::vars())
+    }
+
+    /// The settings in `table` with the `N_BODY_*` variables among `vars`
+    /// laid over them. Fails only when `table` itself is invalid; a variable
+    /// whose value doesn't fit its setting is logged and skipped, so a typo
+    /// in the environment never costs the rest of the settings.
+    fn with_vars(
+        mut table: toml::Table,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut config: Config = table
+            .clone()
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+        let defaults = toml::Table::try_from(Config::default())
+            .map_err(|e| format!("Failed to serialize defaults: {}", e))?;
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            // Older name of N_BODY_SERVER__DEBUG
+            let key = if key == "DEBUG" { "SERVER__DEBUG" } else { key };
+            let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+            if path.len() < 2 || path.iter().any(String::is_empty) {
+                log::warn!(
+                    "Ignoring {}: name settings {}<TABLE>__<KEY>, e.g. {}SERVER__PORT",
+                    name,
+                    ENV_PREFIX,
+                    ENV_PREFIX
+                );
+                continue;
+            }
+            // The value the setting would otherwise have tells its type
+            let current = lookup(&table, &path).or_else(|| lookup(&defaults, &path));
+            let value = env_value(&raw, current);
+            let mut overridden = table.clone();
+            let applied = insert(&mut overridden, &path, value).and_then(|()| {
+                overridden
+                    .clone()
+                    .try_into()
+                    .map_err(|e: toml::de::Error| e.to_string())
+            });
+            match applied {
+                Ok(updated) => {
+                    table = overridden;
+                    config = updated;
+                    log::info!("{} overrides {}", name, path.join("."));
+                }
+                Err(e) => log::warn!("Ignoring {}: {}", name, e.trim_end()),
+            }
+        }
+        Ok(config)
+    }
+
     pub fn load() -> Self {
-        let config_path = "config.toml";
+        let config_path = CONFIG_PATH;
 
         if Path::new(config_path).exists() {
-            match fs::read_to_string(config_path) {
-                Ok(content) => match toml::from_str::<Config>(&content) {
-                    Ok(mut config) => {
-                        log::info!("Loaded configuration from {}", config_path);
-
-                        // Check for debug environment variable override
-                        if std::env::var("N_BODY_DEBUG").is_ok() {
-                            config.server.debug = true;
-                            log::info!("Debug mode enabled via N_BODY_DEBUG environment variable");
-                        }
-
-                        config
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to parse {}: {}. Using defaults.", config_path, e);
-                        Self::default()
-                    }
-                },
+            match Self::read(config_path) {
+                Ok(config) => {
+                    log::info!("Loaded configuration from {}", config_path);
+                    config
+                }
                 Err(e) => {
-                    log::warn!("Failed to read {}: {}. Using defaults.", config_path, e);
-                    Self::default()
+                    // The environment still applies, since credentials such
+                    // as API keys and the JWT secret may be set only there
+                    log::warn!("{}. Using defaults.", e);
+                    Self::defaults_with_env()
                 }
             }
         } else {
             log::info!("No config.toml found, using default configuration");
-            let mut config = Self::default();
 
-            // Check for debug environment variable override
-            if std::env::var("N_BODY_DEBUG").is_ok() {
-                config.server.debug = true;
-                log::info!("Debug mode enabled via N_BODY_DEBUG environment variable");
-            }
-
-            // Write default config file
-            if let Ok(toml_str) = toml::to_string_pretty(&config) {
+            // Write default config file, without the environment's settings,
+            // which may well be secrets
+            if let Ok(toml_str) = toml::to_string_pretty(&Self::default()) {
                 if let Err(e) = fs::write(config_path, toml_str) {
                     log::warn!("Failed to write default config: {}", e);
                 }
             }
 
-            config
+            Self::defaults_with_env()
+        }
+    }
+
+    /// The default settings with the environment's laid over them
+    fn defaults_with_env() -> Self {
+        toml::Table::try_from(Self::default())
+            .map_err(|e| e.to_string())
+            .and_then(Self::with_env)
+            .unwrap_or_else(|e| {
+                log::warn!("{}. Using defaults.", e);
+                Self::default()
+            })
+    }
+}
+
+/// Value at `path` in `table`, if any
+fn lookup<'a>(table: &'a toml::Table, path: &[String]) -> Option<&'a toml::Value> {
+    let (last, tables) = path.split_last()?;
+    let mut table = table;
+    for key in tables {
+        table = table.get(key)?.as_table()?;
+    }
+    table.get(last)
+}
+
+/// Set `path` in `table` to `value`, creating the tables on the way
+fn insert(table: &mut toml::Table, path: &[String], value: toml::Value) -> Result<(), String> {
+    let Some((last, tables)) = path.split_last() else {
+        return Ok(());
+    };
+    let mut table = table;
+    for key in tables {
+        table = table
+            .entry(key.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("{} is not a table", key))?;
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+/// `raw` as a TOML value of the same type as `current`. Strings are taken
+/// as they are, and lists may be given comma-separated, e.g. `a, b`;
+/// anything else is read as TOML, e.g. `4000`, `0.5`, `true`, `["a", "b"]`
+/// or `{ File = "three_way" }`, falling back to a string.
+fn env_value(raw: &str, current: Option<&toml::Value>) -> toml::Value {
+    match current {
+        Some(toml::Value::String(_)) => return toml::Value::String(raw.to_string()),
+        Some(toml::Value::Array(items)) if !raw.trim_start().starts_with('[') => {
+            // Items are typed like the list's first, or else taken as strings
+            let hint = items
+                .first()
+                .cloned()
+                .unwrap_or_else(|| toml::Value::String(String::new()));
+            return toml::Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| env_value(item, Some(&hint)))
+                    .collect(),
+            );
         }
+        Some(toml::Value::Boolean(_)) => match raw.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => return toml::Value::Boolean(true),
+            "0" | "false" | "no" | "off" => return toml::Value::Boolean(false),
+            _ => {}
+        },
+        _ => {}
+    }
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn file_with_key() -> toml::Table {
+        let mut config = Config::default();
+        config.auth.api_keys = vec!["from-file".to_string()];
+        config.server.port = 5000;
+        toml::Table::try_from(config).unwrap()
+    }
+
+    #[test]
+    fn malformed_override_is_skipped() {
+        let config = Config::with_vars(
+            file_with_key(),
+            vars(&[
+                ("N_BODY_SERVER__PORT", "not-a-port"),
+                ("N_BODY_DEBUG", ""),
+                ("N_BODY_SERVER__HOST", "0.0.0.0"),
+            ]),
+        )
+        .unwrap();
+
+        // The bad values are ignored without losing the file's settings
+        assert_eq!(config.server.port, 5000);
+        assert!(!config.server.debug);
+        assert_eq!(config.auth.api_keys, ["from-file"]);
+        assert_eq!(config.server.host, "0.0.0.0");
+    }
+
+    #[test]
+    fn list_override_accepts_commas_and_toml() {
+        let config = Config::with_vars(
+            toml::Table::try_from(Config::default()).unwrap(),
+            vars(&[("N_BODY_AUTH__API_KEYS", "mykey")]),
+        )
+        .unwrap();
+        assert_eq!(config.auth.api_keys, ["mykey"]);
+
+        let config = Config::with_vars(
+            file_with_key(),
+            vars(&[
+                ("N_BODY_AUTH__API_KEYS", "key-1, key-2"),
+                (
+                    "N_BODY_SERVER__ALLOWED_ORIGINS",
+                    r#"["https://a.example", "https://b.example"]"#,
+                ),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.auth.api_keys, ["key-1", "key-2"]);
+        assert_eq!(
+            config.server.allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
     }
 }
@@ -0,0 +1,137 @@
+// Network level of detail: a client that asks for fewer particles than the
+// simulation has gets a representative subset, while the full set keeps
+// evolving on the server. The subset is stratified by group and by order of
+// magnitude of mass, so small groups and rare heavy bodies stay visible.
+// It is picked by position in the particle order, so it stays the same from
+// frame to frame only until the simulation re-sorts its particles along the
+// Morton curve (every `sort_interval` frames) or their count changes; then
+// a different but equally representative subset is sent.
+use n_body_shared::SimulationState;
+use std::collections::BTreeMap;
+
+/// At most about `max` particles of `state`, or `None` when it already has
+/// no more. Pinned particles are always kept, and every stratum keeps at
+/// least one particle, which may add a few past `max`.
+pub fn sample(state: &SimulationState, max: usize) -> Option<SimulationState> {
+    let total = state.len();
+    if total <= max {
+        return None;
+    }
+
+    let mut strata: BTreeMap<(u16, i32), Vec<usize>> = BTreeMap::new();
+    let mut keep = Vec::new();
+    for index in 0..total {
+        if state.is_fixed(index) {
+            keep.push(index);
+        } else {
+            strata.entry(stratum(state, index)).or_default().push(index);
+        }
+    }
+
+    let budget = max.saturating_sub(keep.len());
+    let free = total - keep.len();
+    for members in strata.values() {
+        let quota = (members.len() * budget / free.max(1)).clamp(1, members.len());
+        // Evenly through the stratum, which follows the particle order
+        let stride = members.len() as f64 / quota as f64;
+        keep.extend((0..quota).map(|slot| members[(slot as f64 * stride) as usize]));
+    }
+    // Draw in the simulation's order, like the full stream
+    keep.sort_unstable();
+
+    Some(SimulationState {
+        positions: pick(&state.positions, 3, &keep),
+        velocities: pick(&state.velocities, 3, &keep),
+        masses: pick(&state.masses, 1, &keep),
+        colors: pick(&state.colors, 4, &keep),
+        fixed: pick(&state.fixed, 1, &keep),
+        groups: pick(&state.groups, 1, &keep),
+        sim_time: state.sim_time,
+        frame_number: state.frame_number,
+        interpolated: state.interpolated,
+    })
+}
+
+/// The `width` values of each kept particle; an empty array stays empty
+fn pick<T: Copy>(values: &[T], width: usize, keep: &[usize]) -> Vec<T> {
+    if values.is_empty() {
+        return Vec::new();
+    }
+    keep.iter()
+        .flat_map(|&index| &values[index * width..(index + 1) * width])
+        .copied()
+        .collect()
+}
+
+/// Group and order of magnitude of mass of particle `index`
+fn stratum(state: &SimulationState, index: usize) -> (u16, i32) {
+    let mass = state.masses[index];
+    let magnitude = if mass > 0.0 {
+        mass.log10().floor() as i32
+    } else {
+        i32::MIN
+    };
+    (state.group(index), magnitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use n_body_shared::Particle;
+    use nalgebra::{Point3, Vector3};
+
+    fn particle(index: usize, mass: f32, group: u16, fixed: bool) -> Particle {
+        Particle {
+            position: Point3::new(index as f32, 0.0, 0.0),
+            velocity: Vector3::zeros(),
+            mass,
+            color: [1.0; 4],
+            fixed,
+            group,
+        }
+    }
+
+    /// 1000 light particles in group 0, 10 in group 1, 2 heavy ones and a
+    /// pinned one
+    fn state() -> SimulationState {
+        let mut particles: Vec<Particle> = (0..1000).map(|i| particle(i, 1.0, 0, false)).collect();
+        particles.extend((1000..1010).map(|i| particle(i, 1.0, 1, false)));
+        particles.extend((1010..1012).map(|i| particle(i, 1000.0, 0, false)));
+        particles.push(particle(1012, 1.0, 0, true));
+        SimulationState::new(&particles, 1.0, 7)
+    }
+
+    #[test]
+    fn small_states_are_sent_whole() {
+        assert!(sample(&state(), 2000).is_none());
+    }
+
+    #[test]
+    fn keeps_every_stratum_and_pinned_particles() {
+        let sampled = sample(&state(), 100).unwrap();
+        sampled.validate().unwrap();
+        assert!(sampled.len() <= 104, "{} particles", sampled.len());
+        assert_eq!(sampled.frame_number, 7);
+
+        let count = |test: &dyn Fn(&Particle) -> bool| sampled.particles().filter(test).count();
+        assert_eq!(count(&|p| p.fixed), 1);
+        assert!(count(&|p| p.group == 1) >= 1);
+        assert!(count(&|p| p.mass > 100.0) >= 1);
+        // Strata keep their share
+        assert!((90..=100).contains(&count(&|p| p.group == 0 && p.mass == 1.0 && !p.fixed)));
+    }
+
+    #[test]
+    fn keeps_particle_order() {
+        let sampled = sample(&state(), 100).unwrap();
+        let xs: Vec<f32> = sampled.particles().map(|p| p.position.x).collect();
+        assert!(xs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn same_subset_for_the_same_particles() {
+        let first = sample(&state(), 100).unwrap();
+        let second = sample(&state(), 100).unwrap();
+        assert_eq!(first.positions, second.positions);
+    }
+}
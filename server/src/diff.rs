@@ -0,0 +1,228 @@
+// Keyframe/diff streaming. Between full keyframes every particle's change
+// since the previous frame is sent as 16-bit steps, a fraction of the size
+// of full coordinates when particles move slowly. The encoder keeps the
+// client's reconstruction, so each diff also corrects the rounding of the
+// ones before it.
+use n_body_shared::{SimulationState, StateDiff};
+use nalgebra::Vector3;
+
+/// Largest quantized change either way
+const STEPS: f32 = i16::MAX as f32;
+
+pub struct DiffEncoder {
+    /// Frames per keyframe, the keyframe included
+    interval: u32,
+    /// Frames sent since the last keyframe
+    since_keyframe: u32,
+    /// The state as the client has reconstructed it, `None` until a keyframe
+    seen: Option<SimulationState>,
+}
+
+impl DiffEncoder {
+    pub fn new(interval: u32) -> Self {
+        DiffEncoder {
+            interval: interval.max(1),
+            since_keyframe: 0,
+            seen: None,
+        }
+    }
+
+    /// Send a keyframe next, e.g. after the client was sent one directly
+    pub fn invalidate(&mut self) {
+        self.seen = None;
+    }
+
+    /// The diff bringing the client up to `state`, or `None` when a keyframe
+    /// is due or the particle set changed. The client must then be sent
+    /// `state` in full.
+    pub fn diff(&mut self, state: &SimulationState) -> Option<StateDiff> {
+        self.since_keyframe += 1;
+        let fits = self.since_keyframe < self.interval
+            && self
+                .seen
+                .as_ref()
+                .is_some_and(|seen| same_particles(seen, state));
+        if !fits {
+            return self.keyframe(state);
+        }
+
+        let seen = self.seen.as_mut().expect("checked above");
+        let positions = quantize(changes(&seen.positions, &state.positions));
+        let velocities = quantize(changes(&seen.velocities, &state.velocities));
+        let (Some((position_scale, positions)), Some((velocity_scale, velocities))) =
+            (positions, velocities)
+        else {
+            return self.keyframe(state);
+        };
+        let diff = StateDiff {
+            sim_time: state.sim_time,
+            frame_number: state.frame_number,
+            interpolated: state.interpolated,
+            position_scale,
+            velocity_scale,
+            positions,
+            velocities,
+        };
+        // Exactly what the client computes
+        if diff.apply(seen).is_err() {
+            return self.keyframe(state);
+        }
+        Some(diff)
+    }
+
+    /// Start over from `state`, which the client gets in full
+    fn keyframe(&mut self, state: &SimulationState) -> Option<StateDiff> {
+        self.since_keyframe = 0;
+        self.seen = Some(state.clone());
+        None
+    }
+}
+
+/// Anything but motion means a new scene, e.g. after a reset
+fn same_particles(seen: &SimulationState, state: &SimulationState) -> bool {
+    seen.masses == state.masses && seen.colors == state.colors && seen.fixed == state.fixed
+}
+
+/// Per-particle change between two flat x, y, z arrays of the same length
+fn changes<'a>(old: &'a [f32], new: &'a [f32]) -> impl Iterator<Item = Vector3<f32>> + Clone + 'a {
+    old.chunks_exact(3)
+        .zip(new.chunks_exact(3))
+        .map(|(old, new)| Vector3::from_column_slice(new) - Vector3::from_column_slice(old))
+}
+
+/// `changes` as steps of a scale that fits the largest of them, or `None`
+/// if one is not a number
+fn quantize(changes: impl Iterator<Item = Vector3<f32>> + Clone) -> Option<(f32, Vec<[i16; 3]>)> {
+    let largest = changes.clone().try_fold(0.0, |largest: f32, change| {
+        change
+            .iter()
+            .all(|value| value.is_finite())
+            .then(|| largest.max(change.amax()))
+    })?;
+    if largest == 0.0 {
+        return Some((0.0, changes.map(|_| [0; 3]).collect()));
+    }
+    let scale = largest / STEPS;
+    let steps = changes
+        .map(|change| {
+            let step = |value: f32| (value / scale).round().clamp(-STEPS, STEPS) as i16;
+            [step(change.x), step(change.y), step(change.z)]
+        })
+        .collect();
+    Some((scale, steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::sort_by_morton;
+    use n_body_shared::Particle;
+    use nalgebra::Point3;
+
+    /// A spiral of `count` particles, with distinct masses unless `uniform`
+    fn particles(count: usize, uniform: bool) -> Vec<Particle> {
+        (0..count)
+            .map(|index| {
+                let angle = index as f32 * 0.7;
+                let radius = 1.0 + index as f32 * 0.1;
+                Particle {
+                    position: Point3::new(radius * angle.cos(), radius * angle.sin(), 0.0),
+                    velocity: Vector3::new(-angle.sin(), angle.cos(), 0.1),
+                    mass: if uniform { 1.0 } else { 1.0 + index as f32 },
+                    color: [1.0; 4],
+                    fixed: false,
+                    group: 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Move every particle along its velocity for `dt`
+    fn advance(particles: &mut [Particle], dt: f32) {
+        for particle in particles {
+            particle.position += particle.velocity * dt;
+            particle.velocity.z -= dt;
+        }
+    }
+
+    /// Check that `client` is within half a quantization step of `state`
+    fn assert_close(client: &SimulationState, state: &SimulationState, diff: &StateDiff) {
+        let close = |got: &[f32], want: &[f32], scale: f32| {
+            got.iter()
+                .zip(want)
+                .all(|(got, want)| (got - want).abs() <= scale * 0.5 + 1e-5)
+        };
+        assert!(close(
+            &client.positions,
+            &state.positions,
+            diff.position_scale
+        ));
+        assert!(close(
+            &client.velocities,
+            &state.velocities,
+            diff.velocity_scale
+        ));
+        assert_eq!(client.frame_number, state.frame_number);
+    }
+
+    #[test]
+    fn diffs_reconstruct_the_stream() {
+        let mut encoder = DiffEncoder::new(30);
+        let mut moving = particles(50, false);
+        let first = SimulationState::new(&moving, 0.0, 0);
+        assert!(encoder.diff(&first).is_none(), "starts with a keyframe");
+
+        let mut client = first;
+        for frame in 1..20 {
+            advance(&mut moving, 0.01);
+            let state = SimulationState::new(&moving, frame as f32 * 0.01, frame);
+            let diff = encoder.diff(&state).expect("diff between keyframes");
+            diff.apply(&mut client).unwrap();
+            assert_close(&client, &state, &diff);
+        }
+    }
+
+    #[test]
+    fn keyframe_every_interval() {
+        let mut encoder = DiffEncoder::new(3);
+        let state = SimulationState::new(&particles(10, false), 0.0, 0);
+        let sent: Vec<bool> = (0..7).map(|_| encoder.diff(&state).is_some()).collect();
+        assert_eq!(sent, [false, true, true, false, true, true, false]);
+    }
+
+    #[test]
+    fn reorder_of_distinct_particles_is_a_keyframe() {
+        let mut encoder = DiffEncoder::new(30);
+        let mut moving = particles(50, false);
+        encoder.diff(&SimulationState::new(&moving, 0.0, 0));
+
+        sort_by_morton(&mut moving);
+        assert!(encoder
+            .diff(&SimulationState::new(&moving, 0.01, 1))
+            .is_none());
+    }
+
+    #[test]
+    fn reorder_of_identical_particles_stays_bounded() {
+        let mut encoder = DiffEncoder::new(30);
+        let mut moving = particles(50, true);
+        let first = SimulationState::new(&moving, 0.0, 0);
+        encoder.diff(&first);
+        let mut client = first;
+
+        // Indistinguishable particles swapping places look like large
+        // jumps; the error is still half a step of the frame's scale
+        sort_by_morton(&mut moving);
+        let state = SimulationState::new(&moving, 0.01, 1);
+        let diff = encoder.diff(&state).expect("same particles");
+        diff.apply(&mut client).unwrap();
+        assert_close(&client, &state, &diff);
+
+        // and the next diff corrects the rounding of the jump
+        advance(&mut moving, 0.01);
+        let state = SimulationState::new(&moving, 0.02, 2);
+        let diff = encoder.diff(&state).expect("same particles");
+        diff.apply(&mut client).unwrap();
+        assert_close(&client, &state, &diff);
+    }
+}
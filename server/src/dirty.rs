@@ -0,0 +1,80 @@
+// Dirty-region frames for mostly static scenes. When most particles are
+// pinned or barely drifting, resending every particle each frame wastes
+// bandwidth; instead each session remembers what its client last saw and
+// sends only the particles that moved noticeably since.
+use n_body_shared::{SimulationState, StateDelta};
+use nalgebra::Point3;
+
+pub struct DirtyTracker {
+    /// Squared distance a particle must move before it is resent
+    threshold_sq: f32,
+    /// Above this fraction of moved particles a full frame is sent
+    max_fraction: f32,
+    /// The particles as the client has them, `None` until a full frame
+    seen: Option<SimulationState>,
+}
+
+impl DirtyTracker {
+    pub fn new(threshold: f32, max_fraction: f32) -> Self {
+        DirtyTracker {
+            threshold_sq: threshold.max(0.0).powi(2),
+            max_fraction: max_fraction.clamp(0.0, 1.0),
+            seen: None,
+        }
+    }
+
+    /// Send a full frame next, e.g. after the client was sent one directly
+    pub fn invalidate(&mut self) {
+        self.seen = None;
+    }
+
+    /// The delta bringing the client up to `state`, with the particles that
+    /// moved, or `None` when the particle set changed or too many of them
+    /// moved for a delta to pay off. The client must then be sent `state`
+    /// in full.
+    pub fn delta(&mut self, state: &SimulationState) -> Option<StateDelta> {
+        let moved = self.seen.as_ref().and_then(|seen| self.moved(seen, state));
+        let Some(indices) = moved else {
+            self.seen = Some(state.clone());
+            return None;
+        };
+
+        let seen = self.seen.as_mut().expect("checked above");
+        let positions = indices
+            .iter()
+            .map(|&index| {
+                let start = index as usize * 3;
+                let coords = &state.positions[start..start + 3];
+                seen.positions[start..start + 3].copy_from_slice(coords);
+                Point3::from_slice(coords)
+            })
+            .collect();
+        Some(StateDelta {
+            sim_time: state.sim_time,
+            frame_number: state.frame_number,
+            interpolated: state.interpolated,
+            indices,
+            positions,
+        })
+    }
+
+    /// Indices of the particles that moved past the threshold, or `None` if
+    /// a full frame is needed
+    fn moved(&self, seen: &SimulationState, state: &SimulationState) -> Option<Vec<u32>> {
+        // Anything but a move means a new scene, e.g. after a reset
+        if seen.masses != state.masses || seen.colors != state.colors {
+            return None;
+        }
+        let limit = (self.max_fraction * state.len() as f32) as usize;
+        let mut indices = Vec::new();
+        for index in 0..state.len() {
+            if (state.position(index) - seen.position(index)).norm_squared() > self.threshold_sq {
+                if indices.len() == limit {
+                    return None;
+                }
+                indices.push(index as u32);
+            }
+        }
+        Some(indices)
+    }
+}
@@ -0,0 +1,589 @@
+// Domain decomposition across server processes. The frontend, which also
+// serves the clients, splits the particles into slabs of equal count along
+// x, one per node, whenever forces are needed. Each worker is sent its slab
+// followed by ghosts: the particles of other slabs within `halo_width`,
+// exactly, and everything farther away merged into one mass per grid cell.
+// Workers send back the accelerations of their slab while the frontend
+// computes its own, then the frontend integrates as usual, so it always
+// holds the full state to stream. Forces are the quadratic part, so each
+// node added lets the frontend run another MAX_PARTICLES.
+use log::{info, warn};
+use n_body_shared::{ForceSolver, Particle, MAX_PARTICLES};
+use nalgebra::{Point3, Vector3};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::auth::constant_time_eq;
+use crate::config::DistributedConfig;
+use crate::physics::{barnes_hut_accelerations, direct_accelerations, ForceParams};
+
+/// Start of every request, so a stray connection is rejected early
+const MAGIC: u32 = 0x4e42_4431;
+/// Wait this long before trying an unreachable worker again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Nodes a simulation can be shared between, the frontend included
+const MAX_NODES: usize = 16;
+/// Bodies a request may carry: a slab with ghosts is at most every
+/// particle plus one mass per cell
+const MAX_BODIES: usize = 2 * MAX_PARTICLES * MAX_NODES;
+/// Longest shared key a request may carry
+const MAX_KEY_LEN: usize = 1024;
+
+/// A frontend's connections to its workers
+pub struct WorkerPool {
+    workers: Vec<Mutex<Worker>>,
+    key: String,
+    halo_width: f32,
+    cell_size: f32,
+}
+
+struct Worker {
+    address: String,
+    stream: Option<TcpStream>,
+    timeout: Duration,
+    /// When an unreachable worker may be tried again
+    retry_at: Option<Instant>,
+}
+
+impl WorkerPool {
+    /// Connections are made on first use and re-made after failures
+    pub fn new(config: &DistributedConfig) -> Self {
+        let timeout = Duration::from_secs(config.timeout_sec.max(1));
+        if config.workers.len() >= MAX_NODES {
+            warn!(
+                "Using the first {} of {} distributed workers",
+                MAX_NODES - 1,
+                config.workers.len()
+            );
+        }
+        WorkerPool {
+            workers: config
+                .workers
+                .iter()
+                .take(MAX_NODES - 1)
+                .map(|address| {
+                    Mutex::new(Worker {
+                        address: address.clone(),
+                        stream: None,
+                        timeout,
+                        retry_at: None,
+                    })
+                })
+                .collect(),
+            key: config.key.clone().unwrap_or_default(),
+            halo_width: config.halo_width.max(0.0),
+            cell_size: config.cell_size.max(f32::EPSILON),
+        }
+    }
+
+    /// Particles the frontend may hold: one machine's worth per node
+    pub fn max_particles(&self) -> usize {
+        MAX_PARTICLES * (self.workers.len() + 1)
+    }
+
+    /// Accelerations of all `particles`, computed by the frontend and its
+    /// workers one slab each. Fails if any worker does, so the caller can
+    /// fall back to computing everything itself.
+    pub fn accelerations(
+        &self,
+        particles: &[Particle],
+        params: &ForceParams,
+        solver: ForceSolver,
+        theta: f32,
+    ) -> Result<Vec<Vector3<f32>>, String> {
+        if params.periodic_box.is_some() {
+            return Err("Distributed forces need open boundaries".to_string());
+        }
+
+        let mut order: Vec<u32> = (0..particles.len() as u32).collect();
+        order.par_sort_unstable_by(|&a, &b| {
+            particles[a as usize]
+                .position
+                .x
+                .total_cmp(&particles[b as usize].position.x)
+        });
+        let slab_size = particles.len().div_ceil(self.workers.len() + 1).max(1);
+        let slabs: Vec<&[u32]> = order.chunks(slab_size).collect();
+        let mut owner = vec![0u32; particles.len()];
+        for (slab, members) in slabs.iter().enumerate() {
+            for &index in members.iter() {
+                owner[index as usize] = slab as u32;
+            }
+        }
+        let cells = Cells::build(particles, self.cell_size);
+        let bodies = |slab: usize| {
+            slab_bodies(
+                particles,
+                slabs[slab],
+                slab as u32,
+                &owner,
+                &cells,
+                self.halo_width,
+            )
+        };
+
+        let results = thread::scope(|scope| {
+            let remote: Vec<_> = (1..slabs.len())
+                .zip(&self.workers)
+                .map(|(slab, worker)| {
+                    let owned = slabs[slab].len();
+                    scope.spawn(move || {
+                        let request =
+                            encode_request(&self.key, &bodies(slab), owned, params, solver, theta);
+                        match worker.lock() {
+                            Ok(mut worker) => worker.exchange(&request),
+                            Err(e) => Err(format!("Failed to lock worker: {}", e)),
+                        }
+                    })
+                })
+                .collect();
+            let mut results = vec![Ok(slab_accelerations(
+                &bodies(0),
+                slabs.first().map_or(0, |slab| slab.len()),
+                params,
+                solver,
+                theta,
+            ))];
+            for handle in remote {
+                results.push(
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("Worker thread panicked".to_string())),
+                );
+            }
+            results
+        });
+
+        let mut accelerations = vec![Vector3::zeros(); particles.len()];
+        for (members, result) in slabs.iter().zip(results) {
+            let slab_accelerations = result?;
+            if slab_accelerations.len() != members.len() {
+                return Err(format!(
+                    "Worker returned {} accelerations for {} particles",
+                    slab_accelerations.len(),
+                    members.len()
+                ));
+            }
+            for (&index, acceleration) in members.iter().zip(slab_accelerations) {
+                accelerations[index as usize] = acceleration;
+            }
+        }
+        Ok(accelerations)
+    }
+}
+
+impl Worker {
+    /// Send one request and read the accelerations it returns. A failed
+    /// connection is dropped and reopened on a later call.
+    fn exchange(&mut self, request: &[u8]) -> Result<Vec<Vector3<f32>>, String> {
+        if self.stream.is_none() {
+            if self.retry_at.is_some_and(|at| Instant::now() < at) {
+                return Err(format!("Worker {} is unreachable", self.address));
+            }
+            match self.connect() {
+                Ok(stream) => {
+                    info!("Connected to worker {}", self.address);
+                    self.stream = Some(stream);
+                    self.retry_at = None;
+                }
+                Err(e) => {
+                    self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                    return Err(format!(
+                        "Failed to connect to worker {}: {}",
+                        self.address, e
+                    ));
+                }
+            }
+        }
+
+        let stream = self.stream.as_mut().expect("connected above");
+        let result = stream
+            .write_all(request)
+            .and_then(|_| read_reply(&mut BufReader::new(&*stream)));
+        result.map_err(|e| {
+            self.stream = None;
+            format!("Worker {} failed: {}", self.address, e)
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+        let stream = TcpStream::connect_timeout(&address, self.timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(stream)
+    }
+}
+
+/// Mass and centre of mass of the particles in one grid cell
+struct Cell {
+    mass: f32,
+    weighted: Vector3<f32>,
+    members: Vec<u32>,
+}
+
+struct Cells {
+    size: f32,
+    cells: Vec<((i32, i32, i32), Cell)>,
+}
+
+impl Cells {
+    fn build(particles: &[Particle], size: f32) -> Self {
+        let mut cells: HashMap<(i32, i32, i32), Cell> = HashMap::new();
+        for (index, particle) in particles.iter().enumerate() {
+            let position = particle.position / size;
+            let key = (
+                position.x.floor() as i32,
+                position.y.floor() as i32,
+                position.z.floor() as i32,
+            );
+            let cell = cells.entry(key).or_insert(Cell {
+                mass: 0.0,
+                weighted: Vector3::zeros(),
+                members: Vec::new(),
+            });
+            cell.mass += particle.mass;
+            cell.weighted += particle.position.coords * particle.mass;
+            cell.members.push(index as u32);
+        }
+        Cells {
+            size,
+            cells: cells.into_iter().collect(),
+        }
+    }
+}
+
+/// The bodies a node computes forces with: the slab's particles, then the
+/// particles of other slabs in cells within `halo_width` of it, then one
+/// mass for each cell farther away
+fn slab_bodies(
+    particles: &[Particle],
+    slab: &[u32],
+    slab_id: u32,
+    owner: &[u32],
+    cells: &Cells,
+    halo_width: f32,
+) -> Vec<Particle> {
+    let mut bodies: Vec<Particle> = slab
+        .iter()
+        .map(|&i| particles[i as usize].clone())
+        .collect();
+    let (Some(&first), Some(&last)) = (slab.first(), slab.last()) else {
+        return bodies;
+    };
+    let low = particles[first as usize].position.x - halo_width;
+    let high = particles[last as usize].position.x + halo_width;
+
+    for ((x, _, _), cell) in &cells.cells {
+        let near = (*x + 1) as f32 * cells.size >= low && *x as f32 * cells.size <= high;
+        if near {
+            bodies.extend(
+                cell.members
+                    .iter()
+                    .filter(|&&i| owner[i as usize] != slab_id)
+                    .map(|&i| particles[i as usize].clone()),
+            );
+        } else if cell.mass > 0.0 {
+            bodies.push(body(Point3::from(cell.weighted / cell.mass), cell.mass));
+        }
+    }
+    bodies
+}
+
+/// A point mass that only exerts forces
+fn body(position: Point3<f32>, mass: f32) -> Particle {
+    Particle {
+        position,
+        velocity: Vector3::zeros(),
+        mass,
+        color: [0.0; 4],
+        fixed: true,
+        group: 0,
+    }
+}
+
+/// Accelerations of the first `owned` of `bodies` from all of them
+fn slab_accelerations(
+    bodies: &[Particle],
+    owned: usize,
+    params: &ForceParams,
+    solver: ForceSolver,
+    theta: f32,
+) -> Vec<Vector3<f32>> {
+    let mut accelerations = match solver {
+        ForceSolver::Direct => direct_accelerations(bodies, params),
+        ForceSolver::BarnesHut => barnes_hut_accelerations(bodies, params, theta).0,
+    };
+    accelerations.truncate(owned);
+    accelerations
+}
+
+// Requests are MAGIC, the key's length (u32) and UTF-8 bytes, gravity,
+// softening, theta (f32), solver and compensation flags (u8), owned and total
+// body counts (u32), then x, y, z and mass (f32) per body. Replies are a
+// count (u32), then x, y and z (f32) per acceleration. Everything is
+// little-endian.
+
+fn encode_request(
+    key: &str,
+    bodies: &[Particle],
+    owned: usize,
+    params: &ForceParams,
+    solver: ForceSolver,
+    theta: f32,
+) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(30 + key.len() + bodies.len() * 16);
+    buffer.extend(MAGIC.to_le_bytes());
+    buffer.extend((key.len() as u32).to_le_bytes());
+    buffer.extend(key.as_bytes());
+    for value in [params.gravity, params.softening, theta] {
+        buffer.extend(value.to_le_bytes());
+    }
+    buffer.push(matches!(solver, ForceSolver::BarnesHut) as u8);
+    buffer.push(params.compensated as u8);
+    buffer.extend((owned as u32).to_le_bytes());
+    buffer.extend((bodies.len() as u32).to_le_bytes());
+    for particle in bodies {
+        let p = particle.position;
+        for value in [p.x, p.y, p.z, particle.mass] {
+            buffer.extend(value.to_le_bytes());
+        }
+    }
+    buffer
+}
+
+struct Request {
+    params: ForceParams,
+    solver: ForceSolver,
+    theta: f32,
+    owned: usize,
+    bodies: Vec<Particle>,
+}
+
+/// Read a request, refusing it unless it carries `key`
+fn read_request(reader: &mut impl Read, key: &str) -> io::Result<Request> {
+    if read_u32(reader)? != MAGIC {
+        return Err(invalid("not a distributed force request"));
+    }
+    let key_len = read_u32(reader)? as usize;
+    if key_len > MAX_KEY_LEN {
+        return Err(invalid("key too long"));
+    }
+    let mut sent_key = vec![0u8; key_len];
+    reader.read_exact(&mut sent_key)?;
+    if !constant_time_eq(&String::from_utf8_lossy(&sent_key), key) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong distributed key",
+        ));
+    }
+    let gravity = read_f32(reader)?;
+    let softening = read_f32(reader)?;
+    let theta = read_f32(reader)?;
+    let mut flags = [0u8; 2];
+    reader.read_exact(&mut flags)?;
+    let owned = read_u32(reader)? as usize;
+    let count = read_u32(reader)? as usize;
+    if owned > count || count > MAX_BODIES {
+        return Err(invalid("too many bodies"));
+    }
+
+    let values = read_f32s(reader, count * 4)?;
+    let bodies = values
+        .chunks_exact(4)
+        .map(|v| body(Point3::new(v[0], v[1], v[2]), v[3]))
+        .collect();
+    Ok(Request {
+        params: ForceParams {
+            gravity,
+            softening,
+            periodic_box: None,
+            compensated: flags[1] != 0,
+        },
+        solver: if flags[0] != 0 {
+            ForceSolver::BarnesHut
+        } else {
+            ForceSolver::Direct
+        },
+        theta,
+        owned,
+        bodies,
+    })
+}
+
+fn write_reply(writer: &mut impl Write, accelerations: &[Vector3<f32>]) -> io::Result<()> {
+    writer.write_all(&(accelerations.len() as u32).to_le_bytes())?;
+    for acceleration in accelerations {
+        for value in [acceleration.x, acceleration.y, acceleration.z] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+fn read_reply(reader: &mut impl Read) -> io::Result<Vec<Vector3<f32>>> {
+    let count = read_u32(reader)? as usize;
+    if count > MAX_BODIES {
+        return Err(invalid("too many accelerations"));
+    }
+    let values = read_f32s(reader, count * 3)?;
+    Ok(values
+        .chunks_exact(3)
+        .map(|v| Vector3::new(v[0], v[1], v[2]))
+        .collect())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    read_u32(reader).map(f32::from_bits)
+}
+
+fn read_f32s(reader: &mut impl Read, count: usize) -> io::Result<Vec<f32>> {
+    let mut bytes = vec![0u8; count * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Run as a worker: compute forces for every frontend that connects with
+/// the configured key, one thread per connection, until the process is
+/// stopped
+pub fn serve(config: &DistributedConfig) -> io::Result<()> {
+    let key = match &config.key {
+        Some(key) if !key.is_empty() => key.clone(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A distributed worker needs `key` set under [distributed]",
+            ))
+        }
+    };
+    let listener = TcpListener::bind(&config.listen)?;
+    info!("Distributed worker listening on {}", config.listen);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept frontend: {}", e);
+                continue;
+            }
+        };
+        let key = key.clone();
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "(unknown)".to_string(), |peer| peer.to_string());
+            info!("Frontend {} connected", peer);
+            match handle_frontend(stream, &key) {
+                Ok(()) => info!("Frontend {} disconnected", peer),
+                Err(e) => warn!("Frontend {} dropped: {}", peer, e),
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle_frontend(stream: TcpStream, key: &str) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let request = match read_request(&mut reader, key) {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let accelerations = slab_accelerations(
+            &request.bodies,
+            request.owned,
+            &request.params,
+            request.solver,
+            request.theta,
+        );
+        write_reply(&mut writer, &accelerations)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "shared-key";
+
+    fn params() -> ForceParams {
+        ForceParams {
+            gravity: 1.5,
+            softening: 0.05,
+            periodic_box: None,
+            compensated: true,
+        }
+    }
+
+    fn bodies() -> Vec<Particle> {
+        (0..5)
+            .map(|i| body(Point3::new(i as f32, -(i as f32), 0.5), 1.0 + i as f32))
+            .collect()
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let bodies = bodies();
+        let encoded = encode_request(KEY, &bodies, 3, &params(), ForceSolver::BarnesHut, 0.7);
+        let request = read_request(&mut encoded.as_slice(), KEY).unwrap();
+
+        assert_eq!(request.owned, 3);
+        assert_eq!(request.theta, 0.7);
+        assert_eq!(request.solver, ForceSolver::BarnesHut);
+        assert_eq!(request.params.gravity, 1.5);
+        assert_eq!(request.params.softening, 0.05);
+        assert!(request.params.compensated);
+        assert_eq!(request.bodies.len(), bodies.len());
+        for (got, sent) in request.bodies.iter().zip(&bodies) {
+            assert_eq!(got.position, sent.position);
+            assert_eq!(got.mass, sent.mass);
+        }
+    }
+
+    #[test]
+    fn request_with_another_key_is_refused() {
+        let encoded = encode_request("guess", &bodies(), 3, &params(), ForceSolver::Direct, 0.5);
+        let error = read_request(&mut encoded.as_slice(), KEY).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn request_owning_more_than_it_carries_is_refused() {
+        let encoded = encode_request(KEY, &bodies(), 6, &params(), ForceSolver::Direct, 0.5);
+        let error = read_request(&mut encoded.as_slice(), KEY).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        let accelerations = vec![Vector3::new(1.0, -2.0, 3.5), Vector3::zeros()];
+        let mut encoded = Vec::new();
+        write_reply(&mut encoded, &accelerations).unwrap();
+        assert_eq!(read_reply(&mut encoded.as_slice()).unwrap(), accelerations);
+    }
+}
@@ -0,0 +1,135 @@
+// Drift of conserved quantities over long runs: each report compares energy,
+// momentum and angular momentum with their values at the start, so an
+// unattended job shows, and can fail on, accumulated integration error
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::physics::{angular_momentum, linear_momentum, total_energy};
+use crate::simulation::Simulation;
+
+/// Relative drift above which a job warns; each check is off until set
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, JsonSchema)]
+pub struct DriftThresholds {
+    /// Fraction of the initial total energy, e.g. 0.01 for 1%
+    #[serde(default)]
+    pub energy: Option<f64>,
+    /// Fraction of the initial sum of particle momentum magnitudes, since
+    /// the total is usually close to zero
+    #[serde(default)]
+    pub momentum: Option<f64>,
+    /// Fraction of the initial sum of particle angular momentum magnitudes
+    #[serde(default)]
+    pub angular_momentum: Option<f64>,
+}
+
+/// Conserved quantities at one moment. Energy and angular momentum are not
+/// conserved in a periodic box and are left out there.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conserved {
+    pub energy: Option<f64>,
+    pub momentum: [f64; 3],
+    pub angular_momentum: Option<[f64; 3]>,
+    #[serde(skip)]
+    momentum_scale: f64,
+    #[serde(skip)]
+    angular_momentum_scale: f64,
+}
+
+impl Conserved {
+    /// O(N²) for the energy
+    pub fn measure(simulation: &Simulation) -> Self {
+        let particles = simulation.particles();
+        let config = simulation.get_config();
+        let open = config.periodic_box.is_none();
+        let (momentum, momentum_scale) = linear_momentum(particles);
+        let (angular, angular_momentum_scale) = angular_momentum(particles);
+        Conserved {
+            energy: open
+                .then(|| total_energy(particles, config.gravity_strength, simulation.softening())),
+            momentum: momentum.into(),
+            angular_momentum: open.then(|| angular.into()),
+            momentum_scale,
+            angular_momentum_scale,
+        }
+    }
+}
+
+/// A drift above its threshold
+#[derive(Debug, Clone, Serialize)]
+pub struct Exceeded {
+    pub quantity: &'static str,
+    pub drift: f64,
+    pub threshold: f64,
+}
+
+/// One entry of a job's drift report
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftReport {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    pub values: Conserved,
+    /// Relative drifts from the initial values
+    pub energy_drift: Option<f64>,
+    pub momentum_drift: f64,
+    pub angular_momentum_drift: Option<f64>,
+    pub exceeded: Vec<Exceeded>,
+}
+
+impl DriftReport {
+    /// Measure `simulation` and compare it with `initial`
+    pub fn new(initial: &Conserved, simulation: &Simulation, thresholds: &DriftThresholds) -> Self {
+        let values = Conserved::measure(simulation);
+        let relative = |change: f64, scale: f64| change.abs() / scale.abs().max(f64::MIN_POSITIVE);
+
+        let energy_drift = initial
+            .energy
+            .zip(values.energy)
+            .map(|(start, now)| relative(now - start, start));
+        let momentum_drift = relative(
+            distance(&values.momentum, &initial.momentum),
+            initial.momentum_scale,
+        );
+        let angular_momentum_drift = initial
+            .angular_momentum
+            .zip(values.angular_momentum)
+            .map(|(start, now)| relative(distance(&now, &start), initial.angular_momentum_scale));
+
+        let exceeded = [
+            ("energy", energy_drift, thresholds.energy),
+            ("momentum", Some(momentum_drift), thresholds.momentum),
+            (
+                "angular momentum",
+                angular_momentum_drift,
+                thresholds.angular_momentum,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(quantity, drift, threshold)| {
+            let (drift, threshold) = drift.zip(threshold)?;
+            (drift > threshold).then_some(Exceeded {
+                quantity,
+                drift,
+                threshold,
+            })
+        })
+        .collect();
+
+        DriftReport {
+            frame_number: simulation.frame_number(),
+            sim_time: simulation.sim_time(),
+            values,
+            energy_drift,
+            momentum_drift,
+            angular_momentum_drift,
+            exceeded,
+        }
+    }
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
@@ -0,0 +1,205 @@
+// Wire encodings for websocket messages and streamed state frames
+use n_body_shared::{wire, SimulationState, StateDiff};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Format of every message of a websocket session, negotiated with the
+/// `Sec-WebSocket-Protocol` header. JSON travels as text messages, the
+/// others as binary ones; all of them carry the same self-describing maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+impl WireFormat {
+    const ALL: [WireFormat; 3] = [WireFormat::Json, WireFormat::MsgPack, WireFormat::Cbor];
+
+    /// Subprotocol names of all formats
+    pub const PROTOCOLS: [&'static str; 3] = ["nbody-json", "nbody-msgpack", "nbody-cbor"];
+
+    pub fn protocol(self) -> &'static str {
+        Self::PROTOCOLS[self as usize]
+    }
+
+    /// The first subprotocol of a `Sec-WebSocket-Protocol` request header
+    /// that names a format, the same one the handshake confirms
+    pub fn negotiate(header: &str) -> Option<WireFormat> {
+        header.split(',').map(str::trim).find_map(|protocol| {
+            Self::ALL
+                .into_iter()
+                .find(|format| format.protocol() == protocol)
+        })
+    }
+
+    /// `message` as a websocket message of this format. MessagePack keeps
+    /// field names so scripts get the same maps as from JSON.
+    pub fn encode<T: Serialize>(self, message: &T) -> Result<Encoded, String> {
+        match self {
+            WireFormat::Json => serde_json::to_string(message)
+                .map(Encoded::Text)
+                .map_err(|e| format!("Failed to serialize message: {}", e)),
+            WireFormat::MsgPack => rmp_serde::to_vec_named(message)
+                .map(Encoded::Binary)
+                .map_err(|e| format!("Failed to encode MessagePack: {}", e)),
+            WireFormat::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(message, &mut bytes)
+                    .map_err(|e| format!("Failed to encode CBOR: {}", e))?;
+                Ok(Encoded::Binary(bytes))
+            }
+        }
+    }
+
+    /// A message received in this format
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            WireFormat::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            WireFormat::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            WireFormat::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// How a websocket session receives state frames, chosen with the
+/// `encoding` query parameter. Every other message is in the session's
+/// `WireFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StateEncoding {
+    /// `ServerMessage::State` in the session's `WireFormat`, JSON text
+    /// unless another was negotiated
+    #[default]
+    Json,
+    /// `wire::BinaryMessage::State` in bincode's default little-endian,
+    /// fixed-width layout in a binary message
+    Bincode,
+    /// `wire::BinaryMessage::QuantizedState` in bincode's layout: 16-bit
+    /// positions within the frame's bounding box and half-float velocities,
+    /// two thirds the size of `Bincode` at rendering precision
+    Quantized,
+    /// One Apache Arrow IPC stream per frame in a binary message, with
+    /// columns x, y, z, vx, vy, vz, mass (requires the `arrow` feature)
+    Arrow,
+}
+
+impl StateEncoding {
+    /// Whether this build can produce the encoding
+    pub fn is_available(self) -> bool {
+        match self {
+            StateEncoding::Json | StateEncoding::Bincode | StateEncoding::Quantized => true,
+            StateEncoding::Arrow => cfg!(feature = "arrow"),
+        }
+    }
+}
+
+/// An encoded frame, ready to go out as a text or binary websocket message
+pub enum Encoded {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// `ServerMessage::State` borrowing its state, so a frame shared by many
+/// sessions is serialized without copying it for each
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum StateMessage<'a> {
+    State(&'a SimulationState),
+    StateDiff(&'a StateDiff),
+}
+
+/// `state` as a `ServerMessage::State` in `format`
+pub fn state_message(state: &SimulationState, format: WireFormat) -> Result<Encoded, String> {
+    format.encode(&StateMessage::State(state))
+}
+
+pub fn encode_state(
+    state: &SimulationState,
+    encoding: StateEncoding,
+    format: WireFormat,
+) -> Result<Encoded, String> {
+    match encoding {
+        StateEncoding::Json => state_message(state, format),
+        StateEncoding::Bincode => wire::encode_bincode(state).map(Encoded::Binary),
+        StateEncoding::Quantized => wire::encode_quantized(state).map(Encoded::Binary),
+        #[cfg(feature = "arrow")]
+        StateEncoding::Arrow => arrow_ipc::encode(state).map(Encoded::Binary),
+        #[cfg(not(feature = "arrow"))]
+        StateEncoding::Arrow => Err("Arrow encoding requires the `arrow` feature".to_string()),
+    }
+}
+
+/// `diff` as a `ServerMessage::StateDiff` in `format`, or in bincode's
+/// layout for bincode sessions. Quantized and Arrow streams have no diff
+/// frames.
+pub fn encode_diff(
+    diff: &StateDiff,
+    encoding: StateEncoding,
+    format: WireFormat,
+) -> Result<Encoded, String> {
+    match encoding {
+        StateEncoding::Json => format.encode(&StateMessage::StateDiff(diff)),
+        StateEncoding::Bincode => wire::encode_bincode_diff(diff).map(Encoded::Binary),
+        StateEncoding::Quantized | StateEncoding::Arrow => {
+            Err(format!("{:?} streams have no diff frames", encoding))
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+mod arrow_ipc {
+    use arrow_array::{ArrayRef, Float32Array, RecordBatch};
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{DataType, Field, Schema};
+    use n_body_shared::SimulationState;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    const COLUMNS: [&str; 7] = ["x", "y", "z", "vx", "vy", "vz", "mass"];
+
+    /// Encode a frame as a self-contained IPC stream: the schema, with frame
+    /// number, sim time and interpolation flag as metadata, then one batch
+    pub fn encode(state: &SimulationState) -> Result<Vec<u8>, String> {
+        // Every third value of a flat x, y, z array
+        let axis = |values: &[f32], axis: usize| -> ArrayRef {
+            Arc::new(Float32Array::from_iter_values(
+                values.iter().skip(axis).step_by(3).copied(),
+            ))
+        };
+        let columns = vec![
+            axis(&state.positions, 0),
+            axis(&state.positions, 1),
+            axis(&state.positions, 2),
+            axis(&state.velocities, 0),
+            axis(&state.velocities, 1),
+            axis(&state.velocities, 2),
+            Arc::new(Float32Array::from(state.masses.clone())) as ArrayRef,
+        ];
+
+        let metadata = HashMap::from([
+            ("frame_number".to_string(), state.frame_number.to_string()),
+            ("sim_time".to_string(), state.sim_time.to_string()),
+            ("interpolated".to_string(), state.interpolated.to_string()),
+        ]);
+        let fields: Vec<Field> = COLUMNS
+            .iter()
+            .map(|name| Field::new(*name, DataType::Float32, false))
+            .collect();
+        let schema = Arc::new(Schema::new(fields).with_metadata(metadata));
+
+        let batch = RecordBatch::try_new(schema.clone(), columns)
+            .map_err(|e| format!("Failed to build Arrow batch: {}", e))?;
+        let mut buffer = Vec::new();
+        let mut writer = StreamWriter::try_new(&mut buffer, &schema)
+            .map_err(|e| format!("Failed to start Arrow stream: {}", e))?;
+        writer
+            .write(&batch)
+            .and_then(|_| writer.finish())
+            .map_err(|e| format!("Failed to write Arrow batch: {}", e))?;
+        drop(writer);
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,135 @@
+// Energy partition channel: every few frames the kinetic and potential
+// energy of each particle group is measured off the physics loop, kept as a
+// short history and streamed to clients, to plot the energy exchange of a
+// merger
+use log::{error, info};
+use n_body_shared::EnergySample;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::config::EnergyConfig;
+use crate::physics::energy_partition;
+use crate::service::Frame;
+
+/// Samples buffered per subscriber before slow clients skip ahead
+const SAMPLE_CHANNEL_CAPACITY: usize = 16;
+
+pub struct EnergyMonitor {
+    history: Mutex<VecDeque<EnergySample>>,
+    samples: broadcast::Sender<EnergySample>,
+}
+
+impl EnergyMonitor {
+    /// Measure the frames published on `frames` until they end. Must be
+    /// called from within a Tokio runtime.
+    pub fn spawn(config: &EnergyConfig, frames: &broadcast::Sender<Arc<Frame>>) -> Arc<Self> {
+        let (samples, _) = broadcast::channel(SAMPLE_CHANNEL_CAPACITY);
+        let monitor = Arc::new(EnergyMonitor {
+            history: Mutex::new(VecDeque::new()),
+            samples,
+        });
+        if config.enabled && config.history > 0 {
+            tokio::spawn(monitor.clone().run(config.clone(), frames.subscribe()));
+        }
+        monitor
+    }
+
+    /// Retained samples of the current run, oldest first
+    pub fn history(&self) -> Vec<EnergySample> {
+        match self.history.lock() {
+            Ok(history) => history.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Receive every sample taken from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<EnergySample> {
+        self.samples.subscribe()
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        config: EnergyConfig,
+        mut frames: broadcast::Receiver<Arc<Frame>>,
+    ) {
+        let interval = config.interval.max(1);
+        let mut last_sampled: Option<u64> = None;
+        let mut too_large = false;
+
+        loop {
+            let frame = match frames.recv().await {
+                Ok(frame) => frame,
+                // Measuring took longer than the frames it missed
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let frame_number = frame.stats.frame_number;
+
+            // A reset starts a new series
+            let reset = last_sampled.is_some_and(|last| frame_number < last);
+            if reset {
+                if let Ok(mut history) = self.history.lock() {
+                    history.clear();
+                }
+            }
+            if !reset && last_sampled.is_some_and(|last| frame_number < last + interval) {
+                continue;
+            }
+
+            if frame.state.len() > config.max_particles {
+                if !too_large {
+                    info!(
+                        "Energy partition paused: {} particles is above max_particles {}",
+                        frame.state.len(),
+                        config.max_particles
+                    );
+                }
+                too_large = true;
+                continue;
+            }
+            too_large = false;
+
+            // The pair potential assumes open boundaries
+            if frame.params.periodic_box.is_some() {
+                last_sampled = Some(frame_number);
+                continue;
+            }
+
+            // O(N²); keep it off the executor
+            let measured = frame.clone();
+            let partition = match tokio::task::spawn_blocking(move || {
+                energy_partition(
+                    &measured.state.particles().collect::<Vec<_>>(),
+                    measured.params.gravity,
+                    measured.params.softening,
+                )
+            })
+            .await
+            {
+                Ok(partition) => partition,
+                Err(e) => {
+                    error!("Energy measurement failed: {}", e);
+                    continue;
+                }
+            };
+            last_sampled = Some(frame_number);
+
+            let sample = EnergySample {
+                frame_number,
+                sim_time: frame.stats.sim_time,
+                kinetic: partition.kinetic.iter().map(|&e| e as f32).collect(),
+                potential: partition.potential.iter().map(|&e| e as f32).collect(),
+                interaction: partition.interaction as f32,
+            };
+            if let Ok(mut history) = self.history.lock() {
+                history.push_back(sample.clone());
+                while history.len() > config.history {
+                    history.pop_front();
+                }
+            }
+            // Nobody listening is not an error
+            let _ = self.samples.send(sample);
+        }
+    }
+}
@@ -0,0 +1,20 @@
+// Optional subsystems compiled in through cargo features
+
+/// Every optional feature with whether this build includes it
+const FEATURES: [(&str, bool); 5] = [
+    ("scripting", cfg!(feature = "scripting")),
+    ("recording", cfg!(feature = "recording")),
+    ("arrow", cfg!(feature = "arrow")),
+    ("tls", cfg!(feature = "tls")),
+    ("webhooks", cfg!(feature = "webhooks")),
+];
+
+/// Names of the optional features compiled into this server, reported to
+/// clients in the handshake so they can hide what is unavailable
+pub fn enabled() -> Vec<String> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
@@ -0,0 +1,1395 @@
+// Initial-condition generators for galaxies and star clusters
+use n_body_shared::{GalaxySpec, MassFunction, Morphology, Particle, Spin};
+use nalgebra::{Point3, Rotation3, Vector3};
+
+use crate::config::{
+    BinaryConfig, ClusterConfig, ColdCollapseConfig, CollapseShape, ProtoplanetaryConfig,
+    RingConfig,
+};
+use crate::presets::orbital_state;
+use crate::rng::Rng;
+use crate::simulation::DEFAULT_SOFTENING;
+
+/// Mean particle mass, matching the spiral generator's 1..3 mass ramp
+const MEAN_PARTICLE_MASS: f32 = 2.0;
+
+/// Segments of the Kroupa IMF as (lower mass, upper mass, slope α) in solar
+/// masses, dN/dm ∝ m^-α
+const KROUPA_SEGMENTS: [(f32, f32, f32); 3] =
+    [(0.01, 0.08, 0.3), (0.08, 0.5, 1.3), (0.5, 100.0, 2.3)];
+
+/// The Salpeter IMF as a single segment
+const SALPETER_SEGMENTS: [(f32, f32, f32); 1] = [(0.1, 100.0, 2.35)];
+
+/// Star colors by log10 of the mass in solar masses, from red dwarfs through
+/// the Sun to blue O stars
+const STAR_COLORS: [(f32, [f32; 3]); 5] = [
+    (-1.0, [1.0, 0.55, 0.35]),
+    (-0.3, [1.0, 0.75, 0.5]),
+    (0.0, [1.0, 0.95, 0.85]),
+    (0.5, [0.85, 0.9, 1.0]),
+    (1.5, [0.6, 0.7, 1.0]),
+];
+
+/// The default collision: two galaxies of the given shapes, velocity
+/// dispersion and mass function, splitting `total_particles`, on a head-on
+/// course along the x axis
+pub fn default_collision(
+    total_particles: usize,
+    morphologies: [Morphology; 2],
+    dispersion: f32,
+    mass_function: MassFunction,
+) -> Vec<GalaxySpec> {
+    let galaxy = |center: f32, velocity: f32, color, morphology| GalaxySpec {
+        count: total_particles / 2,
+        center: [center, 0.0, 0.0],
+        velocity: [velocity, 0.0, 0.0],
+        radius: 2.0,
+        color,
+        spin_axis: [0.0, 0.0, 1.0],
+        euler_angles: [0.0; 3],
+        spin: Spin::Prograde,
+        morphology,
+        dispersion,
+        mass_function,
+    };
+    vec![
+        galaxy(-5.0, 0.5, [0.8, 0.8, 1.0, 1.0], morphologies[0]), // Blue
+        galaxy(5.0, -0.5, [1.0, 0.8, 0.8, 1.0], morphologies[1]), // Red
+    ]
+}
+
+/// Check that every galaxy can be generated
+pub fn validate_galaxies(specs: &[GalaxySpec]) -> Result<(), String> {
+    for (index, spec) in specs.iter().enumerate() {
+        if !spec.radius.is_finite() || spec.radius <= 0.0 {
+            return Err(format!(
+                "Galaxy {} radius must be positive, got {}",
+                index + 1,
+                spec.radius
+            ));
+        }
+        let axis = Vector3::from(spec.spin_axis);
+        if !axis.iter().all(|c| c.is_finite()) || axis.norm() == 0.0 {
+            return Err(format!(
+                "Galaxy {} spin axis {:?} must be a non-zero vector",
+                index + 1,
+                spec.spin_axis
+            ));
+        }
+        if !spec.euler_angles.iter().all(|angle| angle.is_finite()) {
+            return Err(format!(
+                "Galaxy {} Euler angles {:?} must be finite",
+                index + 1,
+                spec.euler_angles
+            ));
+        }
+        if !spec.dispersion.is_finite() || spec.dispersion < 0.0 {
+            return Err(format!(
+                "Galaxy {} dispersion must not be negative, got {}",
+                index + 1,
+                spec.dispersion
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Every galaxy in `specs`, in order, each in its own group
+pub fn generate_galaxies(specs: &[GalaxySpec], gravity: f32, rng: &mut Rng) -> Vec<Particle> {
+    let mut particles = Vec::with_capacity(specs.iter().map(|spec| spec.count).sum());
+    for (group, spec) in specs.iter().enumerate() {
+        particles.extend(in_group(generate_galaxy(spec, gravity, rng), group));
+    }
+    particles
+}
+
+/// Tag `particles` as belonging to `group`, e.g. the n-th galaxy
+pub fn in_group(mut particles: Vec<Particle>, group: usize) -> Vec<Particle> {
+    let group = group.min(u16::MAX as usize) as u16;
+    for particle in &mut particles {
+        particle.group = group;
+    }
+    particles
+}
+
+/// One galaxy, built at rest at the origin spinning about z, then turned to
+/// its spin axis and Euler angles, reversed if retrograde, and moved into
+/// place
+pub fn generate_galaxy(spec: &GalaxySpec, gravity: f32, rng: &mut Rng) -> Vec<Particle> {
+    let origin = Point3::origin();
+    let rest = Vector3::zeros();
+    let mut particles = match spec.morphology {
+        Morphology::Spiral => {
+            generate_spiral_galaxy(spec.count, origin, rest, spec.radius, spec.color, rng)
+        }
+        Morphology::Elliptical => generate_elliptical_galaxy(
+            spec.count,
+            origin,
+            rest,
+            spec.radius,
+            spec.color,
+            gravity,
+            rng,
+        ),
+        Morphology::Composite => generate_composite_galaxy(
+            spec.count,
+            origin,
+            rest,
+            spec.radius,
+            spec.color,
+            gravity,
+            rng,
+        ),
+    };
+    add_dispersion(&mut particles, rest, spec.dispersion, rng);
+    apply_mass_function(&mut particles, spec.mass_function, rng);
+
+    let axis = Vector3::from(spec.spin_axis);
+    // Antiparallel axes have no unique shortest rotation; flip about x
+    let to_axis = Rotation3::rotation_between(&Vector3::z(), &axis)
+        .unwrap_or_else(|| Rotation3::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI));
+    let [roll, pitch, yaw] = spec.euler_angles.map(f32::to_radians);
+    let rotation = Rotation3::from_euler_angles(roll, pitch, yaw) * to_axis;
+    // Reversing every internal velocity keeps the shape but turns the
+    // rotation around
+    let sense = match spec.spin {
+        Spin::Prograde => 1.0,
+        Spin::Retrograde => -1.0,
+    };
+    let center = Vector3::from(spec.center);
+    let velocity = Vector3::from(spec.velocity);
+    for particle in &mut particles {
+        particle.position = Point3::from(rotation * particle.position.coords + center);
+        particle.velocity = rotation * particle.velocity * sense + velocity;
+    }
+    particles
+}
+
+/// Warm up a galaxy moving at `bulk_velocity`: every particle gets an
+/// isotropic Gaussian velocity with a standard deviation of `dispersion`
+/// times its orbital speed, which in the cold disks is the local circular
+/// speed. The kicks are balanced so the galaxy keeps its bulk motion.
+pub fn add_dispersion(
+    particles: &mut [Particle],
+    bulk_velocity: Vector3<f32>,
+    dispersion: f32,
+    rng: &mut Rng,
+) {
+    if dispersion <= 0.0 {
+        return;
+    }
+    let kicks: Vec<Vector3<f32>> = particles
+        .iter()
+        .map(|particle| {
+            let speed = (particle.velocity - bulk_velocity).norm();
+            Vector3::new(rng.gaussian(), rng.gaussian(), rng.gaussian()) * (dispersion * speed)
+        })
+        .collect();
+
+    let mass: f32 = particles.iter().map(|p| p.mass).sum();
+    if mass <= 0.0 {
+        return;
+    }
+    let mean_kick = particles
+        .iter()
+        .zip(&kicks)
+        .map(|(particle, kick)| kick * particle.mass)
+        .sum::<Vector3<f32>>()
+        / mass;
+    for (particle, kick) in particles.iter_mut().zip(&kicks) {
+        particle.velocity += kick - mean_kick;
+    }
+}
+
+/// Redraw the particle masses from an initial mass function. Each particle
+/// keeps its generated mass scaled by its star's mass relative to the IMF
+/// mean, so heavy halo particles stay heavy, and the total mass is kept so
+/// the orbits still fit the potential. The centre of mass and bulk motion
+/// are restored afterwards; colors follow the star's mass, keeping alpha.
+pub fn apply_mass_function(particles: &mut [Particle], mass_function: MassFunction, rng: &mut Rng) {
+    let segments: &[(f32, f32, f32)] = match mass_function {
+        MassFunction::Ramp => return,
+        MassFunction::Salpeter => &SALPETER_SEGMENTS,
+        MassFunction::Kroupa => &KROUPA_SEGMENTS,
+    };
+    let total: f32 = particles.iter().map(|p| p.mass).sum();
+    if total <= 0.0 {
+        return;
+    }
+    let center = particles
+        .iter()
+        .map(|p| p.position.coords * p.mass)
+        .sum::<Vector3<f32>>()
+        / total;
+    let velocity = particles
+        .iter()
+        .map(|p| p.velocity * p.mass)
+        .sum::<Vector3<f32>>()
+        / total;
+
+    let stars: Vec<f32> = particles
+        .iter()
+        .map(|_| sample_power_law(segments, rng))
+        .collect();
+    let mean_star = stars.iter().sum::<f32>() / stars.len() as f32;
+    for (particle, &star) in particles.iter_mut().zip(&stars) {
+        particle.mass *= star / mean_star;
+        let [r, g, b] = star_color(star);
+        particle.color = [r, g, b, particle.color[3]];
+    }
+    let scale = total / particles.iter().map(|p| p.mass).sum::<f32>();
+    for particle in particles.iter_mut() {
+        particle.mass *= scale;
+    }
+    place(particles, Point3::from(center), velocity);
+}
+
+/// Draw a mass from a continuous piecewise power law
+fn sample_power_law(segments: &[(f32, f32, f32)], rng: &mut Rng) -> f32 {
+    // Number of stars in each segment, with the normalisation chosen so the
+    // density is continuous at the breaks
+    let integral = |lower: f32, upper: f32, alpha: f32| {
+        if (alpha - 1.0).abs() < 1e-6 {
+            (upper / lower).ln()
+        } else {
+            (upper.powf(1.0 - alpha) - lower.powf(1.0 - alpha)) / (1.0 - alpha)
+        }
+    };
+    let mut norm = 1.0;
+    let mut weights = Vec::with_capacity(segments.len());
+    for (i, &(lower, upper, alpha)) in segments.iter().enumerate() {
+        if i > 0 {
+            let previous = segments[i - 1].2;
+            norm *= lower.powf(alpha - previous);
+        }
+        weights.push(norm * integral(lower, upper, alpha));
+    }
+    let mut pick = rng.uniform() * weights.iter().sum::<f32>();
+    let mut segment = segments[segments.len() - 1];
+    for (&candidate, &weight) in segments.iter().zip(&weights) {
+        if pick < weight {
+            segment = candidate;
+            break;
+        }
+        pick -= weight;
+    }
+
+    // Invert the segment's cumulative distribution
+    let (lower, upper, alpha) = segment;
+    let u = rng.uniform();
+    if (alpha - 1.0).abs() < 1e-6 {
+        lower * (upper / lower).powf(u)
+    } else {
+        let exponent = 1.0 - alpha;
+        let low = lower.powf(exponent);
+        (low + u * (upper.powf(exponent) - low)).powf(1.0 / exponent)
+    }
+}
+
+/// Color of a main-sequence star of `mass` solar masses
+fn star_color(mass: f32) -> [f32; 3] {
+    let x = mass.log10();
+    let (first, last) = (STAR_COLORS[0], STAR_COLORS[STAR_COLORS.len() - 1]);
+    if x <= first.0 {
+        return first.1;
+    }
+    for pair in STAR_COLORS.windows(2) {
+        let ((x0, c0), (x1, c1)) = (pair[0], pair[1]);
+        if x <= x1 {
+            let t = (x - x0) / (x1 - x0);
+            return [0, 1, 2].map(|i| c0[i] + (c1[i] - c0[i]) * t);
+        }
+    }
+    last.1
+}
+
+/// Thin rotating disk of the given radius with two spiral arms
+pub fn generate_spiral_galaxy(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    radius: f32,
+    base_color: [f32; 4],
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    (0..num_particles)
+        .map(|i| {
+            let t = i as f32 / num_particles as f32;
+            let angle = t * std::f32::consts::PI * 4.0;
+            let r = t * radius;
+
+            let thickness = 0.1 * radius;
+            let z_offset = (rng.uniform() - 0.5) * thickness;
+
+            let x = r * angle.cos();
+            let y = r * angle.sin();
+            let z = z_offset;
+
+            let local_pos = Vector3::new(x, y, z);
+            let position = center + local_pos;
+
+            let orbital_speed = (1.0 / (r + 0.1).sqrt()) * 2.0;
+            let tangent = Vector3::new(-angle.sin(), angle.cos(), 0.0);
+            let orbital_velocity = tangent * orbital_speed;
+
+            let velocity = bulk_velocity + orbital_velocity;
+            let mass = 1.0 + (1.0 - t) * 2.0;
+
+            let color_variation = 0.2;
+            let rand = rng.uniform();
+            let color = [
+                base_color[0] + (rand - 0.5) * color_variation,
+                base_color[1] + (rand - 0.5) * color_variation,
+                base_color[2] + (rand - 0.5) * color_variation,
+                base_color[3],
+            ];
+
+            Particle {
+                position,
+                velocity,
+                mass,
+                color,
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect()
+}
+
+/// Hernquist sphere, the de Vaucouleurs-like profile of elliptical galaxies,
+/// with scale length `radius / 2`, truncated at `2 * radius`. Velocities are
+/// drawn from the model's isotropic Jeans dispersion so the sphere starts in
+/// equilibrium under `gravity`.
+pub fn generate_elliptical_galaxy(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    radius: f32,
+    base_color: [f32; 4],
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let scale = radius * 0.5;
+    let cutoff = radius * 2.0;
+    // Enclosed mass fraction M(r)/M = r² / (r + a)² at the truncation radius
+    let cutoff_fraction = (cutoff / (cutoff + scale)).powi(2);
+    // Total mass of the untruncated model whose inner part we sample
+    let model_mass = num_particles as f32 * MEAN_PARTICLE_MASS / cutoff_fraction;
+
+    (0..num_particles)
+        .map(|_| {
+            // Invert the cumulative mass profile
+            let root = (rng.uniform() * cutoff_fraction).sqrt();
+            let r = scale * root / (1.0 - root);
+            let position = center + rng.direction() * r;
+
+            let sigma = hernquist_dispersion(r, scale, gravity * model_mass);
+            let mut velocity = Vector3::new(rng.gaussian(), rng.gaussian(), rng.gaussian()) * sigma;
+            // Unbound stragglers would just fly off; keep them inside the well
+            let escape_speed = (2.0 * gravity * model_mass / (r + scale)).sqrt();
+            let speed = velocity.norm();
+            if speed > 0.95 * escape_speed {
+                velocity *= 0.95 * escape_speed / speed;
+            }
+
+            // Redder toward the core, like the old stellar populations of ellipticals
+            let warmth = 0.15 * (1.0 - (r / cutoff).min(1.0));
+            let color = [
+                (base_color[0] + warmth).min(1.0),
+                base_color[1],
+                (base_color[2] - warmth).max(0.0),
+                base_color[3],
+            ];
+
+            Particle {
+                position,
+                velocity: bulk_velocity + velocity,
+                mass: MEAN_PARTICLE_MASS,
+                color,
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect()
+}
+
+/// Fractions of a composite galaxy's particles in the disk and bulge; the
+/// rest make up the halo
+const DISK_PARTICLES: f32 = 0.5;
+const BULGE_PARTICLES: f32 = 0.15;
+/// Fractions of its mass in the disk and bulge; the dark halo holds the rest
+/// and most of the mass, as in real galaxies
+const DISK_MASS: f32 = 0.25;
+const BULGE_MASS: f32 = 0.1;
+/// Toomre stability parameter of the disk; above 1 it resists clumping
+const DISK_TOOMRE_Q: f32 = 1.5;
+/// Composite galaxy mass per unit radius. Unlike the spiral and elliptical
+/// generators the mass does not grow with the particle count, which keeps
+/// orbital speeds near 1.5 at the disk edge and the orbits resolved by the
+/// default time step whatever the resolution.
+const COMPOSITE_MASS_PER_RADIUS: f32 = 5.0;
+
+/// Galaxy built from three components, each sampled with its own
+/// kinematics so the whole starts close to equilibrium and keeps its shape
+/// for many rotations:
+/// - an exponential disk of scale length `radius / 3`, truncated at five
+///   scale lengths, on near-circular orbits with Toomre-stable dispersions
+/// - a Hernquist bulge of scale `radius / 10`
+/// - a dim Hernquist dark halo of scale `radius`, truncated at `3 * radius`
+///
+/// Circular speeds and the spheroids' isotropic Jeans dispersions are found
+/// from the combined mass of all three, treating the disk as spherical.
+pub fn generate_composite_galaxy(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    radius: f32,
+    base_color: [f32; 4],
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let (_, mut particles, spheroids) =
+        composite_components(num_particles, radius, base_color, gravity, rng);
+    particles.extend(spheroids);
+    place(&mut particles, center, bulk_velocity);
+    particles
+}
+
+/// The mass model of a composite galaxy with its disk particles, and its
+/// bulge and halo particles, at rest on the origin
+fn composite_components(
+    num_particles: usize,
+    radius: f32,
+    base_color: [f32; 4],
+    gravity: f32,
+    rng: &mut Rng,
+) -> (CompositeModel, Vec<Particle>, Vec<Particle>) {
+    let disk_count = (num_particles as f32 * DISK_PARTICLES) as usize;
+    let bulge_count = (num_particles as f32 * BULGE_PARTICLES) as usize;
+    let halo_count = num_particles - disk_count - bulge_count;
+    let total_mass = COMPOSITE_MASS_PER_RADIUS * radius;
+    let model = CompositeModel::new(radius, total_mass, gravity);
+
+    let disk_particle_mass = total_mass * DISK_MASS / disk_count.max(1) as f32;
+    let disk = (0..disk_count)
+        .map(|_| {
+            let r = loop {
+                // The radii of an exponential disk follow a Gamma(2) distribution
+                let r = -model.disk_scale * ((1.0 - rng.uniform()) * (1.0 - rng.uniform())).ln();
+                if r < model.disk_cutoff {
+                    break r;
+                }
+            };
+            let angle = std::f32::consts::TAU * rng.uniform();
+            // Isothermal sech² vertical profile
+            let z = model.disk_height * (2.0 * rng.uniform() - 1.0).clamp(-0.999, 0.999).atanh();
+            let radial = Vector3::new(angle.cos(), angle.sin(), 0.0);
+            let tangent = Vector3::new(-angle.sin(), angle.cos(), 0.0);
+
+            let (rotation, sigma_r, sigma_phi, sigma_z) = model.disk_kinematics(r);
+            let velocity = tangent * (rotation + sigma_phi * rng.gaussian())
+                + radial * (sigma_r * rng.gaussian())
+                + Vector3::z() * (sigma_z * rng.gaussian());
+
+            let rand = rng.uniform();
+            Particle {
+                position: Point3::new(r * angle.cos(), r * angle.sin(), z),
+                velocity,
+                mass: disk_particle_mass,
+                color: [
+                    base_color[0] + (rand - 0.5) * 0.2,
+                    base_color[1] + (rand - 0.5) * 0.2,
+                    base_color[2] + (rand - 0.5) * 0.2,
+                    base_color[3],
+                ],
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect();
+
+    // Bulge, warm colored like the elliptical generator's core
+    let bulge_color = [
+        (base_color[0] + 0.15).min(1.0),
+        base_color[1],
+        (base_color[2] - 0.15).max(0.0),
+        base_color[3],
+    ];
+    let mut spheroids = model.bulge.sample(
+        bulge_count,
+        total_mass * BULGE_MASS / bulge_count.max(1) as f32,
+        bulge_color,
+        rng,
+    );
+
+    // Halo, dimmed so it does not hide the disk
+    let halo_color = [
+        base_color[0] * 0.5,
+        base_color[1] * 0.5,
+        base_color[2] * 0.5,
+        base_color[3] * 0.3,
+    ];
+    let halo_mass = total_mass * (1.0 - DISK_MASS - BULGE_MASS);
+    spheroids.extend(model.halo.sample(
+        halo_count,
+        halo_mass / halo_count.max(1) as f32,
+        halo_color,
+        rng,
+    ));
+
+    (model, disk, spheroids)
+}
+
+/// Move the centre of mass to `center` with velocity `bulk_velocity`. This
+/// also removes the net momentum that sampling noise leaves behind.
+fn place(particles: &mut [Particle], center: Point3<f32>, bulk_velocity: Vector3<f32>) {
+    let mass: f32 = particles.iter().map(|p| p.mass).sum();
+    if mass <= 0.0 {
+        return;
+    }
+    let mean_position = particles
+        .iter()
+        .map(|p| p.position.coords * p.mass)
+        .sum::<Vector3<f32>>()
+        / mass;
+    let mean_velocity = particles
+        .iter()
+        .map(|p| p.velocity * p.mass)
+        .sum::<Vector3<f32>>()
+        / mass;
+    for particle in particles {
+        particle.position += center.coords - mean_position;
+        particle.velocity += bulk_velocity - mean_velocity;
+    }
+}
+
+/// Ring galaxy like the Cartwheel: a composite galaxy shortly after a compact
+/// companion punched through the middle of its disk along the spin axis.
+/// The passage gave every disk star an inward kick
+/// Δv = -2Gm r / (V (r² + b²)), which it has followed on its epicycle for
+/// `age` since. The kick and the epicyclic frequency both fall with radius,
+/// so orbits crowd together into a ring that expands outward (Lynds & Toomre
+/// 1976); stars in the crowded ring are tinted blue like the young stars the
+/// compressed gas forms there. A tenth of the particles make up the
+/// companion, receding along z.
+pub fn generate_ring_galaxy(
+    total_particles: usize,
+    ring: &RingConfig,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let companion_particles = total_particles / 10;
+    let (model, mut disk, spheroids) = composite_components(
+        total_particles - companion_particles,
+        2.0,
+        [0.8, 0.8, 1.0, 1.0],
+        gravity,
+        rng,
+    );
+
+    let galaxy_mass = disk.iter().chain(&spheroids).map(|p| p.mass).sum::<f32>();
+    let companion_mass = galaxy_mass * ring.companion_mass_fraction;
+    let speed = ring.impact_speed;
+    let impact_radius = ring.companion_radius;
+    // Where a star at `r0` is now, and its radial velocity
+    let displaced = |r0: f32| {
+        let kick = -2.0 * gravity * companion_mass * r0
+            / (speed * (r0 * r0 + impact_radius * impact_radius));
+        let kappa = model.epicyclic_frequency(r0);
+        let phase = kappa * ring.age;
+        (
+            (r0 + kick / kappa * phase.sin()).max(0.1 * r0),
+            kick * phase.cos(),
+        )
+    };
+
+    for star in &mut disk {
+        let planar = Vector3::new(star.position.x, star.position.y, 0.0);
+        let r0 = planar.norm();
+        if r0 < 1e-6 {
+            continue;
+        }
+        let radial = planar / r0;
+        let tangent = Vector3::new(-radial.y, radial.x, 0.0);
+        let (r, v_kick) = displaced(r0);
+
+        // Angular momentum is kept, so stars pulled inward spin faster
+        star.velocity = radial * (star.velocity.dot(&radial) + v_kick)
+            + tangent * (star.velocity.dot(&tangent) * r0 / r)
+            + Vector3::z() * star.velocity.z;
+        star.position = Point3::new(radial.x * r, radial.y * r, star.position.z);
+
+        // Density grows as 1 / (dr/dr0) where neighbouring orbits converge
+        let dr0 = 1e-3 * model.disk_scale;
+        let stretch = (displaced(r0 + dr0).0 - displaced((r0 - dr0).max(1e-6)).0) / (2.0 * dr0);
+        let compression = 1.0 / stretch.abs().max(0.05);
+        let young = ((compression - 1.0) / 3.0).clamp(0.0, 1.0);
+        for (channel, blue) in star.color.iter_mut().zip([0.6, 0.8, 1.0]) {
+            *channel += (blue - *channel) * young;
+        }
+    }
+
+    let mut particles = disk;
+    particles.extend(spheroids);
+    place(&mut particles, Point3::origin(), Vector3::zeros());
+
+    // The companion has travelled on since the impact, ignoring the drag of
+    // the galaxy's pull
+    let mut companion = generate_plummer_sphere(
+        companion_particles,
+        companion_mass,
+        impact_radius,
+        gravity,
+        rng,
+    );
+    place(
+        &mut companion,
+        Point3::new(0.0, 0.0, speed * ring.age),
+        Vector3::new(0.0, 0.0, speed),
+    );
+    particles.extend(in_group(companion, 1));
+    particles
+}
+
+/// Mass model of a composite galaxy, in the simulation's units
+struct CompositeModel {
+    gravity: f32,
+    disk_scale: f32,
+    disk_cutoff: f32,
+    disk_height: f32,
+    /// Mass of the untruncated exponential disk
+    disk_mass: f32,
+    bulge: Spheroid,
+    halo: Spheroid,
+}
+
+impl CompositeModel {
+    fn new(radius: f32, total_mass: f32, gravity: f32) -> Self {
+        let disk_scale = radius / 3.0;
+        let disk_cutoff = 5.0 * disk_scale;
+        let x = disk_cutoff / disk_scale;
+        let disk_fraction = 1.0 - (1.0 + x) * (-x).exp();
+        let mut model = CompositeModel {
+            gravity,
+            disk_scale,
+            disk_cutoff,
+            disk_height: 0.1 * disk_scale,
+            disk_mass: total_mass * DISK_MASS / disk_fraction,
+            bulge: Spheroid::new(radius * 0.1, radius * 0.5, total_mass * BULGE_MASS),
+            halo: Spheroid::new(
+                radius,
+                radius * 3.0,
+                total_mass * (1.0 - DISK_MASS - BULGE_MASS),
+            ),
+        };
+        model.bulge.dispersions = model.jeans_table(&model.bulge);
+        model.halo.dispersions = model.jeans_table(&model.halo);
+        model
+    }
+
+    fn enclosed_mass(&self, r: f32) -> f32 {
+        let x = r / self.disk_scale;
+        self.disk_mass * (1.0 - (1.0 + x) * (-x).exp())
+            + self.bulge.enclosed_mass(r)
+            + self.halo.enclosed_mass(r)
+    }
+
+    /// Inward acceleration at radius `r`, softened like the simulation's
+    /// forces so orbits near the centre match what the particles will feel
+    fn acceleration(&self, r: f32) -> f32 {
+        let softening = DEFAULT_SOFTENING;
+        self.gravity * self.enclosed_mass(r) * r / (r * r + softening * softening).powf(1.5)
+    }
+
+    /// Epicyclic frequency κ² = R dΩ²/dR + 4Ω², by finite difference
+    fn epicyclic_frequency(&self, r: f32) -> f32 {
+        let r = r.max(1e-3);
+        let omega_sq = |r: f32| self.acceleration(r) / r;
+        let dr = 1e-3 * self.disk_scale;
+        (r * (omega_sq(r + dr) - omega_sq((r - dr).max(1e-4))) / (2.0 * dr) + 4.0 * omega_sq(r))
+            .max(1e-12)
+            .sqrt()
+    }
+
+    /// Mean rotation speed and radial, azimuthal and vertical dispersions of
+    /// the disk at radius `r`
+    fn disk_kinematics(&self, r: f32) -> (f32, f32, f32, f32) {
+        let r = r.max(1e-3);
+        let v_circular_sq = r * self.acceleration(r);
+        let kappa = self.epicyclic_frequency(r);
+        let omega = (self.acceleration(r) / r).sqrt();
+
+        let surface_density = self.disk_mass / (std::f32::consts::TAU * self.disk_scale.powi(2))
+            * (-r / self.disk_scale).exp();
+        let sigma_r = (DISK_TOOMRE_Q * 3.36 * self.gravity * surface_density / kappa)
+            .min(0.5 * v_circular_sq.sqrt());
+        let sigma_phi = sigma_r * kappa / (2.0 * omega.max(1e-6));
+        let sigma_z =
+            (std::f32::consts::PI * self.gravity * surface_density * self.disk_height).sqrt();
+        // Asymmetric drift: random motions carry part of the support
+        let drift = sigma_r * sigma_r * (2.0 * r / self.disk_scale - 0.5);
+        let rotation = (v_circular_sq - drift).max(0.0).sqrt();
+        (rotation, sigma_r, sigma_phi, sigma_z)
+    }
+
+    /// Isotropic Jeans dispersion of `spheroid` in the combined potential,
+    /// σ²(r) = ∫ ρ g dr / ρ from r out to the truncation radius, tabulated
+    /// on a logarithmic grid
+    fn jeans_table(&self, spheroid: &Spheroid) -> Vec<(f32, f32)> {
+        const STEPS: usize = 256;
+        let inner = spheroid.scale * 1e-3;
+        let ratio = (spheroid.cutoff / inner).powf(1.0 / (STEPS - 1) as f32);
+        let radii: Vec<f32> = (0..STEPS).map(|i| inner * ratio.powi(i as i32)).collect();
+        // f64 keeps the steep inner density from swamping the sum
+        let integrand = |r: f32| spheroid.density_shape(r) as f64 * self.acceleration(r) as f64;
+
+        let mut table = vec![(spheroid.cutoff, 0.0); STEPS];
+        let mut integral = 0.0f64;
+        for i in (0..STEPS - 1).rev() {
+            let (a, b) = (radii[i], radii[i + 1]);
+            integral += 0.5 * (integrand(a) + integrand(b)) * (b - a) as f64;
+            let variance = integral / spheroid.density_shape(a) as f64;
+            table[i] = (a, variance.max(0.0).sqrt() as f32);
+        }
+        table
+    }
+}
+
+/// Truncated Hernquist sphere: one of a composite galaxy's spheroids
+struct Spheroid {
+    scale: f32,
+    cutoff: f32,
+    /// Fraction of the untruncated model's mass inside the cutoff
+    cutoff_fraction: f32,
+    /// Mass of the untruncated model
+    model_mass: f32,
+    /// (radius, dispersion) pairs with increasing radius
+    dispersions: Vec<(f32, f32)>,
+}
+
+impl Spheroid {
+    fn new(scale: f32, cutoff: f32, mass: f32) -> Self {
+        let cutoff_fraction = (cutoff / (cutoff + scale)).powi(2);
+        Spheroid {
+            scale,
+            cutoff,
+            cutoff_fraction,
+            model_mass: mass / cutoff_fraction,
+            dispersions: Vec::new(),
+        }
+    }
+
+    fn enclosed_mass(&self, r: f32) -> f32 {
+        let r = r.min(self.cutoff);
+        self.model_mass * (r / (r + self.scale)).powi(2)
+    }
+
+    /// Density up to a constant factor
+    fn density_shape(&self, r: f32) -> f32 {
+        let r = r.max(1e-6);
+        1.0 / (r * (r + self.scale).powi(3))
+    }
+
+    fn dispersion(&self, r: f32) -> f32 {
+        let index = self.dispersions.partition_point(|&(radius, _)| radius < r);
+        match index {
+            0 => self.dispersions.first().map_or(0.0, |&(_, sigma)| sigma),
+            i if i >= self.dispersions.len() => 0.0,
+            i => {
+                let (r0, s0) = self.dispersions[i - 1];
+                let (r1, s1) = self.dispersions[i];
+                s0 + (s1 - s0) * (r - r0) / (r1 - r0)
+            }
+        }
+    }
+
+    fn sample(&self, count: usize, mass: f32, color: [f32; 4], rng: &mut Rng) -> Vec<Particle> {
+        (0..count)
+            .map(|_| {
+                let root = (rng.uniform() * self.cutoff_fraction).sqrt();
+                let r = self.scale * root / (1.0 - root);
+                let sigma = self.dispersion(r);
+                Particle {
+                    position: Point3::from(rng.direction() * r),
+                    velocity: Vector3::new(rng.gaussian(), rng.gaussian(), rng.gaussian()) * sigma,
+                    mass,
+                    color,
+                    fixed: false,
+                    group: 0,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Plummer sphere of total `mass` and virial radius `virial_radius`, centred
+/// at rest on the origin. With both set to 1 and `gravity` 1 these are
+/// standard N-body (Hénon) units with total energy -1/4, so results can be
+/// compared directly with published benchmarks. Velocities are sampled from
+/// the self-consistent isotropic distribution function following Aarseth,
+/// Hénon & Wielen (1974), and the sphere is truncated at 10 scale radii.
+pub fn generate_plummer_sphere(
+    num_particles: usize,
+    mass: f32,
+    virial_radius: f32,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let scale = 3.0 * std::f32::consts::PI / 16.0 * virial_radius;
+    let particle_mass = mass / num_particles.max(1) as f32;
+    // Enclosed mass fraction r³ / (r² + a²)^(3/2) at r = 10a
+    let cutoff_fraction = 1000.0 / 101.0f32.powf(1.5);
+
+    let mut particles: Vec<Particle> = (0..num_particles)
+        .map(|_| {
+            let fraction = rng.uniform() * cutoff_fraction;
+            let r = scale / (fraction.powf(-2.0 / 3.0) - 1.0).max(1e-6).sqrt();
+            let position = Point3::from(rng.direction() * r);
+
+            // Speed as a fraction q of the local escape speed, by rejection
+            // from g(q) = q² (1 - q²)^3.5, whose maximum is just under 0.1
+            let q = loop {
+                let q = rng.uniform();
+                if 0.1 * rng.uniform() < q * q * (1.0 - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            let escape_speed = (2.0 * gravity * mass).sqrt() * (r * r + scale * scale).powf(-0.25);
+            let velocity = rng.direction() * (q * escape_speed);
+
+            let shade = 0.1 * (rng.uniform() - 0.5);
+            Particle {
+                position,
+                velocity,
+                mass: particle_mass,
+                color: [1.0, 0.9 + shade, 0.7 + shade, 1.0],
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect();
+
+    // Put the centre of mass at rest at the origin
+    let n = particles.len().max(1) as f32;
+    let mean_position = particles
+        .iter()
+        .map(|p| p.position.coords)
+        .sum::<Vector3<f32>>()
+        / n;
+    let mean_velocity = particles.iter().map(|p| p.velocity).sum::<Vector3<f32>>() / n;
+    for particle in &mut particles {
+        particle.position -= mean_position;
+        particle.velocity -= mean_velocity;
+    }
+
+    particles
+}
+
+/// Uniform sphere or cube of unit total mass for cold collapse experiments.
+/// Velocities are isotropic Gaussians scaled to the configured virial ratio
+/// 2T/|W|; at zero the cloud starts at rest and collapses in a free-fall time
+/// of (π/2) √(R³ / 2GM). Particles are colored by starting radius, yellow
+/// at the centre to blue at the edge, so the mixing during violent
+/// relaxation shows.
+pub fn generate_cold_collapse(
+    num_particles: usize,
+    cloud: &ColdCollapseConfig,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let mass = 1.0;
+    let extent = cloud.extent;
+    // Self-gravitational energy of the uniform cloud: 3/5 GM²/R for a
+    // sphere, 0.9411 GM²/a for a cube of side a
+    let binding_energy = match cloud.shape {
+        CollapseShape::Sphere => 0.6 * gravity * mass * mass / extent,
+        CollapseShape::Cube => 0.9411 * gravity * mass * mass / (2.0 * extent),
+    };
+    // T = Q|W|/2 = (3/2) M σ²
+    let sigma = (cloud.temperature.max(0.0) * binding_energy / (3.0 * mass)).sqrt();
+    let particle_mass = mass / num_particles.max(1) as f32;
+    // Farthest a particle can start from the centre
+    let outer = match cloud.shape {
+        CollapseShape::Sphere => extent,
+        CollapseShape::Cube => extent * 3.0f32.sqrt(),
+    };
+
+    let mut particles: Vec<Particle> = (0..num_particles)
+        .map(|_| {
+            let position = match cloud.shape {
+                // Radius from the inverse of the enclosed mass fraction r³/R³
+                CollapseShape::Sphere => rng.direction() * (extent * rng.uniform().cbrt()),
+                CollapseShape::Cube => Vector3::new(
+                    (2.0 * rng.uniform() - 1.0) * extent,
+                    (2.0 * rng.uniform() - 1.0) * extent,
+                    (2.0 * rng.uniform() - 1.0) * extent,
+                ),
+            };
+            let velocity = Vector3::new(rng.gaussian(), rng.gaussian(), rng.gaussian()) * sigma;
+            let depth = (position.norm() / outer).min(1.0);
+            Particle {
+                position: Point3::from(position),
+                velocity,
+                mass: particle_mass,
+                color: [1.0 - 0.4 * depth, 0.9 - 0.1 * depth, 0.5 + 0.5 * depth, 1.0],
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect();
+
+    // No net drift, so the collapse stays in view
+    place(&mut particles, Point3::origin(), Vector3::zeros());
+    particles
+}
+
+/// Star with a thin disk of planetesimals on Keplerian orbits: one particle
+/// is the star, the rest share `disk_mass`. Semi-major axes are spread
+/// evenly between the inner and outer radius (surface density ∝ 1/r),
+/// eccentricities follow a Rayleigh distribution of the configured scale and
+/// inclinations one of half that, with random orbit orientations and
+/// phases. Particles are colored from warm rock at the inner edge to ice at
+/// the outer. Add a planet body in a scenario file to watch resonances and
+/// gaps form.
+pub fn generate_protoplanetary_disk(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    disk: &ProtoplanetaryConfig,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    if num_particles == 0 {
+        return Vec::new();
+    }
+    let mut particles = vec![Particle {
+        position: Point3::origin(),
+        velocity: Vector3::zeros(),
+        mass: disk.star_mass,
+        color: [1.0, 0.95, 0.6, 1.0],
+        fixed: false,
+        group: 0,
+    }];
+
+    let planetesimals = num_particles - 1;
+    let particle_mass = disk.disk_mass / planetesimals.max(1) as f32;
+    let mu = gravity as f64 * (disk.star_mass + particle_mass) as f64;
+    let width = disk.outer_radius - disk.inner_radius;
+    let rayleigh = |scale: f32, rng: &mut Rng| scale * (-2.0 * (1.0 - rng.uniform()).ln()).sqrt();
+
+    for _ in 0..planetesimals {
+        let depth = rng.uniform();
+        let semi_major_axis = disk.inner_radius + width * depth;
+        // Nearly radial orbits would plunge into the star
+        let eccentricity = rayleigh(disk.eccentricity, rng).min(0.9);
+        let inclination = rayleigh(0.5 * disk.eccentricity, rng).to_degrees();
+        let [mean_longitude, perihelion, node] = [0; 3].map(|_| 360.0 * rng.uniform_f64());
+        let (position, velocity) = orbital_state(
+            &[
+                semi_major_axis as f64,
+                eccentricity as f64,
+                inclination as f64,
+                mean_longitude,
+                perihelion,
+                node,
+            ],
+            mu,
+        );
+        particles.push(Particle {
+            position: Point3::from(position.cast::<f32>()),
+            velocity: velocity.cast::<f32>(),
+            mass: particle_mass,
+            color: [0.9 - 0.3 * depth, 0.6 + 0.2 * depth, 0.4 + 0.6 * depth, 1.0],
+            fixed: false,
+            group: 0,
+        });
+    }
+
+    // Barycentre at the centre, so the star wobbles in place instead of
+    // drifting off
+    place(&mut particles, center, bulk_velocity);
+    particles
+}
+
+/// Colors of the binary cluster's single stars, binaries and triples, so
+/// exchanges and ejections can be followed
+const SINGLE_COLOR: [f32; 4] = [1.0, 0.95, 0.8, 1.0];
+const BINARY_COLOR: [f32; 4] = [1.0, 0.6, 0.3, 1.0];
+const TRIPLE_COLOR: [f32; 4] = [0.5, 0.8, 1.0, 1.0];
+
+/// Plummer cluster of equal-mass stars grouped into single stars, binaries
+/// and hierarchical triples (an inner binary with a third star on a wider
+/// orbit around it). Systems are drawn in the configured proportions until
+/// the particles run out; the Plummer sphere sets where each system's centre
+/// of mass sits and moves. Orbits have random orientations and phases.
+/// Tight binaries orbit in a fraction of a time unit, so give them a small
+/// time step or encounter sub-stepping.
+pub fn generate_binary_cluster(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    config: &BinaryConfig,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let mut sizes = Vec::new();
+    let mut remaining = num_particles;
+    while remaining > 0 {
+        let draw = rng.uniform();
+        let size = if draw < config.triple_fraction {
+            3
+        } else if draw < config.triple_fraction + config.binary_fraction {
+            2
+        } else {
+            1
+        }
+        .min(remaining);
+        sizes.push(size);
+        remaining -= size;
+    }
+
+    let star_mass = config.cluster_mass / num_particles.max(1) as f32;
+    let mu = |mass: f32| gravity as f64 * mass as f64;
+    let orbit = |semi_major_axis: f32, eccentricity: f32, mass: f32, rng: &mut Rng| {
+        let inclination = (1.0 - 2.0 * rng.uniform_f64()).acos().to_degrees();
+        let [mean_longitude, perihelion, node] = [0; 3].map(|_| 360.0 * rng.uniform_f64());
+        let (position, velocity) = orbital_state(
+            &[
+                semi_major_axis as f64,
+                eccentricity as f64,
+                inclination,
+                mean_longitude,
+                perihelion,
+                node,
+            ],
+            mu(mass),
+        );
+        (position.cast::<f32>(), velocity.cast::<f32>())
+    };
+    let star = |position: Vector3<f32>, velocity: Vector3<f32>, color| Particle {
+        position: Point3::from(position),
+        velocity,
+        mass: star_mass,
+        color,
+        fixed: false,
+        group: 0,
+    };
+
+    let systems = generate_plummer_sphere(
+        sizes.len(),
+        config.cluster_mass,
+        config.cluster_radius,
+        gravity,
+        rng,
+    );
+    let mut particles = Vec::with_capacity(num_particles);
+    for (system, &size) in systems.iter().zip(&sizes) {
+        let (position, velocity) = (system.position.coords, system.velocity);
+        if size == 1 {
+            particles.push(star(position, velocity, SINGLE_COLOR));
+            continue;
+        }
+
+        let (min, max) = (config.min_semi_major_axis, config.max_semi_major_axis);
+        let semi_major_axis = min * (max / min).powf(rng.uniform());
+        let eccentricity = config.min_eccentricity
+            + (config.max_eccentricity - config.min_eccentricity) * rng.uniform();
+        let (separation, relative_velocity) =
+            orbit(semi_major_axis, eccentricity, 2.0 * star_mass, rng);
+
+        // A triple's inner binary and third star orbit their common centre
+        // of mass, which holds a third of the mass at the outer star
+        let (inner_position, inner_velocity, color) = if size == 3 {
+            let (outer_separation, outer_velocity) = orbit(
+                semi_major_axis * config.outer_ratio,
+                config.outer_eccentricity,
+                3.0 * star_mass,
+                rng,
+            );
+            particles.push(star(
+                position + outer_separation * (2.0 / 3.0),
+                velocity + outer_velocity * (2.0 / 3.0),
+                TRIPLE_COLOR,
+            ));
+            (
+                position - outer_separation / 3.0,
+                velocity - outer_velocity / 3.0,
+                TRIPLE_COLOR,
+            )
+        } else {
+            (position, velocity, BINARY_COLOR)
+        };
+        particles.push(star(
+            inner_position + separation * 0.5,
+            inner_velocity + relative_velocity * 0.5,
+            color,
+        ));
+        particles.push(star(
+            inner_position - separation * 0.5,
+            inner_velocity - relative_velocity * 0.5,
+            color,
+        ));
+    }
+
+    log::info!(
+        "Binary cluster: {} singles, {} binaries, {} triples",
+        sizes.iter().filter(|&&size| size == 1).count(),
+        sizes.iter().filter(|&&size| size == 2).count(),
+        sizes.iter().filter(|&&size| size == 3).count()
+    );
+    place(&mut particles, center, bulk_velocity);
+    particles
+}
+
+/// Globular cluster following a King model, on a circular orbit around an
+/// elliptical host galaxy at the origin. A quarter of the particles form the
+/// cluster; without an explicit tidal radius the cluster is cut off at its
+/// Jacobi radius, where the host's tide balances its own gravity.
+pub fn generate_cluster_orbiting_host(
+    total_particles: usize,
+    cluster: &ClusterConfig,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let cluster_particles = total_particles / 4;
+    let host_particles = total_particles - cluster_particles;
+    let mut particles = generate_elliptical_galaxy(
+        host_particles,
+        Point3::origin(),
+        Vector3::zeros(),
+        2.0,
+        [0.8, 0.8, 1.0, 1.0], // Blue
+        gravity,
+        rng,
+    );
+
+    // The orbit lies outside the host's truncation radius, so all of its mass
+    // is enclosed
+    let host_mass = particles.iter().map(|p| p.mass).sum::<f32>();
+    let cluster_mass = host_mass * cluster.mass_fraction;
+    let distance = cluster.orbit_radius;
+    let tidal_radius = cluster
+        .tidal_radius
+        .unwrap_or_else(|| distance * (cluster_mass / (3.0 * host_mass)).cbrt());
+    let orbital_speed = (gravity * host_mass / distance).sqrt();
+
+    particles.extend(in_group(
+        generate_king_cluster(
+            cluster_particles,
+            Point3::new(distance, 0.0, 0.0),
+            Vector3::new(0.0, orbital_speed, 0.0),
+            &KingParams {
+                mass: cluster_mass,
+                tidal_radius,
+                concentration: cluster.concentration,
+            },
+            gravity,
+            rng,
+        ),
+        1,
+    ));
+    particles
+}
+
+/// Shape of a King model cluster
+#[derive(Debug, Clone, Copy)]
+pub struct KingParams {
+    pub mass: f32,
+    /// Outer truncation radius
+    pub tidal_radius: f32,
+    /// c = log10(tidal radius / core radius)
+    pub concentration: f32,
+}
+
+/// King (1966) model star cluster, sampled from the lowered isothermal
+/// distribution function so the cluster starts in equilibrium
+pub fn generate_king_cluster(
+    num_particles: usize,
+    center: Point3<f32>,
+    bulk_velocity: Vector3<f32>,
+    params: &KingParams,
+    gravity: f32,
+    rng: &mut Rng,
+) -> Vec<Particle> {
+    let KingParams {
+        mass,
+        tidal_radius,
+        concentration,
+    } = *params;
+    let profile = KingProfile::with_concentration(concentration as f64);
+    let total_mass = *profile.mass.last().unwrap_or(&1.0);
+    // Convert from units of the King radius and central dispersion
+    let length = tidal_radius as f64 / profile.tidal_radius();
+    let speed = (gravity as f64 * mass as f64 / (total_mass * length)).sqrt();
+    let particle_mass = mass / num_particles.max(1) as f32;
+
+    (0..num_particles)
+        .map(|_| {
+            let (r, w) = profile.sample_radius(rng.uniform_f64());
+            let position = center + rng.direction() * (r * length) as f32;
+
+            // Speed from p(v) ∝ v² (exp(W - v²/2) - 1) on [0, sqrt(2W)], by
+            // rejection under a bound taken from a coarse scan of the peak
+            let escape = (2.0 * w).sqrt();
+            let density = |v: f64| v * v * ((w - 0.5 * v * v).exp() - 1.0);
+            let peak = (1..=32)
+                .map(|k| density(escape * k as f64 / 33.0))
+                .fold(0.0, f64::max)
+                * 1.1;
+            let v = loop {
+                let v = escape * rng.uniform_f64();
+                if peak <= 0.0 || peak * rng.uniform_f64() < density(v) {
+                    break v;
+                }
+            };
+            let velocity = rng.direction() * (v * speed) as f32;
+
+            let shade = 0.1 * (rng.uniform() - 0.5);
+            Particle {
+                position,
+                velocity: bulk_velocity + velocity,
+                mass: particle_mass,
+                color: [1.0, 0.9 + shade, 0.7 + shade, 1.0],
+                fixed: false,
+                group: 0,
+            }
+        })
+        .collect()
+}
+
+/// King model tabulated from the centre to the tidal radius, in units of the
+/// King radius r0, the central dispersion σ and G = 1: `potential` is the
+/// dimensionless W = (φ(r_t) - φ(r)) / σ² and `mass` the enclosed mass
+struct KingProfile {
+    radius: Vec<f64>,
+    potential: Vec<f64>,
+    mass: Vec<f64>,
+}
+
+impl KingProfile {
+    /// Find the central potential W0 matching `concentration` by bisection;
+    /// c grows monotonically with W0
+    fn with_concentration(concentration: f64) -> Self {
+        let (mut low, mut high) = (0.05, 18.0);
+        for _ in 0..40 {
+            let w0 = 0.5 * (low + high);
+            if Self::solve(w0).tidal_radius().log10() < concentration {
+                low = w0;
+            } else {
+                high = w0;
+            }
+        }
+        Self::solve(0.5 * (low + high))
+    }
+
+    /// Integrate Poisson's equation W'' + 2W'/r = -9 ρ(W)/ρ(W0) outward in
+    /// ln r with RK4 until W reaches zero at the tidal radius
+    fn solve(w0: f64) -> Self {
+        let central_density = king_density(w0);
+        // With s = ln r: W_ss + W_s = -9 r² ρ(W)/ρ0
+        let derivative = |s: f64, w: f64, dw: f64| {
+            let r2 = (2.0 * s).exp();
+            (dw, -dw - 9.0 * r2 * king_density(w) / central_density)
+        };
+
+        let step = 0.002;
+        let mut s = (1e-3f64).ln();
+        // Series solution near the centre: W ≈ W0 - 1.5 r²
+        let r2 = (2.0 * s).exp();
+        let (mut w, mut dw) = (w0 - 1.5 * r2, -3.0 * r2);
+        let mut profile = KingProfile {
+            radius: vec![0.0],
+            potential: vec![w0],
+            mass: vec![0.0],
+        };
+
+        while w > 0.0 && s < 12.0 {
+            let (k1w, k1d) = derivative(s, w, dw);
+            let (k2w, k2d) =
+                derivative(s + 0.5 * step, w + 0.5 * step * k1w, dw + 0.5 * step * k1d);
+            let (k3w, k3d) =
+                derivative(s + 0.5 * step, w + 0.5 * step * k2w, dw + 0.5 * step * k2d);
+            let (k4w, k4d) = derivative(s + step, w + step * k3w, dw + step * k3d);
+            let next_w = w + step / 6.0 * (k1w + 2.0 * k2w + 2.0 * k3w + k4w);
+            let next_dw = dw + step / 6.0 * (k1d + 2.0 * k2d + 2.0 * k3d + k4d);
+
+            if next_w <= 0.0 {
+                // Interpolate the zero crossing for the tidal radius
+                let t = w / (w - next_w);
+                let r = (s + t * step).exp();
+                let slope = dw + t * (next_dw - dw);
+                profile.push(r, 0.0, -r * slope);
+                break;
+            }
+
+            s += step;
+            w = next_w;
+            dw = next_dw;
+            // Enclosed mass M = -r² dW/dr = -r dW/ds (in units σ² r0 / G)
+            profile.push(s.exp(), w, -s.exp() * dw);
+        }
+        profile
+    }
+
+    fn push(&mut self, radius: f64, potential: f64, mass: f64) {
+        self.radius.push(radius);
+        self.potential.push(potential);
+        self.mass.push(mass);
+    }
+
+    fn tidal_radius(&self) -> f64 {
+        *self.radius.last().unwrap_or(&1.0)
+    }
+
+    /// Radius and potential enclosing fraction `u` of the total mass
+    fn sample_radius(&self, u: f64) -> (f64, f64) {
+        let target = u * self.mass.last().unwrap_or(&0.0);
+        let upper = self
+            .mass
+            .partition_point(|&m| m < target)
+            .clamp(1, self.mass.len() - 1);
+        let lower = upper - 1;
+        let span = self.mass[upper] - self.mass[lower];
+        let t = if span > 0.0 {
+            (target - self.mass[lower]) / span
+        } else {
+            0.0
+        };
+        (
+            self.radius[lower] + t * (self.radius[upper] - self.radius[lower]),
+            (self.potential[lower] + t * (self.potential[upper] - self.potential[lower])).max(0.0),
+        )
+    }
+}
+
+/// King density at dimensionless potential W, up to a constant:
+/// e^W erf(√W) - √(4W/π) (1 + 2W/3). Summed as the series
+/// (2/√π) Σ_{n≥2} 2ⁿ W^(n+½) / (2n+1)!!, which avoids both erf and the
+/// cancellation between the two terms near the tidal radius.
+fn king_density(w: f64) -> f64 {
+    if w <= 0.0 {
+        return 0.0;
+    }
+    // n = 2 term: 4 W^2.5 / 15
+    let mut term = 4.0 * w * w * w.sqrt() / 15.0;
+    let mut sum = term;
+    let mut n = 2.0;
+    while term > sum * 1e-15 {
+        n += 1.0;
+        term *= 2.0 * w / (2.0 * n + 1.0);
+        sum += term;
+    }
+    sum * 2.0 / std::f64::consts::PI.sqrt()
+}
+
+/// One-dimensional velocity dispersion of an isotropic Hernquist sphere
+/// (Hernquist 1990, eq. 10), for `gm` = G times the total mass
+fn hernquist_dispersion(r: f32, scale: f32, gm: f32) -> f32 {
+    // Evaluated in f64: the two terms cancel heavily at small radii
+    let x = (r / scale).max(1e-4) as f64;
+    let variance = (gm as f64 / (12.0 * scale as f64))
+        * (12.0 * x * (x + 1.0).powi(3) * ((x + 1.0) / x).ln()
+            - x / (x + 1.0) * (25.0 + 52.0 * x + 42.0 * x * x + 12.0 * x * x * x));
+    variance.max(0.0).sqrt() as f32
+}
@@ -0,0 +1,118 @@
+// Time-lapse gallery: downsampled snapshots of the live run taken every so
+// much simulation time, for a quick visual history at `/api/gallery`
+use n_body_shared::{Particle, SimulationState};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::config::GalleryConfig;
+
+/// What `GET /api/gallery` lists about a snapshot
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct GalleryEntry {
+    pub id: u64,
+    pub sim_time: f32,
+    pub frame_number: u64,
+    /// Particles in the run when the snapshot was taken
+    pub particle_count: usize,
+    /// Particles kept in the snapshot
+    pub sampled: usize,
+}
+
+struct Snapshot {
+    entry: GalleryEntry,
+    particles: Vec<Particle>,
+}
+
+pub struct Gallery {
+    config: GalleryConfig,
+    /// Current spacing, doubled each time the gallery is thinned out
+    interval: f32,
+    next_capture: f32,
+    next_id: u64,
+    snapshots: Vec<Snapshot>,
+}
+
+impl Gallery {
+    pub fn new(config: &GalleryConfig) -> Self {
+        if config.enabled && !(config.interval.is_finite() && config.interval > 0.0) {
+            log::error!(
+                "Gallery interval must be positive, got {}; gallery disabled",
+                config.interval
+            );
+        }
+        Gallery {
+            config: config.clone(),
+            interval: config.interval,
+            next_capture: 0.0,
+            next_id: 0,
+            snapshots: Vec::new(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.enabled
+            && self.config.interval.is_finite()
+            && self.config.interval > 0.0
+            && self.config.max_entries > 0
+    }
+
+    /// Start a new history, e.g. when the run is reset. Ids keep counting
+    /// so a client never mistakes a new snapshot for an old one.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.interval = self.config.interval;
+        self.next_capture = 0.0;
+    }
+
+    /// Take a snapshot if one is due at `sim_time`
+    pub fn observe(&mut self, particles: &[Particle], sim_time: f32, frame_number: u64) {
+        if !self.enabled() || sim_time < self.next_capture {
+            return;
+        }
+
+        // Every n-th particle keeps the overall shape of the run
+        let particle_count = particles.len();
+        let stride = particles
+            .len()
+            .div_ceil(self.config.max_particles.max(1))
+            .max(1);
+        let particles: Vec<Particle> = particles.iter().step_by(stride).cloned().collect();
+        self.snapshots.push(Snapshot {
+            entry: GalleryEntry {
+                id: self.next_id,
+                sim_time,
+                frame_number,
+                particle_count,
+                sampled: particles.len(),
+            },
+            particles,
+        });
+        self.next_id += 1;
+
+        if self.snapshots.len() > self.config.max_entries {
+            // Keeps the first snapshot of the run and every second after it
+            let mut index = 0;
+            self.snapshots.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            self.interval *= 2.0;
+        }
+        let last = self.snapshots.last().map_or(sim_time, |s| s.entry.sim_time);
+        self.next_capture = last + self.interval;
+    }
+
+    pub fn entries(&self) -> Vec<GalleryEntry> {
+        self.snapshots.iter().map(|s| s.entry.clone()).collect()
+    }
+
+    /// A snapshot as a frozen state frame
+    pub fn state(&self, id: u64) -> Option<SimulationState> {
+        let snapshot = self.snapshots.iter().find(|s| s.entry.id == id)?;
+        Some(SimulationState::new(
+            &snapshot.particles,
+            snapshot.entry.sim_time,
+            snapshot.entry.frame_number,
+        ))
+    }
+}
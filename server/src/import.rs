@@ -0,0 +1,352 @@
+// Initial conditions imported from other codes: CSV tables and Gadget-2
+// snapshots, loaded from the scenarios directory by name like scenario files
+use n_body_shared::Particle;
+use nalgebra::{Point3, Vector3};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::scenarios::validate_name;
+
+/// Color of CSV particles without color columns
+const DEFAULT_COLOR: [f32; 4] = [0.8, 0.8, 1.0, 1.0];
+
+/// Gadget particle types: gas, halo, disk, bulge, stars and boundary
+const GADGET_COLORS: [[f32; 4]; 6] = [
+    [0.4, 0.6, 1.0, 1.0],
+    [0.5, 0.5, 0.6, 0.6],
+    [0.8, 0.8, 1.0, 1.0],
+    [1.0, 0.85, 0.6, 1.0],
+    [1.0, 1.0, 0.8, 1.0],
+    [0.8, 0.4, 0.4, 1.0],
+];
+
+/// Supported file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Text table with a header row naming the columns
+    Csv,
+    /// Gadget-2 binary snapshot, format 1 or 2, either byte order
+    Gadget,
+}
+
+impl ImportFormat {
+    /// Extension the format is stored under in the scenarios directory
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Gadget => "gadget",
+        }
+    }
+
+    pub fn parse(self, data: &[u8]) -> Result<Vec<Particle>, String> {
+        let particles = match self {
+            ImportFormat::Csv => {
+                let text = std::str::from_utf8(data)
+                    .map_err(|e| format!("CSV file is not valid UTF-8: {}", e))?;
+                parse_csv(text)?
+            }
+            ImportFormat::Gadget => parse_gadget(data)?,
+        };
+        if particles.is_empty() {
+            return Err("File contains no particles".to_string());
+        }
+        Ok(particles)
+    }
+}
+
+/// Where an upload of `name` in `format` is stored
+pub fn path_for(dir: &Path, name: &str, format: ImportFormat) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    Ok(dir.join(format!("{}.{}", name, format.extension())))
+}
+
+/// Load `<dir>/<name>.csv`, or a Gadget snapshot `<dir>/<name>.gadget` or
+/// `<dir>/<name>` as Gadget itself names them (e.g. `snapshot_000`)
+pub fn load(dir: &Path, name: &str) -> Result<Vec<Particle>, String> {
+    validate_name(name)?;
+    let candidates = [
+        (dir.join(format!("{}.csv", name)), ImportFormat::Csv),
+        (dir.join(format!("{}.gadget", name)), ImportFormat::Gadget),
+        (dir.join(name), ImportFormat::Gadget),
+    ];
+    let (path, format) = candidates
+        .iter()
+        .find(|(path, _)| path.is_file())
+        .ok_or_else(|| {
+            format!(
+                "No file {}.csv, {}.gadget or {} to import in {}",
+                name,
+                name,
+                name,
+                dir.display()
+            )
+        })?;
+    let data =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    format
+        .parse(&data)
+        .map_err(|e| format!("Failed to import {}: {}", path.display(), e))
+}
+
+/// CSV with a header row. `x`, `y`, `z` and `mass` are required; `vx`, `vy`,
+/// `vz` default to 0, `r`, `g`, `b`, `a` to a pale blue and `fixed` (0 or 1)
+/// to free. Column names are case-insensitive, blank lines and lines
+/// starting with `#` are skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<Particle>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+    let (_, header) = lines.next().ok_or("CSV file is empty")?;
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let required = |name: &str| column(name).ok_or(format!("CSV header has no '{}' column", name));
+    let position = [required("x")?, required("y")?, required("z")?];
+    let mass = required("mass")?;
+    let velocity = [column("vx"), column("vy"), column("vz")];
+    let color = [column("r"), column("g"), column("b"), column("a")];
+    let fixed = column("fixed");
+
+    lines
+        .map(|(index, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let value = |column: usize| -> Result<f32, String> {
+                let field = fields.get(column).ok_or(format!(
+                    "line {}: expected {} columns, found {}",
+                    index + 1,
+                    columns.len(),
+                    fields.len()
+                ))?;
+                let value: f32 = field
+                    .parse()
+                    .map_err(|_| format!("line {}: '{}' is not a number", index + 1, field))?;
+                if value.is_finite() {
+                    Ok(value)
+                } else {
+                    Err(format!("line {}: '{}' is not finite", index + 1, field))
+                }
+            };
+            let optional = |column: Option<usize>, default: f32| column.map_or(Ok(default), value);
+
+            let mut rgba = DEFAULT_COLOR;
+            for (channel, column) in rgba.iter_mut().zip(color) {
+                *channel = optional(column, *channel)?;
+            }
+            let particle = Particle {
+                position: Point3::new(
+                    value(position[0])?,
+                    value(position[1])?,
+                    value(position[2])?,
+                ),
+                velocity: Vector3::new(
+                    optional(velocity[0], 0.0)?,
+                    optional(velocity[1], 0.0)?,
+                    optional(velocity[2], 0.0)?,
+                ),
+                mass: value(mass)?,
+                color: rgba,
+                fixed: optional(fixed, 0.0)? != 0.0,
+                group: 0,
+            };
+            if particle.mass <= 0.0 {
+                return Err(format!("line {}: mass must be positive", index + 1));
+            }
+            Ok(particle)
+        })
+        .collect()
+}
+
+/// Reads Fortran-style records, each framed by its length before and after
+struct Records<'a> {
+    data: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+    /// Format 2 puts a small record naming each block in front of it
+    labelled: bool,
+}
+
+impl<'a> Records<'a> {
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn raw(&mut self) -> Result<&'a [u8], String> {
+        let length = self
+            .u32_at(self.offset)
+            .ok_or("Snapshot ends in the middle of a block")? as usize;
+        let start = self.offset + 4;
+        let end = start + length;
+        if self.u32_at(end) != Some(length as u32) {
+            return Err(format!(
+                "Corrupt snapshot: block at byte {} is not framed by its length",
+                self.offset
+            ));
+        }
+        self.offset = end + 4;
+        Ok(&self.data[start..end])
+    }
+
+    /// The next data block, skipping its label in format 2
+    fn next(&mut self) -> Result<&'a [u8], String> {
+        if self.labelled {
+            self.raw()?;
+        }
+        self.raw()
+    }
+
+    fn i32s(&self, block: &[u8], offset: usize, count: usize) -> Vec<i32> {
+        (0..count)
+            .map(|i| {
+                let bytes: [u8; 4] = block[offset + 4 * i..offset + 4 * i + 4]
+                    .try_into()
+                    .expect("four bytes");
+                if self.big_endian {
+                    i32::from_be_bytes(bytes)
+                } else {
+                    i32::from_le_bytes(bytes)
+                }
+            })
+            .collect()
+    }
+
+    /// Floats of either precision, told apart by the block size
+    fn floats(&self, block: &[u8], count: usize, what: &str) -> Result<Vec<f32>, String> {
+        let read = |chunk: &[u8]| -> f32 {
+            match (chunk.len(), self.big_endian) {
+                (4, false) => f32::from_le_bytes(chunk.try_into().expect("four bytes")),
+                (4, true) => f32::from_be_bytes(chunk.try_into().expect("four bytes")),
+                (_, false) => f64::from_le_bytes(chunk.try_into().expect("eight bytes")) as f32,
+                (_, true) => f64::from_be_bytes(chunk.try_into().expect("eight bytes")) as f32,
+            }
+        };
+        match block.len() {
+            length if length == count * 4 => Ok(block.chunks_exact(4).map(read).collect()),
+            length if length == count * 8 => Ok(block.chunks_exact(8).map(read).collect()),
+            length => Err(format!(
+                "{} block holds {} bytes, expected {} values",
+                what, length, count
+            )),
+        }
+    }
+}
+
+/// Gadget-2 snapshot (one file of a set): the header, then positions,
+/// velocities and IDs of every particle ordered by type, then masses for
+/// the types without a fixed mass in the header. Velocities are taken as
+/// stored, so those of cosmological runs still carry Gadget's √a factor.
+pub fn parse_gadget(data: &[u8]) -> Result<Vec<Particle>, String> {
+    let first = |big_endian| {
+        let bytes: [u8; 4] = data.get(0..4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let (big_endian, labelled) = match (first(false), first(true)) {
+        (Some(256), _) => (false, false),
+        (_, Some(256)) => (true, false),
+        (Some(8), _) => (false, true),
+        (_, Some(8)) => (true, true),
+        _ => return Err("Not a Gadget-2 snapshot: no 256-byte header".to_string()),
+    };
+    let mut records = Records {
+        data,
+        offset: 0,
+        big_endian,
+        labelled,
+    };
+
+    let header = records.next()?;
+    if header.len() != 256 {
+        return Err(format!("Gadget header is {} bytes, not 256", header.len()));
+    }
+    let counts: Vec<usize> = records
+        .i32s(header, 0, 6)
+        .into_iter()
+        .map(|n| n.max(0) as usize)
+        .collect();
+    let type_masses = records.floats(&header[24..72], 6, "Header mass")?;
+    let num_files = records.i32s(header, 124, 1)[0];
+    if num_files > 1 {
+        log::warn!(
+            "Snapshot is one of {} files; importing only its own particles",
+            num_files
+        );
+    }
+
+    let total: usize = counts.iter().sum();
+    let block = records.next()?;
+    let positions = records.floats(block, 3 * total, "Position")?;
+    let block = records.next()?;
+    let velocities = records.floats(block, 3 * total, "Velocity")?;
+    // IDs carry nothing the simulation needs
+    records.next()?;
+    let listed: usize = counts
+        .iter()
+        .zip(&type_masses)
+        .filter(|&(_, &mass)| mass == 0.0)
+        .map(|(&count, _)| count)
+        .sum();
+    let mut listed_masses = if listed > 0 {
+        let block = records.next()?;
+        records.floats(block, listed, "Mass")?
+    } else {
+        Vec::new()
+    }
+    .into_iter();
+
+    let mut particles = Vec::with_capacity(total);
+    let mut index = 0;
+    for (kind, (&count, &type_mass)) in counts.iter().zip(&type_masses).enumerate() {
+        for _ in 0..count {
+            let mass = if type_mass == 0.0 {
+                listed_masses.next().ok_or("Mass block is too short")?
+            } else {
+                type_mass
+            };
+            let at = |values: &[f32]| {
+                Vector3::new(
+                    values[3 * index],
+                    values[3 * index + 1],
+                    values[3 * index + 2],
+                )
+            };
+            particles.push(Particle {
+                position: Point3::from(at(&positions)),
+                velocity: at(&velocities),
+                mass,
+                color: GADGET_COLORS[kind],
+                fixed: false,
+                group: 0,
+            });
+            index += 1;
+        }
+    }
+    if let Some(particle) = particles.iter().find(|p| {
+        p.mass.is_nan()
+            || p.mass <= 0.0
+            || !p
+                .position
+                .iter()
+                .chain(p.velocity.iter())
+                .all(|c| c.is_finite())
+    }) {
+        return Err(format!(
+            "Snapshot has an invalid particle: mass {} at {:?}",
+            particle.mass, particle.position
+        ));
+    }
+    Ok(particles)
+}
@@ -0,0 +1,575 @@
+// Batch simulations submitted over HTTP and run headless by the scheduler,
+// on threads kept apart from the live simulation. Results are written to a
+// directory per job.
+use log::{info, warn};
+use n_body_shared::{ForceSolver, Integrator, Scenario, MAX_PARTICLES};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{JobsConfig, SimulationConfig};
+use crate::drift::{Conserved, DriftReport, DriftThresholds};
+use crate::import;
+use crate::physics::total_energy;
+use crate::scenarios;
+use crate::scheduler::{CpuPartition, Scheduler};
+use crate::simulation::Simulation;
+use crate::webhooks::{EventKind, Notifier, WebhookEvent};
+
+/// Body of `POST /api/jobs`: overrides on top of the server's simulation
+/// defaults, plus how long to run and what to record
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct JobRequest {
+    #[serde(default)]
+    pub scenario: Option<Scenario>,
+    #[serde(default)]
+    pub particle_count: Option<usize>,
+    #[serde(default)]
+    pub time_step: Option<f32>,
+    #[serde(default)]
+    pub gravity_strength: Option<f32>,
+    #[serde(default)]
+    pub solver: Option<ForceSolver>,
+    #[serde(default)]
+    pub theta: Option<f32>,
+    #[serde(default)]
+    pub integrator: Option<Integrator>,
+    /// Simulation time to run for
+    pub duration: f32,
+    /// Simulation time between full-state snapshots; the final state is
+    /// always saved
+    #[serde(default)]
+    pub snapshot_interval: Option<f32>,
+    /// Simulation time between rows of the data log, by default a hundredth
+    /// of the duration
+    #[serde(default)]
+    pub log_interval: Option<f32>,
+    /// Simulation time between drift reports in `manifest.json`, by default
+    /// a tenth of the duration
+    #[serde(default)]
+    pub drift_interval: Option<f32>,
+    /// Drift of conserved quantities that raises a warning
+    #[serde(default)]
+    pub drift_thresholds: DriftThresholds,
+    /// Fail the job when a drift threshold is exceeded instead of only
+    /// warning
+    #[serde(default)]
+    pub abort_on_drift: bool,
+    /// Queued jobs with a higher priority start first
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress report returned by `GET /api/jobs/{id}`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct JobStatus {
+    pub id: u64,
+    pub state: JobState,
+    pub priority: i32,
+    /// Fraction of the requested duration simulated so far
+    pub progress: f32,
+    pub sim_time: f32,
+    pub frame_number: u64,
+    /// Result files, downloadable from `/api/jobs/{id}/files/{name}`
+    pub files: Vec<String>,
+    pub error: Option<String>,
+    /// Drift thresholds exceeded so far
+    pub warnings: Vec<String>,
+    /// Unix timestamps in seconds
+    pub submitted_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+struct Job {
+    status: JobStatus,
+    cancel: Arc<AtomicBool>,
+}
+
+/// The queue of submitted jobs and the worker that runs them
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<u64, Job>>>,
+    scheduler: Scheduler<JobRequest>,
+    next_id: AtomicU64,
+    config: JobsConfig,
+    defaults: SimulationConfig,
+}
+
+impl JobQueue {
+    /// Start the scheduler's workers. Jobs run with `defaults` as their base
+    /// simulation configuration and report drift warnings to `notifier`.
+    pub fn new(config: &JobsConfig, defaults: &SimulationConfig, notifier: Notifier) -> Self {
+        let jobs: Arc<Mutex<HashMap<u64, Job>>> = Arc::default();
+
+        let worker_jobs = jobs.clone();
+        let worker_defaults = defaults.clone();
+        let dir = PathBuf::from(&config.dir);
+        let partition = CpuPartition::new(config, num_cpus::get());
+        let scheduler = Scheduler::new(
+            partition.job_threads,
+            config.concurrency,
+            move |id, request: JobRequest| {
+                run_job(
+                    id,
+                    &request,
+                    &worker_defaults,
+                    &dir,
+                    &worker_jobs,
+                    &notifier,
+                );
+            },
+        );
+
+        JobQueue {
+            jobs,
+            scheduler,
+            next_id: AtomicU64::new(1),
+            config: config.clone(),
+            defaults: defaults.clone(),
+        }
+    }
+
+    /// Check a request and queue it, returning the new job's id
+    pub fn submit(&self, request: JobRequest) -> Result<u64, String> {
+        if !request.duration.is_finite() || request.duration <= 0.0 {
+            return Err(format!(
+                "Duration must be positive, got {}",
+                request.duration
+            ));
+        }
+        let time_step = request.time_step.unwrap_or(0.01);
+        if !time_step.is_finite() || time_step <= 0.0 {
+            return Err(format!("Time step must be positive, got {}", time_step));
+        }
+        let steps = (request.duration / time_step).ceil() as u64;
+        if steps > self.config.max_steps {
+            return Err(format!(
+                "Job needs {} steps, more than the limit of {}",
+                steps, self.config.max_steps
+            ));
+        }
+        for (name, interval) in [
+            ("Snapshot interval", request.snapshot_interval),
+            ("Log interval", request.log_interval),
+            ("Drift interval", request.drift_interval),
+        ] {
+            if let Some(interval) = interval {
+                if !interval.is_finite() || interval <= 0.0 {
+                    return Err(format!("{} must be positive, got {}", name, interval));
+                }
+            }
+        }
+        let thresholds = request.drift_thresholds;
+        for (name, threshold) in [
+            ("Energy", thresholds.energy),
+            ("Momentum", thresholds.momentum),
+            ("Angular momentum", thresholds.angular_momentum),
+        ] {
+            if let Some(threshold) = threshold {
+                if !threshold.is_finite() || threshold < 0.0 {
+                    return Err(format!(
+                        "{} drift threshold must not be negative, got {}",
+                        name, threshold
+                    ));
+                }
+            }
+        }
+        match &request.scenario {
+            Some(Scenario::File(name)) => {
+                scenarios::load(Path::new(&self.defaults.scenarios_dir), name)?;
+            }
+            Some(Scenario::Import(name)) => {
+                import::load(Path::new(&self.defaults.scenarios_dir), name)?;
+            }
+            _ => {}
+        }
+        if let Some(count) = request.particle_count {
+            let limit = self
+                .defaults
+                .max_particles
+                .map_or(MAX_PARTICLES, |max| max.min(MAX_PARTICLES));
+            if count > limit {
+                return Err(format!(
+                    "Particle count {} exceeds maximum of {}",
+                    count, limit
+                ));
+            }
+        }
+
+        let mut jobs = self
+            .jobs
+            .lock()
+            .map_err(|_| "Job list lock poisoned".to_string())?;
+        let pending = jobs
+            .values()
+            .filter(|job| matches!(job.status.state, JobState::Queued | JobState::Running))
+            .count();
+        if pending >= self.config.max_pending {
+            return Err(format!(
+                "{} jobs are already queued or running; try again later",
+                pending
+            ));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        jobs.insert(
+            id,
+            Job {
+                status: JobStatus {
+                    id,
+                    state: JobState::Queued,
+                    priority: request.priority,
+                    progress: 0.0,
+                    sim_time: 0.0,
+                    frame_number: 0,
+                    files: Vec::new(),
+                    error: None,
+                    warnings: Vec::new(),
+                    submitted_at: unix_now(),
+                    started_at: None,
+                    finished_at: None,
+                },
+                cancel: Arc::new(AtomicBool::new(false)),
+            },
+        );
+        drop(jobs);
+
+        let priority = request.priority;
+        self.scheduler.push(id, priority, request)?;
+        info!("Queued job {} with priority {}", id, priority);
+        Ok(id)
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().ok()?;
+        jobs.get(&id).map(|job| job.status.clone())
+    }
+
+    /// Every job, oldest first
+    pub fn list(&self) -> Vec<JobStatus> {
+        let Ok(jobs) = self.jobs.lock() else {
+            return Vec::new();
+        };
+        let mut statuses: Vec<JobStatus> = jobs.values().map(|job| job.status.clone()).collect();
+        statuses.sort_by_key(|status| status.id);
+        statuses
+    }
+
+    /// Stop a queued or running job; it keeps the files written so far
+    pub fn cancel(&self, id: u64) -> Result<(), String> {
+        let jobs = self
+            .jobs
+            .lock()
+            .map_err(|_| "Job list lock poisoned".to_string())?;
+        let job = jobs.get(&id).ok_or_else(|| format!("No job {}", id))?;
+        job.cancel.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Path of a finished result file, if `name` is one of the job's files
+    pub fn file_path(&self, id: u64, name: &str) -> Option<PathBuf> {
+        let status = self.status(id)?;
+        status
+            .files
+            .iter()
+            .any(|file| file == name)
+            .then(|| job_dir(Path::new(&self.config.dir), id).join(name))
+    }
+}
+
+fn job_dir(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("job-{}", id))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Apply `update` to the job's status
+fn update_status(jobs: &Mutex<HashMap<u64, Job>>, id: u64, update: impl FnOnce(&mut JobStatus)) {
+    if let Ok(mut jobs) = jobs.lock() {
+        if let Some(job) = jobs.get_mut(&id) {
+            update(&mut job.status);
+        }
+    }
+}
+
+fn run_job(
+    id: u64,
+    request: &JobRequest,
+    defaults: &SimulationConfig,
+    dir: &Path,
+    jobs: &Mutex<HashMap<u64, Job>>,
+    notifier: &Notifier,
+) {
+    let cancel = match jobs.lock() {
+        Ok(jobs) => match jobs.get(&id) {
+            Some(job) => job.cancel.clone(),
+            None => return,
+        },
+        Err(_) => return,
+    };
+    if cancel.load(Ordering::Relaxed) {
+        update_status(jobs, id, |status| {
+            status.state = JobState::Cancelled;
+            status.finished_at = Some(unix_now());
+        });
+        return;
+    }
+
+    update_status(jobs, id, |status| {
+        status.state = JobState::Running;
+        status.started_at = Some(unix_now());
+    });
+    info!("Starting job {}", id);
+
+    let result = simulate(
+        id,
+        request,
+        defaults,
+        &job_dir(dir, id),
+        jobs,
+        &cancel,
+        notifier,
+    );
+    let cancelled = cancel.load(Ordering::Relaxed);
+    if let Err(e) = &result {
+        warn!("Job {} failed: {}", id, e);
+    } else {
+        info!(
+            "Job {} {}",
+            id,
+            if cancelled { "cancelled" } else { "done" }
+        );
+    }
+    update_status(jobs, id, |status| {
+        status.state = match (&result, cancelled) {
+            (Err(_), _) => JobState::Failed,
+            (Ok(()), true) => JobState::Cancelled,
+            (Ok(()), false) => JobState::Completed,
+        };
+        status.error = result.err();
+        status.finished_at = Some(unix_now());
+    });
+}
+
+/// `manifest.json`: what a job ran and how well it kept its conserved
+/// quantities, rewritten after each drift report
+#[derive(Serialize)]
+struct RunManifest {
+    job: u64,
+    duration: f32,
+    drift_interval: f32,
+    drift_thresholds: DriftThresholds,
+    initial: Conserved,
+    drift: Vec<DriftReport>,
+}
+
+/// Run the simulation, writing `log.csv` as it goes, snapshots at each
+/// snapshot interval and drift reports to the manifest
+fn simulate(
+    id: u64,
+    request: &JobRequest,
+    defaults: &SimulationConfig,
+    dir: &Path,
+    jobs: &Mutex<HashMap<u64, Job>>,
+    cancel: &AtomicBool,
+    notifier: &Notifier,
+) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let mut sim_config = defaults.clone();
+    if let Some(scenario) = &request.scenario {
+        sim_config.scenario = scenario.clone();
+    }
+    if let Some(count) = request.particle_count {
+        sim_config.default_particles = count;
+    }
+    if let Some(gravity) = request.gravity_strength {
+        sim_config.gravity_strength = gravity;
+    }
+    if let Some(solver) = request.solver {
+        sim_config.solver = solver;
+    }
+    if let Some(theta) = request.theta {
+        sim_config.theta = theta;
+    }
+    if let Some(integrator) = request.integrator {
+        sim_config.integrator = integrator;
+    }
+    // Jobs write their own snapshots
+    sim_config.gallery.enabled = false;
+    let mut simulation = Simulation::new(&sim_config, false);
+
+    // Runs the same checks as a client's config update
+    let mut config = simulation.get_config().clone();
+    if let Some(time_step) = request.time_step {
+        config.time_step = time_step;
+    }
+    simulation.update_config(config)?;
+
+    let config_json = serde_json::to_string_pretty(simulation.get_config())
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    write_file(dir, "config.json", &config_json, jobs, id)?;
+
+    let drift_interval = request.drift_interval.unwrap_or(request.duration / 10.0);
+    let mut manifest = RunManifest {
+        job: id,
+        duration: request.duration,
+        drift_interval,
+        drift_thresholds: request.drift_thresholds,
+        initial: Conserved::measure(&simulation),
+        drift: Vec::new(),
+    };
+    write_manifest(dir, &manifest, jobs, id)?;
+    let mut next_drift = drift_interval;
+    // Each quantity is warned about once per job
+    let mut warned: Vec<&str> = Vec::new();
+    let mut aborted = None;
+
+    let log_interval = request.log_interval.unwrap_or(request.duration / 100.0);
+    let mut log = String::from("frame,sim_time,total_energy,computation_time_ms,substeps\n");
+    let mut next_log = 0.0;
+    let mut next_snapshot = request.snapshot_interval.map(|_| 0.0);
+    let mut snapshots = 0;
+
+    loop {
+        let sim_time = simulation.sim_time();
+        let finished = sim_time >= request.duration || cancel.load(Ordering::Relaxed);
+
+        if sim_time >= next_drift || finished {
+            let report =
+                DriftReport::new(&manifest.initial, &simulation, &request.drift_thresholds);
+            for exceeded in &report.exceeded {
+                if warned.contains(&exceeded.quantity) {
+                    continue;
+                }
+                warned.push(exceeded.quantity);
+                let message = format!(
+                    "Job {}: {} drifted by {:.3e} (threshold {:.3e}) at t = {:.2}",
+                    id, exceeded.quantity, exceeded.drift, exceeded.threshold, sim_time
+                );
+                warn!("{}", message);
+                notifier.notify(WebhookEvent {
+                    event: EventKind::DriftExceeded,
+                    message: message.clone(),
+                    frame_number: report.frame_number,
+                    sim_time: Some(sim_time),
+                });
+                if request.abort_on_drift && aborted.is_none() {
+                    aborted = Some(message.clone());
+                }
+                update_status(jobs, id, |status| status.warnings.push(message));
+            }
+            manifest.drift.push(report);
+            write_manifest(dir, &manifest, jobs, id)?;
+            next_drift += drift_interval;
+        }
+        let done = finished || aborted.is_some();
+
+        if sim_time >= next_log || done {
+            let stats = simulation.stats();
+            // Energy is not defined for a periodic box; leave the column empty
+            let energy = match simulation.get_config().periodic_box {
+                Some(_) => String::new(),
+                None => total_energy(
+                    simulation.particles(),
+                    simulation.get_config().gravity_strength,
+                    simulation.softening(),
+                )
+                .to_string(),
+            };
+            let _ = writeln!(
+                log,
+                "{},{},{},{:.3},{}",
+                stats.frame_number,
+                stats.sim_time,
+                energy,
+                stats.computation_time_ms,
+                stats.substeps
+            );
+            next_log += log_interval;
+        }
+
+        let snapshot_due = next_snapshot.is_some_and(|at| sim_time >= at);
+        if snapshot_due || done {
+            let name = format!("snapshot-{:04}.json", snapshots);
+            let json = serde_json::to_string(&simulation.state())
+                .map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+            write_file(dir, &name, &json, jobs, id)?;
+            snapshots += 1;
+            if let (Some(at), Some(interval)) = (next_snapshot.as_mut(), request.snapshot_interval)
+            {
+                *at += interval;
+            }
+        }
+
+        if done {
+            break;
+        }
+
+        simulation.step();
+        let (sim_time, frame_number) = (simulation.sim_time(), simulation.frame_number());
+        update_status(jobs, id, |status| {
+            status.sim_time = sim_time;
+            status.frame_number = frame_number;
+            status.progress = (sim_time / request.duration).min(1.0);
+        });
+    }
+
+    write_file(dir, "log.csv", &log, jobs, id)?;
+    match aborted {
+        Some(message) => Err(format!("Aborted: {}", message)),
+        None => Ok(()),
+    }
+}
+
+fn write_manifest(
+    dir: &Path,
+    manifest: &RunManifest,
+    jobs: &Mutex<HashMap<u64, Job>>,
+    id: u64,
+) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    write_file(dir, "manifest.json", &json, jobs, id)
+}
+
+/// Write a result file and list it in the job's status
+fn write_file(
+    dir: &Path,
+    name: &str,
+    content: &str,
+    jobs: &Mutex<HashMap<u64, Job>>,
+    id: u64,
+) -> Result<(), String> {
+    let path = dir.join(name);
+    std::fs::write(&path, content)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    // The manifest is rewritten as the run goes
+    update_status(jobs, id, |status| {
+        if !status.files.iter().any(|file| file == name) {
+            status.files.push(name.to_string());
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,530 @@
+//! The n-body simulation server as a library. [`run_server`] runs the
+//! complete standalone server; [`AppState`] and [`configure`] let another
+//! actix application host the simulation under its own routes, and
+//! [`SimulationService`] drives a simulation with no networking at all.
+
+use actix_cors::Cors;
+use actix_web::{middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web_actors::ws;
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use n_body_shared::{Role, PROTOCOL_VERSION};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+pub mod api;
+pub mod auth;
+pub mod autoscale;
+pub mod config;
+pub mod decimate;
+pub mod diff;
+pub mod dirty;
+pub mod distributed;
+pub mod drift;
+pub mod encoding;
+pub mod energy;
+pub mod features;
+pub mod galaxy;
+pub mod gallery;
+pub mod import;
+pub mod jobs;
+pub mod metrics;
+pub mod net;
+pub mod physics;
+pub mod presets;
+pub mod rate_limit;
+pub mod recording;
+pub mod reload;
+pub mod replay;
+pub mod rewind;
+pub mod rng;
+pub mod rooms;
+pub mod scenarios;
+pub mod scheduler;
+pub mod script;
+pub mod service;
+pub mod session;
+pub mod simulation;
+pub mod snapshot;
+pub mod spec;
+pub mod stepper;
+pub mod theta;
+pub mod tls;
+pub mod udp;
+pub mod watchdog;
+pub mod webhooks;
+pub mod websocket;
+
+use auth::Authenticator;
+use config::{Config, NodeRole};
+use distributed::WorkerPool;
+use encoding::{StateEncoding, WireFormat};
+use energy::EnergyMonitor;
+use jobs::JobQueue;
+use metrics::Metrics;
+use rate_limit::RateLimiter;
+use rooms::{Room, Rooms};
+use session::SessionStore;
+use simulation::Simulation;
+use stepper::Presence;
+use watchdog::SimulationWatchdog;
+use webhooks::Notifier;
+use websocket::SimulationWebSocket;
+
+pub use service::{Frame, SimulationHandle, SimulationService, Subscription};
+
+/// Everything the page and websocket routes share. Build it once with
+/// [`AppState::new`] and register it as app data next to [`configure`].
+pub struct AppState {
+    /// The simulation at `/ws`, which the REST endpoints report on
+    main: Arc<Room>,
+    /// Simulations of their own at `/ws/{room}`
+    rooms: Arc<Rooms>,
+    sessions: Arc<SessionStore>,
+    auth: Arc<Authenticator>,
+    metrics: Arc<Metrics>,
+    /// Watches the main simulation for hangs
+    watchdog: Arc<SimulationWatchdog>,
+    notifier: Notifier,
+    /// `None` when `[jobs]` is disabled
+    jobs: Option<Arc<JobQueue>>,
+    /// The server's settings, replaced when config.toml is reloaded
+    settings: watch::Sender<Arc<Config>>,
+}
+
+impl AppState {
+    /// Create the simulation and its supporting services from `config` and
+    /// start stepping it. Must be called from within a Tokio runtime.
+    pub fn new(config: Config) -> Self {
+        let notifier = Notifier::from_config(&config.webhooks);
+
+        // Start watchdog thread to monitor for hung computations
+        let watchdog = Arc::new(SimulationWatchdog::new().with_notifier(notifier.clone()));
+        watchdog.start(10); // 10 second timeout before logging errors
+        info!("Watchdog thread started (10s hang detection)");
+
+        let mut simulation = Simulation::new(&config.simulation, config.server.debug);
+        if config.distributed.role == NodeRole::Frontend {
+            let has_key = !config.distributed.key.as_deref().unwrap_or("").is_empty();
+            if has_key {
+                info!(
+                    "Sharing forces with {} distributed workers",
+                    config.distributed.workers.len()
+                );
+                simulation =
+                    simulation.with_workers(Arc::new(WorkerPool::new(&config.distributed)));
+            } else {
+                log::error!(
+                    "Ignoring distributed workers: set the key they share under [distributed]"
+                );
+            }
+        }
+        let main = Arc::new(Room::start(
+            simulation,
+            &config.simulation,
+            Some(watchdog.clone()),
+        ));
+
+        AppState {
+            main,
+            rooms: Rooms::spawn(&config),
+            sessions: Arc::new(SessionStore::new(config.websocket.session_ttl_sec)),
+            auth: Arc::new(Authenticator::from_config(&config.auth)),
+            metrics: Arc::new(Metrics::default()),
+            watchdog,
+            jobs: config.jobs.enabled.then(|| {
+                Arc::new(JobQueue::new(
+                    &config.jobs,
+                    &config.simulation,
+                    notifier.clone(),
+                ))
+            }),
+            notifier,
+            settings: watch::Sender::new(Arc::new(config)),
+        }
+    }
+
+    pub fn simulation(&self) -> &Arc<Mutex<Simulation>> {
+        &self.main.simulation
+    }
+
+    /// The latest frame of the main simulation, read without its lock
+    pub fn latest(&self) -> &Arc<ArcSwap<Frame>> {
+        &self.main.latest
+    }
+
+    /// Clients following the main simulation
+    pub fn presence(&self) -> &Arc<Presence> {
+        &self.main.presence
+    }
+
+    pub fn energy(&self) -> &Arc<EnergyMonitor> {
+        &self.main.energy
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.settings.borrow())
+    }
+
+    /// Apply the settings of a reloaded config.toml that a running server
+    /// can change, and pass them on to every connected client
+    pub fn reload(&self, reloaded: &Config) {
+        let mut config = Config::clone(&self.config());
+        config.reload_from(reloaded);
+        self.main.reload(&config.simulation);
+        self.rooms.reload(&config);
+        self.settings.send_replace(Arc::new(config));
+    }
+
+    pub fn jobs(&self) -> Option<&Arc<JobQueue>> {
+        self.jobs.as_ref()
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn watchdog(&self) -> &Arc<SimulationWatchdog> {
+        &self.watchdog
+    }
+
+    /// What the client behind `req` may do
+    pub fn role_for(&self, req: &HttpRequest) -> Role {
+        if self.config().demo.enabled {
+            // Public demos only hand out control to explicitly authenticated users
+            self.auth.authenticated_role(req).unwrap_or(Role::Viewer)
+        } else {
+            self.auth.role_for(req)
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct WsQuery {
+    /// Token from a previous welcome message, used to resume that session
+    session: Option<String>,
+    /// Wire protocol version of the connecting client build
+    protocol: Option<u32>,
+    /// Crate version of the connecting client build, for logging
+    client: Option<String>,
+    /// Wire format for state frames: `json` (default), `bincode`, `quantized`
+    /// or `arrow`
+    #[serde(default)]
+    encoding: StateEncoding,
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<WsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let main = data.main.clone();
+    start_websocket(req, stream, query.into_inner(), &data, main)
+}
+
+/// `/ws/{room}`: a simulation of the room's own, created on first use
+async fn ws_room(
+    req: HttpRequest,
+    stream: web::Payload,
+    room: web::Path<String>,
+    query: web::Query<WsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    match data.rooms.join(&room) {
+        Ok(room) => start_websocket(req, stream, query.into_inner(), &data, room),
+        Err(e) => {
+            warn!("{}", e);
+            Ok(HttpResponse::ServiceUnavailable().body(e))
+        }
+    }
+}
+
+/// `/ws/replay/{name}`: a recording played back, opened on first use
+#[cfg(feature = "recording")]
+async fn ws_replay(
+    req: HttpRequest,
+    stream: web::Payload,
+    name: web::Path<String>,
+    query: web::Query<WsQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    match data.rooms.replay(&name) {
+        Ok(room) => start_websocket(req, stream, query.into_inner(), &data, room),
+        Err(e) => {
+            warn!("{}", e);
+            Ok(HttpResponse::ServiceUnavailable().body(e))
+        }
+    }
+}
+
+fn start_websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: WsQuery,
+    data: &AppState,
+    room: Arc<Room>,
+) -> Result<HttpResponse, Error> {
+    let config = data.config();
+    let server_config = &config.server;
+
+    // Behind a public URL, refuse websocket upgrades from foreign pages
+    if server_config.public_url.is_some() {
+        if let Some(origin) = req
+            .headers()
+            .get("Origin")
+            .and_then(|value| value.to_str().ok())
+        {
+            let host = req.headers().get("Host").and_then(|v| v.to_str().ok());
+            if !net::origin_allowed(
+                origin,
+                &req.connection_info(),
+                host,
+                server_config.public_url.as_deref(),
+                &server_config.allowed_origins,
+                server_config.trust_proxy_headers,
+            ) {
+                warn!("Rejected websocket from origin {}", origin);
+                return Ok(HttpResponse::Forbidden().body("Origin not allowed"));
+            }
+        }
+    }
+
+    let sessions = data.sessions.clone();
+    let metrics = data.metrics.clone();
+    let role = data.role_for(&req);
+    if query.protocol != Some(PROTOCOL_VERSION) {
+        warn!(
+            "Client {} speaks protocol {:?}, server speaks {}; asking it to reload",
+            query.client.as_deref().unwrap_or("(unknown)"),
+            query.protocol,
+            PROTOCOL_VERSION
+        );
+    }
+    let encoding = if query.encoding.is_available() {
+        query.encoding
+    } else {
+        warn!(
+            "{:?} encoding is not compiled into this server; sending JSON",
+            query.encoding
+        );
+        StateEncoding::Json
+    };
+    // Scripts and notebooks pick a compact message format as a subprotocol
+    let format = req
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|value| value.to_str().ok())
+        .and_then(WireFormat::negotiate);
+    let actor = SimulationWebSocket::new(
+        room,
+        sessions,
+        metrics,
+        query.session,
+        role,
+        query.protocol,
+        data.settings.subscribe(),
+    )
+    .with_state_encoding(encoding)
+    .with_wire_format(format.unwrap_or_default());
+    match format {
+        Some(format) => ws::WsResponseBuilder::new(actor, &req, stream)
+            .protocols(&[format.protocol()])
+            .start(),
+        None => ws::start(actor, &req, stream),
+    }
+}
+
+async fn index() -> Result<HttpResponse, Error> {
+    info!("Index route called");
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(include_str!("../../www/index.html")))
+}
+
+/// Register the simulation page, its static assets and the websocket
+/// endpoint. Embedding applications can mount this under a scope, e.g.
+/// `web::scope("/nbody").configure(n_body_server::configure)`, with a
+/// `web::Data<AppState>` registered on the app.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/ws", web::get().to(ws_index))
+        .route("/ws/{room}", web::get().to(ws_room));
+    // Before the static files, which would otherwise take the path
+    #[cfg(feature = "recording")]
+    cfg.route("/ws/replay/{name}", web::get().to(ws_replay));
+    cfg.route("/api/scenarios", web::get().to(api::list_scenarios))
+        .route("/api/gallery", web::get().to(api::list_gallery))
+        .route("/api/gallery/{id}", web::get().to(api::gallery_entry))
+        .route("/api/energy", web::get().to(api::energy_history))
+        .route("/api/stats/schema", web::get().to(api::stats_schema))
+        .route("/api/stats/stream", web::get().to(api::stats_stream))
+        .route("/metrics", web::get().to(api::metrics))
+        .route("/health", web::get().to(api::health))
+        .route("/api/spec", web::get().to(api::openapi_spec))
+        .route("/api/spec/websocket", web::get().to(api::asyncapi_spec))
+        .route("/api/playground", web::get().to(api::playground))
+        .route("/api/snapshots", web::post().to(api::save_snapshot))
+        .route(
+            "/api/snapshots/{name}/load",
+            web::post().to(api::load_snapshot),
+        )
+        .route("/api/jobs", web::get().to(api::list_jobs))
+        .route("/api/jobs", web::post().to(api::submit_job))
+        .route("/api/jobs/{id}", web::get().to(api::job_status))
+        .route("/api/jobs/{id}", web::delete().to(api::cancel_job))
+        .route("/api/jobs/{id}/files/{name}", web::get().to(api::job_file))
+        .service(
+            web::resource("/api/import/{name}")
+                .app_data(web::PayloadConfig::new(api::MAX_IMPORT_BYTES))
+                .route(web::post().to(api::import_particles)),
+        )
+        .service(actix_files::Files::new("/", "www").index_file("index.html"));
+}
+
+/// Run the standalone server described by `config` until it shuts down
+pub async fn run_server(mut config: Config) -> std::io::Result<()> {
+    if config.distributed.role == NodeRole::Worker {
+        // Workers only compute forces for their frontend
+        let distributed = config.distributed.clone();
+        return tokio::task::spawn_blocking(move || distributed::serve(&distributed))
+            .await
+            .map_err(std::io::Error::other)?;
+    }
+
+    config.apply_demo_preset();
+
+    info!("Compiled features: {:?}", features::enabled());
+
+    if config.server.debug {
+        info!("=== DEBUG MODE ENABLED ===");
+        info!("Server config: {:?}", config.server);
+        info!("Simulation config: {:?}", config.simulation);
+        info!("WebSocket config: {:?}", config.websocket);
+        info!("Units: {:?}", config.unit_system());
+    }
+
+    if config.demo.enabled {
+        info!("=== PUBLIC DEMO MODE ===");
+        info!("Demo config: {:?}", config.demo);
+    }
+
+    let app_state = web::Data::new(AppState::new(config.clone()));
+    if config.server.watch_config {
+        reload::spawn_watcher(
+            config::CONFIG_PATH,
+            Arc::downgrade(&app_state.clone().into_inner()),
+        );
+    }
+
+    // Demo mode cycles the simulation so visitors always see a fresh collision
+    if config.demo.enabled && config.demo.auto_reset_sec > 0 {
+        let simulation = app_state.simulation().clone();
+        let period = std::time::Duration::from_secs(config.demo.auto_reset_sec);
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Ok(mut sim) = simulation.lock() {
+                    info!("Demo auto-reset");
+                    sim.reset();
+                }
+            }
+        });
+    }
+
+    if config.udp.enabled {
+        udp::spawn_broadcaster(
+            &config.udp,
+            app_state.latest().clone(),
+            app_state.presence().join(),
+        );
+    }
+    webhooks::spawn_monitor(
+        &config.webhooks,
+        app_state.latest().clone(),
+        app_state.notifier.clone(),
+    );
+
+    // Forwarded client addresses are only believed behind a trusted proxy
+    let proxy_hops = if config.server.trust_proxy_headers {
+        config.server.trusted_proxy_hops
+    } else {
+        0
+    };
+    let rate_limiter = web::Data::new(RateLimiter::per_minute(
+        config.demo.requests_per_minute,
+        proxy_hops,
+    ));
+    let demo_enabled = config.demo.enabled;
+    let allowed_origins = Arc::new(config.server.allowed_origins.clone());
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        warn!("CORS allows any origin; every site can call the API");
+    }
+
+    // Log the real client address when a trusted proxy forwards it. The query
+    // string is left out, since session and access tokens travel in it
+    let log_format = r#"%{client}xi "%{method}xi %U" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
+
+    let tls = tls::load(&config.server)?;
+    let listener = net::bind_listener(&config.server.host, config.server.port)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    info!("Server starting at {}://{}", scheme, listener.local_addr()?);
+    if let Some(public_url) = &config.server.public_url {
+        info!("Public URL: {}", public_url);
+    }
+    info!("Current working directory: {:?}", std::env::current_dir());
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_state.clone())
+            .app_data(rate_limiter.clone())
+            .wrap(middleware::Condition::new(
+                demo_enabled,
+                middleware::from_fn(rate_limit::limit_requests),
+            ))
+            .wrap(
+                middleware::Logger::new(log_format)
+                    .custom_request_replace("client", move |req| {
+                        net::client_ip(req.headers(), req.peer_addr(), proxy_hops)
+                            .map_or_else(|| "-".to_string(), |ip| ip.to_string())
+                    })
+                    .custom_request_replace("method", |req| req.method().to_string()),
+            )
+            // Revalidate static assets on every load so a deploy is picked up
+            // instead of pairing a cached client with a newer server
+            .wrap(middleware::DefaultHeaders::new().add(("Cache-Control", "no-cache")))
+            .wrap({
+                let allowed_origins = allowed_origins.clone();
+                Cors::default()
+                    .allowed_origin_fn(move |origin, _| {
+                        origin
+                            .to_str()
+                            .is_ok_and(|origin| net::origin_listed(origin, &allowed_origins))
+                    })
+                    // Same-origin requests carry an Origin too; other
+                    // origins only miss the CORS headers, so browsers
+                    // withhold the response from their pages
+                    .block_on_origin_mismatch(false)
+                    .allow_any_method()
+                    .allow_any_header()
+                    .max_age(3600)
+            })
+            .configure(configure)
+    });
+    #[cfg(feature = "tls")]
+    let server = match tls {
+        Some(tls) => server.listen_rustls_0_23(listener, tls)?,
+        None => server.listen(listener)?,
+    };
+    #[cfg(not(feature = "tls"))]
+    let server = match tls {
+        Some(never) => match never {},
+        None => server.listen(listener)?,
+    };
+    server.run().await
+}
@@ -1,48 +1,6 @@
-use actix_cors::Cors;
-use actix_web::{middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer};
-use actix_web_actors::ws;
 use log::info;
-use std::sync::{Arc, Mutex};
-
-mod config;
-mod physics;
-mod simulation;
-mod watchdog;
-mod websocket;
-
-use config::Config;
-use simulation::Simulation;
-use watchdog::SimulationWatchdog;
-use websocket::SimulationWebSocket;
-
-pub struct AppState {
-    simulation: Arc<Mutex<Simulation>>,
-    watchdog: Arc<SimulationWatchdog>,
-    config: Config,
-}
-
-async fn ws_index(
-    req: HttpRequest,
-    stream: web::Payload,
-    data: web::Data<AppState>,
-) -> Result<HttpResponse, Error> {
-    let simulation = data.simulation.clone();
-    let watchdog = data.watchdog.clone();
-    let ws_config = &data.config.websocket;
-    let sim_config = &data.config.simulation;
-    ws::start(
-        SimulationWebSocket::new(simulation, watchdog, ws_config, sim_config),
-        &req,
-        stream,
-    )
-}
-
-async fn index() -> Result<HttpResponse, Error> {
-    info!("Index route called");
-    Ok(HttpResponse::Ok()
-        .content_type("text/html")
-        .body(include_str!("../../www/index.html")))
-}
+use n_body_server::config::Config;
+use n_body_server::scheduler::CpuPartition;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -54,58 +12,18 @@ async fn main() -> std::io::Result<()> {
     let num_threads = num_cpus::get();
     info!("Starting N-Body server with {} CPU threads", num_threads);
 
-    if config.server.debug {
-        info!("=== DEBUG MODE ENABLED ===");
-        info!("Server config: {:?}", config.server);
-        info!("Simulation config: {:?}", config.simulation);
-        info!("WebSocket config: {:?}", config.websocket);
+    // The live simulation gets every thread not reserved for batch jobs
+    let partition = CpuPartition::new(&config.jobs, num_threads);
+    if partition.job_threads > 0 {
+        info!(
+            "{} threads for the live simulation, {} for batch jobs",
+            partition.live_threads, partition.job_threads
+        );
     }
-
-    // Initialize rayon with all available threads
     rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
+        .num_threads(partition.live_threads)
         .build_global()
         .unwrap();
 
-    let simulation = Arc::new(Mutex::new(Simulation::new(
-        &config.simulation,
-        config.server.debug,
-    )));
-
-    // Start watchdog thread to monitor for hung computations
-    let watchdog = Arc::new(SimulationWatchdog::new());
-    watchdog.start(10); // 10 second timeout before logging errors
-    info!("Watchdog thread started (10s hang detection)");
-
-    let app_state = web::Data::new(AppState {
-        simulation,
-        watchdog,
-        config: config.clone(),
-    });
-
-    let bind_address = format!("{}:{}", config.server.host, config.server.port);
-    info!(
-        "Server starting at http://{}:{}",
-        config.server.host, config.server.port
-    );
-    info!("Current working directory: {:?}", std::env::current_dir());
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())
-            .wrap(middleware::Logger::default())
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header()
-                    .max_age(3600),
-            )
-            .route("/", web::get().to(index))
-            .route("/ws", web::get().to(ws_index))
-            .service(actix_files::Files::new("/", "www").index_file("index.html"))
-    })
-    .bind(&bind_address)?
-    .run()
-    .await
+    n_body_server::run_server(config).await
 }
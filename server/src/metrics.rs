@@ -0,0 +1,101 @@
+// Operational metrics in the Prometheus text exposition format, for
+// scraping into a dashboard. Frame figures come from the latest published
+// frame at scrape time; traffic is counted as websocket sessions send it.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use crate::service::Frame;
+use crate::watchdog::SimulationWatchdog;
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counters shared by every websocket session
+#[derive(Default)]
+pub struct Metrics {
+    clients: AtomicI64,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+impl Metrics {
+    pub fn connected(&self) {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self) {
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count a websocket message of `bytes` payload bytes
+    pub fn sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// All metrics of the main simulation's `frame` and its `watchdog`
+    pub fn render(&self, frame: &Frame, watchdog: &SimulationWatchdog) -> String {
+        let stats = &frame.stats;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP nbody_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE nbody_{} {}", name, kind);
+            let _ = writeln!(out, "nbody_{} {}", name, value);
+        };
+        metric(
+            "frames_per_second",
+            "gauge",
+            "Physics frames per second the last frame's computation time allows",
+            stats.fps as f64,
+        );
+        metric(
+            "computation_seconds",
+            "gauge",
+            "Time the last physics frame took",
+            stats.computation_time_ms as f64 / 1000.0,
+        );
+        metric(
+            "particles",
+            "gauge",
+            "Particles in the simulation",
+            stats.particle_count as f64,
+        );
+        metric(
+            "frame_number",
+            "gauge",
+            "Physics steps since the last reset",
+            stats.frame_number as f64,
+        );
+        metric(
+            "websocket_clients",
+            "gauge",
+            "Open websocket connections, rooms included",
+            self.clients.load(Ordering::Relaxed) as f64,
+        );
+        metric(
+            "websocket_messages_sent_total",
+            "counter",
+            "Websocket messages sent to clients",
+            self.messages_sent.load(Ordering::Relaxed) as f64,
+        );
+        metric(
+            "websocket_bytes_sent_total",
+            "counter",
+            "Websocket payload bytes sent to clients",
+            self.bytes_sent.load(Ordering::Relaxed) as f64,
+        );
+        metric(
+            "watchdog_stalls_total",
+            "counter",
+            "Times the simulation hung past the watchdog timeout",
+            watchdog.stalls() as f64,
+        );
+        metric(
+            "watchdog_hung",
+            "gauge",
+            "1 while the simulation is hung past the watchdog timeout",
+            if watchdog.is_hung() { 1.0 } else { 0.0 },
+        );
+        out
+    }
+}
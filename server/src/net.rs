@@ -0,0 +1,123 @@
+use actix_web::dev::ConnectionInfo;
+use actix_web::http::header::HeaderMap;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs};
+
+/// Bind a listener for `host:port`. IPv6 hosts such as `::` are bound
+/// dual-stack so IPv4 clients can still connect through the same socket.
+pub fn bind_listener(host: &str, port: u16) -> io::Result<TcpListener> {
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
+}
+
+/// Client IP for logging and rate limiting. With `proxy_hops` trusted
+/// proxies in front of the server, the client is the address the outermost
+/// of them appended to X-Forwarded-For, counting hops from the right; entries
+/// further left come from the client and could be spoofed. With no trusted
+/// proxies, or a shorter chain than expected, it is the peer address.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer_addr: Option<SocketAddr>,
+    proxy_hops: usize,
+) -> Option<IpAddr> {
+    if proxy_hops > 0 {
+        let hops: Vec<&str> = headers
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if let Some(ip) = hops
+            .len()
+            .checked_sub(proxy_hops)
+            .and_then(|index| forwarded_ip(hops[index]))
+        {
+            return Some(ip);
+        }
+    }
+    peer_addr.map(|addr| addr.ip())
+}
+
+/// Address of an X-Forwarded-For entry, which may carry a port
+fn forwarded_ip(entry: &str) -> Option<IpAddr> {
+    entry
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Whether `origin` is one of the configured `allowed_origins`, where `*`
+/// stands for any origin
+pub fn origin_listed(origin: &str, allowed_origins: &[String]) -> bool {
+    allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*" || origin.eq_ignore_ascii_case(url_origin(allowed)))
+}
+
+/// Whether a websocket `Origin` is allowed: the configured public origin,
+/// one of `allowed_origins`, or the origin the request itself was addressed
+/// to (as reported by a trusted proxy via `X-Forwarded-Proto`/`X-Forwarded-Host`)
+pub fn origin_allowed(
+    origin: &str,
+    info: &ConnectionInfo,
+    host_header: Option<&str>,
+    public_url: Option<&str>,
+    allowed_origins: &[String],
+    trust_proxy: bool,
+) -> bool {
+    if origin_listed(origin, allowed_origins) {
+        return true;
+    }
+    if let Some(public_origin) = public_url.map(url_origin) {
+        if origin.eq_ignore_ascii_case(public_origin) {
+            return true;
+        }
+    }
+
+    if trust_proxy {
+        let forwarded = format!("{}://{}", info.scheme(), info.host());
+        return origin.eq_ignore_ascii_case(&forwarded);
+    }
+
+    // Without a trusted proxy only the host can be compared, not the scheme
+    match (origin.split_once("://"), host_header) {
+        (Some((_, authority)), Some(host)) => authority.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+/// Websocket endpoint for a public page URL, e.g. `https://example.com/nbody`
+/// becomes `wss://example.com/nbody/ws`
+pub fn public_websocket_url(public_url: &str) -> String {
+    let base = public_url.trim_end_matches('/');
+    let base = if let Some(rest) = base.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = base.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        base.to_string()
+    };
+    format!("{}/ws", base)
+}
+
+/// `scheme://host[:port]` part of a URL
+fn url_origin(url: &str) -> &str {
+    let authority_start = url.find("://").map_or(0, |i| i + 3);
+    match url[authority_start..].find('/') {
+        Some(path_start) => &url[..authority_start + path_start],
+        None => url,
+    }
+}
@@ -0,0 +1,270 @@
+// Hand-built small-N scenarios with fixed bodies rather than sampled particles
+use n_body_shared::Particle;
+use nalgebra::{Point3, Rotation3, Vector3};
+
+/// Body of the solar system preset: name, mass in solar masses, J2000 mean
+/// orbital elements (semi-major axis a in AU, eccentricity e, and inclination
+/// I, mean longitude L, longitude of perihelion ϖ and longitude of the
+/// ascending node Ω in degrees), and display colour.
+struct Planet {
+    name: &'static str,
+    mass: f64,
+    elements: [f64; 6],
+    color: [f32; 4],
+}
+
+/// Mean elements from JPL's "Approximate Positions of the Planets"
+/// (Standish), valid 1800-2050. Earth stands for the Earth-Moon barycentre.
+const PLANETS: [Planet; 8] = [
+    Planet {
+        name: "Mercury",
+        mass: 1.6601e-7,
+        elements: [
+            0.387_099,
+            0.205_636,
+            7.004_979,
+            252.250_324,
+            77.457_796,
+            48.330_766,
+        ],
+        color: [0.7, 0.7, 0.7, 1.0],
+    },
+    Planet {
+        name: "Venus",
+        mass: 2.4478e-6,
+        elements: [
+            0.723_336,
+            0.006_777,
+            3.394_676,
+            181.979_100,
+            131.602_467,
+            76.679_843,
+        ],
+        color: [0.95, 0.85, 0.6, 1.0],
+    },
+    Planet {
+        name: "Earth",
+        mass: 3.0404e-6,
+        elements: [1.000_003, 0.016_711, 0.0, 100.464_572, 102.937_682, 0.0],
+        color: [0.3, 0.55, 1.0, 1.0],
+    },
+    Planet {
+        name: "Mars",
+        mass: 3.2272e-7,
+        elements: [
+            1.523_710,
+            0.093_394,
+            1.849_691,
+            -4.553_432,
+            -23.943_630,
+            49.559_539,
+        ],
+        color: [1.0, 0.45, 0.3, 1.0],
+    },
+    Planet {
+        name: "Jupiter",
+        mass: 9.5479e-4,
+        elements: [
+            5.202_887,
+            0.048_386,
+            1.304_397,
+            34.396_441,
+            14.728_480,
+            100.473_909,
+        ],
+        color: [0.9, 0.75, 0.6, 1.0],
+    },
+    Planet {
+        name: "Saturn",
+        mass: 2.8589e-4,
+        elements: [
+            9.536_676,
+            0.053_862,
+            2.485_992,
+            49.954_244,
+            92.598_878,
+            113.662_424,
+        ],
+        color: [0.95, 0.9, 0.65, 1.0],
+    },
+    Planet {
+        name: "Uranus",
+        mass: 4.3662e-5,
+        elements: [
+            19.189_165,
+            0.047_257,
+            0.772_638,
+            313.238_105,
+            170.954_276,
+            74.016_925,
+        ],
+        color: [0.6, 0.9, 0.95, 1.0],
+    },
+    Planet {
+        name: "Neptune",
+        mass: 5.1514e-5,
+        elements: [
+            30.069_923,
+            0.008_590,
+            1.770_043,
+            -55.120_030,
+            44.964_762,
+            131.784_226,
+        ],
+        color: [0.35, 0.5, 1.0, 1.0],
+    },
+];
+
+/// Softening for presets of point-like bodies, small enough not to disturb
+/// even Mercury's orbit (1e-4 AU is about 15,000 km) or the close passes of
+/// the three-body problems
+pub const POINT_MASS_SOFTENING: f32 = 1e-4;
+
+/// The Sun and eight planets at their J2000 positions, in the ecliptic
+/// frame. Units are AU and solar masses with time in years / 2π, so G = 1 and
+/// Earth's orbit takes 2π time units. Velocities scale with √`gravity` to keep
+/// the orbits bound at other values of G. The frame is barycentric so the
+/// system stays put on screen.
+pub fn generate_solar_system(gravity: f32) -> Vec<Particle> {
+    let gravity = gravity as f64;
+    let mut bodies = vec![(
+        1.0,
+        Vector3::zeros(),
+        Vector3::zeros(),
+        [1.0, 0.95, 0.6, 1.0],
+    )];
+
+    for planet in &PLANETS {
+        let (position, velocity) = orbital_state(&planet.elements, gravity * (1.0 + planet.mass));
+        log::debug!(
+            "{} starts at {:.3} AU from the Sun",
+            planet.name,
+            position.norm()
+        );
+        bodies.push((planet.mass, position, velocity, planet.color));
+    }
+
+    let total_mass: f64 = bodies.iter().map(|(mass, ..)| mass).sum();
+    let barycentre = bodies.iter().map(|(m, p, ..)| p * *m).sum::<Vector3<f64>>() / total_mass;
+    let drift = bodies
+        .iter()
+        .map(|(m, _, v, _)| v * *m)
+        .sum::<Vector3<f64>>()
+        / total_mass;
+
+    bodies
+        .into_iter()
+        .map(|(mass, position, velocity, color)| Particle {
+            position: Point3::from((position - barycentre).cast::<f32>()),
+            velocity: (velocity - drift).cast::<f32>(),
+            mass: mass as f32,
+            color,
+            fixed: false,
+            group: 0,
+        })
+        .collect()
+}
+
+/// Heliocentric position and velocity from mean orbital elements, for
+/// `mu` = G (M_sun + m)
+pub(crate) fn orbital_state(elements: &[f64; 6], mu: f64) -> (Vector3<f64>, Vector3<f64>) {
+    let [a, e, inclination, mean_longitude, perihelion, node] = *elements;
+    let [inclination, mean_longitude, perihelion, node] =
+        [inclination, mean_longitude, perihelion, node].map(f64::to_radians);
+
+    let argument_of_perihelion = perihelion - node;
+    let mean_anomaly = mean_longitude - perihelion;
+
+    // Solve Kepler's equation E - e sin E = M by Newton iteration
+    let mut eccentric = mean_anomaly + e * mean_anomaly.sin();
+    for _ in 0..20 {
+        let delta = (eccentric - e * eccentric.sin() - mean_anomaly) / (1.0 - e * eccentric.cos());
+        eccentric -= delta;
+        if delta.abs() < 1e-14 {
+            break;
+        }
+    }
+
+    let semi_minor = a * (1.0 - e * e).sqrt();
+    let rate = (mu / (a * a * a)).sqrt() / (1.0 - e * eccentric.cos());
+    let position = Vector3::new(a * (eccentric.cos() - e), semi_minor * eccentric.sin(), 0.0);
+    let velocity = Vector3::new(
+        -a * eccentric.sin() * rate,
+        semi_minor * eccentric.cos() * rate,
+        0.0,
+    );
+
+    // Orbital plane to ecliptic: Rz(Ω) Rx(I) Rz(ω)
+    let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), node)
+        * Rotation3::from_axis_angle(&Vector3::x_axis(), inclination)
+        * Rotation3::from_axis_angle(&Vector3::z_axis(), argument_of_perihelion);
+    (rotation * position, rotation * velocity)
+}
+
+/// Chenciner-Montgomery figure-eight choreography: three equal masses
+/// chasing each other along one figure-eight curve, period ≈ 6.326 at G = 1.
+/// Initial conditions from Simó (2000).
+pub fn generate_figure_eight(gravity: f32) -> Vec<Particle> {
+    let speed = gravity.sqrt();
+    let (x, y) = (0.970_004_4, -0.243_087_5);
+    let (vx, vy) = (-0.932_407_4 * speed, -0.864_731_5 * speed);
+    vec![
+        body([x, y], [-vx / 2.0, -vy / 2.0], 1.0, RED),
+        body([-x, -y], [-vx / 2.0, -vy / 2.0], 1.0, GREEN),
+        body([0.0, 0.0], [vx, vy], 1.0, BLUE),
+    ]
+}
+
+/// Lagrange's equilateral solution: three equal masses on the corners of a
+/// rotating triangle of side 2, period 2π √(8/3) ≈ 10.26 at G = 1. Unstable
+/// for equal masses, so round-off eventually breaks the triangle up; how long
+/// it survives is a handy gauge of integrator accuracy.
+pub fn generate_lagrange_triangle(gravity: f32) -> Vec<Particle> {
+    let side = 2.0f32;
+    let radius = side / 3.0f32.sqrt();
+    // Each body circles the centre at v = √(G m / side)
+    let speed = (gravity / side).sqrt();
+    [RED, GREEN, BLUE]
+        .into_iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let angle = std::f32::consts::FRAC_PI_2 + i as f32 * std::f32::consts::TAU / 3.0;
+            let (sin, cos) = angle.sin_cos();
+            body(
+                [radius * cos, radius * sin],
+                [-speed * sin, speed * cos],
+                1.0,
+                color,
+            )
+        })
+        .collect()
+}
+
+/// Burrau's Pythagorean problem: masses 3, 4 and 5 released from rest at the
+/// corners of a 3-4-5 right triangle opposite the matching sides. After a
+/// series of close encounters a binary and a single star escape (t ≈ 60 at
+/// G = 1), a demanding test that needs sub-stepping or a high-order
+/// integrator to reproduce.
+pub fn generate_pythagorean() -> Vec<Particle> {
+    vec![
+        body([1.0, 3.0], [0.0, 0.0], 3.0, RED),
+        body([-2.0, -1.0], [0.0, 0.0], 4.0, GREEN),
+        body([1.0, -1.0], [0.0, 0.0], 5.0, BLUE),
+    ]
+}
+
+const RED: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+const GREEN: [f32; 4] = [0.4, 1.0, 0.5, 1.0];
+const BLUE: [f32; 4] = [0.5, 0.6, 1.0, 1.0];
+
+/// Point mass moving in the xy plane
+fn body(position: [f32; 2], velocity: [f32; 2], mass: f32, color: [f32; 4]) -> Particle {
+    Particle {
+        position: Point3::new(position[0], position[1], 0.0),
+        velocity: Vector3::new(velocity[0], velocity[1], 0.0),
+        mass,
+        color,
+        fixed: false,
+        group: 0,
+    }
+}
@@ -0,0 +1,84 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::net::client_ip;
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// Fixed-window request counter per client IP
+pub struct RateLimiter {
+    limit: u32,
+    proxy_hops: usize,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, Window>>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(limit: u32, proxy_hops: usize) -> Self {
+        RateLimiter {
+            limit,
+            proxy_hops,
+            window: Duration::from_secs(60),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request from `ip`, returning false once it is over the limit
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let mut clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // Keep the table from growing without bound on a public server
+        if clients.len() > 10_000 {
+            let window = self.window;
+            clients.retain(|_, w| w.started.elapsed() < window);
+        }
+
+        let entry = clients.entry(ip).or_insert(Window {
+            started: Instant::now(),
+            count: 0,
+        });
+        if entry.started.elapsed() >= self.window {
+            entry.started = Instant::now();
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.count <= self.limit
+    }
+}
+
+/// Middleware rejecting requests with 429 once a client exceeds the limit
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>();
+    let ip =
+        limiter.and_then(|limiter| client_ip(req.headers(), req.peer_addr(), limiter.proxy_hops));
+    let allowed = match (limiter, ip) {
+        (Some(limiter), Some(ip)) => limiter.check(ip),
+        _ => true,
+    };
+
+    if !allowed {
+        log::warn!("Rate limit exceeded for {:?}", ip);
+        return Ok(req
+            .into_response(HttpResponse::TooManyRequests().body("Too many requests"))
+            .map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
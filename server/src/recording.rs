@@ -0,0 +1,403 @@
+// Recordings of a live run: every Nth published frame, with the settings and
+// seed it ran with, written to a compact binary file so an interesting run can
+// be archived and analysed later. A file is `MAGIC` followed by records, each a
+// little-endian u32 length and a bincode-encoded `Record`: the header, then the
+// particles' fixed attributes, then frames of positions and velocities, with a
+// new particle set whenever a reset or trim changes the particles. Without
+// the `recording` feature only the file format is compiled in, and starting a
+// recording fails.
+use log::error;
+use n_body_shared::{PhysicsConfig, RecordingStatus};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+#[cfg(feature = "recording")]
+use {
+    log::info,
+    n_body_shared::SimulationState,
+    std::fs::File,
+    std::io::{BufWriter, Write},
+    std::path::{Path, PathBuf},
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::sync::Weak,
+    std::thread,
+    std::time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::RecordingConfig;
+use crate::service::Frame;
+use crate::simulation::Simulation;
+
+/// First bytes of every recording file
+pub const MAGIC: &[u8; 8] = b"NBODYREC";
+
+/// Version of the record layout; raise it whenever a record changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Extension of recording files
+pub const EXTENSION: &str = "nbrec";
+
+/// Recordings started by this process, to tell apart files started within
+/// the same second
+#[cfg(feature = "recording")]
+static STARTED: AtomicU64 = AtomicU64::new(0);
+
+/// One entry of a recording file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Record {
+    Header(RecordingHeader),
+    Particles(ParticleSet),
+    Frame(RecordedFrame),
+}
+
+/// The first record: what was run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordingHeader {
+    pub format_version: u32,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    /// Published frames between recorded ones
+    pub interval: u64,
+    /// Seed of the initial conditions
+    pub seed: u64,
+    pub config: PhysicsConfig,
+}
+
+/// What stays the same from frame to frame, written before the first frame
+/// and again whenever the particles change, with the settings then in effect
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParticleSet {
+    pub config: PhysicsConfig,
+    pub masses: Vec<f32>,
+    /// RGBA of each particle in turn, 0-255
+    pub colors: Vec<u8>,
+    /// Empty when no particle is pinned
+    pub fixed: Vec<bool>,
+    /// Empty when all particles are in group 0
+    pub groups: Vec<u16>,
+}
+
+/// A recorded frame of the last particle set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedFrame {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    /// x, y, z of each particle in turn
+    pub positions: Vec<f32>,
+    /// vx, vy, vz of each particle in turn
+    pub velocities: Vec<f32>,
+}
+
+/// A recording being written
+#[cfg(feature = "recording")]
+struct Recording {
+    /// Tells the thread writing it apart from those of earlier recordings
+    id: u64,
+    file: String,
+    interval: u64,
+    writer: BufWriter<File>,
+    /// Published frames seen, recorded or not
+    seen: u64,
+    frames: u64,
+    /// Simulation version of the last frame seen, to skip paused frames
+    last_version: Option<u64>,
+    /// State whose particle set was written last
+    particles: Option<Arc<SimulationState>>,
+}
+
+#[cfg(feature = "recording")]
+impl Recording {
+    fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            file: Some(self.file.clone()),
+            interval: self.interval,
+            frames: self.frames,
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), String> {
+        let bytes =
+            bincode::serialize(record).map_err(|e| format!("Failed to encode recording: {}", e))?;
+        self.writer
+            .write_all(&(bytes.len() as u32).to_le_bytes())
+            .and_then(|_| self.writer.write_all(&bytes))
+            .map_err(|e| format!("Failed to write {}: {}", self.file, e))
+    }
+
+    /// Write `frame` if it is due, preceded by its particle set if that
+    /// changed
+    fn record(
+        &mut self,
+        frame: &Frame,
+        simulation: &Weak<Mutex<Simulation>>,
+    ) -> Result<(), String> {
+        // Nothing moves while paused
+        if self.last_version == Some(frame.version) {
+            return Ok(());
+        }
+        self.last_version = Some(frame.version);
+        let due = self.seen.is_multiple_of(self.interval);
+        self.seen += 1;
+        if !due {
+            return Ok(());
+        }
+
+        let state = &frame.state;
+        let changed = self.particles.as_ref().is_none_or(|written| {
+            written.masses != state.masses
+                || written.colors != state.colors
+                || written.fixed != state.fixed
+                || written.groups != state.groups
+        });
+        if changed {
+            let config = simulation
+                .upgrade()
+                .ok_or_else(|| "Simulation has stopped".to_string())?
+                .lock()
+                .map_err(|e| format!("Failed to lock simulation: {}", e))?
+                .get_config()
+                .clone();
+            self.write(&Record::Particles(ParticleSet {
+                config,
+                masses: state.masses.clone(),
+                colors: state.colors.clone(),
+                fixed: state.fixed.clone(),
+                groups: state.groups.clone(),
+            }))?;
+            self.particles = Some(state.clone());
+        }
+        self.write(&Record::Frame(RecordedFrame {
+            frame_number: state.frame_number,
+            sim_time: state.sim_time,
+            positions: state.positions.clone(),
+            velocities: state.velocities.clone(),
+        }))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flush what is buffered and report the recording's final status
+    fn finish(mut self) -> RecordingStatus {
+        if let Err(e) = self.writer.flush() {
+            error!("Failed to finish recording {}: {}", self.file, e);
+        }
+        info!("Recorded {} frames to {}", self.frames, self.file);
+        RecordingStatus {
+            file: None,
+            ..self.status()
+        }
+    }
+}
+
+/// Starts and stops the recordings of one simulation
+#[cfg(feature = "recording")]
+pub struct Recorder {
+    simulation: Weak<Mutex<Simulation>>,
+    frames: broadcast::Sender<Arc<Frame>>,
+    config: RecordingConfig,
+    active: Arc<Mutex<Option<Recording>>>,
+}
+
+#[cfg(feature = "recording")]
+impl Recorder {
+    /// A recorder of the frames `simulation` publishes on `frames`, which
+    /// starts right away when `config` enables recording
+    pub fn new(
+        config: &RecordingConfig,
+        simulation: &Arc<Mutex<Simulation>>,
+        frames: &broadcast::Sender<Arc<Frame>>,
+    ) -> Self {
+        let recorder = Recorder {
+            simulation: Arc::downgrade(simulation),
+            frames: frames.clone(),
+            config: config.clone(),
+            active: Arc::default(),
+        };
+        if config.enabled {
+            if let Err(e) = recorder.start(None) {
+                error!("Failed to start recording: {}", e);
+            }
+        }
+        recorder
+    }
+
+    /// Start recording every `interval`th frame, the configured interval by
+    /// default. While already recording, reports the recording in progress.
+    pub fn start(&self, interval: Option<u64>) -> Result<RecordingStatus, String> {
+        let interval = interval.unwrap_or(self.config.interval);
+        if interval == 0 {
+            return Err("Recording interval must be at least 1".to_string());
+        }
+        let mut active = self
+            .active
+            .lock()
+            .map_err(|_| "Recorder lock poisoned".to_string())?;
+        if let Some(recording) = active.as_ref() {
+            return Ok(recording.status());
+        }
+
+        let config = self
+            .simulation
+            .upgrade()
+            .ok_or_else(|| "Simulation has stopped".to_string())?
+            .lock()
+            .map_err(|e| format!("Failed to lock simulation: {}", e))?
+            .get_config()
+            .clone();
+        let dir = Path::new(&self.config.dir);
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let started_at = unix_now();
+        let id = STARTED.fetch_add(1, Ordering::Relaxed);
+        let file = format!("run-{}-{}.{}", started_at, id, EXTENSION);
+        let path: PathBuf = dir.join(&file);
+        let mut writer = File::create(&path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        writer
+            .write_all(MAGIC)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+        let mut recording = Recording {
+            id,
+            file,
+            interval,
+            writer,
+            seen: 0,
+            frames: 0,
+            last_version: None,
+            particles: None,
+        };
+        recording.write(&Record::Header(RecordingHeader {
+            format_version: FORMAT_VERSION,
+            started_at,
+            interval,
+            seed: config.seed,
+            config,
+        }))?;
+        info!(
+            "Recording every {} frame(s) to {}",
+            interval,
+            path.display()
+        );
+        let status = recording.status();
+        *active = Some(recording);
+        drop(active);
+
+        self.spawn_writer(id);
+        Ok(status)
+    }
+
+    /// Finish the recording in progress, if any, and report how it ended
+    pub fn stop(&self) -> RecordingStatus {
+        let finished = self.active.lock().ok().and_then(|mut active| active.take());
+        match finished {
+            Some(recording) => recording.finish(),
+            None => self.idle_status(),
+        }
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        match self.active.lock().ok().as_deref() {
+            Some(Some(recording)) => recording.status(),
+            _ => self.idle_status(),
+        }
+    }
+
+    fn idle_status(&self) -> RecordingStatus {
+        RecordingStatus {
+            file: None,
+            interval: self.config.interval,
+            frames: 0,
+        }
+    }
+
+    /// Write the published frames to recording `id` on a thread of its own
+    /// until it is stopped, so slow disks never hold up the stepping loop
+    fn spawn_writer(&self, id: u64) {
+        let mut frames = self.frames.subscribe();
+        let active = self.active.clone();
+        let simulation = self.simulation.clone();
+        let spawned = thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || loop {
+                let frame = match frames.blocking_recv() {
+                    Ok(frame) => frame,
+                    // Writing took longer than the frames it missed
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let Ok(mut active) = active.lock() else {
+                    break;
+                };
+                let Some(recording) = active.as_mut().filter(|recording| recording.id == id) else {
+                    // Stopped, and possibly restarted by another thread
+                    break;
+                };
+                if let Err(e) = recording.record(&frame, &simulation) {
+                    error!("Recording stopped: {}", e);
+                    if let Some(recording) = active.take() {
+                        recording.finish();
+                    }
+                    break;
+                }
+            });
+        if let Err(e) = spawned {
+            error!("Failed to start recorder thread: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "recording")]
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Stands in for the recorder without the `recording` feature, never
+/// recording anything
+#[cfg(not(feature = "recording"))]
+pub struct Recorder {
+    interval: u64,
+}
+
+#[cfg(not(feature = "recording"))]
+impl Recorder {
+    pub fn new(
+        config: &RecordingConfig,
+        _simulation: &Arc<Mutex<Simulation>>,
+        _frames: &broadcast::Sender<Arc<Frame>>,
+    ) -> Self {
+        if config.enabled {
+            error!("Failed to start recording: requires the `recording` feature");
+        }
+        Recorder {
+            interval: config.interval,
+        }
+    }
+
+    pub fn start(&self, _interval: Option<u64>) -> Result<RecordingStatus, String> {
+        Err("Recording requires the `recording` feature".to_string())
+    }
+
+    pub fn stop(&self) -> RecordingStatus {
+        self.status()
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            file: None,
+            interval: self.interval,
+            frames: 0,
+        }
+    }
+}
+
+#[cfg(feature = "recording")]
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
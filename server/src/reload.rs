@@ -0,0 +1,50 @@
+// Hot reload of config.toml: the file is checked every few seconds and, once
+// it changed and still parses, the settings a running server can change are
+// applied and every connected client is sent the simulation's config again.
+// A broken edit is logged and leaves the running settings alone.
+use log::{info, warn};
+use std::sync::Weak;
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::AppState;
+
+/// How often config.toml is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reload `state` from `path` whenever the file changes, until the state is
+/// dropped. Must be called from within a Tokio runtime.
+pub fn spawn_watcher(path: &str, state: Weak<AppState>) {
+    let path = path.to_string();
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+            match Config::read(&path) {
+                Ok(config) => {
+                    info!(
+                        "Reloaded {}: updates every {} ms, stats every {} frames",
+                        path, config.simulation.update_rate_ms, config.simulation.stats_frequency
+                    );
+                    state.reload(&config);
+                }
+                Err(e) => warn!("Keeping the running settings: {}", e),
+            }
+        }
+    });
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
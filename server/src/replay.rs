@@ -0,0 +1,236 @@
+// Replays of recordings: a recording is indexed once when opened, then its
+// frames are read back one at a time, in order or from any point, so a run
+// can be shown again without computing a single force. Needs the `recording`
+// feature.
+use n_body_shared::{PhysicsConfig, SimulationState};
+
+use crate::recording::RecordingHeader;
+#[cfg(feature = "recording")]
+use {
+    crate::recording::{ParticleSet, Record, RecordedFrame, EXTENSION, FORMAT_VERSION, MAGIC},
+    crate::scenarios::validate_name,
+    std::fs::File,
+    std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+    std::path::Path,
+};
+
+/// Where a recorded frame is in the file
+#[cfg(feature = "recording")]
+struct Entry {
+    /// Start of the frame's record
+    offset: u64,
+    /// Index of the particle set it is a frame of
+    set: usize,
+    frame_number: u64,
+}
+
+/// A recording opened for playback
+#[cfg(feature = "recording")]
+pub struct Replay {
+    name: String,
+    reader: BufReader<File>,
+    header: RecordingHeader,
+    sets: Vec<ParticleSet>,
+    frames: Vec<Entry>,
+    /// Index of the frame to play next
+    position: usize,
+}
+
+#[cfg(feature = "recording")]
+impl Replay {
+    /// Open and index recording `<dir>/<name>.nbrec`
+    pub fn open(dir: &Path, name: &str) -> Result<Self, String> {
+        validate_name(name).map_err(|_| {
+            format!(
+                "Invalid recording name '{}': use only letters, digits, '-' and '_'",
+                name
+            )
+        })?;
+        let path = dir.join(format!("{}.{}", name, EXTENSION));
+        let file =
+            File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .ok()
+            .filter(|_| &magic == MAGIC)
+            .ok_or_else(|| format!("{} is not a recording", path.display()))?;
+        let mut offset = MAGIC.len() as u64;
+
+        let mut header = None;
+        let mut sets = Vec::new();
+        let mut frames = Vec::new();
+        while let Some((record, length)) = read_record(&mut reader)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        {
+            let start = offset;
+            offset += 4 + length;
+            match (record, &header) {
+                (Record::Header(read), None) => {
+                    if read.format_version != FORMAT_VERSION {
+                        return Err(format!(
+                            "{} was recorded in format {}, but only format {} can be replayed",
+                            path.display(),
+                            read.format_version,
+                            FORMAT_VERSION
+                        ));
+                    }
+                    header = Some(read);
+                }
+                (Record::Particles(set), Some(_)) => sets.push(set),
+                (Record::Frame(frame), Some(_)) if !sets.is_empty() => {
+                    let set = &sets[sets.len() - 1];
+                    if frame.positions.len() != set.masses.len() * 3
+                        || frame.velocities.len() != set.masses.len() * 3
+                    {
+                        return Err(format!(
+                            "{} is corrupt: frame {} doesn't match its particles",
+                            path.display(),
+                            frame.frame_number
+                        ));
+                    }
+                    frames.push(Entry {
+                        offset: start,
+                        set: sets.len() - 1,
+                        frame_number: frame.frame_number,
+                    });
+                }
+                _ => {
+                    return Err(format!(
+                        "{} is corrupt: records out of order",
+                        path.display()
+                    ))
+                }
+            }
+        }
+        let header = header.ok_or_else(|| format!("{} has no header", path.display()))?;
+        if frames.is_empty() {
+            return Err(format!("{} has no frames", path.display()));
+        }
+
+        Ok(Replay {
+            name: name.to_string(),
+            reader,
+            header,
+            sets,
+            frames,
+            position: 0,
+        })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// Recorded frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Continue from the first recorded frame
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Continue from the first recorded frame numbered `frame` or later, or
+    /// the last one when the run never got that far
+    pub fn seek(&mut self, frame: u64) {
+        self.position = self
+            .frames
+            .iter()
+            .position(|entry| entry.frame_number >= frame)
+            .unwrap_or(self.frames.len() - 1);
+    }
+
+    /// The next recorded frame, with the settings it ran with. Starts over
+    /// after the last one, so a replay loops.
+    pub fn next_frame(&mut self) -> Result<(SimulationState, &PhysicsConfig), String> {
+        if self.position >= self.frames.len() {
+            self.position = 0;
+        }
+        let entry = &self.frames[self.position];
+        self.position += 1;
+
+        let frame = self
+            .reader
+            .seek(SeekFrom::Start(entry.offset))
+            .map_err(|e| e.to_string())
+            .and_then(|_| read_record(&mut self.reader).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to read recording {}: {}", self.name, e))?;
+        let Some((Record::Frame(frame), _)) = frame else {
+            return Err(format!(
+                "Recording {} changed while replaying it",
+                self.name
+            ));
+        };
+        let set = &self.sets[entry.set];
+        let RecordedFrame {
+            frame_number,
+            sim_time,
+            positions,
+            velocities,
+        } = frame;
+        let state = SimulationState {
+            positions,
+            velocities,
+            masses: set.masses.clone(),
+            colors: set.colors.clone(),
+            fixed: set.fixed.clone(),
+            groups: set.groups.clone(),
+            sim_time,
+            frame_number,
+            ..Default::default()
+        };
+        Ok((state, &set.config))
+    }
+}
+
+/// Never opened without the `recording` feature
+#[cfg(not(feature = "recording"))]
+pub enum Replay {}
+
+#[cfg(not(feature = "recording"))]
+impl Replay {
+    pub fn header(&self) -> &RecordingHeader {
+        match *self {}
+    }
+
+    pub fn rewind(&mut self) {
+        match *self {}
+    }
+
+    pub fn seek(&mut self, _frame: u64) {
+        match *self {}
+    }
+
+    pub fn next_frame(&mut self) -> Result<(SimulationState, &PhysicsConfig), String> {
+        match *self {}
+    }
+}
+
+/// The next record and its encoded length, or `None` at the end of the file
+#[cfg(feature = "recording")]
+fn read_record(reader: &mut impl Read) -> std::io::Result<Option<(Record, u64)>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_le_bytes(length) as u64;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        // Cut short while being written; play what is complete
+        return Ok(None);
+    }
+    let record =
+        bincode::deserialize(&bytes).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some((record, length)))
+}
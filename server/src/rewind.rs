@@ -0,0 +1,101 @@
+// Rewind buffer: full copies of the live run taken every so many frames, so
+// a client can jump back to re-watch a close pass and carry on from there.
+// Unlike the gallery nothing is sampled away; the run continues from a
+// keyframe exactly as it was.
+use n_body_shared::{Particle, PhysicsConfig};
+use std::collections::VecDeque;
+
+use crate::config::RewindConfig;
+
+/// The run at one frame
+pub struct Keyframe {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    /// Settings in effect then
+    pub config: PhysicsConfig,
+    pub particles: Vec<Particle>,
+}
+
+pub struct Rewind {
+    config: RewindConfig,
+    /// Oldest first
+    keyframes: VecDeque<Keyframe>,
+}
+
+impl Rewind {
+    pub fn new(config: &RewindConfig) -> Self {
+        Rewind {
+            config: config.clone(),
+            keyframes: VecDeque::new(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.enabled && self.config.interval > 0 && self.config.keyframes > 0
+    }
+
+    /// Forget the run, e.g. when it is reset
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// Take a keyframe if one is due at `frame_number`
+    pub fn observe(
+        &mut self,
+        particles: &[Particle],
+        config: &PhysicsConfig,
+        sim_time: f32,
+        frame_number: u64,
+    ) {
+        if !self.enabled() || !frame_number.is_multiple_of(self.config.interval) {
+            return;
+        }
+        let size = std::mem::size_of_val(particles);
+        let budget = self.config.max_mb.saturating_mul(1024 * 1024);
+        if size > budget {
+            return;
+        }
+
+        self.keyframes.push_back(Keyframe {
+            frame_number,
+            sim_time,
+            config: config.clone(),
+            particles: particles.to_vec(),
+        });
+        let mut total: usize = self
+            .keyframes
+            .iter()
+            .map(|keyframe| std::mem::size_of_val(keyframe.particles.as_slice()))
+            .sum();
+        while self.keyframes.len() > self.config.keyframes || total > budget {
+            let Some(oldest) = self.keyframes.pop_front() else {
+                break;
+            };
+            total -= std::mem::size_of_val(oldest.particles.as_slice());
+        }
+    }
+
+    /// The latest keyframe at or before `frame`, and those after it
+    /// dropped: the run goes on from there and will differ from them once
+    /// anything is changed.
+    pub fn seek(&mut self, frame: u64) -> Result<&Keyframe, String> {
+        let Some(oldest) = self.keyframes.front() else {
+            return Err("Nothing to seek to yet; no keyframes are buffered".to_string());
+        };
+        if frame < oldest.frame_number {
+            return Err(format!(
+                "Frame {} is no longer buffered; the oldest keyframe is frame {}",
+                frame, oldest.frame_number
+            ));
+        }
+        let kept = self
+            .keyframes
+            .iter()
+            .take_while(|keyframe| keyframe.frame_number <= frame)
+            .count();
+        self.keyframes.truncate(kept);
+        self.keyframes
+            .back()
+            .ok_or_else(|| "Rewind buffer is empty".to_string())
+    }
+}
@@ -0,0 +1,50 @@
+// Seedable random numbers for initial conditions. A hand-rolled SplitMix64
+// keeps particle sets identical for a given seed across platforms and crate
+// upgrades, which general-purpose RNG crates do not promise.
+use nalgebra::Vector3;
+
+/// SplitMix64 generator (Steele, Lea & Flood 2014). Fast, passes BigCrush,
+/// and any 64-bit seed is a good one.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform sample in [0, 1)
+    pub fn uniform(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform sample in [0, 1) with double precision
+    pub fn uniform_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal sample (Box-Muller)
+    pub fn gaussian(&mut self) -> f32 {
+        let u = self.uniform();
+        let v = self.uniform();
+        (-2.0 * (1.0 - u).ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+    }
+
+    /// Unit vector uniformly distributed over the sphere
+    pub fn direction(&mut self) -> Vector3<f32> {
+        let z = 2.0 * self.uniform() - 1.0;
+        let phi = std::f32::consts::TAU * self.uniform();
+        let s = (1.0 - z * z).max(0.0).sqrt();
+        Vector3::new(s * phi.cos(), s * phi.sin(), z)
+    }
+}
@@ -0,0 +1,297 @@
+// Rooms: `/ws/{room}` connects to a simulation of the room's own, stepped
+// and measured like the main one, so several experiments can run on one
+// server without interfering. Rooms named in `[simulations]` start with the
+// server and keep their own settings; any other name opens an ad-hoc room
+// with the `[simulation]` settings. `/ws/replay/{name}` opens a room that
+// plays back a recording instead, shared by everyone watching it.
+use arc_swap::ArcSwap;
+use log::{error, info};
+use std::collections::HashMap;
+#[cfg(feature = "recording")]
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::config::{Config, RoomsConfig, SimulationConfig};
+use crate::energy::EnergyMonitor;
+use crate::recording::Recorder;
+#[cfg(feature = "recording")]
+use crate::replay::Replay;
+use crate::scenarios::validate_name;
+use crate::service::Frame;
+use crate::simulation::Simulation;
+use crate::stepper::{self, Presence};
+use crate::watchdog::SimulationWatchdog;
+
+/// How often rooms are checked for having been left
+const PRUNE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A simulation with its stepping loop, energy monitor and recorder, which
+/// stop once the room and every client in it are gone
+pub struct Room {
+    /// Locked only to control the simulation; frames are read from `latest`
+    /// or `frames`
+    pub simulation: Arc<Mutex<Simulation>>,
+    /// The most recently published frame
+    pub latest: Arc<ArcSwap<Frame>>,
+    pub frames: broadcast::Sender<Arc<Frame>>,
+    pub energy: Arc<EnergyMonitor>,
+    pub recorder: Recorder,
+    /// Clients following the simulation
+    pub presence: Arc<Presence>,
+    /// Settings the simulation was created and is stepped with, swapped
+    /// when config.toml is reloaded
+    pub config: Arc<ArcSwap<SimulationConfig>>,
+}
+
+impl Room {
+    /// Create a simulation from `config` and start stepping it. Must be
+    /// called from within a Tokio runtime.
+    pub fn spawn(
+        config: &SimulationConfig,
+        debug: bool,
+        watchdog: Option<Arc<SimulationWatchdog>>,
+    ) -> Self {
+        Room::start(Simulation::new(config, debug), config, watchdog)
+    }
+
+    /// Start stepping a simulation created from `config`
+    pub fn start(
+        simulation: Simulation,
+        config: &SimulationConfig,
+        watchdog: Option<Arc<SimulationWatchdog>>,
+    ) -> Self {
+        let latest = Arc::new(ArcSwap::from_pointee(Frame::current(&simulation)));
+        let simulation = Arc::new(Mutex::new(simulation));
+        let presence = Arc::new(Presence::default());
+        let settings = Arc::new(ArcSwap::from_pointee(config.clone()));
+        let frames = stepper::spawn(
+            &simulation,
+            &latest,
+            watchdog,
+            config.pause_when_idle.then(|| presence.clone()),
+            &settings,
+        );
+        let energy = EnergyMonitor::spawn(&config.energy, &frames);
+        let recorder = Recorder::new(&config.recording, &simulation, &frames);
+        Room {
+            simulation,
+            latest,
+            frames,
+            energy,
+            recorder,
+            presence,
+            config: settings,
+        }
+    }
+
+    /// Take the settings a running simulation can change from `reloaded`
+    pub fn reload(&self, reloaded: &SimulationConfig) {
+        let mut config = SimulationConfig::clone(&self.config.load());
+        config.reload_from(reloaded);
+        if let Ok(mut sim) = self.simulation.lock() {
+            sim.reload_scenario_settings(&config);
+        }
+        self.config.store(Arc::new(config));
+    }
+}
+
+struct Entry {
+    /// Clients hold on to it while connected
+    room: Arc<Room>,
+    /// When the room was first seen without clients
+    empty_since: Option<Instant>,
+}
+
+pub struct Rooms {
+    settings: RoomsConfig,
+    /// Simulation settings new rooms start from
+    config: ArcSwap<Config>,
+    /// The `[simulations]`, which run until the server stops
+    named: HashMap<String, Arc<Room>>,
+    rooms: Mutex<HashMap<String, Entry>>,
+}
+
+impl Rooms {
+    /// Start the `[simulations]`. A named simulation with invalid settings
+    /// is left out. Must be called from within a Tokio runtime.
+    pub fn spawn(config: &Config) -> Arc<Self> {
+        let mut named = HashMap::new();
+        for name in config.simulations.keys() {
+            let simulation = validate_name(name)
+                .map_err(|_| "use only letters, digits, '-' and '_'".to_string())
+                .and_then(|_| config.named_simulation(name));
+            match simulation {
+                Ok(simulation) => {
+                    info!(
+                        "Starting simulation '{}': {} particles every {} ms",
+                        name, simulation.default_particles, simulation.update_rate_ms
+                    );
+                    let room = Room::spawn(&simulation, config.server.debug, None);
+                    named.insert(name.clone(), Arc::new(room));
+                }
+                Err(e) => error!("Skipping simulation '{}': {}", name, e),
+            }
+        }
+
+        let rooms = Arc::new(Rooms {
+            settings: config.rooms.clone(),
+            config: ArcSwap::from_pointee(config.clone()),
+            named,
+            rooms: Mutex::new(HashMap::new()),
+        });
+        if rooms.settings.enabled {
+            tokio::spawn(prune(Arc::downgrade(&rooms)));
+        }
+        rooms
+    }
+
+    /// The named simulation or room called `name`, a room being created
+    /// on first use
+    pub fn join(&self, name: &str) -> Result<Arc<Room>, String> {
+        if let Some(room) = self.named.get(name) {
+            return Ok(room.clone());
+        }
+        if !self.settings.enabled {
+            return Err("Rooms are disabled on this server".to_string());
+        }
+        validate_name(name).map_err(|_| {
+            format!(
+                "Invalid room name '{}': use only letters, digits, '-' and '_'",
+                name
+            )
+        })?;
+
+        let mut rooms = self
+            .rooms
+            .lock()
+            .map_err(|e| format!("Failed to lock rooms: {}", e))?;
+        if let Some(entry) = rooms.get_mut(name) {
+            entry.empty_since = None;
+            return Ok(entry.room.clone());
+        }
+        if rooms.len() >= self.settings.max_rooms {
+            return Err(format!(
+                "All {} rooms are in use; try again later",
+                self.settings.max_rooms
+            ));
+        }
+
+        info!("Opening room '{}'", name);
+        let config = self.config.load();
+        let room = Arc::new(Room::spawn(
+            &config.simulation,
+            config.server.debug,
+            None,
+        ));
+        rooms.insert(
+            name.to_string(),
+            Entry {
+                room: room.clone(),
+                empty_since: None,
+            },
+        );
+        Ok(room)
+    }
+
+    /// The room playing back recording `name`, opened on first use
+    #[cfg(feature = "recording")]
+    pub fn replay(&self, name: &str) -> Result<Arc<Room>, String> {
+        if !self.settings.enabled {
+            return Err("Replays are disabled on this server".to_string());
+        }
+        // Not a valid room name, so replays and rooms can't collide
+        let key = format!("replay:{}", name);
+
+        let mut rooms = self
+            .rooms
+            .lock()
+            .map_err(|e| format!("Failed to lock rooms: {}", e))?;
+        if let Some(entry) = rooms.get_mut(&key) {
+            entry.empty_since = None;
+            return Ok(entry.room.clone());
+        }
+        if rooms.len() >= self.settings.max_rooms {
+            return Err(format!(
+                "All {} rooms are in use; try again later",
+                self.settings.max_rooms
+            ));
+        }
+
+        let loaded = self.config.load();
+        let settings = &loaded.simulation;
+        let replay = Replay::open(Path::new(&settings.recording.dir), name)?;
+        info!(
+            "Replaying recording '{}': {} frames, every {} published",
+            name,
+            replay.len(),
+            replay.header().interval
+        );
+        // Recorded frames are shown as far apart as they were published,
+        // and a replay is not recorded again
+        let mut config = settings.clone();
+        config.update_rate_ms = settings.update_rate_ms * replay.header().interval;
+        config.recording.enabled = false;
+        let simulation = Simulation::replay(&config, loaded.server.debug, replay)?;
+        let room = Arc::new(Room::start(simulation, &config, None));
+        rooms.insert(
+            key,
+            Entry {
+                room: room.clone(),
+                empty_since: None,
+            },
+        );
+        Ok(room)
+    }
+
+    /// Apply a reloaded config.toml to the named simulations and open
+    /// rooms, and open new rooms with it. Replays keep their pace.
+    pub fn reload(&self, config: &Config) {
+        for (name, room) in &self.named {
+            match config.named_simulation(name) {
+                Ok(simulation) => room.reload(&simulation),
+                Err(e) => error!("Not reloading simulation '{}': {}", name, e),
+            }
+        }
+        if let Ok(rooms) = self.rooms.lock() {
+            for (name, entry) in rooms.iter() {
+                if !name.starts_with("replay:") {
+                    entry.room.reload(&config.simulation);
+                }
+            }
+        }
+        self.config.store(Arc::new(config.clone()));
+    }
+}
+
+/// Close rooms left empty for longer than the idle timeout
+async fn prune(rooms: Weak<Rooms>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(rooms) = rooms.upgrade() else {
+            break;
+        };
+        let timeout = Duration::from_secs(rooms.settings.idle_timeout_sec);
+        let Ok(mut entries) = rooms.rooms.lock() else {
+            break;
+        };
+        entries.retain(|name, entry| {
+            if Arc::strong_count(&entry.room) > 1 {
+                entry.empty_since = None;
+                return true;
+            }
+            let empty_since = *entry.empty_since.get_or_insert_with(Instant::now);
+            let keep = empty_since.elapsed() < timeout;
+            if !keep {
+                info!(
+                    "Closing room '{}' after {}s without clients",
+                    name,
+                    timeout.as_secs()
+                );
+            }
+            keep
+        });
+    }
+}
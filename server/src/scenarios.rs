@@ -0,0 +1,662 @@
+// Scenario definition files: custom initial conditions assembled from the
+// built-in generators and individual bodies, loaded from the scenarios
+// directory by name
+use n_body_shared::{MassFunction, Particle, ScenarioInfo};
+use nalgebra::{Point3, Vector3};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::config::{BinaryConfig, ProtoplanetaryConfig};
+use crate::galaxy::{
+    add_dispersion, apply_mass_function, generate_binary_cluster, generate_composite_galaxy,
+    generate_elliptical_galaxy, generate_king_cluster, generate_plummer_sphere,
+    generate_protoplanetary_disk, generate_spiral_galaxy, in_group, KingParams,
+};
+use crate::import::ImportFormat;
+use crate::rng::Rng;
+use crate::script;
+
+/// Recognised file extensions, tried in this order
+const EXTENSIONS: [&str; 2] = ["toml", "json"];
+
+/// A scenario file: an optional title and description, a softening length
+/// and the components placed on every reset
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioFile {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    /// Gravitational softening; defaults to the one for sampled particles,
+    /// set it much smaller for scenarios made of point masses
+    #[serde(default)]
+    pub softening: Option<f32>,
+    pub components: Vec<Component>,
+}
+
+/// One building block of a scenario, selected by its `generator` field.
+/// Positions and velocities are `[x, y, z]`, colors `[r, g, b, a]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "generator", rename_all = "lowercase")]
+pub enum Component {
+    /// Rotating two-armed disk galaxy
+    Spiral {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(default = "default_galaxy_radius")]
+        radius: f32,
+        #[serde(default = "default_galaxy_color")]
+        color: [f32; 4],
+        /// Random velocities as a fraction of the orbital speed
+        #[serde(default)]
+        dispersion: f32,
+        #[serde(default)]
+        mass_function: MassFunction,
+    },
+    /// Hernquist sphere in equilibrium
+    Elliptical {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(default = "default_galaxy_radius")]
+        radius: f32,
+        #[serde(default = "default_galaxy_color")]
+        color: [f32; 4],
+        /// Random velocities as a fraction of the orbital speed
+        #[serde(default)]
+        dispersion: f32,
+        #[serde(default)]
+        mass_function: MassFunction,
+    },
+    /// Disk, bulge and dark halo galaxy
+    Composite {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(default = "default_galaxy_radius")]
+        radius: f32,
+        #[serde(default = "default_galaxy_color")]
+        color: [f32; 4],
+        /// Random velocities as a fraction of the orbital speed
+        #[serde(default)]
+        dispersion: f32,
+        #[serde(default)]
+        mass_function: MassFunction,
+    },
+    /// Plummer sphere of total `mass` and virial radius `radius`
+    Plummer {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(default = "default_one")]
+        mass: f32,
+        #[serde(default = "default_one")]
+        radius: f32,
+        #[serde(default)]
+        color: Option<[f32; 4]>,
+    },
+    /// King model star cluster
+    King {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(default = "default_one")]
+        mass: f32,
+        #[serde(default = "default_one")]
+        tidal_radius: f32,
+        #[serde(default = "default_concentration")]
+        concentration: f32,
+        #[serde(default)]
+        color: Option<[f32; 4]>,
+    },
+    /// Star with a thin Keplerian disk of planetesimals; `particles`
+    /// includes the star. Set a small `softening` in the file.
+    Disk {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(flatten)]
+        disk: ProtoplanetaryConfig,
+    },
+    /// Plummer cluster of single stars, binaries and hierarchical triples;
+    /// set a small `softening` in the file
+    Binaries {
+        particles: usize,
+        #[serde(default)]
+        center: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        #[serde(flatten)]
+        binaries: BinaryConfig,
+    },
+    /// A single point mass
+    Body {
+        position: [f32; 3],
+        #[serde(default)]
+        velocity: [f32; 3],
+        mass: f32,
+        #[serde(default = "default_body_color")]
+        color: [f32; 4],
+        /// Pinned in place, like the central attractor
+        #[serde(default)]
+        fixed: bool,
+    },
+    /// Particles emitted by a Rhai script, run again at every reset; see
+    /// [`script::run`] for what it can call
+    Script {
+        script: String,
+        /// Particles the script made when the file was loaded
+        #[serde(skip)]
+        particles: usize,
+    },
+}
+
+fn default_galaxy_radius() -> f32 {
+    2.0
+}
+
+fn default_galaxy_color() -> [f32; 4] {
+    [0.8, 0.8, 1.0, 1.0]
+}
+
+fn default_body_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_concentration() -> f32 {
+    1.5
+}
+
+impl Component {
+    fn particle_count(&self) -> usize {
+        match self {
+            Component::Spiral { particles, .. }
+            | Component::Elliptical { particles, .. }
+            | Component::Composite { particles, .. }
+            | Component::Disk { particles, .. }
+            | Component::Binaries { particles, .. }
+            | Component::Plummer { particles, .. }
+            | Component::King { particles, .. }
+            | Component::Script { particles, .. } => *particles,
+            Component::Body { .. } => 1,
+        }
+    }
+
+    /// Sizes and masses must be positive for the generators to make sense
+    fn validate(&self) -> Result<(), String> {
+        let positive = |what: &str, value: f32| {
+            if value.is_finite() && value > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be positive, got {}", what, value))
+            }
+        };
+        let non_negative = |what: &str, value: f32| {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must not be negative, got {}", what, value))
+            }
+        };
+        match self {
+            Component::Spiral {
+                radius, dispersion, ..
+            }
+            | Component::Elliptical {
+                radius, dispersion, ..
+            }
+            | Component::Composite {
+                radius, dispersion, ..
+            } => positive("radius", *radius).and(non_negative("dispersion", *dispersion)),
+            Component::Plummer { mass, radius, .. } => {
+                positive("mass", *mass).and(positive("radius", *radius))
+            }
+            Component::King {
+                mass,
+                tidal_radius,
+                concentration,
+                ..
+            } => positive("mass", *mass)
+                .and(positive("tidal_radius", *tidal_radius))
+                .and(positive("concentration", *concentration)),
+            Component::Disk { disk, .. } => disk.validate(),
+            Component::Binaries { binaries, .. } => binaries.validate(),
+            Component::Body { mass, .. } => positive("mass", *mass),
+            // Scripts are checked by running them when the file is loaded
+            Component::Script { .. } => Ok(()),
+        }
+    }
+
+    fn generate(&self, gravity: f32, rng: &mut Rng) -> Vec<Particle> {
+        match *self {
+            Component::Spiral {
+                particles,
+                center,
+                velocity,
+                radius,
+                color,
+                dispersion,
+                mass_function,
+            } => {
+                let mut galaxy = generate_spiral_galaxy(
+                    particles,
+                    Point3::from(center),
+                    Vector3::from(velocity),
+                    radius,
+                    color,
+                    rng,
+                );
+                add_dispersion(&mut galaxy, Vector3::from(velocity), dispersion, rng);
+                apply_mass_function(&mut galaxy, mass_function, rng);
+                galaxy
+            }
+            Component::Elliptical {
+                particles,
+                center,
+                velocity,
+                radius,
+                color,
+                dispersion,
+                mass_function,
+            } => {
+                let mut galaxy = generate_elliptical_galaxy(
+                    particles,
+                    Point3::from(center),
+                    Vector3::from(velocity),
+                    radius,
+                    color,
+                    gravity,
+                    rng,
+                );
+                add_dispersion(&mut galaxy, Vector3::from(velocity), dispersion, rng);
+                apply_mass_function(&mut galaxy, mass_function, rng);
+                galaxy
+            }
+            Component::Composite {
+                particles,
+                center,
+                velocity,
+                radius,
+                color,
+                dispersion,
+                mass_function,
+            } => {
+                let mut galaxy = generate_composite_galaxy(
+                    particles,
+                    Point3::from(center),
+                    Vector3::from(velocity),
+                    radius,
+                    color,
+                    gravity,
+                    rng,
+                );
+                add_dispersion(&mut galaxy, Vector3::from(velocity), dispersion, rng);
+                apply_mass_function(&mut galaxy, mass_function, rng);
+                galaxy
+            }
+            Component::Plummer {
+                particles,
+                center,
+                velocity,
+                mass,
+                radius,
+                color,
+            } => {
+                let mut sphere = generate_plummer_sphere(particles, mass, radius, gravity, rng);
+                for particle in &mut sphere {
+                    particle.position += Vector3::from(center);
+                    particle.velocity += Vector3::from(velocity);
+                }
+                recolor(sphere, color)
+            }
+            Component::King {
+                particles,
+                center,
+                velocity,
+                mass,
+                tidal_radius,
+                concentration,
+                color,
+            } => recolor(
+                generate_king_cluster(
+                    particles,
+                    Point3::from(center),
+                    Vector3::from(velocity),
+                    &KingParams {
+                        mass,
+                        tidal_radius,
+                        concentration,
+                    },
+                    gravity,
+                    rng,
+                ),
+                color,
+            ),
+            Component::Disk {
+                particles,
+                center,
+                velocity,
+                ref disk,
+            } => generate_protoplanetary_disk(
+                particles,
+                Point3::from(center),
+                Vector3::from(velocity),
+                disk,
+                gravity,
+                rng,
+            ),
+            Component::Binaries {
+                particles,
+                center,
+                velocity,
+                ref binaries,
+            } => generate_binary_cluster(
+                particles,
+                Point3::from(center),
+                Vector3::from(velocity),
+                binaries,
+                gravity,
+                rng,
+            ),
+            Component::Body {
+                position,
+                velocity,
+                mass,
+                color,
+                fixed,
+            } => vec![Particle {
+                position: Point3::from(position),
+                velocity: if fixed {
+                    Vector3::zeros()
+                } else {
+                    Vector3::from(velocity)
+                },
+                mass,
+                color,
+                fixed,
+                group: 0,
+            }],
+            Component::Script { ref script, .. } => {
+                match script::run(script, gravity, rng.next_u64()) {
+                    Ok(particles) => particles,
+                    Err(e) => {
+                        log::error!("Scenario script stopped working: {}", e);
+                        Vec::new()
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn recolor(mut particles: Vec<Particle>, color: Option<[f32; 4]>) -> Vec<Particle> {
+    if let Some(color) = color {
+        for particle in &mut particles {
+            particle.color = color;
+        }
+    }
+    particles
+}
+
+impl ScenarioFile {
+    /// Total number of particles the scenario generates
+    pub fn particle_count(&self) -> usize {
+        self.components.iter().map(Component::particle_count).sum()
+    }
+
+    pub fn generate(&self, gravity: f32, rng: &mut Rng) -> Vec<Particle> {
+        let mut particles = Vec::with_capacity(self.particle_count());
+        for (group, component) in self.components.iter().enumerate() {
+            particles.extend(in_group(component.generate(gravity, rng), group));
+        }
+        particles
+    }
+}
+
+/// Names are limited to letters, digits, `-` and `_` since clients choose
+/// them and they become file names
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid scenario name '{}': use only letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Load and check `<dir>/<name>.toml` or `<dir>/<name>.json`
+pub fn load(dir: &Path, name: &str) -> Result<ScenarioFile, String> {
+    validate_name(name)?;
+
+    let (path, content) = EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find_map(|path| std::fs::read_to_string(&path).ok().map(|c| (path, c)))
+        .ok_or_else(|| {
+            format!(
+                "No scenario file {}.toml or {}.json in {}",
+                name,
+                name,
+                dir.display()
+            )
+        })?;
+
+    let mut scenario: ScenarioFile = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&content).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    // A trial run catches script errors now rather than at every reset, and
+    // counts the particles for the size checks
+    for (index, component) in scenario.components.iter_mut().enumerate() {
+        if let Component::Script { script, particles } = component {
+            *particles = script::run(script, 1.0, 0)
+                .map_err(|e| format!("{}: component {}: {}", path.display(), index + 1, e))?
+                .len();
+        }
+    }
+
+    if scenario.particle_count() == 0 {
+        return Err(format!("{} defines no particles", path.display()));
+    }
+    for (index, component) in scenario.components.iter().enumerate() {
+        component
+            .validate()
+            .map_err(|e| format!("{}: component {}: {}", path.display(), index + 1, e))?;
+    }
+    if let Some(softening) = scenario.softening {
+        if softening.is_nan() || softening <= 0.0 {
+            return Err(format!(
+                "{}: softening must be positive, got {}",
+                path.display(),
+                softening
+            ));
+        }
+    }
+
+    Ok(scenario)
+}
+
+/// Built-in scenarios as offered to clients: name, title, description,
+/// recommended particle count and whether the scenario has fixed bodies
+const BUILT_IN: [(&str, &str, &str, usize, bool); 11] = [
+    (
+        "GalaxyCollision",
+        "Galaxy collision",
+        "Two galaxies on a head-on collision course",
+        4000,
+        false,
+    ),
+    (
+        "Plummer",
+        "Plummer sphere",
+        "Plummer sphere in standard N-body units, the classic benchmark model",
+        2000,
+        false,
+    ),
+    (
+        "GlobularCluster",
+        "Globular cluster",
+        "King model globular cluster orbiting an elliptical host galaxy",
+        5000,
+        false,
+    ),
+    (
+        "RingGalaxy",
+        "Ring galaxy",
+        "Cartwheel-like ring galaxy, expanding after a companion punched through its disk",
+        5000,
+        false,
+    ),
+    (
+        "ColdCollapse",
+        "Cold collapse",
+        "Uniform cloud falling in on itself from (nearly) rest",
+        2000,
+        false,
+    ),
+    (
+        "ProtoplanetaryDisk",
+        "Protoplanetary disk",
+        "Star ringed by a thin Keplerian disk of low-mass planetesimals",
+        3000,
+        false,
+    ),
+    (
+        "SolarSystem",
+        "Solar system",
+        "The Sun and planets at their J2000 positions, in AU; zoom out for the outer planets",
+        9,
+        true,
+    ),
+    (
+        "FigureEight",
+        "Three-body: figure-eight",
+        "Three equal masses on the periodic figure-eight orbit",
+        3,
+        true,
+    ),
+    (
+        "LagrangeTriangle",
+        "Three-body: Lagrange triangle",
+        "Three equal masses on a rotating equilateral triangle",
+        3,
+        true,
+    ),
+    (
+        "Pythagorean",
+        "Three-body: Pythagorean problem",
+        "Burrau's problem: masses 3, 4 and 5 released from rest",
+        3,
+        true,
+    ),
+    (
+        "BinaryCluster",
+        "Cluster with binaries",
+        "Star cluster seeded with binaries and hierarchical triples, to watch them harden and eject stars",
+        1000,
+        false,
+    ),
+];
+
+/// Every scenario a client can switch to: the built-in ones, then the
+/// scenario files and imported particle sets in `dir` by name. Files that
+/// fail to load are left out with a warning.
+pub fn catalog(dir: &Path) -> Vec<ScenarioInfo> {
+    let mut scenarios: Vec<ScenarioInfo> = BUILT_IN
+        .iter()
+        .map(|&(id, title, description, particles, fixed)| ScenarioInfo {
+            id: id.to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            recommended_particles: Some(particles),
+            fixed_particles: fixed,
+        })
+        .collect();
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Cannot list scenarios in {}: {}", dir.display(), e);
+            return scenarios;
+        }
+    };
+    // Sorted by name, and a name stored in two formats listed once, as
+    // loading picks the first of them
+    let mut files = BTreeSet::new();
+    let mut imports = BTreeMap::new();
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let (Some(stem), Some(extension)) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|e| e.to_str()),
+        ) else {
+            continue;
+        };
+        if validate_name(stem).is_err() {
+            continue;
+        }
+        if EXTENSIONS.contains(&extension) {
+            files.insert(stem.to_string());
+        } else if let Some(format) = [ImportFormat::Csv, ImportFormat::Gadget]
+            .into_iter()
+            .find(|format| format.extension() == extension)
+        {
+            imports.entry(stem.to_string()).or_insert(format);
+        }
+    }
+
+    for name in files {
+        match load(dir, &name) {
+            Ok(file) => scenarios.push(ScenarioInfo {
+                id: format!("file:{}", name),
+                title: file.name.clone().unwrap_or_else(|| name.clone()),
+                description: file.description.clone(),
+                recommended_particles: Some(file.particle_count()),
+                fixed_particles: true,
+            }),
+            Err(e) => log::warn!("Leaving scenario '{}' out of the list: {}", name, e),
+        }
+    }
+    // Imports are only counted when loaded, which is too slow for a listing
+    for (name, format) in imports {
+        scenarios.push(ScenarioInfo {
+            id: format!("import:{}", name),
+            title: name,
+            description: match format {
+                ImportFormat::Csv => "Particles imported from a CSV table".to_string(),
+                ImportFormat::Gadget => "Particles imported from a Gadget-2 snapshot".to_string(),
+            },
+            recommended_particles: None,
+            fixed_particles: true,
+        });
+    }
+    scenarios
+}
@@ -0,0 +1,154 @@
+// Splits the CPU between the live simulation and batch jobs. The live
+// simulation keeps rayon's global pool; jobs run on a separate, smaller pool,
+// so however many are queued they can never take the threads the
+// interactive simulation steps on.
+use log::{info, warn};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::config::JobsConfig;
+
+/// Rayon threads for each side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuPartition {
+    pub live_threads: usize,
+    /// 0 when jobs are disabled
+    pub job_threads: usize,
+}
+
+impl CpuPartition {
+    /// Split `cpus` threads. Jobs get `threads` from the config, by default a
+    /// quarter of the machine; the live simulation always keeps at least one
+    /// thread to itself unless the machine has only one.
+    pub fn new(config: &JobsConfig, cpus: usize) -> Self {
+        let cpus = cpus.max(1);
+        if !config.enabled {
+            return CpuPartition {
+                live_threads: cpus,
+                job_threads: 0,
+            };
+        }
+        let job_threads = config
+            .threads
+            .unwrap_or(cpus / 4)
+            .clamp(1, (cpus - 1).max(1));
+        CpuPartition {
+            live_threads: (cpus - job_threads).max(1),
+            job_threads,
+        }
+    }
+}
+
+/// A queued job. Higher priorities run first, equal ones in submission order.
+struct Pending<T> {
+    priority: i32,
+    id: u64,
+    work: T,
+}
+
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Pending<T> {}
+
+impl<T> PartialOrd for Pending<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Pending<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+struct Queue<T> {
+    pending: Mutex<BinaryHeap<Pending<T>>>,
+    ready: Condvar,
+}
+
+/// Priority queue feeding `concurrency` worker threads, which run each job
+/// inside the dedicated job thread pool
+pub struct Scheduler<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Send + 'static> Scheduler<T> {
+    /// Start the workers. `run` is called with each job's id and work.
+    pub fn new(
+        job_threads: usize,
+        concurrency: usize,
+        run: impl Fn(u64, T) + Send + Sync + 'static,
+    ) -> Self {
+        let queue = Arc::new(Queue {
+            pending: Mutex::new(BinaryHeap::new()),
+            ready: Condvar::new(),
+        });
+
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(job_threads.max(1))
+            .thread_name(|index| format!("job-{}", index))
+            .build()
+        {
+            Ok(pool) => Some(Arc::new(pool)),
+            Err(e) => {
+                // Jobs still run, sharing the live simulation's threads
+                warn!("Failed to build the job thread pool: {}", e);
+                None
+            }
+        };
+        info!(
+            "Job scheduler: {} worker(s) on {} thread(s)",
+            concurrency.max(1),
+            job_threads.max(1)
+        );
+
+        let run = Arc::new(run);
+        for _ in 0..concurrency.max(1) {
+            let queue = queue.clone();
+            let pool = pool.clone();
+            let run = run.clone();
+            std::thread::spawn(move || {
+                while let Some(Pending { id, work, .. }) = queue.next() {
+                    match &pool {
+                        Some(pool) => pool.install(|| run(id, work)),
+                        None => run(id, work),
+                    }
+                }
+            });
+        }
+
+        Scheduler { queue }
+    }
+
+    pub fn push(&self, id: u64, priority: i32, work: T) -> Result<(), String> {
+        let mut pending = self
+            .queue
+            .pending
+            .lock()
+            .map_err(|_| "Job queue lock poisoned".to_string())?;
+        pending.push(Pending { priority, id, work });
+        self.queue.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Queue<T> {
+    /// Block until a job is queued; `None` if the lock is poisoned
+    fn next(&self) -> Option<Pending<T>> {
+        let mut pending = self.pending.lock().ok()?;
+        loop {
+            if let Some(job) = pending.pop() {
+                return Some(job);
+            }
+            pending = self.ready.wait(pending).ok()?;
+        }
+    }
+}
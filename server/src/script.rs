@@ -0,0 +1,153 @@
+// Rhai scripts as scenario components: a small program run at every reset
+// emits the particles, so users can invent generators without recompiling
+use n_body_shared::Particle;
+
+/// Run `source` and collect the particles it emits. The script sees the
+/// constant `gravity` and these functions:
+///
+/// - `rand()`: uniform random number in [0, 1)
+/// - `gaussian()`: standard normal random number
+/// - `particle(#{ position: [x, y, z], velocity: [vx, vy, vz], mass: m,
+///   color: [r, g, b, a], fixed: false })`: emit one particle; only
+///   `position` and `mass` are required
+///
+/// Random numbers come from `seed`, so the same seed gives the same particles.
+#[cfg(feature = "scripting")]
+pub fn run(source: &str, gravity: f32, seed: u64) -> Result<Vec<Particle>, String> {
+    engine::run(source, gravity, seed)
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run(_source: &str, _gravity: f32, _seed: u64) -> Result<Vec<Particle>, String> {
+    Err("Scenario scripts require the `scripting` feature".to_string())
+}
+
+#[cfg(feature = "scripting")]
+mod engine {
+    use n_body_shared::{Particle, MAX_PARTICLES};
+    use nalgebra::{Point3, Vector3};
+    use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::rng::Rng;
+
+    /// Budget of script operations, enough for a few hundred per particle at
+    /// the particle limit while still stopping runaway loops within seconds
+    const MAX_OPERATIONS: u64 = 200_000_000;
+
+    /// Color of emitted particles that do not set one
+    const DEFAULT_COLOR: [f32; 4] = [0.8, 0.8, 1.0, 1.0];
+
+    pub fn run(source: &str, gravity: f32, seed: u64) -> Result<Vec<Particle>, String> {
+        let particles = Rc::new(RefCell::new(Vec::new()));
+        let rng = Rc::new(RefCell::new(Rng::new(seed)));
+
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_array_size(MAX_PARTICLES)
+            .set_max_map_size(64)
+            .set_max_string_size(4096)
+            .on_print(|text| log::info!("Scenario script: {}", text));
+
+        let uniform = rng.clone();
+        engine.register_fn("rand", move || uniform.borrow_mut().uniform_f64());
+        let normal = rng.clone();
+        engine.register_fn("gaussian", move || normal.borrow_mut().gaussian() as f64);
+        let emitted = particles.clone();
+        engine.register_fn(
+            "particle",
+            move |fields: Map| -> Result<(), Box<EvalAltResult>> {
+                let mut emitted = emitted.borrow_mut();
+                if emitted.len() >= MAX_PARTICLES {
+                    return Err(
+                        format!("Script emits more than {} particles", MAX_PARTICLES).into(),
+                    );
+                }
+                emitted.push(particle(&fields)?);
+                Ok(())
+            },
+        );
+
+        let mut scope = Scope::new();
+        scope.push_constant("gravity", gravity as f64);
+        engine
+            .run_with_scope(&mut scope, source)
+            .map_err(|e| format!("Script failed: {}", e))?;
+
+        let particles = particles.take();
+        Ok(particles)
+    }
+
+    /// A particle from the fields of a script's object map
+    fn particle(fields: &Map) -> Result<Particle, String> {
+        let field = |name: &str| fields.get(name);
+        let required = |name: &str| field(name).ok_or(format!("particle() needs '{}'", name));
+
+        let position = vector(required("position")?, "position")?;
+        let velocity = field("velocity").map_or(Ok([0.0; 3]), |v| vector(v, "velocity"))?;
+        let mass = number(required("mass")?, "mass")?;
+        if mass.is_nan() || mass <= 0.0 {
+            return Err(format!("particle() mass must be positive, got {}", mass));
+        }
+        let color = match field("color") {
+            Some(value) => numbers::<4>(value, "color")?,
+            None => DEFAULT_COLOR,
+        };
+        let fixed = match field("fixed") {
+            Some(value) => value
+                .as_bool()
+                .map_err(|_| "particle() 'fixed' must be true or false".to_string())?,
+            None => false,
+        };
+
+        Ok(Particle {
+            position: Point3::from(position),
+            velocity: Vector3::from(velocity),
+            mass,
+            color,
+            fixed,
+            group: 0,
+        })
+    }
+
+    fn vector(value: &Dynamic, what: &str) -> Result<[f32; 3], String> {
+        numbers::<3>(value, what)
+    }
+
+    /// An array of exactly `N` numbers
+    fn numbers<const N: usize>(value: &Dynamic, what: &str) -> Result<[f32; N], String> {
+        let items = value
+            .clone()
+            .into_array()
+            .map_err(|_| format!("particle() '{}' must be an array of {} numbers", what, N))?;
+        if items.len() != N {
+            return Err(format!(
+                "particle() '{}' must have {} numbers, got {}",
+                what,
+                N,
+                items.len()
+            ));
+        }
+        let mut result = [0.0; N];
+        for (slot, item) in result.iter_mut().zip(&items) {
+            *slot = number(item, what)?;
+        }
+        Ok(result)
+    }
+
+    /// A finite number; integers are accepted too, so `[1, 0, 0]` works
+    fn number(value: &Dynamic, what: &str) -> Result<f32, String> {
+        let number = value
+            .as_float()
+            .or_else(|_| value.as_int().map(|int| int as f64))
+            .map_err(|_| format!("particle() '{}' must be numeric", what))?
+            as f32;
+        if number.is_finite() {
+            Ok(number)
+        } else {
+            Err(format!("particle() '{}' must be finite", what))
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Network-free simulation driver for embedding in other Rust programs
+use n_body_shared::{ParticleScaling, PhysicsConfig, SimulationState, SimulationStats};
+use rayon::prelude::*;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::config::Config;
+use crate::physics::wrap_position;
+use crate::rooms::Room;
+use crate::simulation::Simulation;
+use crate::stepper::Attendance;
+
+/// One published simulation step
+#[derive(Debug)]
+pub struct Frame {
+    /// Shared by every consumer of the frame, and by the frames after it
+    /// until the simulation changes
+    pub state: Arc<SimulationState>,
+    pub stats: SimulationStats,
+    /// Simulation version after the step; unchanged while paused
+    pub version: u64,
+    pub params: FrameParams,
+    /// Particle count change made by the step, for clients to announce
+    pub scaling: Option<ParticleScaling>,
+}
+
+/// Settings the frame was stepped with, so consumers can measure and
+/// extrapolate it without locking the simulation
+#[derive(Debug, Clone, Copy)]
+pub struct FrameParams {
+    /// Simulated time between this frame and the next: the time step times
+    /// the steps per update
+    pub time_step: f32,
+    pub gravity: f32,
+    pub softening: f32,
+    pub periodic_box: Option<f32>,
+    pub paused: bool,
+}
+
+impl Frame {
+    /// `state` and `stats` as just produced by `sim`
+    pub fn new(sim: &Simulation, state: Arc<SimulationState>, stats: SimulationStats) -> Self {
+        let config = sim.get_config();
+        Frame {
+            state,
+            stats,
+            version: sim.version(),
+            params: FrameParams {
+                time_step: sim.frame_time_step(),
+                gravity: config.gravity_strength,
+                softening: sim.softening(),
+                periodic_box: config.periodic_box,
+                paused: sim.is_paused(),
+            },
+            scaling: None,
+        }
+    }
+
+    /// The simulation as it is now, without stepping it
+    pub fn current(sim: &Simulation) -> Self {
+        Frame::new(sim, Arc::new(sim.state()), sim.stats())
+    }
+
+    /// The frame after a step of `sim` that produced `stats`. The particles
+    /// are only copied when the step changed them; while paused the state
+    /// of `previous` is shared instead.
+    pub fn stepped(sim: &Simulation, stats: SimulationStats, previous: &Frame) -> Self {
+        let state = if previous.version == sim.version() {
+            previous.state.clone()
+        } else {
+            Arc::new(sim.state())
+        };
+        Frame {
+            scaling: sim.scaling().cloned(),
+            ..Frame::new(sim, state, stats)
+        }
+    }
+
+    /// State advanced kinematically by `fraction` of the way to the next
+    /// frame, for streaming smooth frames between slow physics updates.
+    /// Nothing moves while paused, so there is nothing to interpolate then.
+    pub fn extrapolated(&self, fraction: f32) -> Option<SimulationState> {
+        if self.params.paused {
+            return None;
+        }
+
+        let dt = self.params.time_step * fraction;
+        let periodic_box = self.params.periodic_box;
+        let mut state = SimulationState::clone(&self.state);
+        state
+            .positions
+            .par_chunks_exact_mut(3)
+            .enumerate()
+            .filter(|(index, _)| !self.state.is_fixed(*index))
+            .for_each(|(index, coords)| {
+                let mut position = self.state.position(index) + self.state.velocity(index) * dt;
+                if let Some(box_size) = periodic_box {
+                    position = wrap_position(position, box_size);
+                }
+                coords.copy_from_slice(position.coords.as_slice());
+            });
+        state.sim_time += dt;
+        state.interpolated = true;
+        Some(state)
+    }
+}
+
+/// Runs a simulation on a physics thread of its own, stepping it every
+/// `update_rate_ms` and publishing each frame to subscribers, the same way
+/// the server runs its rooms.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), String> {
+/// use n_body_server::{config::Config, SimulationService};
+///
+/// let handle = SimulationService::spawn(&Config::default());
+/// let mut frames = handle.subscribe();
+///
+/// handle.pause().await?;
+/// let frame = handle.step(10).await?;
+/// println!("t = {}", frame.state.sim_time);
+///
+/// handle.resume().await?;
+/// while let Ok(frame) = frames.recv().await {
+///     println!("frame {}", frame.state.frame_number);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SimulationService;
+
+impl SimulationService {
+    /// Create a simulation from `config` and start driving it. Must be called
+    /// from within a Tokio runtime. With `pause_when_idle`, it only steps
+    /// while subscribed to, and it stops once every handle has been dropped.
+    pub fn spawn(config: &Config) -> SimulationHandle {
+        let room = Room::spawn(&config.simulation, config.server.debug, None);
+        SimulationHandle {
+            room: Arc::new(room),
+        }
+    }
+}
+
+/// Cloneable handle for controlling a [`SimulationService`] and receiving its
+/// frames
+#[derive(Clone)]
+pub struct SimulationHandle {
+    room: Arc<Room>,
+}
+
+impl SimulationHandle {
+    /// Receive every frame published from now on
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            frames: self.room.frames.subscribe(),
+            _attendance: self.room.presence.join(),
+        }
+    }
+
+    /// The most recently published frame
+    pub fn latest(&self) -> Arc<Frame> {
+        self.room.latest.load_full()
+    }
+
+    /// Apply a new configuration, with the same validation as the websocket API
+    pub async fn update_config(&self, config: PhysicsConfig) -> Result<(), String> {
+        self.with_simulation(|sim| sim.update_config(config))
+            .await?
+    }
+
+    pub async fn config(&self) -> Result<PhysicsConfig, String> {
+        self.with_simulation(|sim| sim.get_config().clone()).await
+    }
+
+    /// Regenerate the initial conditions and restart the clock
+    pub async fn reset(&self) -> Result<(), String> {
+        self.with_simulation(|sim| sim.reset()).await
+    }
+
+    pub async fn pause(&self) -> Result<(), String> {
+        self.with_simulation(|sim| sim.set_paused(true)).await
+    }
+
+    pub async fn resume(&self) -> Result<(), String> {
+        self.with_simulation(|sim| sim.set_paused(false)).await
+    }
+
+    /// Advance the paused simulation by exactly `count` steps and return the
+    /// resulting frame; subscribers get it with the next published frame
+    pub async fn step(&self, count: u32) -> Result<Arc<Frame>, String> {
+        let latest = self.room.latest.clone();
+        self.with_simulation(move |sim| {
+            let stats = sim.step_paused(count)?;
+            Ok(Arc::new(Frame::stepped(sim, stats, &latest.load())))
+        })
+        .await?
+    }
+
+    /// Run `f` on the simulation off the async executor, as the lock may be
+    /// held by a long step
+    async fn with_simulation<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Simulation) -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let simulation = self.room.simulation.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut sim = simulation
+                .lock()
+                .map_err(|e| format!("Failed to lock simulation: {}", e))?;
+            Ok(f(&mut sim))
+        })
+        .await
+        .map_err(|e| format!("Simulation task failed: {}", e))?
+    }
+}
+
+/// Frames from [`SimulationHandle::subscribe`]. While it exists it counts as
+/// following the simulation, like a connected websocket client.
+pub struct Subscription {
+    frames: broadcast::Receiver<Arc<Frame>>,
+    _attendance: Attendance,
+}
+
+impl Subscription {
+    /// The next frame. A subscription that falls more than a few frames
+    /// behind skips ahead and sees `RecvError::Lagged`.
+    pub async fn recv(&mut self) -> Result<Arc<Frame>, broadcast::error::RecvError> {
+        self.frames.recv().await
+    }
+}
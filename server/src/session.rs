@@ -0,0 +1,112 @@
+use n_body_shared::{Role, ViewConfig};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+struct Session {
+    settings: ViewConfig,
+    last_seen: Instant,
+}
+
+/// A session as handed to a newly connected client
+pub struct OpenedSession {
+    pub token: String,
+    pub settings: ViewConfig,
+    pub role: Role,
+    pub resumed: bool,
+}
+
+/// Remembers per-client settings across reconnects, keyed by the session token
+/// issued in the welcome handshake
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(ttl_seconds: u64) -> Self {
+        SessionStore {
+            sessions: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(ttl_seconds),
+        }
+    }
+
+    /// Resume the session for `token` if it is still live, otherwise open a new
+    /// one seeded with `defaults`. The role is always the one authenticated for
+    /// this connection; a token only carries view settings, so presenting one
+    /// never grants more than the credentials sent alongside it.
+    pub fn open(&self, token: Option<&str>, defaults: ViewConfig, role: Role) -> OpenedSession {
+        let mut sessions = match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // Drop sessions whose client never came back
+        let ttl = self.ttl;
+        sessions.retain(|_, session| session.last_seen.elapsed() < ttl);
+
+        if let Some(token) = token {
+            if let Some(session) = sessions.get_mut(token) {
+                session.last_seen = Instant::now();
+                return OpenedSession {
+                    token: token.to_string(),
+                    settings: session.settings.clone(),
+                    role,
+                    resumed: true,
+                };
+            }
+        }
+
+        let token = generate_token();
+        sessions.insert(
+            token.clone(),
+            Session {
+                settings: defaults.clone(),
+                last_seen: Instant::now(),
+            },
+        );
+        OpenedSession {
+            token,
+            settings: defaults,
+            role,
+            resumed: false,
+        }
+    }
+
+    /// Store the latest settings for a session and refresh its expiry
+    pub fn update(&self, token: &str, settings: ViewConfig) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            if let Some(session) = sessions.get_mut(token) {
+                session.settings = settings;
+                session.last_seen = Instant::now();
+            }
+        }
+    }
+}
+
+fn generate_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // RandomState is keyed from OS randomness, so hashing a counter and the
+    // current time through it yields tokens that can't be guessed from outside
+    let state = RandomState::new();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut parts = [0u64; 2];
+    for (i, part) in parts.iter_mut().enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(count);
+        hasher.write_u128(nanos);
+        hasher.write_usize(i);
+        *part = hasher.finish();
+    }
+
+    format!("{:016x}{:016x}", parts[0], parts[1])
+}
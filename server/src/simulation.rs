@@ -1,30 +1,144 @@
 use n_body_shared::{
-    Particle, SimulationConfig, SimulationState, SimulationStats, MAX_COMPUTATION_TIME_MS,
-    MAX_PARTICLES,
+    ForceSolver, GalaxySpec, Integrator, MassFunction, Particle, ParticleScaling, PhysicsConfig,
+    Scenario, SimulationState, SimulationStats, ThetaTuning, TreeStats, MAX_COMPUTATION_TIME_MS,
+    MAX_PARTICLES, MAX_SINGLE_STEPS, MAX_STEPS_PER_UPDATE, STATS_SCHEMA_VERSION,
 };
 use nalgebra::{Point3, Vector3};
 use rayon::prelude::*;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Instant;
 
+use crate::autoscale::{self, AutoScale, Decision};
+use crate::config::{
+    BinaryConfig, ClusterConfig, ColdCollapseConfig, ProtoplanetaryConfig, RingConfig,
+};
+use crate::distributed::WorkerPool;
+use crate::galaxy::{
+    default_collision, generate_binary_cluster, generate_cluster_orbiting_host,
+    generate_cold_collapse, generate_galaxies, generate_plummer_sphere,
+    generate_protoplanetary_disk, generate_ring_galaxy, validate_galaxies,
+};
+use crate::gallery::Gallery;
+use crate::import;
+use crate::physics::{
+    barnes_hut_accelerations, closest_encounter, direct_accelerations,
+    post_newtonian_accelerations, sort_by_morton, wrap_position, ForceParams,
+};
+use crate::presets::{
+    generate_figure_eight, generate_lagrange_triangle, generate_pythagorean, generate_solar_system,
+    POINT_MASS_SOFTENING,
+};
+use crate::replay::Replay;
+use crate::rewind::Rewind;
+use crate::rng::Rng;
+use crate::scenarios::{self, ScenarioFile};
+use crate::snapshot::Snapshot;
+use crate::theta;
+
+/// Softening for sampled particle distributions
+pub(crate) const DEFAULT_SOFTENING: f32 = 0.1;
+
 pub struct Simulation {
     particles: Vec<Particle>,
-    config: SimulationConfig,
+    config: PhysicsConfig,
     sim_time: f32,
     frame_number: u64,
+    /// Bumped whenever the particles change, so unchanged frames can be
+    /// recognised without comparing them
+    version: u64,
     is_paused: bool,
     last_computation_time: f32,
     consecutive_slow_frames: u32,
+    max_particles: usize,
+    /// `max_particles` as configured, which may exceed what one machine
+    /// allows once workers share the forces
+    configured_max_particles: Option<usize>,
+    /// Frames between Morton re-sorts, 0 to never sort
+    sort_interval: u64,
+    compensated_summation: bool,
+    encounter_distance: Option<f32>,
+    max_substeps: u32,
+    /// Sub-steps taken by the most recent frame
+    last_substeps: u32,
+    /// Barnes-Hut costs of the most recent frame
+    last_tree_stats: Option<TreeStats>,
+    /// Compare the next Barnes-Hut forces with the exact sum
+    sample_force_error: bool,
+    gallery: Gallery,
+    /// Recent keyframes to seek back to
+    rewind: Rewind,
+    /// Latest sampled force error, and whether theta has been tuned to it
+    force_error: Option<f32>,
+    force_error_pending: bool,
+    central_attractor_mass: Option<f32>,
+    cluster: ClusterConfig,
+    ring: RingConfig,
+    cold_collapse: ColdCollapseConfig,
+    protoplanetary: ProtoplanetaryConfig,
+    binaries: BinaryConfig,
+    /// Velocity dispersion of the default collision's galaxies
+    dispersion: f32,
+    /// Mass function of the default collision's galaxies
+    mass_function: MassFunction,
+    scenarios_dir: PathBuf,
+    /// Last successfully loaded definition of the current `Scenario::File`
+    scenario_file: Option<ScenarioFile>,
+    /// Last successfully loaded particles of the current `Scenario::Import`
+    imported: Option<Vec<Particle>>,
+    /// Other server processes to share the force computation with
+    workers: Option<Arc<WorkerPool>>,
+    /// Whether the last attempt to use `workers` failed, to log only changes
+    workers_failing: bool,
+    /// Fits the particle count to the frame-time budget, when enabled
+    auto_scale: Option<AutoScale>,
+    /// Particle count last set by configuration or a client, which
+    /// automatic scaling never raises the count above
+    requested_particles: usize,
+    /// Change of the particle count made by the most recent step
+    scaling: Option<ParticleScaling>,
+    /// Recording played back in place of the physics
+    replay: Option<Replay>,
 }
 
 impl Simulation {
     pub fn new(sim_config: &crate::config::SimulationConfig, debug: bool) -> Self {
-        let config = SimulationConfig {
+        let mut sim = Simulation::configured(sim_config, debug);
+        sim.reset();
+        sim
+    }
+
+    /// Play back `replay` from its first frame instead of stepping physics.
+    /// The recorded settings replace those of `sim_config`.
+    pub fn replay(
+        sim_config: &crate::config::SimulationConfig,
+        debug: bool,
+        replay: Replay,
+    ) -> Result<Self, String> {
+        let mut sim = Simulation::configured(sim_config, debug);
+        sim.replay = Some(replay);
+        sim.play()?;
+        Ok(sim)
+    }
+
+    /// Settings from `sim_config`, without particles yet
+    fn configured(sim_config: &crate::config::SimulationConfig, debug: bool) -> Self {
+        let config = PhysicsConfig {
             particle_count: sim_config.default_particles,
             time_step: 0.01,
-            gravity_strength: 1.0,
-            visual_fps: 30,
-            zoom_level: 1.0,
+            steps_per_update: sim_config.steps_per_update.clamp(1, MAX_STEPS_PER_UPDATE),
+            gravity_strength: sim_config.gravity_strength,
             debug,
+            periodic_box: sim_config.periodic_box,
+            solver: sim_config.solver,
+            theta: sim_config.theta,
+            theta_tuning: sim_config.theta_tuning,
+            integrator: sim_config.integrator,
+            speed_of_light: sim_config.speed_of_light,
+            scenario: sim_config.scenario.clone(),
+            morphologies: sim_config.morphologies,
+            galaxies: sim_config.galaxies.clone(),
+            seed: sim_config.seed,
         };
 
         let mut sim = Simulation {
@@ -32,73 +146,624 @@ impl Simulation {
             config,
             sim_time: 0.0,
             frame_number: 0,
+            version: 0,
             is_paused: false,
             last_computation_time: 0.0,
             consecutive_slow_frames: 0,
+            max_particles: sim_config
+                .max_particles
+                .map_or(MAX_PARTICLES, |max| max.min(MAX_PARTICLES)),
+            configured_max_particles: sim_config.max_particles,
+            sort_interval: sim_config.sort_interval,
+            compensated_summation: sim_config.compensated_summation,
+            encounter_distance: sim_config.encounter_distance,
+            max_substeps: sim_config.max_substeps.max(1),
+            last_substeps: 1,
+            last_tree_stats: None,
+            sample_force_error: false,
+            gallery: Gallery::new(&sim_config.gallery),
+            rewind: Rewind::new(&sim_config.rewind),
+            force_error: None,
+            force_error_pending: false,
+            central_attractor_mass: sim_config.central_attractor_mass,
+            cluster: sim_config.cluster.clone(),
+            ring: sim_config.ring.clone(),
+            cold_collapse: sim_config.cold_collapse.clone(),
+            protoplanetary: sim_config.protoplanetary.clone(),
+            binaries: sim_config.binaries.clone(),
+            dispersion: sim_config.dispersion,
+            mass_function: sim_config.mass_function,
+            scenarios_dir: PathBuf::from(&sim_config.scenarios_dir),
+            scenario_file: None,
+            imported: None,
+            workers: None,
+            workers_failing: false,
+            auto_scale: None,
+            requested_particles: sim_config.default_particles,
+            scaling: None,
+            replay: None,
         };
 
-        sim.reset();
+        if sim_config.auto_scale.enabled {
+            match sim_config.auto_scale.validate() {
+                Ok(()) => sim.auto_scale = Some(AutoScale::new(sim_config.auto_scale.clone())),
+                Err(e) => log::error!("Ignoring configured auto-scaling: {}", e),
+            }
+        }
+
+        if let Err(e) = validate_galaxies(&sim.config.galaxies) {
+            log::error!("Ignoring configured galaxies: {}", e);
+            sim.config.galaxies.clear();
+        }
+        sim.check_scenario_settings();
+        if let Err(e) = theta::validate(sim.config.theta_tuning) {
+            log::error!("Ignoring configured theta tuning: {}", e);
+            sim.config.theta_tuning = ThetaTuning::Off;
+        }
         sim
     }
 
+    /// Take the scenarios' settings from a reloaded `sim_config`; they
+    /// apply from the next reset
+    pub fn reload_scenario_settings(&mut self, sim_config: &crate::config::SimulationConfig) {
+        self.central_attractor_mass = sim_config.central_attractor_mass;
+        self.cluster = sim_config.cluster.clone();
+        self.ring = sim_config.ring.clone();
+        self.cold_collapse = sim_config.cold_collapse.clone();
+        self.protoplanetary = sim_config.protoplanetary.clone();
+        self.binaries = sim_config.binaries.clone();
+        self.dispersion = sim_config.dispersion;
+        self.mass_function = sim_config.mass_function;
+        self.check_scenario_settings();
+    }
+
+    /// Replace scenario settings that can't generate particles by defaults
+    fn check_scenario_settings(&mut self) {
+        if !self.dispersion.is_finite() || self.dispersion < 0.0 {
+            log::error!(
+                "Ignoring invalid velocity dispersion {}; using cold orbits",
+                self.dispersion
+            );
+            self.dispersion = 0.0;
+        }
+        if let Err(e) = self.protoplanetary.validate() {
+            log::error!("Ignoring configured protoplanetary disk: {}", e);
+            self.protoplanetary = ProtoplanetaryConfig::default();
+        }
+        if let Err(e) = self.binaries.validate() {
+            log::error!("Ignoring configured binary cluster: {}", e);
+            self.binaries = BinaryConfig::default();
+        }
+    }
+
     pub fn reset(&mut self) {
-        self.particles = generate_galaxy_collision(self.config.particle_count);
+        // A replay starts over instead
+        if let Some(replay) = &mut self.replay {
+            replay.rewind();
+            self.play_or_pause();
+            return;
+        }
+
+        // Re-read files on every reset so edits show up without a restart;
+        // a broken edit keeps the last good version
+        match &self.config.scenario {
+            Scenario::File(name) => match self.load_scenario_file(name) {
+                Ok(scenario) => self.scenario_file = Some(scenario),
+                Err(e) => log::error!("{}", e),
+            },
+            Scenario::Import(name) => match self.load_import(name) {
+                Ok(particles) => self.imported = Some(particles),
+                Err(e) => log::error!("{}", e),
+            },
+            _ => {}
+        }
+
+        // Same seed, same particles
+        let mut rng = Rng::new(self.config.seed);
+        self.particles = match &self.config.scenario {
+            Scenario::GalaxyCollision => generate_galaxies(
+                &self.collision_galaxies(),
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::GlobularCluster => generate_cluster_orbiting_host(
+                self.config.particle_count,
+                &self.cluster,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::RingGalaxy => generate_ring_galaxy(
+                self.config.particle_count,
+                &self.ring,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::ColdCollapse => generate_cold_collapse(
+                self.config.particle_count,
+                &self.cold_collapse,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::ProtoplanetaryDisk => generate_protoplanetary_disk(
+                self.config.particle_count,
+                Point3::origin(),
+                Vector3::zeros(),
+                &self.protoplanetary,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::Plummer => generate_plummer_sphere(
+                self.config.particle_count,
+                1.0,
+                1.0,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::SolarSystem => generate_solar_system(self.config.gravity_strength),
+            Scenario::FigureEight => generate_figure_eight(self.config.gravity_strength),
+            Scenario::LagrangeTriangle => generate_lagrange_triangle(self.config.gravity_strength),
+            Scenario::Pythagorean => generate_pythagorean(),
+            Scenario::BinaryCluster => generate_binary_cluster(
+                self.config.particle_count,
+                Point3::origin(),
+                Vector3::zeros(),
+                &self.binaries,
+                self.config.gravity_strength,
+                &mut rng,
+            ),
+            Scenario::File(name) => match &self.scenario_file {
+                Some(scenario) => scenario.generate(self.config.gravity_strength, &mut rng),
+                None => {
+                    log::warn!(
+                        "Scenario file '{}' unavailable, generating a galaxy collision instead",
+                        name
+                    );
+                    generate_galaxies(
+                        &default_collision(
+                            self.config.particle_count,
+                            self.config.morphologies,
+                            self.dispersion,
+                            self.mass_function,
+                        ),
+                        self.config.gravity_strength,
+                        &mut rng,
+                    )
+                }
+            },
+            Scenario::Import(name) => match &self.imported {
+                Some(particles) => particles.clone(),
+                None => {
+                    log::warn!(
+                        "Imported particles '{}' unavailable, generating a galaxy collision instead",
+                        name
+                    );
+                    generate_galaxies(
+                        &default_collision(
+                            self.config.particle_count,
+                            self.config.morphologies,
+                            self.dispersion,
+                            self.mass_function,
+                        ),
+                        self.config.gravity_strength,
+                        &mut rng,
+                    )
+                }
+            },
+        };
+        if let Some(mass) = self.central_attractor_mass {
+            // The pinned mass takes the place of one generated particle
+            if self.particles.len() >= self.config.particle_count {
+                self.particles
+                    .truncate(self.config.particle_count.saturating_sub(1));
+            }
+            self.particles
+                .push(pinned_attractor(Point3::origin(), mass));
+        }
+        self.wrap_particles();
         self.sim_time = 0.0;
         self.frame_number = 0;
+        self.version += 1;
+        self.gallery.clear();
+        self.gallery
+            .observe(&self.particles, self.sim_time, self.frame_number);
+        self.rewind.clear();
+        self.rewind.observe(
+            &self.particles,
+            &self.config,
+            self.sim_time,
+            self.frame_number,
+        );
+    }
+
+    /// The configured galaxies, or the default head-on pair
+    fn collision_galaxies(&self) -> Vec<GalaxySpec> {
+        if self.config.galaxies.is_empty() {
+            default_collision(
+                self.config.particle_count,
+                self.config.morphologies,
+                self.dispersion,
+                self.mass_function,
+            )
+        } else {
+            self.config.galaxies.clone()
+        }
     }
 
-    pub fn update_config(&mut self, config: SimulationConfig) -> Result<(), String> {
+    /// Switch to `scenario` and rebuild the particles from it. Selecting the
+    /// current scenario again resets it, which also rereads its file.
+    pub fn set_scenario(&mut self, scenario: Scenario) -> Result<(), String> {
+        if self.config.scenario == scenario {
+            self.reset();
+            return Ok(());
+        }
+        let mut config = self.config.clone();
+        config.scenario = scenario;
+        self.update_config(config)
+    }
+
+    /// Bring out-of-range counts within the server's limits, describing
+    /// each change so the client can be told what it got instead
+    pub fn clamp_config(&self, config: &mut PhysicsConfig) -> Vec<String> {
+        let mut adjusted = Vec::new();
+        if config.particle_count > self.max_particles {
+            adjusted.push(format!(
+                "particle count {} lowered to the maximum of {}",
+                config.particle_count, self.max_particles
+            ));
+            config.particle_count = self.max_particles;
+        }
+        let steps = config.steps_per_update.clamp(1, MAX_STEPS_PER_UPDATE);
+        if steps != config.steps_per_update {
+            adjusted.push(format!(
+                "steps per update {} changed to {}",
+                config.steps_per_update, steps
+            ));
+            config.steps_per_update = steps;
+        }
+        adjusted
+    }
+
+    /// Reject settings the simulation can't run with
+    fn check_config(&self, config: &PhysicsConfig) -> Result<(), String> {
         // Validate particle count
-        if config.particle_count > MAX_PARTICLES {
+        if config.particle_count > self.max_particles {
             return Err(format!(
                 "Particle count {} exceeds maximum of {}. Please reduce the particle count to prevent server overload.",
-                config.particle_count, MAX_PARTICLES
+                config.particle_count, self.max_particles
+            ));
+        }
+
+        if !config.time_step.is_finite() || config.time_step <= 0.0 {
+            return Err(format!(
+                "Time step must be positive, got {}",
+                config.time_step
+            ));
+        }
+
+        if !config.gravity_strength.is_finite() {
+            return Err(format!(
+                "Gravity strength must be a finite number, got {}",
+                config.gravity_strength
             ));
         }
 
-        let need_reset = self.config.particle_count != config.particle_count;
+        if !(1..=MAX_STEPS_PER_UPDATE).contains(&config.steps_per_update) {
+            return Err(format!(
+                "Steps per update must be between 1 and {}, got {}",
+                MAX_STEPS_PER_UPDATE, config.steps_per_update
+            ));
+        }
+
+        if let Some(box_size) = config.periodic_box {
+            if !box_size.is_finite() || box_size <= 0.0 {
+                return Err(format!(
+                    "Periodic box size must be positive and finite, got {}",
+                    box_size
+                ));
+            }
+        }
+
+        if let Some(c) = config.speed_of_light {
+            if !c.is_finite() || c <= 0.0 {
+                return Err(format!(
+                    "Speed of light must be positive and finite, got {}",
+                    c
+                ));
+            }
+        }
+
+        if config.theta.is_nan() || config.theta < 0.0 {
+            return Err(format!(
+                "Barnes-Hut theta must be zero or positive, got {}",
+                config.theta
+            ));
+        }
+        theta::validate(config.theta_tuning)?;
+
+        if !config.galaxies.is_empty() {
+            validate_galaxies(&config.galaxies)?;
+            let total: usize = config.galaxies.iter().map(|galaxy| galaxy.count).sum();
+            if total > self.max_particles {
+                return Err(format!(
+                    "Galaxies add up to {} particles, more than the maximum of {}",
+                    total, self.max_particles
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_config(&mut self, config: PhysicsConfig) -> Result<(), String> {
+        if self.replay.is_some() {
+            return Err("A replay keeps the settings it was recorded with".to_string());
+        }
+        self.check_config(&config)?;
+
+        // Catch a missing or broken scenario or import file before
+        // switching to it
+        if self.config.scenario != config.scenario {
+            match &config.scenario {
+                Scenario::File(name) => self.scenario_file = Some(self.load_scenario_file(name)?),
+                Scenario::Import(name) => self.imported = Some(self.load_import(name)?),
+                _ => {}
+            }
+        }
+
+        let count_changed = self.config.particle_count != config.particle_count;
+        let scenario_changed = self.config.scenario != config.scenario
+            || self.config.morphologies != config.morphologies
+            || self.config.galaxies != config.galaxies
+            || self.config.seed != config.seed;
+        let need_reset = count_changed || scenario_changed;
+        let box_changed = self.config.periodic_box != config.periodic_box;
         let old_count = self.config.particle_count;
         let new_count = config.particle_count;
         self.config = config;
 
-        if need_reset {
+        if box_changed && !need_reset {
+            // Bring everything inside the new box; a reset does this anyway
+            self.wrap_particles();
+            self.version += 1;
+        }
+
+        if count_changed {
+            self.requested_particles = new_count;
             // Log the particle count change for better UX feedback
             log::info!(
                 "Particle count changed from {} to {}, resetting simulation",
                 old_count,
                 new_count
             );
+        }
+        if scenario_changed {
+            log::info!(
+                "Scenario changed to {:?} ({:?}, seed {}), resetting simulation",
+                self.config.scenario,
+                self.config.morphologies,
+                self.config.seed
+            );
+        }
+        if need_reset {
             self.reset();
         }
 
         Ok(())
     }
 
+    /// Negate every velocity so the run retraces its steps. Leapfrog and
+    /// Yoshida are time-symmetric, so they lead back to where the run came
+    /// from up to rounding; symplectic Euler only roughly.
+    pub fn reverse_time(&mut self) -> Result<(), String> {
+        if self.replay.is_some() {
+            return Err("A replay can't be reversed".to_string());
+        }
+        self.particles
+            .par_iter_mut()
+            .for_each(|particle| particle.velocity = -particle.velocity);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// The run as it is now, to be saved as `name`, or a name made from the
+    /// time
+    pub fn snapshot(&self, name: Option<String>) -> Result<Snapshot, String> {
+        Snapshot::new(
+            name,
+            self.config.clone(),
+            self.particles.clone(),
+            self.sim_time,
+            self.frame_number,
+        )
+    }
+
+    /// Continue from `snapshot` where it was saved, with its settings
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        if self.replay.is_some() {
+            return Err("A replay can't continue from a snapshot".to_string());
+        }
+        if snapshot.particles.len() > self.max_particles {
+            return Err(format!(
+                "Snapshot '{}' has {} particles, more than the maximum of {}",
+                snapshot.info.name,
+                snapshot.particles.len(),
+                self.max_particles
+            ));
+        }
+        self.check_config(&snapshot.config)?;
+
+        self.resume_at(
+            snapshot.config,
+            snapshot.particles,
+            snapshot.info.sim_time,
+            snapshot.info.frame_number,
+        );
+        self.gallery.clear();
+        self.gallery
+            .observe(&self.particles, self.sim_time, self.frame_number);
+        self.rewind.clear();
+        self.rewind.observe(
+            &self.particles,
+            &self.config,
+            self.sim_time,
+            self.frame_number,
+        );
+        Ok(())
+    }
+
+    /// Carry on from `particles` at `frame_number`, run with `config`
+    fn resume_at(
+        &mut self,
+        config: PhysicsConfig,
+        particles: Vec<Particle>,
+        sim_time: f32,
+        frame_number: u64,
+    ) {
+        self.config = PhysicsConfig {
+            debug: self.config.debug,
+            ..config
+        };
+        self.requested_particles = self.config.particle_count;
+        self.particles = particles;
+        self.sim_time = sim_time;
+        self.frame_number = frame_number;
+        self.version += 1;
+        // The softening of a scenario file comes from the file
+        if let Scenario::File(name) = &self.config.scenario {
+            match self.load_scenario_file(name) {
+                Ok(scenario) => self.scenario_file = Some(scenario),
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    }
+
+    /// Compute forces together with the distributed `workers`, allowing as
+    /// many more particles as they add nodes
+    pub fn with_workers(mut self, workers: Arc<WorkerPool>) -> Self {
+        let limit = workers.max_particles();
+        self.max_particles = self
+            .configured_max_particles
+            .map_or(limit, |max| max.min(limit));
+        self.workers = Some(workers);
+        self
+    }
+
     pub fn set_paused(&mut self, paused: bool) {
         self.is_paused = paused;
     }
 
-    pub fn step(&mut self) -> (SimulationState, SimulationStats) {
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Jump back to the latest keyframe at or before `frame` and continue
+    /// the run from there with the settings it had then. A replay jumps to
+    /// recorded frame `frame` instead, or as close after it as was recorded.
+    pub fn seek(&mut self, frame: u64) -> Result<(), String> {
+        if let Some(replay) = &mut self.replay {
+            replay.seek(frame);
+            return self.play();
+        }
+        if frame > self.frame_number {
+            return Err(format!(
+                "Frame {} hasn't been simulated yet; the run is at frame {}",
+                frame, self.frame_number
+            ));
+        }
+        let keyframe = self.rewind.seek(frame)?;
+        let (config, particles, sim_time, frame_number) = (
+            keyframe.config.clone(),
+            keyframe.particles.clone(),
+            keyframe.sim_time,
+            keyframe.frame_number,
+        );
+        self.resume_at(config, particles, sim_time, frame_number);
+        Ok(())
+    }
+
+    /// Show the next frame of the replay
+    fn play(&mut self) -> Result<(), String> {
+        let Some(replay) = &mut self.replay else {
+            return Ok(());
+        };
+        let (state, config) = replay.next_frame()?;
+        self.config = PhysicsConfig {
+            debug: self.config.debug,
+            ..config.clone()
+        };
+        self.particles = (0..state.len())
+            .map(|index| state.particle(index))
+            .collect();
+        self.sim_time = state.sim_time;
+        self.frame_number = state.frame_number;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Show the next frame of the replay, pausing it when that can't be read
+    fn play_or_pause(&mut self) {
+        if let Err(e) = self.play() {
+            log::error!("Replay paused: {}", e);
+            self.is_paused = true;
+        }
+    }
+
+    /// Advance one time step, unless paused. The state is not copied here;
+    /// take it with `state` when it is actually going to be sent or saved.
+    pub fn step(&mut self) -> SimulationStats {
         let start = Instant::now();
+        self.scaling = None;
+
+        if self.replay.is_some() {
+            if !self.is_paused {
+                self.play_or_pause();
+            }
+            self.last_computation_time = start.elapsed().as_secs_f32() * 1000.0;
+            return self.stats();
+        }
 
         if !self.is_paused {
-            // Parallel physics computation using rayon
-            let accelerations = self.calculate_accelerations_parallel();
+            // Particles drift apart in memory as they move; restore locality
+            if self.sort_interval > 0 && self.frame_number.is_multiple_of(self.sort_interval) {
+                sort_by_morton(&mut self.particles);
+            }
 
-            // Update particles in parallel
-            self.particles
-                .par_iter_mut()
-                .zip(accelerations.par_iter())
-                .for_each(|(particle, &acceleration)| {
-                    particle.velocity += acceleration * self.config.time_step;
-                    particle.position += particle.velocity * self.config.time_step;
-                });
+            // Tight pairs need a finer step than the rest of the system,
+            // otherwise they spuriously gain energy
+            let substeps = self.encounter_substeps();
+            let dt = self.config.time_step / substeps as f32;
+            self.last_tree_stats = None;
+            self.sample_force_error =
+                matches!(self.config.theta_tuning, ThetaTuning::ForceError { .. })
+                    && self
+                        .frame_number
+                        .is_multiple_of(theta::ERROR_SAMPLE_INTERVAL);
+            for _ in 0..substeps {
+                self.advance(dt);
+            }
+            self.last_substeps = substeps;
 
             self.sim_time += self.config.time_step;
             self.frame_number += 1;
+            self.version += 1;
+            self.gallery
+                .observe(&self.particles, self.sim_time, self.frame_number);
+            self.rewind.observe(
+                &self.particles,
+                &self.config,
+                self.sim_time,
+                self.frame_number,
+            );
         }
 
         self.last_computation_time = start.elapsed().as_secs_f32() * 1000.0;
+        if !self.is_paused {
+            self.tune_theta();
+            self.scale_particles();
+        }
 
         // Monitor computation time and log warnings
         if self.last_computation_time > MAX_COMPUTATION_TIME_MS {
@@ -127,13 +792,128 @@ impl Simulation {
             self.consecutive_slow_frames = 0;
         }
 
-        let state = SimulationState {
-            particles: self.particles.clone(),
-            sim_time: self.sim_time,
-            frame_number: self.frame_number,
+        self.stats()
+    }
+
+    /// Advance by one published frame: `steps_per_update` physics steps.
+    /// Returns the stats after the last one; a particle count change made by
+    /// any of them is kept for `scaling`.
+    pub fn step_update(&mut self) -> SimulationStats {
+        // Recorded frames are already whole updates apart
+        if self.replay.is_some() {
+            return self.step();
+        }
+        let mut stats = self.step();
+        let mut scaling = self.scaling.take();
+        for _ in 1..self.config.steps_per_update {
+            stats = self.step();
+            scaling = self.scaling.take().or(scaling);
+        }
+        self.scaling = scaling;
+        stats
+    }
+
+    /// Advance a paused simulation by exactly `count` steps, leaving it
+    /// paused, for walking through a run frame by frame
+    pub fn step_paused(&mut self, count: u32) -> Result<SimulationStats, String> {
+        if !self.is_paused {
+            return Err("Pause the simulation before stepping it".to_string());
+        }
+        if !(1..=MAX_SINGLE_STEPS).contains(&count) {
+            return Err(format!(
+                "Step count must be between 1 and {} (got {})",
+                MAX_SINGLE_STEPS, count
+            ));
+        }
+        self.is_paused = false;
+        let mut stats = self.step();
+        for _ in 1..count {
+            stats = self.step();
+        }
+        self.is_paused = true;
+        Ok(stats)
+    }
+
+    /// Move theta towards the budget of the current tuning mode
+    fn tune_theta(&mut self) {
+        if self.config.solver != ForceSolver::BarnesHut {
+            return;
+        }
+        let force_error = if self.force_error_pending {
+            self.force_error_pending = false;
+            self.force_error
+        } else {
+            None
+        };
+        self.config.theta = theta::adjust(
+            self.config.theta,
+            self.config.theta_tuning,
+            self.last_computation_time,
+            force_error,
+        );
+    }
+
+    /// Trim particles after sustained slow frames, or raise the count of the
+    /// next reset after sustained fast ones
+    fn scale_particles(&mut self) {
+        let Some(auto_scale) = &mut self.auto_scale else {
+            return;
         };
+        let Some((decision, mean_ms)) = auto_scale.observe(
+            self.last_computation_time,
+            self.particles.len(),
+            self.config.particle_count,
+            self.requested_particles,
+            self.config.solver,
+        ) else {
+            return;
+        };
+        let scaling = auto_scale.scaling(decision, self.particles.len(), mean_ms);
+
+        match decision {
+            Decision::Trim(count) => {
+                log::warn!(
+                    "Frames took {:.1}ms on average, over the {:.1}ms target; trimming particles from {} to {}",
+                    mean_ms,
+                    scaling.target_ms,
+                    scaling.from,
+                    count
+                );
+                autoscale::thin(&mut self.particles, count);
+                self.config.particle_count = count;
+                self.version += 1;
+            }
+            Decision::Grow(count) => {
+                log::info!(
+                    "Frames took {:.1}ms on average, well under the {:.1}ms target; the next reset generates {} particles",
+                    mean_ms,
+                    scaling.target_ms,
+                    count
+                );
+                self.config.particle_count = count;
+            }
+        }
+        self.scaling = Some(scaling);
+    }
+
+    /// The particle count change the last step made, if any
+    pub fn scaling(&self) -> Option<&ParticleScaling> {
+        self.scaling.as_ref()
+    }
 
-        let stats = SimulationStats {
+    pub fn gallery(&self) -> &Gallery {
+        &self.gallery
+    }
+
+    /// The current state, as the last step left it
+    pub fn state(&self) -> SimulationState {
+        SimulationState::new(&self.particles, self.sim_time, self.frame_number)
+    }
+
+    /// Performance and progress figures for the most recent frame
+    pub fn stats(&self) -> SimulationStats {
+        SimulationStats {
+            schema_version: STATS_SCHEMA_VERSION,
             fps: if self.last_computation_time > 0.0 {
                 1000.0 / self.last_computation_time
             } else {
@@ -144,38 +924,230 @@ impl Simulation {
             sim_time: self.sim_time,
             cpu_usage: self.estimate_cpu_usage(),
             frame_number: self.frame_number,
+            substeps: self.last_substeps,
+            sim_time_display: String::new(),
+            seed: self.config.seed,
+            theta: self.config.theta,
+            force_error: match self.config.theta_tuning {
+                ThetaTuning::ForceError { .. } => self.force_error,
+                _ => None,
+            },
+            tree: if self.config.debug {
+                self.last_tree_stats.clone()
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Sub-steps needed this frame: one normally, more when the closest pair is
+    /// inside the encounter distance. The dynamical time of a pair scales as
+    /// r^1.5, so the step shrinks with that power of the separation.
+    fn encounter_substeps(&self) -> u32 {
+        let Some(distance) = self.encounter_distance else {
+            return 1;
         };
+        match closest_encounter(&self.particles, distance, self.config.periodic_box) {
+            Some(closest) => {
+                let ratio = distance / closest.max(f32::EPSILON);
+                (ratio.powf(1.5).ceil() as u32).clamp(1, self.max_substeps)
+            }
+            None => 1,
+        }
+    }
 
-        (state, stats)
+    /// Advance positions and velocities by one time step `dt`
+    fn advance(&mut self, dt: f32) {
+        match self.config.integrator {
+            Integrator::SymplecticEuler => {
+                self.kick(dt);
+                self.drift(dt);
+            }
+            Integrator::Leapfrog => {
+                self.drift(0.5 * dt);
+                self.kick(dt);
+                self.drift(0.5 * dt);
+            }
+            Integrator::Yoshida4 => {
+                // Triple-jump composition of drift-kick-drift leapfrog steps
+                // with weights w1, w0, w1 (Yoshida 1990)
+                let cbrt2 = 2.0f32.cbrt();
+                let w1 = 1.0 / (2.0 - cbrt2);
+                let w0 = -cbrt2 / (2.0 - cbrt2);
+                self.drift(0.5 * w1 * dt);
+                self.kick(w1 * dt);
+                self.drift(0.5 * (w0 + w1) * dt);
+                self.kick(w0 * dt);
+                self.drift(0.5 * (w0 + w1) * dt);
+                self.kick(w1 * dt);
+                self.drift(0.5 * w1 * dt);
+            }
+        }
     }
 
-    fn calculate_accelerations_parallel(&self) -> Vec<Vector3<f32>> {
-        let n = self.particles.len();
-        let softening = 0.1f32;
-        let gravity = self.config.gravity_strength;
+    /// Update velocities from the forces at the current positions
+    fn kick(&mut self, dt: f32) {
+        // Parallel physics computation using rayon
+        let accelerations = self.calculate_accelerations_parallel();
+
+        self.particles
+            .par_iter_mut()
+            .zip(accelerations.par_iter())
+            .filter(|(particle, _)| !particle.fixed)
+            .for_each(|(particle, &acceleration)| {
+                particle.velocity += acceleration * dt;
+            });
+    }
 
-        // Use rayon to parallelize the outer loop
-        (0..n)
-            .into_par_iter()
-            .map(|i| {
-                let mut acceleration = Vector3::zeros();
-                let particle_i = &self.particles[i];
+    /// Move particles along their current velocities
+    fn drift(&mut self, dt: f32) {
+        let periodic_box = self.config.periodic_box;
+        self.particles.par_iter_mut().for_each(|particle| {
+            if particle.fixed {
+                return;
+            }
+            particle.position += particle.velocity * dt;
+            if let Some(box_size) = periodic_box {
+                particle.position = wrap_position(particle.position, box_size);
+            }
+        });
+    }
 
-                // Inner loop remains sequential but is parallelized across different i values
-                for j in 0..n {
-                    if i != j {
-                        let particle_j = &self.particles[j];
-                        let diff = particle_j.position - particle_i.position;
-                        let dist_sq = diff.magnitude_squared() + softening * softening;
-                        let force_magnitude = gravity * particle_j.mass / dist_sq;
+    fn calculate_accelerations_parallel(&mut self) -> Vec<Vector3<f32>> {
+        let params = ForceParams {
+            gravity: self.config.gravity_strength,
+            softening: self.softening(),
+            periodic_box: self.config.periodic_box,
+            compensated: self.compensated_summation,
+        };
 
-                        acceleration += diff.normalize() * force_magnitude;
-                    }
+        let mut accelerations = match self.remote_accelerations(&params) {
+            Some(accelerations) => accelerations,
+            None => self.local_accelerations(&params),
+        };
+
+        if let Some(speed_of_light) = self.config.speed_of_light {
+            let corrections =
+                post_newtonian_accelerations(&self.particles, &params, speed_of_light);
+            accelerations
+                .par_iter_mut()
+                .zip(corrections.par_iter())
+                .for_each(|(acceleration, correction)| *acceleration += correction);
+        }
+
+        accelerations
+    }
+
+    /// Newtonian forces computed on this machine with the configured solver
+    fn local_accelerations(&mut self, params: &ForceParams) -> Vec<Vector3<f32>> {
+        match self.config.solver {
+            ForceSolver::Direct => direct_accelerations(&self.particles, params),
+            ForceSolver::BarnesHut => {
+                let (accelerations, tree) =
+                    barnes_hut_accelerations(&self.particles, params, self.config.theta);
+                if self.sample_force_error {
+                    self.sample_force_error = false;
+                    self.force_error = Some(theta::sample_force_error(
+                        &self.particles,
+                        &accelerations,
+                        params,
+                    ));
+                    self.force_error_pending = true;
                 }
+                // Times add up over the frame, the tree's shape is the latest
+                self.last_tree_stats = Some(match self.last_tree_stats.take() {
+                    Some(frame) => TreeStats {
+                        build_time_ms: frame.build_time_ms + tree.build_time_ms,
+                        traversal_time_ms: frame.traversal_time_ms + tree.traversal_time_ms,
+                        ..tree
+                    },
+                    None => tree,
+                });
+                accelerations
+            }
+        }
+    }
 
-                acceleration
-            })
-            .collect()
+    /// Forces from the distributed workers, or `None` to compute them here,
+    /// e.g. while a worker is unreachable
+    fn remote_accelerations(&mut self, params: &ForceParams) -> Option<Vec<Vector3<f32>>> {
+        let workers = self.workers.as_ref()?;
+        match workers.accelerations(
+            &self.particles,
+            params,
+            self.config.solver,
+            self.config.theta,
+        ) {
+            Ok(accelerations) => {
+                if self.workers_failing {
+                    log::info!("Distributed workers are back");
+                    self.workers_failing = false;
+                }
+                Some(accelerations)
+            }
+            Err(e) => {
+                if !self.workers_failing {
+                    log::warn!("{}; computing forces locally", e);
+                    self.workers_failing = true;
+                }
+                None
+            }
+        }
+    }
+
+    /// Gravitational softening length: large enough to tame close
+    /// encounters between sampled particles, tiny for real point masses
+    pub fn softening(&self) -> f32 {
+        match self.config.scenario {
+            Scenario::SolarSystem
+            | Scenario::FigureEight
+            | Scenario::LagrangeTriangle
+            | Scenario::Pythagorean
+            | Scenario::BinaryCluster
+            | Scenario::ProtoplanetaryDisk => POINT_MASS_SOFTENING,
+            Scenario::File(_) => self
+                .scenario_file
+                .as_ref()
+                .and_then(|scenario| scenario.softening)
+                .unwrap_or(DEFAULT_SOFTENING),
+            _ => DEFAULT_SOFTENING,
+        }
+    }
+
+    /// Read a scenario file and check that it fits within the particle limit
+    fn load_scenario_file(&self, name: &str) -> Result<ScenarioFile, String> {
+        let scenario = scenarios::load(&self.scenarios_dir, name)?;
+        if scenario.particle_count() > self.max_particles {
+            return Err(format!(
+                "Scenario '{}' has {} particles, more than the maximum of {}",
+                name,
+                scenario.particle_count(),
+                self.max_particles
+            ));
+        }
+        Ok(scenario)
+    }
+
+    /// Read an import file and check that it fits within the particle limit
+    fn load_import(&self, name: &str) -> Result<Vec<Particle>, String> {
+        let particles = import::load(&self.scenarios_dir, name)?;
+        if particles.len() > self.max_particles {
+            return Err(format!(
+                "Import '{}' has {} particles, more than the maximum of {}",
+                name,
+                particles.len(),
+                self.max_particles
+            ));
+        }
+        Ok(particles)
+    }
+
+    fn wrap_particles(&mut self) {
+        if let Some(box_size) = self.config.periodic_box {
+            for particle in &mut self.particles {
+                particle.position = wrap_position(particle.position, box_size);
+            }
+        }
     }
 
     fn estimate_cpu_usage(&self) -> f32 {
@@ -184,85 +1156,47 @@ impl Simulation {
         (self.last_computation_time / target_frame_time * 100.0).min(100.0)
     }
 
-    pub fn get_config(&self) -> &SimulationConfig {
+    /// Simulated time between published frames: the time step times the
+    /// steps per update, and for a replay the recording interval too
+    pub fn frame_time_step(&self) -> f32 {
+        let interval = self
+            .replay
+            .as_ref()
+            .map_or(1, |replay| replay.header().interval);
+        self.config.time_step * (self.config.steps_per_update as u64 * interval) as f32
+    }
+
+    pub fn get_config(&self) -> &PhysicsConfig {
         &self.config
     }
-}
 
-fn generate_galaxy_collision(total_particles: usize) -> Vec<Particle> {
-    let mut particles = Vec::with_capacity(total_particles);
-
-    // First galaxy
-    particles.extend(generate_spiral_galaxy(
-        total_particles / 2,
-        Point3::new(-5.0, 0.0, 0.0),
-        Vector3::new(0.5, 0.0, 0.0),
-        2.0,
-        [0.8, 0.8, 1.0, 1.0], // Blue
-    ));
-
-    // Second galaxy
-    particles.extend(generate_spiral_galaxy(
-        total_particles / 2,
-        Point3::new(5.0, 0.0, 0.0),
-        Vector3::new(-0.5, 0.0, 0.0),
-        2.0,
-        [1.0, 0.8, 0.8, 1.0], // Red
-    ));
-
-    particles
-}
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
 
-fn generate_spiral_galaxy(
-    num_particles: usize,
-    center: Point3<f32>,
-    bulk_velocity: Vector3<f32>,
-    radius: f32,
-    base_color: [f32; 4],
-) -> Vec<Particle> {
-    (0..num_particles)
-        .map(|i| {
-            let t = i as f32 / num_particles as f32;
-            let angle = t * std::f32::consts::PI * 4.0;
-            let r = t * radius;
-
-            let thickness = 0.1 * radius;
-            let z_offset = (pseudo_random(i) - 0.5) * thickness;
-
-            let x = r * angle.cos();
-            let y = r * angle.sin();
-            let z = z_offset;
-
-            let local_pos = Vector3::new(x, y, z);
-            let position = center + local_pos;
-
-            let orbital_speed = (1.0 / (r + 0.1).sqrt()) * 2.0;
-            let tangent = Vector3::new(-angle.sin(), angle.cos(), 0.0);
-            let orbital_velocity = tangent * orbital_speed;
-
-            let velocity = bulk_velocity + orbital_velocity;
-            let mass = 1.0 + (1.0 - t) * 2.0;
-
-            let color_variation = 0.2;
-            let rand = pseudo_random(i);
-            let color = [
-                base_color[0] + (rand - 0.5) * color_variation,
-                base_color[1] + (rand - 0.5) * color_variation,
-                base_color[2] + (rand - 0.5) * color_variation,
-                base_color[3],
-            ];
-
-            Particle {
-                position,
-                velocity,
-                mass,
-                color,
-            }
-        })
-        .collect()
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    /// Changes whenever the particles do: on every unpaused step, reset and
+    /// rewrap. Equal versions mean identical states.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn sim_time(&self) -> f32 {
+        self.sim_time
+    }
 }
 
-fn pseudo_random(seed: usize) -> f32 {
-    let x = (seed.wrapping_mul(1103515245).wrapping_add(12345) >> 16) & 0x7fff;
-    x as f32 / 32767.0
+/// Immovable mass that only attracts, e.g. a central black hole
+fn pinned_attractor(position: Point3<f32>, mass: f32) -> Particle {
+    Particle {
+        position,
+        velocity: Vector3::zeros(),
+        mass,
+        color: [1.0, 1.0, 0.9, 1.0],
+        fixed: true,
+        group: 0,
+    }
 }
@@ -0,0 +1,106 @@
+// Snapshots: the whole simulation saved under a name, so a long run can be
+// continued later, also after a restart. Random numbers are only drawn when
+// particles are generated, from the seed in the settings, so the particles,
+// settings and clock are all the state there is to restore.
+use n_body_shared::{Particle, PhysicsConfig, SnapshotInfo};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::scenarios::validate_name;
+
+/// Version of the file layout; raise it whenever a field changes meaning
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Contents of a snapshot file, `<snapshots_dir>/<name>.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub format_version: u32,
+    #[serde(flatten)]
+    pub info: SnapshotInfo,
+    /// Settings in effect, seed included
+    pub config: PhysicsConfig,
+    pub particles: Vec<Particle>,
+}
+
+impl Snapshot {
+    /// A snapshot called `name`, or after the current time when `None`
+    pub fn new(
+        name: Option<String>,
+        config: PhysicsConfig,
+        particles: Vec<Particle>,
+        sim_time: f32,
+        frame_number: u64,
+    ) -> Result<Self, String> {
+        let saved_at = unix_now();
+        let name = name.unwrap_or_else(|| format!("snapshot-{}", saved_at));
+        validate_name(&name).map_err(|_| {
+            format!(
+                "Invalid snapshot name '{}': use only letters, digits, '-' and '_'",
+                name
+            )
+        })?;
+        Ok(Snapshot {
+            format_version: FORMAT_VERSION,
+            info: SnapshotInfo {
+                name,
+                saved_at,
+                sim_time,
+                frame_number,
+                particle_count: particles.len(),
+            },
+            config,
+            particles,
+        })
+    }
+
+    /// Write the snapshot to `dir`, replacing one of the same name. A
+    /// temporary file is renamed into place so a crash never leaves half a
+    /// snapshot behind.
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = path_for(dir, &self.info.name);
+        let partial = path.with_extension("json.partial");
+        let json = serde_json::to_vec(self)
+            .map_err(|e| format!("Failed to encode snapshot {}: {}", self.info.name, e))?;
+        std::fs::write(&partial, json)
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Read snapshot `name` from `dir`
+    pub fn load(dir: &Path, name: &str) -> Result<Self, String> {
+        validate_name(name).map_err(|_| {
+            format!(
+                "Invalid snapshot name '{}': use only letters, digits, '-' and '_'",
+                name
+            )
+        })?;
+        let path = path_for(dir, name);
+        let json = std::fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let snapshot: Snapshot = serde_json::from_slice(&json)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        if snapshot.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "{} was saved in format {}, but only format {} can be loaded",
+                path.display(),
+                snapshot.format_version,
+                FORMAT_VERSION
+            ));
+        }
+        Ok(snapshot)
+    }
+}
+
+fn path_for(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", name))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
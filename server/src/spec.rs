@@ -0,0 +1,421 @@
+// Machine-readable contract of the server for third-party clients: OpenAPI
+// for the HTTP endpoints and AsyncAPI for the websocket protocol. Both are
+// generated from the Rust types the handlers read and write, so they change
+// together with the code instead of drifting away from it.
+use n_body_shared::{
+    ClientMessage, EnergySample, ScenarioInfo, ServerMessage, SimulationState, SimulationStats,
+    SnapshotInfo, StatsSchema, CRATE_VERSION, PROTOCOL_VERSION,
+};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::api::{ImportQuery, Imported, SnapshotQuery};
+use crate::encoding::WireFormat;
+use crate::gallery::GalleryEntry;
+use crate::jobs::{JobRequest, JobStatus};
+use crate::watchdog::Health;
+use crate::WsQuery;
+
+const TITLE: &str = "n-body simulation";
+
+/// Where both documents keep their shared schemas
+const SCHEMAS_PATH: &str = "#/components/schemas/";
+
+/// `schema` of `T` as JSON, registering the types it refers to with `gen`
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap_or_default()
+}
+
+/// The fields of struct `T` as OpenAPI parameters found in `location`, e.g.
+/// "query"
+fn parameters<T: JsonSchema>(gen: &mut SchemaGenerator, location: &str) -> Vec<Value> {
+    let root = serde_json::to_value(gen.root_schema_for::<T>().schema).unwrap_or_default();
+    let required = root["required"].as_array().cloned().unwrap_or_default();
+    let Some(properties) = root["properties"].as_object() else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .map(|(name, schema)| {
+            let mut parameter = json!({
+                "name": name,
+                "in": location,
+                "required": required.contains(&json!(name)),
+                "schema": schema,
+            });
+            if let Some(description) = schema.get("description") {
+                parameter["description"] = description.clone();
+            }
+            parameter
+        })
+        .collect()
+}
+
+fn path_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+/// A response with a JSON body matching `schema`
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// A response whose body is a plain-text reason
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+fn operation(summary: &str, responses: Value) -> Value {
+    json!({ "summary": summary, "responses": responses })
+}
+
+/// The OpenAPI 3.0 document served at `/api/spec`
+pub fn openapi() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let id = json!({ "type": "integer", "format": "uint64", "minimum": 0 });
+    let disabled = || text_response("Jobs are disabled on this server");
+    let forbidden = || text_response("The request lacks control access");
+
+    let mut import = operation(
+        "Store an initial-condition file and switch the simulation to it",
+        json!({
+            "200": json_response("The particles were loaded", schema::<Imported>(&mut gen)),
+            "400": text_response("The name or file is invalid"),
+            "403": forbidden(),
+        }),
+    );
+    let mut import_parameters = vec![path_parameter("name", json!({ "type": "string" }))];
+    import_parameters.extend(parameters::<ImportQuery>(&mut gen, "query"));
+    import["parameters"] = Value::Array(import_parameters);
+    import["requestBody"] = json!({
+        "required": true,
+        "content": {
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } },
+        },
+    });
+
+    let mut save_snapshot = operation(
+        "Save the simulation to the snapshots directory",
+        json!({
+            "201": json_response("The snapshot was saved", schema::<SnapshotInfo>(&mut gen)),
+            "400": text_response("The name is invalid or the file can't be written"),
+            "403": forbidden(),
+        }),
+    );
+    save_snapshot["parameters"] = Value::Array(parameters::<SnapshotQuery>(&mut gen, "query"));
+    let mut load_snapshot = operation(
+        "Continue the simulation from a saved snapshot",
+        json!({
+            "200": json_response("The snapshot was loaded", schema::<SnapshotInfo>(&mut gen)),
+            "400": text_response("No such snapshot, or it can't be run"),
+            "403": forbidden(),
+        }),
+    );
+    load_snapshot["parameters"] = json!([path_parameter("name", json!({ "type": "string" }))]);
+
+    let mut submit = operation(
+        "Queue a headless batch run",
+        json!({
+            "202": json_response("The job was queued", schema::<JobStatus>(&mut gen)),
+            "400": text_response("The request is invalid"),
+            "403": forbidden(),
+            "404": disabled(),
+        }),
+    );
+    submit["requestBody"] = json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema::<JobRequest>(&mut gen) } },
+    });
+
+    let mut job = operation(
+        "Progress of a job",
+        json!({
+            "200": json_response("The job", schema::<JobStatus>(&mut gen)),
+            "404": text_response("No such job, or jobs are disabled"),
+        }),
+    );
+    job["parameters"] = json!([path_parameter("id", id.clone())]);
+    let mut cancel = operation(
+        "Cancel a queued or running job",
+        json!({
+            "204": { "description": "The job was cancelled" },
+            "403": forbidden(),
+            "404": text_response("No such job, or jobs are disabled"),
+        }),
+    );
+    cancel["parameters"] = job["parameters"].clone();
+
+    let mut job_file = operation(
+        "Download a result file of a job",
+        json!({
+            "200": {
+                "description": "`config.json`, `log.csv` or a `snapshot-NNNN.json`",
+                "content": { "application/octet-stream": {} },
+            },
+            "404": text_response("No such file"),
+        }),
+    );
+    job_file["parameters"] = json!([
+        path_parameter("id", id.clone()),
+        path_parameter("name", json!({ "type": "string" })),
+    ]);
+
+    let mut gallery_entry = operation(
+        "A time-lapse snapshot as a state frame",
+        json!({
+            "200": json_response("The snapshot", schema::<SimulationState>(&mut gen)),
+            "404": text_response("The entry has been thinned out"),
+        }),
+    );
+    gallery_entry["parameters"] = json!([path_parameter("id", id)]);
+
+    let mut websocket = operation(
+        "Open the websocket of the main simulation",
+        json!({
+            "101": {
+                "description": "Upgraded to the websocket protocol described at `/api/spec/websocket`",
+            },
+            "403": text_response("The page's origin is not allowed"),
+        }),
+    );
+    let query = parameters::<WsQuery>(&mut gen, "query");
+    websocket["parameters"] = Value::Array(query.clone());
+    // Browsers can't set headers on upgrades, so only these take `?token=`
+    let websocket_security = json!([{}, { "bearer": [] }, { "apiKey": [] }, { "token": [] }]);
+    websocket["security"] = websocket_security.clone();
+    let mut room = operation(
+        "Open the websocket of a named simulation or room",
+        json!({
+            "101": {
+                "description": "Upgraded to the websocket protocol described at `/api/spec/websocket`",
+            },
+            "403": text_response("The page's origin is not allowed"),
+            "503": text_response("No more rooms can be created"),
+        }),
+    );
+    room["parameters"] = std::iter::once(path_parameter("room", json!({ "type": "string" })))
+        .chain(query.clone())
+        .collect();
+    room["security"] = websocket_security.clone();
+    let paths = json!({
+        "/api/scenarios": { "get": operation(
+            "Scenarios the server can run",
+            json!({ "200": json_response("The scenarios", schema::<Vec<ScenarioInfo>>(&mut gen)) }),
+        ) },
+        "/api/gallery": { "get": operation(
+            "Time-lapse snapshots of the live run, oldest first",
+            json!({ "200": json_response("The snapshots", schema::<Vec<GalleryEntry>>(&mut gen)) }),
+        ) },
+        "/api/gallery/{id}": { "get": gallery_entry },
+        "/api/energy": { "get": operation(
+            "Energy partition samples of the current run, oldest first",
+            json!({ "200": json_response("The samples", schema::<Vec<EnergySample>>(&mut gen)) }),
+        ) },
+        "/api/stats/schema": { "get": operation(
+            "Metrics carried by the stats JSON with their units",
+            json!({ "200": json_response("The metrics", schema::<StatsSchema>(&mut gen)) }),
+        ) },
+        "/api/stats/stream": { "get": operation(
+            "Server-sent events with the stats JSON as the data of each event",
+            json!({ "200": {
+                "description": "An endless event stream",
+                "content": { "text/event-stream": { "schema": schema::<SimulationStats>(&mut gen) } },
+            } }),
+        ) },
+        "/metrics": { "get": operation(
+            "Frame rate, computation time, particle count, websocket clients and traffic, and watchdog stalls in the Prometheus text format",
+            json!({ "200": {
+                "description": "The metrics",
+                "content": { "text/plain": { "schema": { "type": "string" } } },
+            } }),
+        ) },
+        "/health": { "get": operation(
+            "Watchdog view of the simulation for liveness probes",
+            json!({
+                "200": json_response("Healthy, or stalled for less than the watchdog timeout", schema::<Health>(&mut gen)),
+                "503": json_response("Hung past the watchdog timeout", schema::<Health>(&mut gen)),
+            }),
+        ) },
+        "/api/snapshots": { "post": save_snapshot },
+        "/api/snapshots/{name}/load": { "post": load_snapshot },
+        "/api/jobs": {
+            "get": operation(
+                "All jobs",
+                json!({
+                    "200": json_response("The jobs", schema::<Vec<JobStatus>>(&mut gen)),
+                    "404": disabled(),
+                }),
+            ),
+            "post": submit,
+        },
+        "/api/jobs/{id}": { "get": job, "delete": cancel },
+        "/api/jobs/{id}/files/{name}": { "get": job_file },
+        "/api/import/{name}": { "post": import },
+        "/api/spec": { "get": operation(
+            "This document",
+            json!({ "200": json_response("OpenAPI 3.0", json!({ "type": "object" })) }),
+        ) },
+        "/api/spec/websocket": { "get": operation(
+            "The websocket protocol",
+            json!({ "200": json_response("AsyncAPI 2.6", json!({ "type": "object" })) }),
+        ) },
+        "/ws": { "get": websocket },
+        "/ws/{room}": { "get": room },
+    });
+    #[cfg(feature = "recording")]
+    let paths = {
+        let mut paths = paths;
+        let mut replay = operation(
+            "Open the websocket of a recording played back",
+            json!({
+                "101": {
+                    "description": "Upgraded to the websocket protocol described at `/api/spec/websocket`",
+                },
+                "403": text_response("The page's origin is not allowed"),
+                "503": text_response("The recording can't be opened, or no more rooms can be created"),
+            }),
+        );
+        replay["parameters"] = std::iter::once(path_parameter("name", json!({ "type": "string" })))
+            .chain(query)
+            .collect();
+        replay["security"] = websocket_security;
+        paths["/ws/replay/{name}"] = json!({ "get": replay });
+        paths
+    };
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": TITLE,
+            "version": CRATE_VERSION,
+            "description": "HTTP endpoints of the n-body simulation server. Paths are relative to where the server is mounted.",
+        },
+        "paths": paths,
+        // Requests without credentials get the server's default role
+        "security": [{}, { "bearer": [] }, { "apiKey": [] }],
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                "apiKey": { "type": "apiKey", "in": "header", "name": "X-Api-Key" },
+                "token": { "type": "apiKey", "in": "query", "name": "token" },
+            },
+        },
+    })
+}
+
+/// The AsyncAPI 2.6 document served at `/api/spec/websocket`
+pub fn asyncapi() -> Value {
+    let mut gen = SchemaSettings::draft07()
+        .with(|settings| {
+            settings.definitions_path = SCHEMAS_PATH.to_string();
+            settings.meta_schema = None;
+        })
+        .into_generator();
+    let query = serde_json::to_value(gen.root_schema_for::<WsQuery>().schema).unwrap_or_default();
+    let headers = json!({
+        "type": "object",
+        "properties": {
+            "Sec-WebSocket-Protocol": {
+                "type": "string",
+                "enum": WireFormat::PROTOCOLS,
+                "description": "Message format; the server confirms the first one it knows. Without it messages are JSON text.",
+            },
+        },
+    });
+
+    let mut messages = Map::new();
+    messages.insert(
+        "ClientMessage".to_string(),
+        json!({
+            "contentType": "application/json",
+            "description": "Sent as JSON text, or as a binary message in the negotiated `nbody-msgpack` or `nbody-cbor` subprotocol; `type` names the variant. Viewers may only send `UpdateView`, `ListScenarios` and `RequestState`.",
+            "payload": schema::<ClientMessage>(&mut gen),
+        }),
+    );
+    messages.insert(
+        "ServerMessage".to_string(),
+        json!({
+            "contentType": "application/json",
+            "description": "Sent as JSON text, or as a binary message in the negotiated `nbody-msgpack` or `nbody-cbor` subprotocol; `type` names the variant. A `Reload` means the client's `protocol` differs from the server's and it must reload.",
+            "payload": schema::<ServerMessage>(&mut gen),
+        }),
+    );
+    messages.insert(
+        "BincodeState".to_string(),
+        json!({
+            "contentType": "application/octet-stream",
+            "description": "A state frame as a bincode `wire::BinaryMessage::State` in a binary message (little-endian, fixed-width integers, u64 lengths): u32 variant 0, u64 frame_number, f32 sim_time, u8 interpolated, u64 particle count, then per particle 3 f32 position, 3 f32 velocity, f32 mass, 4 f32 color, u8 fixed and u16 group. Variant 1 is a `StateDiff` with its fields in declaration order, sent between keyframes when the server has `keyframe_interval` set. Sent in place of `State` and `StateDiff` when connecting with `encoding=bincode`",
+            "payload": { "type": "string", "format": "binary" },
+        }),
+    );
+    messages.insert(
+        "QuantizedState".to_string(),
+        json!({
+            "contentType": "application/octet-stream",
+            "description": "A state frame as bincode `wire::BinaryMessage` variant 2, `QuantizedState`: u64 frame_number, f32 sim_time, u8 interpolated, 3 f32 origin, 3 f32 step, u64 particle count, then per particle 3 u16 position steps (origin + steps × step), 3 f16 velocity, f16 mass, 4 u8 color, u8 fixed and u16 group; sent in place of `State` when connecting with `encoding=quantized`",
+            "payload": { "type": "string", "format": "binary" },
+        }),
+    );
+    messages.insert(
+        "ArrowState".to_string(),
+        json!({
+            "contentType": "application/vnd.apache.arrow.stream",
+            "description": "A state frame as one Apache Arrow IPC stream in a binary message, with columns x, y, z, vx, vy, vz and mass; sent in place of `State` when connecting with `encoding=arrow`",
+            "payload": { "type": "string", "format": "binary" },
+        }),
+    );
+
+    let channel = |description: &str| {
+        json!({
+            "description": description,
+            "bindings": { "ws": { "method": "GET", "query": query.clone(), "headers": headers.clone() } },
+            "publish": {
+                "summary": "Commands from the client",
+                "message": { "$ref": "#/components/messages/ClientMessage" },
+            },
+            "subscribe": {
+                "summary": "Handshake, frames, stats and replies from the server",
+                "message": { "oneOf": [
+                    { "$ref": "#/components/messages/ServerMessage" },
+                    { "$ref": "#/components/messages/BincodeState" },
+                    { "$ref": "#/components/messages/QuantizedState" },
+                    { "$ref": "#/components/messages/ArrowState" },
+                ] },
+            },
+        })
+    };
+    let mut room = channel(
+        "A named simulation from the server's configuration, or a room created on first use",
+    );
+    room["parameters"] = json!({ "room": { "schema": { "type": "string" } } });
+
+    json!({
+        "asyncapi": "2.6.0",
+        "info": {
+            "title": TITLE,
+            "version": CRATE_VERSION,
+            "description": format!(
+                "Websocket protocol version {}. Pass it as the `protocol` query parameter; the server sends `Welcome` first.",
+                PROTOCOL_VERSION
+            ),
+            "x-protocol-version": PROTOCOL_VERSION,
+        },
+        "defaultContentType": "application/json",
+        "channels": {
+            "/ws": channel("The main simulation"),
+            "/ws/{room}": room,
+        },
+        "components": {
+            "messages": messages,
+            "schemas": gen.take_definitions(),
+        },
+    })
+}
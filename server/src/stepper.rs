@@ -0,0 +1,163 @@
+// The one loop that advances a live simulation: it steps at the configured
+// rate on a thread of its own, however busy the network side is, and can
+// stand still while no client is following it. Readers never take the
+// simulation lock for frames: websocket actors subscribe to the ones it
+// broadcasts, and pollers load the latest from a lock-free slot.
+use arc_swap::ArcSwap;
+use log::{error, info};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::config::SimulationConfig;
+use crate::service::Frame;
+use crate::simulation::Simulation;
+use crate::watchdog::SimulationWatchdog;
+
+/// Frames buffered per subscriber before slow clients skip ahead
+const FRAME_CHANNEL_CAPACITY: usize = 16;
+
+/// How often an idle loop checks whether its simulation is still around
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The clients following a simulation: websocket connections, stats
+/// streams and the UDP broadcaster
+#[derive(Default)]
+pub struct Presence {
+    clients: Mutex<usize>,
+    changed: Condvar,
+}
+
+/// Counts as one client of a `Presence` until dropped
+pub struct Attendance(Arc<Presence>);
+
+impl Presence {
+    pub fn join(self: &Arc<Self>) -> Attendance {
+        if let Ok(mut clients) = self.clients.lock() {
+            *clients += 1;
+        }
+        self.changed.notify_all();
+        Attendance(self.clone())
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.lock().map_or(0, |clients| *clients)
+    }
+
+    /// Block until a client is present or `timeout` has passed, and tell
+    /// which it was
+    fn wait_for_clients(&self, timeout: Duration) -> bool {
+        let Ok(clients) = self.clients.lock() else {
+            return true;
+        };
+        self.changed
+            .wait_timeout_while(clients, timeout, |clients| *clients == 0)
+            .map_or(true, |(clients, _)| *clients > 0)
+    }
+}
+
+impl Drop for Attendance {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.0.clients.lock() {
+            *clients = clients.saturating_sub(1);
+        }
+    }
+}
+
+/// Start stepping `simulation` every `update_rate_ms` of `config` on a
+/// dedicated physics thread, storing each frame in `latest`, and return the
+/// sender to subscribe to its frames. With a `presence`, stepping stops while
+/// it has no clients. The thread exits once the simulation has been dropped.
+pub fn spawn(
+    simulation: &Arc<Mutex<Simulation>>,
+    latest: &Arc<ArcSwap<Frame>>,
+    watchdog: Option<Arc<SimulationWatchdog>>,
+    presence: Option<Arc<Presence>>,
+    config: &Arc<ArcSwap<SimulationConfig>>,
+) -> broadcast::Sender<Arc<Frame>> {
+    let (frames, _) = broadcast::channel(FRAME_CHANNEL_CAPACITY);
+    let publisher = frames.clone();
+    let simulation = Arc::downgrade(simulation);
+    let latest = latest.clone();
+    let config = config.clone();
+
+    let spawned = thread::Builder::new()
+        .name("physics".to_string())
+        .spawn(move || {
+            let mut next_tick = Instant::now();
+            let mut idle = false;
+
+            loop {
+                if let Some(presence) = &presence {
+                    if !presence.wait_for_clients(IDLE_CHECK_INTERVAL) {
+                        if simulation.strong_count() == 0 {
+                            break;
+                        }
+                        if !idle {
+                            idle = true;
+                            info!("No clients connected; physics paused until one connects");
+                            if let Some(watchdog) = &watchdog {
+                                watchdog.set_idle(true);
+                            }
+                        }
+                        continue;
+                    }
+                    if idle {
+                        idle = false;
+                        info!("Client connected; physics resumed");
+                        if let Some(watchdog) = &watchdog {
+                            watchdog.set_idle(false);
+                        }
+                        next_tick = Instant::now();
+                    }
+                }
+                let Some(simulation) = simulation.upgrade() else {
+                    break;
+                };
+                let stepped = match simulation.lock() {
+                    Ok(mut sim) => {
+                        let stats = sim.step_update();
+                        Ok(Frame::stepped(&sim, stats, &latest.load()))
+                    }
+                    Err(e) => Err(format!("Failed to lock simulation: {}", e)),
+                };
+                // Don't keep the simulation alive while sleeping
+                drop(simulation);
+
+                match stepped {
+                    Ok(frame) => {
+                        if let Some(watchdog) = &watchdog {
+                            watchdog.heartbeat(frame.stats.frame_number);
+                        }
+                        let frame = Arc::new(frame);
+                        latest.store(frame.clone());
+                        // Nobody listening is not an error
+                        let _ = publisher.send(frame);
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        break;
+                    }
+                }
+
+                // Read every time, so a reloaded rate applies at once
+                let period = Duration::from_millis(config.load().update_rate_ms.max(1));
+
+                // A step that overran its period delays the schedule rather
+                // than being followed by a burst of catch-up steps
+                next_tick += period;
+                let now = Instant::now();
+                if next_tick > now {
+                    thread::sleep(next_tick - now);
+                } else {
+                    next_tick = now;
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        error!("Failed to start physics thread: {}", e);
+    }
+
+    frames
+}
@@ -0,0 +1,88 @@
+// Automatic Barnes-Hut opening angle: after every frame theta moves a small
+// step towards the value that meets a frame-time or force-error budget
+use n_body_shared::{Particle, ThetaTuning};
+use nalgebra::Vector3;
+
+use crate::physics::{direct_acceleration, ForceParams};
+
+/// Range theta is tuned within: below it the tree costs more than the
+/// direct sum, above it cells far larger than their distance are accepted
+pub const MIN_THETA: f32 = 0.1;
+pub const MAX_THETA: f32 = 1.5;
+
+/// Largest relative change of theta per adjustment, so one noisy frame
+/// cannot swing it
+const MAX_STEP: f32 = 0.05;
+
+/// Measurements within this fraction of the target leave theta alone
+const TOLERANCE: f32 = 0.1;
+
+/// Frames between force-error samples
+pub const ERROR_SAMPLE_INTERVAL: u64 = 10;
+
+/// Particles whose forces are checked against the exact sum per sample
+const ERROR_SAMPLE_SIZE: usize = 32;
+
+pub fn validate(tuning: ThetaTuning) -> Result<(), String> {
+    let (what, value) = match tuning {
+        ThetaTuning::Off => return Ok(()),
+        ThetaTuning::FrameTime { target_ms } => ("frame time target", target_ms),
+        ThetaTuning::ForceError { target } => ("force error target", target),
+    };
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "Theta tuning {} must be positive, got {}",
+            what, value
+        ))
+    }
+}
+
+/// Theta for the next frame, from the time this frame's physics took and
+/// the force error if one was sampled during it
+pub fn adjust(
+    theta: f32,
+    tuning: ThetaTuning,
+    frame_time_ms: f32,
+    force_error: Option<f32>,
+) -> f32 {
+    // Above 1 theta should grow. Tree cost falls roughly as theta⁻³ and
+    // the monopole error grows as theta², which sets the exponents.
+    let (ratio, exponent) = match (tuning, force_error) {
+        (ThetaTuning::Off, _) => return theta,
+        (ThetaTuning::FrameTime { target_ms }, _) => (frame_time_ms / target_ms, 1.0 / 3.0),
+        (ThetaTuning::ForceError { target }, Some(error)) => {
+            (target / error.max(f32::EPSILON), 0.5)
+        }
+        (ThetaTuning::ForceError { .. }, None) => return theta,
+    };
+    if !ratio.is_finite() || (ratio - 1.0).abs() <= TOLERANCE {
+        return theta;
+    }
+    let factor = ratio.powf(exponent).clamp(1.0 - MAX_STEP, 1.0 + MAX_STEP);
+    (theta * factor).clamp(MIN_THETA, MAX_THETA)
+}
+
+/// Mean relative difference between `accelerations` and the exact sum, over
+/// particles spread evenly through the list
+pub fn sample_force_error(
+    particles: &[Particle],
+    accelerations: &[Vector3<f32>],
+    params: &ForceParams,
+) -> f32 {
+    let stride = (particles.len() / ERROR_SAMPLE_SIZE).max(1);
+    let (sum, count) = (0..particles.len())
+        .step_by(stride)
+        .filter_map(|index| {
+            let exact = direct_acceleration(particles, index, params);
+            let magnitude = exact.magnitude();
+            (magnitude > 0.0).then(|| (accelerations[index] - exact).magnitude() / magnitude)
+        })
+        .fold((0.0, 0), |(sum, count), error| (sum + error, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f32
+    }
+}
@@ -0,0 +1,64 @@
+// TLS termination, so the page and its websocket can be served as https://
+// and wss:// without a reverse proxy in front. Pages served over HTTPS may
+// only open secure websockets.
+use log::info;
+use std::io;
+
+use crate::config::ServerConfig;
+
+/// Settings of the TLS listener
+#[cfg(feature = "tls")]
+pub type TlsConfig = rustls::ServerConfig;
+
+/// Never built without the `tls` feature
+#[cfg(not(feature = "tls"))]
+pub enum TlsConfig {}
+
+/// The TLS settings for `server`, or `None` to serve plain HTTP. Fails when
+/// only one of the certificate and key is configured, or either can't be
+/// read.
+pub fn load(server: &ServerConfig) -> io::Result<Option<TlsConfig>> {
+    let (cert, key) = match (&server.tls_cert, &server.tls_key) {
+        (None, None) => return Ok(None),
+        (Some(cert), Some(key)) => (cert, key),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TLS needs both tls_cert and tls_key",
+            ))
+        }
+    };
+    info!("Loading TLS certificate {} and key {}", cert, key);
+    build(cert, key)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(feature = "tls")]
+fn build(cert: &str, key: &str) -> Result<TlsConfig, String> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read certificates from {}: {}", cert, e))?;
+    if chain.is_empty() {
+        return Err(format!("No certificate found in {}", cert));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Failed to read private key from {}: {}", key, e))?;
+
+    rustls::ServerConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| {
+            builder
+                .with_no_client_auth()
+                .with_single_cert(chain, private_key)
+        })
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))
+}
+
+#[cfg(not(feature = "tls"))]
+fn build(_cert: &str, _key: &str) -> Result<TlsConfig, String> {
+    Err("TLS requires the `tls` feature".to_string())
+}
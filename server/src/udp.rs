@@ -0,0 +1,124 @@
+// Compact binary position frames over UDP for game engines and other viewers.
+// The packet layout is documented in docs/udp.md.
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::config::UdpConfig;
+use crate::service::Frame;
+use crate::stepper::Attendance;
+
+pub const MAGIC: [u8; 4] = *b"NBDY";
+pub const FORMAT_VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 32;
+/// Bytes per particle: x, y, z as f32
+const PARTICLE_LEN: usize = 12;
+
+/// Split one frame into self-describing packets of at most `max_packet_bytes`.
+/// Every packet carries the frame header plus a contiguous run of particles,
+/// so receivers can place positions without waiting for the whole frame.
+pub fn encode_frame(
+    frame_number: u64,
+    sim_time: f32,
+    positions: &[[f32; 3]],
+    max_packet_bytes: usize,
+) -> Vec<Vec<u8>> {
+    let per_packet = (max_packet_bytes.saturating_sub(HEADER_LEN) / PARTICLE_LEN).max(1);
+    let total = positions.len() as u32;
+
+    positions
+        .chunks(per_packet)
+        .enumerate()
+        .map(|(chunk, run)| {
+            let mut packet = Vec::with_capacity(HEADER_LEN + run.len() * PARTICLE_LEN);
+            packet.extend_from_slice(&MAGIC);
+            packet.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+            packet.extend_from_slice(&(HEADER_LEN as u16).to_le_bytes());
+            packet.extend_from_slice(&frame_number.to_le_bytes());
+            packet.extend_from_slice(&sim_time.to_le_bytes());
+            packet.extend_from_slice(&total.to_le_bytes());
+            packet.extend_from_slice(&((chunk * per_packet) as u32).to_le_bytes());
+            packet.extend_from_slice(&(run.len() as u32).to_le_bytes());
+            for position in run {
+                for component in position {
+                    packet.extend_from_slice(&component.to_le_bytes());
+                }
+            }
+            packet
+        })
+        .collect()
+}
+
+/// Start sending every new simulation frame to `config.target`, counting
+/// as a client of the simulation with `attendance` for as long as it runs.
+/// Must be called from within a Tokio runtime.
+pub fn spawn_broadcaster(config: &UdpConfig, latest: Arc<ArcSwap<Frame>>, attendance: Attendance) {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let _attendance = attendance;
+        if let Err(e) = broadcast(config, latest).await {
+            warn!("UDP broadcaster stopped: {}", e);
+        }
+    });
+}
+
+async fn broadcast(config: UdpConfig, latest: Arc<ArcSwap<Frame>>) -> Result<(), String> {
+    let target: SocketAddr = tokio::net::lookup_host(&config.target)
+        .await
+        .map_err(|e| format!("Cannot resolve UDP target {}: {}", config.target, e))?
+        .next()
+        .ok_or_else(|| format!("UDP target {} has no address", config.target))?;
+
+    let bind_addr = if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|e| format!("Failed to enable UDP broadcast: {}", e))?;
+    info!(
+        "UDP broadcaster sending to {} at up to {} Hz",
+        target, config.rate_hz
+    );
+
+    let period = Duration::from_millis(1000 / config.rate_hz.clamp(1, 1000) as u64);
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut last_frame = None;
+    let mut send_failed = false;
+
+    loop {
+        interval.tick().await;
+
+        let frame = latest.load_full();
+        let (frame_number, sim_time) = (frame.state.frame_number, frame.state.sim_time);
+        if last_frame == Some(frame_number) {
+            continue;
+        }
+        last_frame = Some(frame_number);
+        let positions: Vec<[f32; 3]> = (0..frame.state.len())
+            .map(|index| frame.state.position(index).into())
+            .collect();
+
+        for packet in encode_frame(frame_number, sim_time, &positions, config.max_packet_bytes) {
+            match socket.send_to(&packet, target).await {
+                Ok(_) => send_failed = false,
+                Err(e) => {
+                    // Nobody listening is normal; only report the first failure
+                    if !send_failed {
+                        warn!("UDP send to {} failed: {}", target, e);
+                        send_failed = true;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
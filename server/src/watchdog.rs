@@ -1,12 +1,49 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::webhooks::{EventKind, Notifier, WebhookEvent};
+
+/// Frames standing still this long are logged and reported as stalled
+const STALL_WARNING_SECS: u64 = 5;
+
+/// Liveness of the simulation as seen by the watchdog: `GET /health`
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Last frame the simulation reported
+    pub frame_number: u64,
+    /// How long that frame has stood still, 0 while frames advance or the
+    /// simulation is idle on purpose
+    pub stall_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// Frames advance, or stand still on purpose
+    Healthy,
+    /// Frames have stood still for a few seconds but not past the timeout
+    Stalled,
+    /// Frames have stood still past the watchdog timeout
+    Hung,
+}
+
 /// Watchdog that monitors simulation health and detects hung computations
 pub struct SimulationWatchdog {
     last_frame: Arc<AtomicU64>,
     running: Arc<std::sync::atomic::AtomicBool>,
+    /// The simulation stands still on purpose, e.g. without clients
+    idle: Arc<AtomicBool>,
+    /// Hangs past the timeout so far, and whether one is going on
+    stalls: Arc<AtomicU64>,
+    hung: Arc<AtomicBool>,
+    /// Length of the current stall, updated every second
+    stall_seconds: Arc<AtomicU64>,
+    notifier: Notifier,
 }
 
 impl SimulationWatchdog {
@@ -14,32 +51,90 @@ impl SimulationWatchdog {
         SimulationWatchdog {
             last_frame: Arc::new(AtomicU64::new(0)),
             running: Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            idle: Arc::new(AtomicBool::new(false)),
+            stalls: Arc::new(AtomicU64::new(0)),
+            hung: Arc::new(AtomicBool::new(false)),
+            stall_seconds: Arc::new(AtomicU64::new(0)),
+            notifier: Notifier::disabled(),
         }
     }
 
+    /// Also report hangs and recoveries through webhooks
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
     /// Update the watchdog with the current frame number
     pub fn heartbeat(&self, frame_number: u64) {
         self.last_frame.store(frame_number, Ordering::Relaxed);
     }
 
+    /// Whether frames stopping is expected rather than a hang
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    /// Hangs past the timeout since the server started
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+
+    /// Whether the simulation is hung past the timeout right now
+    pub fn is_hung(&self) -> bool {
+        self.hung.load(Ordering::Relaxed)
+    }
+
+    /// Current liveness, for health checks
+    pub fn health(&self) -> Health {
+        let stall_seconds = self.stall_seconds.load(Ordering::Relaxed);
+        let status = if self.is_hung() {
+            HealthStatus::Hung
+        } else if stall_seconds >= STALL_WARNING_SECS {
+            HealthStatus::Stalled
+        } else {
+            HealthStatus::Healthy
+        };
+        Health {
+            status,
+            frame_number: self.last_frame.load(Ordering::Relaxed),
+            stall_seconds,
+        }
+    }
+
     /// Start the watchdog thread
     pub fn start(&self, timeout_seconds: u64) {
         let last_frame = Arc::clone(&self.last_frame);
         let running = Arc::clone(&self.running);
+        let idle = Arc::clone(&self.idle);
+        let stalls = Arc::clone(&self.stalls);
+        let hung = Arc::clone(&self.hung);
+        let stall_seconds = Arc::clone(&self.stall_seconds);
+        let notifier = self.notifier.clone();
 
         thread::spawn(move || {
             let mut last_seen_frame = 0u64;
             let mut stall_start: Option<Instant> = None;
+            let mut stall_reported = false;
 
             while running.load(Ordering::Relaxed) {
                 thread::sleep(Duration::from_secs(1));
 
                 let current_frame = last_frame.load(Ordering::Relaxed);
 
+                if idle.load(Ordering::Relaxed) {
+                    stall_start = None;
+                    hung.store(false, Ordering::Relaxed);
+                    stall_seconds.store(0, Ordering::Relaxed);
+                    last_seen_frame = current_frame;
+                    continue;
+                }
+
                 if current_frame == last_seen_frame {
                     // Simulation appears stalled
                     if let Some(start) = stall_start {
                         let stall_duration = start.elapsed().as_secs();
+                        stall_seconds.store(stall_duration, Ordering::Relaxed);
 
                         if stall_duration >= timeout_seconds {
                             log::error!(
@@ -49,6 +144,21 @@ impl SimulationWatchdog {
                                 current_frame
                             );
 
+                            if !stall_reported {
+                                stall_reported = true;
+                                stalls.fetch_add(1, Ordering::Relaxed);
+                                hung.store(true, Ordering::Relaxed);
+                                notifier.notify(WebhookEvent {
+                                    event: EventKind::WatchdogStall,
+                                    message: format!(
+                                        "Simulation hung for {} seconds at frame {}",
+                                        stall_duration, current_frame
+                                    ),
+                                    frame_number: current_frame,
+                                    sim_time: None,
+                                });
+                            }
+
                             // Log every 30 seconds during hang
                             if stall_duration % 30 == 0 {
                                 log::error!(
@@ -56,7 +166,7 @@ impl SimulationWatchdog {
                                     stall_duration
                                 );
                             }
-                        } else if stall_duration >= 5 {
+                        } else if stall_duration >= STALL_WARNING_SECS {
                             log::warn!(
                                 "WATCHDOG: Simulation stalled for {} seconds at frame {}",
                                 stall_duration,
@@ -70,14 +180,28 @@ impl SimulationWatchdog {
                     // Simulation is progressing
                     if let Some(start) = stall_start {
                         let stall_duration = start.elapsed().as_secs();
-                        if stall_duration >= 5 {
+                        if stall_duration >= STALL_WARNING_SECS {
                             log::info!(
                                 "WATCHDOG: Simulation recovered after {} second stall",
                                 stall_duration
                             );
                         }
+                        if stall_reported {
+                            notifier.notify(WebhookEvent {
+                                event: EventKind::WatchdogRecovered,
+                                message: format!(
+                                    "Simulation recovered after {} second stall",
+                                    stall_duration
+                                ),
+                                frame_number: current_frame,
+                                sim_time: None,
+                            });
+                        }
                     }
                     stall_start = None;
+                    stall_reported = false;
+                    hung.store(false, Ordering::Relaxed);
+                    stall_seconds.store(0, Ordering::Relaxed);
                     last_seen_frame = current_frame;
                 }
             }
@@ -92,6 +216,12 @@ impl SimulationWatchdog {
     }
 }
 
+impl Default for SimulationWatchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Drop for SimulationWatchdog {
     fn drop(&mut self) {
         self.stop();
@@ -0,0 +1,240 @@
+// Webhook notifications about notable simulation events, so long unattended
+// runs can alert Slack, Discord or any other HTTP endpoint
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use crate::config::{WebhookConfig, WebhookFormat};
+use crate::physics::{closest_encounter, total_energy};
+use crate::service::Frame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    WatchdogStall,
+    WatchdogRecovered,
+    RunCompleted,
+    EnergyDrift,
+    Merger,
+    /// A batch job's conserved quantities drifted past a threshold
+    DriftExceeded,
+}
+
+/// One notification, POSTed as JSON in the configured format
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub event: EventKind,
+    pub message: String,
+    pub frame_number: u64,
+    /// Not known to the watchdog
+    pub sim_time: Option<f32>,
+}
+
+/// Cheap handle for raising events from any thread. Delivery happens on a
+/// background thread so a slow endpoint never holds up the caller.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    sender: Option<mpsc::Sender<WebhookEvent>>,
+}
+
+impl Notifier {
+    /// A notifier that drops every event
+    pub fn disabled() -> Self {
+        Notifier::default()
+    }
+
+    /// Start the delivery thread when any URL is configured
+    pub fn from_config(config: &WebhookConfig) -> Self {
+        if config.urls.is_empty() {
+            return Notifier::disabled();
+        }
+        if !cfg!(feature = "webhooks") {
+            warn!("Webhook URLs are configured but the `webhooks` feature is not compiled in");
+            return Notifier::disabled();
+        }
+
+        let (sender, receiver) = mpsc::channel::<WebhookEvent>();
+        let urls = config.urls.clone();
+        let format = config.format;
+        std::thread::spawn(move || {
+            for event in receiver {
+                let payload = payload(&event, format);
+                for url in &urls {
+                    if let Err(e) = post(url, &payload) {
+                        warn!("Webhook to {} failed: {}", url, e);
+                    }
+                }
+            }
+        });
+        info!("Webhooks enabled for {} endpoint(s)", config.urls.len());
+
+        Notifier {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn notify(&self, event: WebhookEvent) {
+        if let Some(sender) = &self.sender {
+            info!("Webhook event {:?}: {}", event.event, event.message);
+            // The delivery thread only exits with the process
+            let _ = sender.send(event);
+        }
+    }
+}
+
+fn payload(event: &WebhookEvent, format: WebhookFormat) -> serde_json::Value {
+    let text = format!("n_body: {}", event.message);
+    match format {
+        WebhookFormat::Json => serde_json::to_value(event).unwrap_or_default(),
+        WebhookFormat::Slack => serde_json::json!({ "text": text }),
+        WebhookFormat::Discord => serde_json::json!({ "content": text }),
+    }
+}
+
+#[cfg(feature = "webhooks")]
+fn post(url: &str, payload: &serde_json::Value) -> Result<(), String> {
+    ureq::post(url)
+        .timeout(Duration::from_secs(10))
+        .send_json(payload)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "webhooks"))]
+fn post(_url: &str, _payload: &serde_json::Value) -> Result<(), String> {
+    Err("Webhooks require the `webhooks` feature".to_string())
+}
+
+/// Watch the simulation for finished runs, energy drift and mergers. Must be
+/// called from within a Tokio runtime.
+pub fn spawn_monitor(config: &WebhookConfig, latest: Arc<ArcSwap<Frame>>, notifier: Notifier) {
+    if !notifier.is_enabled()
+        || (config.run_duration.is_none()
+            && config.energy_drift_threshold.is_none()
+            && config.merger_distance.is_none())
+    {
+        return;
+    }
+    let config = config.clone();
+    tokio::spawn(async move {
+        if let Err(e) = monitor(config, latest, notifier).await {
+            warn!("Webhook monitor stopped: {}", e);
+        }
+    });
+}
+
+/// What has already been reported since the last reset
+#[derive(Default)]
+struct Reported {
+    run_completed: bool,
+    energy_drift: bool,
+    in_encounter: bool,
+    initial_energy: Option<f64>,
+}
+
+async fn monitor(
+    config: WebhookConfig,
+    latest: Arc<ArcSwap<Frame>>,
+    notifier: Notifier,
+) -> Result<(), String> {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_sec.max(1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let energy_interval = Duration::from_secs(config.energy_interval_sec.max(1));
+    let mut last_energy_check: Option<Instant> = None;
+    let mut last_frame = 0;
+    let mut reported = Reported::default();
+
+    loop {
+        interval.tick().await;
+
+        let energy_due = config.energy_drift_threshold.is_some()
+            && last_energy_check.is_none_or(|at| at.elapsed() >= energy_interval);
+
+        let frame = latest.load_full();
+        let (frame_number, sim_time) = (frame.state.frame_number, frame.state.sim_time);
+        let params = frame.params;
+
+        // A reset starts a new run
+        if frame_number < last_frame {
+            reported = Reported::default();
+            last_energy_check = None;
+        }
+        last_frame = frame_number;
+
+        let event = |event, message| WebhookEvent {
+            event,
+            message,
+            frame_number,
+            sim_time: Some(sim_time),
+        };
+
+        if let Some(duration) = config.run_duration {
+            if !reported.run_completed && sim_time >= duration {
+                reported.run_completed = true;
+                notifier.notify(event(
+                    EventKind::RunCompleted,
+                    format!("Run reached t = {:.2} at frame {}", sim_time, frame_number),
+                ));
+            }
+        }
+
+        if !energy_due && config.merger_distance.is_none() {
+            continue;
+        }
+        if energy_due {
+            last_energy_check = Some(Instant::now());
+        }
+
+        // Both measurements scan every particle; keep them off the executor
+        let merger_distance = config.merger_distance;
+        let measure_energy = energy_due && params.periodic_box.is_none();
+        let (closest, energy) = tokio::task::spawn_blocking(move || {
+            let particles = &frame.state.particles().collect::<Vec<_>>();
+            (
+                merger_distance.and_then(|distance| {
+                    closest_encounter(particles, distance, params.periodic_box)
+                }),
+                measure_energy.then(|| total_energy(particles, params.gravity, params.softening)),
+            )
+        })
+        .await
+        .map_err(|e| format!("Measurement task failed: {}", e))?;
+
+        if config.merger_distance.is_some() {
+            // One report per encounter, not one per check while it lasts
+            if let (Some(separation), false) = (closest, reported.in_encounter) {
+                notifier.notify(event(
+                    EventKind::Merger,
+                    format!(
+                        "Two particles within {:.3e} of each other at t = {:.2}",
+                        separation, sim_time
+                    ),
+                ));
+            }
+            reported.in_encounter = closest.is_some();
+        }
+
+        if let (Some(threshold), Some(energy)) = (config.energy_drift_threshold, energy) {
+            let initial = *reported.initial_energy.get_or_insert(energy);
+            let drift = ((energy - initial) / initial.abs().max(f64::MIN_POSITIVE)).abs();
+            if !reported.energy_drift && drift > threshold {
+                reported.energy_drift = true;
+                notifier.notify(event(
+                    EventKind::EnergyDrift,
+                    format!(
+                        "Energy drifted by {:.2}% (threshold {:.2}%) at t = {:.2}",
+                        drift * 100.0,
+                        threshold * 100.0,
+                        sim_time
+                    ),
+                ));
+            }
+        }
+    }
+}
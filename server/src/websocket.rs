@@ -1,48 +1,497 @@
-use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, Handler, Message, SpawnHandle,
+    StreamHandler,
+};
 use actix_web_actors::ws;
+use futures_util::stream;
 use log::{error, info};
-use n_body_shared::{ClientMessage, ServerMessage};
-use std::sync::{Arc, Mutex};
+use n_body_shared::{
+    ClientMessage, EnergySample, PhysicsConfig, Role, ServerMessage, SimulationState, StatsSchema,
+    UnitSystem, ViewConfig, Welcome, CRATE_VERSION, MAX_VISUAL_FPS, PROTOCOL_VERSION,
+};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
 
-use crate::simulation::Simulation;
-use crate::watchdog::SimulationWatchdog;
+use crate::decimate;
+use crate::diff::DiffEncoder;
+use crate::dirty::DirtyTracker;
+use crate::encoding::{self, Encoded, StateEncoding, WireFormat};
+use crate::features;
+use crate::metrics::Metrics;
+use crate::rooms::Room;
+use crate::scenarios;
+use crate::service::Frame;
+use crate::session::SessionStore;
+use crate::snapshot::Snapshot;
+use crate::stepper::Attendance;
 
-use crate::config::{SimulationConfig, WebSocketConfig};
+use crate::config::{Config, SimulationConfig, WebSocketConfig};
+use crate::net::public_websocket_url;
+
+/// How often a paused simulation is reported in place of unchanged frames
+const PAUSED_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Send an encoded message as text or binary, counting it in `metrics`
+fn deliver(
+    ctx: &mut ws::WebsocketContext<SimulationWebSocket>,
+    metrics: &Metrics,
+    encoded: Encoded,
+) {
+    match encoded {
+        Encoded::Text(text) => {
+            metrics.sent(text.len());
+            ctx.text(text);
+        }
+        Encoded::Binary(bytes) => {
+            metrics.sent(bytes.len());
+            ctx.binary(bytes);
+        }
+    }
+}
+
+/// `deliver` a message, or log why it could not be encoded
+fn send(
+    ctx: &mut ws::WebsocketContext<SimulationWebSocket>,
+    metrics: &Metrics,
+    encoded: Result<Encoded, String>,
+) {
+    match encoded {
+        Ok(encoded) => deliver(ctx, metrics, encoded),
+        Err(e) => error!("{}", e),
+    }
+}
+
+/// A frame of the shared stepping loop, delivered to each client's actor
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Published(Arc<Frame>);
+
+/// A new energy partition sample
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Measured(EnergySample);
+
+/// The server's settings after config.toml was reloaded
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Reloaded(Arc<Config>);
+
+/// Config updates held back by the control rate limit, applied as one
+struct PendingConfig {
+    config: PhysicsConfig,
+    /// Ids of the updates merged into it, each acknowledged once applied
+    ids: Vec<u64>,
+    /// Whether any came without an id and expects the new config instead
+    untracked: bool,
+}
 
 pub struct SimulationWebSocket {
-    simulation: Arc<Mutex<Simulation>>,
-    watchdog: Arc<SimulationWatchdog>,
+    /// Simulation the client follows, the main one or a room's; holding it
+    /// keeps the room open
+    room: Arc<Room>,
+    sessions: Arc<SessionStore>,
+    metrics: Arc<Metrics>,
+    /// Token requested by the client until the handshake, then the issued one
+    session_token: Option<String>,
+    session: ViewConfig,
+    role: Role,
+    websocket_url: Option<String>,
+    /// Protocol version the client announced when connecting, if any
+    client_protocol: Option<u32>,
+    state_encoding: StateEncoding,
+    /// Format of every message, negotiated as a websocket subprotocol
+    format: WireFormat,
+    /// Sends keyframes and diffs when `keyframe_interval` is configured
+    diff: Option<DiffEncoder>,
+    /// Sends only moved particles when `dirty_threshold` is configured
+    dirty: Option<DirtyTracker>,
+    units: UnitSystem,
+    time_precision: usize,
     last_heartbeat: Instant,
     last_render: Instant,
+    /// Last frame of any kind streamed, real or interpolated
+    last_frame_sent: Instant,
+    /// Simulation version of the last real frame streamed
+    last_version_sent: Option<u64>,
+    /// Last `Paused` status sent in place of an unchanged frame
+    last_paused_sent: Instant,
+    /// Block of `stats_frequency` frames the last stats were sent in
+    last_stats_period: Option<u64>,
+    /// When the last published frame arrived, for interpolating after it
     last_physics_update: Instant,
+    /// The last published frame, extrapolated between physics steps
+    last_published: Option<Arc<Frame>>,
+    /// Keeps the room's simulation stepping while connected
+    attendance: Option<Attendance>,
+    /// Last config update, reset or scenario switch applied
+    last_control: Option<Instant>,
+    /// Latest config update waiting out `control_interval_ms`
+    pending_config: Option<PendingConfig>,
+    /// The server's settings, updated when config.toml is reloaded
+    settings: watch::Receiver<Arc<Config>>,
     ws_config: WebSocketConfig,
     sim_config: SimulationConfig,
+    /// Pings the client every `heartbeat_interval_sec`
+    heartbeat: Option<SpawnHandle>,
 }
 
 impl SimulationWebSocket {
     pub fn new(
-        simulation: Arc<Mutex<Simulation>>,
-        watchdog: Arc<SimulationWatchdog>,
-        ws_config: &WebSocketConfig,
-        sim_config: &SimulationConfig,
+        room: Arc<Room>,
+        sessions: Arc<SessionStore>,
+        metrics: Arc<Metrics>,
+        session_token: Option<String>,
+        role: Role,
+        client_protocol: Option<u32>,
+        settings: watch::Receiver<Arc<Config>>,
     ) -> Self {
+        let config = Arc::clone(&settings.borrow());
+        // A named simulation has settings of its own
+        let sim_config = SimulationConfig::clone(&room.config.load());
         Self {
-            simulation,
-            watchdog,
+            room,
+            sessions,
+            metrics,
+            session_token,
+            session: ViewConfig::default(),
+            role,
+            websocket_url: config
+                .server
+                .public_url
+                .as_deref()
+                .map(public_websocket_url),
+            client_protocol,
+            state_encoding: StateEncoding::default(),
+            format: WireFormat::default(),
+            diff: config.websocket.keyframe_interval.map(DiffEncoder::new),
+            dirty: config
+                .websocket
+                .dirty_threshold
+                .filter(|_| config.websocket.keyframe_interval.is_none())
+                .map(|threshold| DirtyTracker::new(threshold, config.websocket.dirty_max_fraction)),
+            units: config.unit_system(),
+            time_precision: config.units.time_precision,
             last_heartbeat: Instant::now(),
             last_render: Instant::now(),
+            last_frame_sent: Instant::now(),
+            last_version_sent: None,
+            last_paused_sent: Instant::now(),
+            last_stats_period: None,
             last_physics_update: Instant::now(),
-            ws_config: ws_config.clone(),
-            sim_config: sim_config.clone(),
+            last_published: None,
+            attendance: None,
+            last_control: None,
+            pending_config: None,
+            settings,
+            ws_config: config.websocket.clone(),
+            sim_config,
+            heartbeat: None,
+        }
+    }
+
+    /// Stream state frames in `encoding` instead of the session's format
+    pub fn with_state_encoding(mut self, encoding: StateEncoding) -> Self {
+        self.state_encoding = encoding;
+        if encoding != StateEncoding::Json {
+            // Deltas are messages of the session's format; the other
+            // encodings stay full frames
+            self.dirty = None;
+        }
+        if matches!(encoding, StateEncoding::Quantized | StateEncoding::Arrow) {
+            self.diff = None;
+        }
+        self
+    }
+
+    /// Exchange messages in `format` instead of JSON text
+    pub fn with_wire_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Send `message` in the session's wire format
+    fn send_message<T: Serialize>(&self, ctx: &mut <Self as Actor>::Context, message: &T) {
+        send(ctx, &self.metrics, self.format.encode(message));
+    }
+
+    fn send_state(&self, ctx: &mut <Self as Actor>::Context, state: &SimulationState) {
+        send(
+            ctx,
+            &self.metrics,
+            encoding::encode_state(state, self.state_encoding, self.format),
+        );
+    }
+
+    /// List the scenarios to the client. Building the list runs scenario
+    /// scripts, so it happens on the blocking pool.
+    fn send_scenarios(&self, ctx: &mut <Self as Actor>::Context) {
+        let dir = PathBuf::from(&self.sim_config.scenarios_dir);
+        let listing = actix_web::web::block(move || scenarios::catalog(&dir));
+        ctx.spawn(actix::fut::wrap_future::<_, Self>(listing).map(
+            |listed, act, ctx| match listed {
+                Ok(scenarios) => act.send_message(ctx, &ServerMessage::Scenarios { scenarios }),
+                Err(e) => error!("Failed to list scenarios: {}", e),
+            },
+        ));
+    }
+
+    /// Answer a config update that left `applied` in effect: an `Ack` if it
+    /// came with an id, otherwise the new config or an `Error`
+    fn send_config_result(
+        &self,
+        ctx: &mut <Self as Actor>::Context,
+        id: Option<u64>,
+        result: Result<(), String>,
+        applied: PhysicsConfig,
+    ) {
+        let message = match (id, result) {
+            (Some(id), result) => ServerMessage::Ack {
+                id,
+                applied_config: applied,
+                error: result.err(),
+            },
+            (None, Ok(())) => ServerMessage::Config(applied),
+            (None, Err(message)) => ServerMessage::Error { message },
+        };
+        self.send_message(ctx, &message);
+    }
+
+    /// Time left before this client may change the simulation again, if any
+    fn control_cooldown(&self) -> Option<Duration> {
+        let interval = Duration::from_millis(self.ws_config.control_interval_ms);
+        let elapsed = self.last_control?.elapsed();
+        interval.checked_sub(elapsed).filter(|left| !left.is_zero())
+    }
+
+    /// Apply a config update, or hold it back until the rate limit allows.
+    /// Updates arriving meanwhile replace it, so only the latest one runs.
+    fn update_config(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        id: Option<u64>,
+        config: PhysicsConfig,
+    ) {
+        if let Some(pending) = &mut self.pending_config {
+            pending.config = config;
+            pending.ids.extend(id);
+            pending.untracked |= id.is_none();
+            return;
+        }
+        let update = PendingConfig {
+            config,
+            ids: id.into_iter().collect(),
+            untracked: id.is_none(),
+        };
+        match self.control_cooldown() {
+            None => self.apply_config(ctx, update),
+            Some(wait) => {
+                self.pending_config = Some(update);
+                ctx.run_later(wait, |act, ctx| {
+                    if let Some(update) = act.pending_config.take() {
+                        act.apply_config(ctx, update);
+                    }
+                });
+            }
         }
     }
 
-    fn start_heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+    /// Apply `update` and answer every message merged into it
+    fn apply_config(&mut self, ctx: &mut <Self as Actor>::Context, update: PendingConfig) {
+        let PendingConfig {
+            mut config,
+            ids,
+            untracked,
+        } = update;
+        self.last_control = Some(Instant::now());
+        info!("Updating config: {:?}", config);
+        let (result, applied, adjusted) = match self.room.simulation.lock() {
+            Ok(mut sim) => {
+                let adjusted = sim.clamp_config(&mut config);
+                let result = sim.update_config(config);
+                (result, sim.get_config().clone(), adjusted)
+            }
+            Err(e) => {
+                error!("Failed to lock simulation: {}", e);
+                let message = "Server error: simulation lock failed".to_string();
+                self.send_message(ctx, &ServerMessage::Error { message });
+                return;
+            }
+        };
+        if let Err(error_msg) = &result {
+            error!("Config update failed: {}", error_msg);
+        }
+        // Send back the updated config to confirm
+        for id in ids {
+            self.send_config_result(ctx, Some(id), result.clone(), applied.clone());
+        }
+        let clamped = result.is_ok() && !adjusted.is_empty();
+        if untracked {
+            self.send_config_result(ctx, None, result, applied);
+        }
+        if clamped {
+            let message = format!("Settings adjusted: {}", adjusted.join("; "));
+            info!("{}", message);
+            self.send_message(ctx, &ServerMessage::Error { message });
+        }
+    }
+
+    /// Step the paused simulation `count` times on the blocking pool, as
+    /// many steps can take a while, then send the resulting state. Other
+    /// clients get it with the next published frame.
+    fn step_paused(&self, ctx: &mut <Self as Actor>::Context, count: u32) {
+        info!("Stepping paused simulation {} time(s)", count);
+        let simulation = self.room.simulation.clone();
+        let stepping = actix_web::web::block(move || {
+            let mut sim = simulation
+                .lock()
+                .map_err(|e| format!("Failed to lock simulation: {}", e))?;
+            sim.step_paused(count)?;
+            Ok::<_, String>(sim.state())
+        });
+        ctx.spawn(actix::fut::wrap_future::<_, Self>(stepping).map(
+            |stepped, act, ctx| match stepped {
+                Ok(Ok(state)) => act.send_out_of_band(ctx, &state),
+                Ok(Err(message)) => {
+                    error!("Step failed: {}", message);
+                    act.send_message(ctx, &ServerMessage::Error { message });
+                }
+                Err(e) => error!("Step task failed: {}", e),
+            },
+        ));
+    }
+
+    /// Save the simulation as snapshot `name`, writing the file off the
+    /// event loop and outside the simulation lock
+    fn save_snapshot(&self, ctx: &mut <Self as Actor>::Context, name: Option<String>) {
+        let simulation = self.room.simulation.clone();
+        let dir = PathBuf::from(&self.sim_config.snapshots_dir);
+        let saving = actix_web::web::block(move || {
+            let snapshot = simulation
+                .lock()
+                .map_err(|e| format!("Failed to lock simulation: {}", e))?
+                .snapshot(name)?;
+            snapshot.save(&dir)?;
+            Ok::<_, String>(snapshot.info)
+        });
+        ctx.spawn(
+            actix::fut::wrap_future::<_, Self>(saving).map(|saved, act, ctx| match saved {
+                Ok(Ok(info)) => {
+                    info!("Saved snapshot {}", info.name);
+                    act.send_message(ctx, &ServerMessage::Snapshot(info));
+                }
+                Ok(Err(message)) => {
+                    error!("Snapshot failed: {}", message);
+                    act.send_message(ctx, &ServerMessage::Error { message });
+                }
+                Err(e) => error!("Snapshot task failed: {}", e),
+            }),
+        );
+    }
+
+    /// Continue the simulation from snapshot `name`, reading the file
+    /// before taking the simulation lock
+    fn load_snapshot(&self, ctx: &mut <Self as Actor>::Context, name: String) {
+        let simulation = self.room.simulation.clone();
+        let dir = PathBuf::from(&self.sim_config.snapshots_dir);
+        let loading = actix_web::web::block(move || {
+            let snapshot = Snapshot::load(&dir, &name)?;
+            let info = snapshot.info.clone();
+            let mut sim = simulation
+                .lock()
+                .map_err(|e| format!("Failed to lock simulation: {}", e))?;
+            sim.restore(snapshot)?;
+            Ok::<_, String>((info, sim.get_config().clone(), sim.state()))
+        });
+        ctx.spawn(actix::fut::wrap_future::<_, Self>(loading).map(
+            |loaded, act, ctx| match loaded {
+                Ok(Ok((info, config, state))) => {
+                    info!("Loaded snapshot {}", info.name);
+                    act.send_message(ctx, &ServerMessage::Snapshot(info));
+                    act.send_message(ctx, &ServerMessage::Config(config));
+                    act.send_out_of_band(ctx, &state);
+                }
+                Ok(Err(message)) => {
+                    error!("Loading snapshot failed: {}", message);
+                    act.send_message(ctx, &ServerMessage::Error { message });
+                }
+                Err(e) => error!("Snapshot task failed: {}", e),
+            },
+        ));
+    }
+
+    /// `state` cut down to the client's `max_streamed_particles`
+    fn streamed<'a>(&self, state: &'a SimulationState) -> Cow<'a, SimulationState> {
+        self.session
+            .max_streamed_particles
+            .and_then(|max| decimate::sample(state, max))
+            .map_or(Cow::Borrowed(state), Cow::Owned)
+    }
+
+    /// Send `state` in full outside the stream of frames, e.g. right after
+    /// a reset. The next streamed frame is a full one again.
+    fn send_out_of_band(&mut self, ctx: &mut <Self as Actor>::Context, state: &SimulationState) {
+        let streamed = self.streamed(state);
+        let state = streamed.as_ref();
+        if let Some(diff) = &mut self.diff {
+            diff.invalidate();
+        }
+        if let Some(dirty) = &mut self.dirty {
+            dirty.invalidate();
+        }
+        self.send_state(ctx, state);
+    }
+
+    /// Stream a frame, as a diff since the last one or a delta of the moved
+    /// particles when enabled
+    fn send_frame(&mut self, ctx: &mut <Self as Actor>::Context, state: &SimulationState) {
+        let streamed = self.streamed(state);
+        let state = streamed.as_ref();
+        if let Some(diff) = &mut self.diff {
+            let encoded = match diff.diff(state) {
+                Some(frame) => encoding::encode_diff(&frame, self.state_encoding, self.format),
+                None => encoding::encode_state(state, self.state_encoding, self.format),
+            };
+            match encoded {
+                Ok(encoded) => deliver(ctx, &self.metrics, encoded),
+                Err(e) => {
+                    // The client may not have it; start over from a keyframe
+                    diff.invalidate();
+                    error!("{}", e);
+                }
+            }
+            return;
+        }
+        let Some(dirty) = &mut self.dirty else {
+            self.send_state(ctx, state);
+            return;
+        };
+        let encoded = match dirty.delta(state) {
+            Some(delta) => self.format.encode(&ServerMessage::StateDelta(delta)),
+            None => encoding::state_message(state, self.format),
+        };
+        match encoded {
+            Ok(encoded) => deliver(ctx, &self.metrics, encoded),
+            Err(e) => {
+                // The client may not have it; start over from a full frame
+                dirty.invalidate();
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// Ping the client regularly, replacing the pings of earlier settings
+    fn start_heartbeat(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if let Some(handle) = self.heartbeat.take() {
+            ctx.cancel_future(handle);
+        }
         let heartbeat_interval = Duration::from_secs(self.ws_config.heartbeat_interval_sec);
         let client_timeout = Duration::from_secs(self.ws_config.client_timeout_sec);
 
-        ctx.run_interval(heartbeat_interval, move |act, ctx| {
+        let handle = ctx.run_interval(heartbeat_interval, move |act, ctx| {
             if Instant::now().duration_since(act.last_heartbeat) > client_timeout {
                 info!("WebSocket client heartbeat failed, disconnecting");
                 ctx.stop();
@@ -50,68 +499,262 @@ impl SimulationWebSocket {
             }
             ctx.ping(b"");
         });
+        self.heartbeat = Some(handle);
     }
 
-    fn start_simulation_loop(&self, ctx: &mut <Self as Actor>::Context) {
-        // Run at configured update rate
-        let update_interval = Duration::from_millis(self.sim_config.update_rate_ms);
+    /// Receive the frames of the shared stepping loop. A client too slow
+    /// to keep up skips the frames it missed.
+    fn subscribe(&self, ctx: &mut <Self as Actor>::Context) {
+        let frames = stream::unfold(self.room.frames.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(frame) => return Some((Published(frame), receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        ctx.add_message_stream(frames);
+    }
 
-        ctx.run_interval(update_interval, |act, ctx| {
-            // Step physics simulation
-            if act.last_physics_update.elapsed()
-                >= Duration::from_millis(act.sim_config.update_rate_ms)
-            {
-                act.last_physics_update = Instant::now();
+    /// Receive the server's settings whenever config.toml is reloaded
+    fn subscribe_settings(&self, ctx: &mut <Self as Actor>::Context) {
+        let reloads = stream::unfold(self.settings.clone(), |mut receiver| async move {
+            receiver.changed().await.ok()?;
+            let config = Arc::clone(&receiver.borrow_and_update());
+            Some((Reloaded(config), receiver))
+        });
+        ctx.add_message_stream(reloads);
+    }
 
-                // Check if context is still valid (client connected)
-                if ctx.state() != actix::ActorState::Running {
-                    return;
+    /// Send the energy history so far, then every new sample
+    fn subscribe_energy(&self, ctx: &mut <Self as Actor>::Context) {
+        let history = self.room.energy.history();
+        if !history.is_empty() {
+            self.send_energy(ctx, history);
+        }
+        let samples = stream::unfold(self.room.energy.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(sample) => return Some((Measured(sample), receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
                 }
+            }
+        });
+        ctx.add_message_stream(samples);
+    }
 
-                let (state, stats) = {
-                    match act.simulation.lock() {
-                        Ok(mut sim) => {
-                            let result = sim.step();
-                            // Update watchdog with current frame number
-                            act.watchdog.heartbeat(result.1.frame_number);
-                            result
-                        }
-                        Err(e) => {
-                            error!("Failed to lock simulation: {}", e);
-                            return;
-                        }
-                    }
-                };
+    fn send_energy(&self, ctx: &mut <Self as Actor>::Context, samples: Vec<EnergySample>) {
+        self.send_message(ctx, &ServerMessage::Energy { samples });
+    }
 
-                // Check current visual FPS setting
-                let visual_fps = {
-                    match act.simulation.lock() {
-                        Ok(sim) => sim.get_config().visual_fps,
-                        Err(_) => 30, // fallback
-                    }
-                };
-                let render_interval_ms = 1000 / visual_fps;
+    /// Fill the gaps between slow physics steps with interpolated frames at
+    /// this session's visual FPS. Reschedules itself each tick so a change of
+    /// visual FPS takes effect immediately.
+    fn schedule_interpolated_frame(&self, ctx: &mut <Self as Actor>::Context) {
+        let render_interval = Duration::from_millis(1000 / self.session.visual_fps.max(1) as u64);
 
-                // Only send state update if enough time has passed for visual FPS
-                if act.last_render.elapsed().as_millis() >= render_interval_ms as u128 {
-                    act.last_render = Instant::now();
+        ctx.run_later(render_interval, |act, ctx| {
+            let render_interval =
+                Duration::from_millis(1000 / act.session.visual_fps.max(1) as u64);
+            let physics_interval = Duration::from_millis(act.sim_config.update_rate_ms.max(1));
 
-                    // Send state update with error handling
-                    match serde_json::to_string(&ServerMessage::State(state)) {
-                        Ok(json) => ctx.text(json),
-                        Err(e) => error!("Failed to serialize state: {}", e),
-                    }
+            // Only needed when the real steps did not already send a frame.
+            // Real frames keep their own throttle so they always go out.
+            if act.last_frame_sent.elapsed() >= render_interval {
+                let fraction = (act.last_physics_update.elapsed().as_secs_f32()
+                    / physics_interval.as_secs_f32())
+                .min(1.0);
+                let state = act
+                    .last_published
+                    .as_ref()
+                    .and_then(|frame| frame.extrapolated(fraction));
+                if let Some(state) = state {
+                    act.last_frame_sent = Instant::now();
+                    act.send_frame(ctx, &state);
                 }
+            }
 
-                // Send stats every 30 frames
-                if stats.frame_number % 30 == 0 {
-                    match serde_json::to_string(&ServerMessage::Stats(stats)) {
-                        Ok(json) => ctx.text(json),
-                        Err(e) => error!("Failed to serialize stats: {}", e),
+            act.schedule_interpolated_frame(ctx);
+        });
+    }
+
+    /// Act on a command from the client
+    fn handle_client_message(
+        &mut self,
+        message: ClientMessage,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        match message {
+            // View settings only concern this client and its session
+            ClientMessage::UpdateView(mut view) => {
+                info!("Updating view: {:?}", view);
+                let fps = view.visual_fps.clamp(1, MAX_VISUAL_FPS);
+                if fps != view.visual_fps {
+                    let message = format!(
+                        "Settings adjusted: visual FPS {} changed to {}",
+                        view.visual_fps, fps
+                    );
+                    view.visual_fps = fps;
+                    self.send_message(ctx, &ServerMessage::Error { message });
+                }
+                if let Some(token) = &self.session_token {
+                    self.sessions.update(token, view.clone());
+                }
+                self.session = view;
+            }
+            ClientMessage::ListScenarios => self.send_scenarios(ctx),
+            ClientMessage::RequestState => {
+                // Published by the physics thread; no lock needed
+                let frame = self.room.latest.load_full();
+                self.send_out_of_band(ctx, &frame.state);
+            }
+            msg if self.role < Role::Controller => {
+                // Spectators only receive the stream
+                let message = "This connection is read-only".to_string();
+                // A config update with an id still gets its `Ack`
+                let ack = match msg {
+                    ClientMessage::UpdateConfig { id: Some(id), .. } => self
+                        .room
+                        .simulation
+                        .lock()
+                        .ok()
+                        .map(|sim| (id, sim.get_config().clone())),
+                    _ => None,
+                };
+                match ack {
+                    Some((id, applied)) => {
+                        self.send_config_result(ctx, Some(id), Err(message), applied)
                     }
+                    None => self.send_message(ctx, &ServerMessage::Error { message }),
                 }
             }
-        });
+            ClientMessage::Step { count } => self.step_paused(ctx, count),
+            ClientMessage::StartRecording { interval } => {
+                match self.room.recorder.start(interval) {
+                    Ok(status) => self.send_message(ctx, &ServerMessage::Recording(status)),
+                    Err(message) => {
+                        error!("Recording failed to start: {}", message);
+                        self.send_message(ctx, &ServerMessage::Error { message });
+                    }
+                }
+            }
+            ClientMessage::StopRecording => {
+                let status = self.room.recorder.stop();
+                self.send_message(ctx, &ServerMessage::Recording(status));
+            }
+            ClientMessage::SaveSnapshot { name } => self.save_snapshot(ctx, name),
+            ClientMessage::LoadSnapshot { name } => self.load_snapshot(ctx, name),
+            ClientMessage::UpdateConfig { id, config } => self.update_config(ctx, id, config),
+            ClientMessage::Reset | ClientMessage::SetScenario { .. }
+                if self.control_cooldown().is_some() || self.pending_config.is_some() =>
+            {
+                let message = format!(
+                    "Too many changes; wait {} ms between resets and config updates",
+                    self.ws_config.control_interval_ms
+                );
+                self.send_message(ctx, &ServerMessage::Error { message });
+            }
+            msg => {
+                // Keep the lock apart from `self`, which replies need
+                let room = self.room.clone();
+                match room.simulation.lock() {
+                    Ok(mut sim) => {
+                        match msg {
+                            ClientMessage::SetScenario { scenario } => {
+                                info!("Switching to scenario {}", scenario);
+                                self.last_control = Some(Instant::now());
+                                let result = scenario
+                                    .parse()
+                                    .and_then(|scenario| sim.set_scenario(scenario));
+                                match result {
+                                    Ok(()) => {
+                                        self.send_message(
+                                            ctx,
+                                            &ServerMessage::Config(sim.get_config().clone()),
+                                        );
+                                        // Show the new particles straight away
+                                        let state = sim.state();
+                                        self.send_out_of_band(ctx, &state);
+                                    }
+                                    Err(error_msg) => {
+                                        error!("Scenario switch failed: {}", error_msg);
+                                        self.send_message(
+                                            ctx,
+                                            &ServerMessage::Error { message: error_msg },
+                                        );
+                                    }
+                                }
+                            }
+                            ClientMessage::Reset => {
+                                info!("Resetting simulation");
+                                self.last_control = Some(Instant::now());
+                                sim.reset();
+
+                                // Send immediate state update after reset
+                                let state = sim.state();
+                                self.send_out_of_band(ctx, &state);
+                            }
+                            ClientMessage::Pause => {
+                                info!("Pausing simulation");
+                                sim.set_paused(true);
+                            }
+                            ClientMessage::Resume => {
+                                info!("Resuming simulation");
+                                sim.set_paused(false);
+                            }
+                            ClientMessage::ReverseTime => {
+                                info!("Reversing time");
+                                match sim.reverse_time() {
+                                    Ok(()) => {
+                                        let state = sim.state();
+                                        self.send_out_of_band(ctx, &state);
+                                    }
+                                    Err(message) => {
+                                        error!("Time reversal failed: {}", message);
+                                        self.send_message(ctx, &ServerMessage::Error { message });
+                                    }
+                                }
+                            }
+                            ClientMessage::Seek { frame } => {
+                                info!("Seeking to frame {}", frame);
+                                match sim.seek(frame) {
+                                    Ok(()) => {
+                                        // The settings may have changed since
+                                        self.send_message(
+                                            ctx,
+                                            &ServerMessage::Config(sim.get_config().clone()),
+                                        );
+                                        let state = sim.state();
+                                        self.send_out_of_band(ctx, &state);
+                                    }
+                                    Err(message) => {
+                                        error!("Seek failed: {}", message);
+                                        self.send_message(ctx, &ServerMessage::Error { message });
+                                    }
+                                }
+                            }
+                            // Answered above without the lock
+                            ClientMessage::UpdateView(_)
+                            | ClientMessage::ListScenarios
+                            | ClientMessage::RequestState
+                            | ClientMessage::Step { .. }
+                            | ClientMessage::StartRecording { .. }
+                            | ClientMessage::StopRecording
+                            | ClientMessage::UpdateConfig { .. }
+                            | ClientMessage::SaveSnapshot { .. }
+                            | ClientMessage::LoadSnapshot { .. } => {}
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to lock simulation: {}", e);
+                        let message = "Server error: simulation lock failed".to_string();
+                        self.send_message(ctx, &ServerMessage::Error { message });
+                    }
+                };
+            }
+        }
     }
 }
 
@@ -120,28 +763,175 @@ impl Actor for SimulationWebSocket {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         info!("WebSocket connection established");
+        self.metrics.connected();
+
+        // A client cached from before a deploy would fail to parse what we
+        // send, so tell it to reload the matching build and hang up. Clients
+        // that don't name a version, such as scripts, are let through
+        if matches!(self.client_protocol, Some(version) if version != PROTOCOL_VERSION) {
+            let reload = ServerMessage::Reload {
+                protocol_version: PROTOCOL_VERSION,
+                server_version: CRATE_VERSION.to_string(),
+            };
+            self.send_message(ctx, &reload);
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: Some("Protocol version mismatch".to_string()),
+            }));
+            ctx.stop();
+            return;
+        }
+
+        self.attendance = Some(self.room.presence.join());
         self.start_heartbeat(ctx);
-        self.start_simulation_loop(ctx);
-
-        // Send initial config with error handling
-        match self.simulation.lock() {
-            Ok(sim) => {
-                let config = sim.get_config().clone();
-                match serde_json::to_string(&ServerMessage::Config(config)) {
-                    Ok(json) => ctx.text(json),
-                    Err(e) => error!("Failed to serialize initial config: {}", e),
-                }
-            }
+        self.subscribe(ctx);
+        self.subscribe_settings(ctx);
+        if self.sim_config.interpolate_frames {
+            self.schedule_interpolated_frame(ctx);
+        }
+
+        // Open or resume the session, then send the handshake, the initial
+        // config and the current frame so the client can draw straight away
+        // instead of waiting for the next scheduled frame
+        let config = match self.room.simulation.lock() {
+            Ok(sim) => sim.get_config().clone(),
             Err(e) => {
                 error!("Failed to lock simulation for initial config: {}", e);
                 // Close connection if we can't access simulation
                 ctx.stop();
+                return;
             }
+        };
+        let opened = self.sessions.open(
+            self.session_token.as_deref(),
+            ViewConfig::default(),
+            self.role,
+        );
+        if opened.resumed {
+            info!("Resumed session {} as {:?}", opened.token, opened.role);
         }
+        self.session_token = Some(opened.token.clone());
+        self.session = opened.settings.clone();
+        self.role = opened.role;
+
+        let welcome = Welcome {
+            session_token: opened.token,
+            resumed: opened.resumed,
+            settings: opened.settings,
+            role: opened.role,
+            websocket_url: self.websocket_url.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            server_version: CRATE_VERSION.to_string(),
+            units: self.units,
+            features: features::enabled(),
+        };
+        self.send_message(ctx, &ServerMessage::Welcome(welcome));
+        self.send_message(ctx, &ServerMessage::StatsSchema(StatsSchema::current()));
+        self.send_scenarios(ctx);
+        self.subscribe_energy(ctx);
+
+        self.send_message(ctx, &ServerMessage::Config(config));
+
+        // Published by the physics thread; no lock needed
+        let frame = self.room.latest.load_full();
+        let mut stats = frame.stats.clone();
+        stats.sim_time_display = self.units.format_time(stats.sim_time, self.time_precision);
+        self.send_message(ctx, &ServerMessage::Stats(stats));
+        self.last_render = Instant::now();
+        self.last_frame_sent = self.last_render;
+        self.last_version_sent = Some(frame.version);
+        self.send_frame(ctx, &frame.state);
+        self.last_published = Some(frame);
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         info!("WebSocket connection closed");
+        self.metrics.disconnected();
+        self.attendance = None;
+
+        // Start the resume window from the moment the client went away
+        if let Some(token) = &self.session_token {
+            self.sessions.update(token, self.session.clone());
+        }
+    }
+}
+
+impl Handler<Published> for SimulationWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, Published(frame): Published, ctx: &mut Self::Context) {
+        self.last_physics_update = Instant::now();
+        self.last_published = Some(frame.clone());
+
+        if let Some(scaling) = &frame.scaling {
+            self.send_message(ctx, &ServerMessage::ParticlesScaled(scaling.clone()));
+        }
+
+        // Nothing has moved since the last frame went out, typically
+        // because the simulation is paused: resending the same state and
+        // stats would only waste bandwidth
+        if self.last_version_sent == Some(frame.version) {
+            if self.last_paused_sent.elapsed() >= PAUSED_STATUS_INTERVAL {
+                self.last_paused_sent = Instant::now();
+                let status = ServerMessage::Paused {
+                    frame_number: frame.stats.frame_number,
+                    sim_time: frame.stats.sim_time,
+                };
+                self.send_message(ctx, &status);
+            }
+            return;
+        }
+
+        // Visual FPS is a per-session setting
+        let render_interval_ms = 1000 / self.session.visual_fps.max(1);
+
+        // Only send state update if enough time has passed for visual FPS
+        if self.last_render.elapsed().as_millis() >= render_interval_ms as u128 {
+            self.last_render = Instant::now();
+            self.last_frame_sent = self.last_render;
+            self.last_version_sent = Some(frame.version);
+
+            self.send_frame(ctx, &frame.state);
+        }
+
+        // Send stats every `stats_frequency` frames. Frames advance by
+        // `steps_per_update` at a time, so look for a new block of them
+        // rather than an exact multiple.
+        let period = frame.stats.frame_number / self.sim_config.stats_frequency.max(1);
+        if self.last_stats_period != Some(period) {
+            self.last_stats_period = Some(period);
+            let mut stats = frame.stats.clone();
+            stats.sim_time_display = self.units.format_time(stats.sim_time, self.time_precision);
+            self.send_message(ctx, &ServerMessage::Stats(stats));
+        }
+    }
+}
+
+impl Handler<Measured> for SimulationWebSocket {
+    type Result = ();
+
+    fn handle(&mut self, Measured(sample): Measured, ctx: &mut Self::Context) {
+        self.send_energy(ctx, vec![sample]);
+    }
+}
+
+impl Handler<Reloaded> for SimulationWebSocket {
+    type Result = ();
+
+    /// Take up the reloaded settings and show the client the simulation's
+    fn handle(&mut self, Reloaded(config): Reloaded, ctx: &mut Self::Context) {
+        self.ws_config = config.websocket.clone();
+        self.sim_config = SimulationConfig::clone(&self.room.config.load());
+        self.start_heartbeat(ctx);
+        let config = self
+            .room
+            .simulation
+            .lock()
+            .map(|sim| sim.get_config().clone());
+        match config {
+            Ok(config) => self.send_message(ctx, &ServerMessage::Config(config)),
+            Err(e) => error!("Failed to lock simulation: {}", e),
+        }
     }
 }
 
@@ -159,78 +949,27 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for SimulationWebSock
                 self.last_heartbeat = Instant::now();
 
                 match serde_json::from_str::<ClientMessage>(&text) {
-                    Ok(msg) => {
-                        match self.simulation.lock() {
-                            Ok(mut sim) => {
-                                match msg {
-                                    ClientMessage::UpdateConfig(config) => {
-                                        info!("Updating config: {:?}", config);
-                                        match sim.update_config(config) {
-                                            Ok(()) => {
-                                                // Send back updated config to confirm
-                                                let updated_config = sim.get_config().clone();
-                                                if let Ok(json) = serde_json::to_string(
-                                                    &ServerMessage::Config(updated_config),
-                                                ) {
-                                                    ctx.text(json);
-                                                }
-                                            }
-                                            Err(error_msg) => {
-                                                error!("Config update failed: {}", error_msg);
-                                                // Send error message to client
-                                                if let Ok(json) =
-                                                    serde_json::to_string(&ServerMessage::Error {
-                                                        message: error_msg,
-                                                    })
-                                                {
-                                                    ctx.text(json);
-                                                }
-                                            }
-                                        }
-                                    }
-                                    ClientMessage::Reset => {
-                                        info!("Resetting simulation");
-                                        sim.reset();
-
-                                        // Send immediate state update after reset
-                                        let (state, _) = sim.step();
-                                        if let Ok(json) =
-                                            serde_json::to_string(&ServerMessage::State(state))
-                                        {
-                                            ctx.text(json);
-                                        }
-                                    }
-                                    ClientMessage::Pause => {
-                                        info!("Pausing simulation");
-                                        sim.set_paused(true);
-                                    }
-                                    ClientMessage::Resume => {
-                                        info!("Resuming simulation");
-                                        sim.set_paused(false);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to lock simulation: {}", e);
-                                // Send error message back to client
-                                if let Ok(json) =
-                                    serde_json::to_string(&"Server error: simulation lock failed")
-                                {
-                                    ctx.text(json);
-                                }
-                            }
-                        }
-                    }
+                    Ok(message) => self.handle_client_message(message, ctx),
                     Err(e) => {
                         error!("Failed to parse client message '{}': {}", text, e);
-                        // Send error message back to client
-                        if let Ok(json) = serde_json::to_string(&format!("Parse error: {}", e)) {
-                            ctx.text(json);
-                        }
+                        let message = format!("Parse error: {}", e);
+                        self.send_message(ctx, &ServerMessage::Error { message });
+                    }
+                }
+            }
+            // Commands in the negotiated MessagePack or CBOR format
+            Ok(ws::Message::Binary(bytes)) => {
+                self.last_heartbeat = Instant::now();
+
+                match self.format.decode::<ClientMessage>(&bytes) {
+                    Ok(message) => self.handle_client_message(message, ctx),
+                    Err(e) => {
+                        error!("Failed to decode {:?} client message: {}", self.format, e);
+                        let message = format!("Parse error: {}", e);
+                        self.send_message(ctx, &ServerMessage::Error { message });
                     }
                 }
             }
-            Ok(ws::Message::Binary(_)) => {}
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
                 ctx.stop();
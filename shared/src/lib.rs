@@ -1,6 +1,8 @@
 use nalgebra::{Point3, Vector3};
 use serde::{Deserialize, Serialize};
 
+pub mod wire;
+
 /// Maximum allowed particle count to prevent server overload
 /// With O(n²) algorithm: 15K particles = 225M calculations per frame
 /// This keeps computation time under 100ms for responsive UI
@@ -9,56 +11,1025 @@ pub const MAX_PARTICLES: usize = 15_000;
 /// Maximum computation time per frame in milliseconds before triggering warnings
 pub const MAX_COMPUTATION_TIME_MS: f32 = 200.0;
 
+/// Wire protocol version shared by server and client. Bump it whenever a
+/// message changes incompatibly so stale cached clients are told to reload
+/// instead of failing to parse.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Version of this crate, which server and client are always built against
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Particle {
+    #[cfg_attr(feature = "schema", schemars(with = "[f32; 3]"))]
     pub position: Point3<f32>,
+    #[cfg_attr(feature = "schema", schemars(with = "[f32; 3]"))]
     pub velocity: Vector3<f32>,
     pub mass: f32,
     pub color: [f32; 4],
+    /// Pinned in place: still attracts others but never moves itself. Only
+    /// sent when set, to keep frames small.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub fixed: bool,
+    /// Galaxy or scenario component the particle was generated in, for
+    /// per-group diagnostics and colors. Only sent when not 0.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub group: u16,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u16) -> bool {
+    *value == 0
+}
+
+/// A frame of the simulation as one flat array per particle attribute
+/// rather than a struct per particle: it serializes as plain number arrays,
+/// and the renderer uploads `positions` and `colors` to the GPU as they are.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SimulationState {
-    pub particles: Vec<Particle>,
+    /// x, y, z of each particle in turn
+    pub positions: Vec<f32>,
+    /// vx, vy, vz of each particle in turn
+    pub velocities: Vec<f32>,
+    pub masses: Vec<f32>,
+    /// RGBA of each particle in turn, 0-255
+    pub colors: Vec<u8>,
+    /// Whether each particle is pinned; empty, and not sent, when none is
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fixed: Vec<bool>,
+    /// Group of each particle; empty, and not sent, when all are in group 0
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<u16>,
+    pub sim_time: f32,
+    pub frame_number: u64,
+    /// Positions were extrapolated by the server between physics steps
+    /// (v·dt) to keep the stream smooth, not computed by a real step
+    #[serde(default)]
+    pub interpolated: bool,
+}
+
+impl SimulationState {
+    /// The arrays of `particles` at `sim_time`
+    pub fn new(particles: &[Particle], sim_time: f32, frame_number: u64) -> Self {
+        let mut state = SimulationState {
+            positions: Vec::with_capacity(particles.len() * 3),
+            velocities: Vec::with_capacity(particles.len() * 3),
+            masses: Vec::with_capacity(particles.len()),
+            colors: Vec::with_capacity(particles.len() * 4),
+            sim_time,
+            frame_number,
+            ..Default::default()
+        };
+        for particle in particles {
+            state.positions.extend(particle.position.iter());
+            state.velocities.extend(particle.velocity.iter());
+            state.masses.push(particle.mass);
+            state.colors.extend(particle.color.map(color_byte));
+        }
+        if particles.iter().any(|particle| particle.fixed) {
+            state.fixed = particles.iter().map(|particle| particle.fixed).collect();
+        }
+        if particles.iter().any(|particle| particle.group != 0) {
+            state.groups = particles.iter().map(|particle| particle.group).collect();
+        }
+        state
+    }
+
+    pub fn len(&self) -> usize {
+        self.masses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masses.is_empty()
+    }
+
+    pub fn position(&self, index: usize) -> Point3<f32> {
+        Point3::from_slice(&self.positions[index * 3..index * 3 + 3])
+    }
+
+    pub fn velocity(&self, index: usize) -> Vector3<f32> {
+        Vector3::from_column_slice(&self.velocities[index * 3..index * 3 + 3])
+    }
+
+    pub fn color(&self, index: usize) -> [u8; 4] {
+        let mut color = [0; 4];
+        color.copy_from_slice(&self.colors[index * 4..index * 4 + 4]);
+        color
+    }
+
+    pub fn is_fixed(&self, index: usize) -> bool {
+        self.fixed.get(index).copied().unwrap_or(false)
+    }
+
+    pub fn group(&self, index: usize) -> u16 {
+        self.groups.get(index).copied().unwrap_or(0)
+    }
+
+    /// Particle `index` as a struct, colour widened back to floats
+    pub fn particle(&self, index: usize) -> Particle {
+        Particle {
+            position: self.position(index),
+            velocity: self.velocity(index),
+            mass: self.masses[index],
+            color: self.color(index).map(|channel| channel as f32 / 255.0),
+            fixed: self.is_fixed(index),
+            group: self.group(index),
+        }
+    }
+
+    pub fn particles(&self) -> impl Iterator<Item = Particle> + '_ {
+        (0..self.len()).map(|index| self.particle(index))
+    }
+
+    /// Fails if the arrays disagree on the number of particles, which a
+    /// malformed frame could otherwise make the accessors panic on
+    pub fn validate(&self) -> Result<(), String> {
+        let count = self.len();
+        let fits = self.positions.len() == count * 3
+            && self.velocities.len() == count * 3
+            && self.colors.len() == count * 4
+            && (self.fixed.is_empty() || self.fixed.len() == count)
+            && (self.groups.is_empty() || self.groups.len() == count);
+        if fits {
+            Ok(())
+        } else {
+            Err(format!("State arrays do not agree on {} particles", count))
+        }
+    }
+}
+
+/// A 0-1 colour channel as a byte
+pub fn color_byte(channel: f32) -> u8 {
+    (channel.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The particles that moved since the previous frame, by index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateDelta {
+    pub sim_time: f32,
+    pub frame_number: u64,
+    #[serde(default)]
+    pub interpolated: bool,
+    pub indices: Vec<u32>,
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<[f32; 3]>"))]
+    pub positions: Vec<Point3<f32>>,
+}
+
+impl StateDelta {
+    /// Move the listed particles of `state` and take over the frame's
+    /// timing. Fails, leaving `state` alone, if an index is out of range.
+    pub fn apply(&self, state: &mut SimulationState) -> Result<(), String> {
+        if self.indices.len() != self.positions.len() {
+            return Err("State delta has mismatched indices and positions".to_string());
+        }
+        if let Some(&index) = self
+            .indices
+            .iter()
+            .find(|&&index| index as usize >= state.len())
+        {
+            return Err(format!(
+                "State delta index {} is out of range for {} particles",
+                index,
+                state.len()
+            ));
+        }
+        for (&index, position) in self.indices.iter().zip(&self.positions) {
+            let start = index as usize * 3;
+            state.positions[start..start + 3].copy_from_slice(position.coords.as_slice());
+        }
+        state.sim_time = self.sim_time;
+        state.frame_number = self.frame_number;
+        state.interpolated = self.interpolated;
+        Ok(())
+    }
+}
+
+/// A frame between keyframes as the change of every particle's position and
+/// velocity since the previous frame, quantized to 16 bits against a scale
+/// of the frame's largest change. Applying it in order to the last keyframe
+/// reconstructs the stream; the server tracks the reconstruction so rounding
+/// errors never pile up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StateDiff {
     pub sim_time: f32,
     pub frame_number: u64,
+    pub interpolated: bool,
+    /// Position change per unit of `positions`
+    pub position_scale: f32,
+    /// Velocity change per unit of `velocities`
+    pub velocity_scale: f32,
+    pub positions: Vec<[i16; 3]>,
+    pub velocities: Vec<[i16; 3]>,
+}
+
+impl StateDiff {
+    /// Add the changes to the particles of `state`, the frame before this
+    /// one. Fails, leaving `state` alone, if the particle counts differ.
+    pub fn apply(&self, state: &mut SimulationState) -> Result<(), String> {
+        if self.positions.len() != state.len() || self.velocities.len() != state.len() {
+            return Err(format!(
+                "State diff for {} particles does not fit {} particles",
+                self.positions.len(),
+                state.len()
+            ));
+        }
+        let add = |values: &mut [f32], steps: &[[i16; 3]], scale: f32| {
+            for (value, &step) in values.iter_mut().zip(steps.iter().flatten()) {
+                *value += step as f32 * scale;
+            }
+        };
+        add(&mut state.positions, &self.positions, self.position_scale);
+        add(&mut state.velocities, &self.velocities, self.velocity_scale);
+        state.sim_time = self.sim_time;
+        state.frame_number = self.frame_number;
+        state.interpolated = self.interpolated;
+        Ok(())
+    }
+}
+
+/// Algorithm used to sum gravitational forces
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ForceSolver {
+    /// Exact O(n²) pairwise summation
+    #[default]
+    Direct,
+    /// O(n log n) octree approximation controlled by `theta`
+    BarnesHut,
+}
+
+/// Automatic adjustment of the Barnes-Hut opening angle. While active, the
+/// server moves `theta` a little after every frame to meet the budget, and
+/// `theta` only sets where it starts.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ThetaTuning {
+    /// `theta` stays as set
+    #[default]
+    Off,
+    /// Largest accuracy that keeps a physics frame within `target_ms`
+    FrameTime { target_ms: f32 },
+    /// Cheapest theta whose forces stay within `target` mean relative error
+    /// of the exact sum, e.g. 0.01 for 1%; checked on a sample of particles
+    ForceError { target: f32 },
 }
 
+/// Time integration scheme advancing positions and velocities each step
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Integrator {
+    /// First-order kick-then-drift, one force evaluation per step
+    #[default]
+    SymplecticEuler,
+    /// Second-order drift-kick-drift, one force evaluation per step
+    Leapfrog,
+    /// Fourth-order Yoshida composition of leapfrog, three force evaluations
+    /// per step; far smaller energy error for long-term orbital studies
+    Yoshida4,
+}
+
+/// Initial conditions used on every reset
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Scenario {
+    /// Two galaxies on a head-on collision course
+    #[default]
+    GalaxyCollision,
+    /// Plummer sphere in standard N-body units, the classic benchmark model
+    Plummer,
+    /// King model globular cluster orbiting an elliptical host galaxy
+    GlobularCluster,
+    /// Cartwheel-like ring galaxy, expanding after a companion punched
+    /// through its disk
+    RingGalaxy,
+    /// Uniform sphere or cube falling in on itself from (nearly) rest
+    ColdCollapse,
+    /// Star ringed by a thin Keplerian disk of low-mass planetesimals
+    ProtoplanetaryDisk,
+    /// The Sun and planets at their J2000 positions (AU, solar masses, G = 1);
+    /// ignores the particle count
+    SolarSystem,
+    /// Three equal masses on the periodic figure-eight orbit
+    FigureEight,
+    /// Three equal masses on a rotating equilateral triangle
+    LagrangeTriangle,
+    /// Burrau's Pythagorean three-body problem (masses 3, 4, 5 from rest)
+    Pythagorean,
+    /// Star cluster seeded with binaries and hierarchical triples, for
+    /// studying binary hardening and ejections
+    BinaryCluster,
+    /// Scenario definition file in the server's scenarios directory, by name
+    /// without extension; ignores the particle count
+    File(String),
+    /// Particles imported from `<name>.csv` or a Gadget-2 snapshot in the
+    /// server's scenarios directory; ignores the particle count
+    Import(String),
+}
+
+impl std::str::FromStr for Scenario {
+    type Err = String;
+
+    /// A built-in scenario by its variant name, e.g. `SolarSystem`, or a
+    /// scenario file or imported particle set as `file:<name>` or
+    /// `import:<name>`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if let Some(file) = name.strip_prefix("file:") {
+            return Ok(Scenario::File(file.to_string()));
+        }
+        if let Some(import) = name.strip_prefix("import:") {
+            return Ok(Scenario::Import(import.to_string()));
+        }
+        match name {
+            "GalaxyCollision" => Ok(Scenario::GalaxyCollision),
+            "Plummer" => Ok(Scenario::Plummer),
+            "GlobularCluster" => Ok(Scenario::GlobularCluster),
+            "RingGalaxy" => Ok(Scenario::RingGalaxy),
+            "ColdCollapse" => Ok(Scenario::ColdCollapse),
+            "ProtoplanetaryDisk" => Ok(Scenario::ProtoplanetaryDisk),
+            "SolarSystem" => Ok(Scenario::SolarSystem),
+            "FigureEight" => Ok(Scenario::FigureEight),
+            "LagrangeTriangle" => Ok(Scenario::LagrangeTriangle),
+            "Pythagorean" => Ok(Scenario::Pythagorean),
+            "BinaryCluster" => Ok(Scenario::BinaryCluster),
+            other => Err(format!("Unknown scenario '{}'", other)),
+        }
+    }
+}
+
+/// Entry of the scenario list servers offer, for building a scenario picker
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScenarioInfo {
+    /// Name to switch to it with, see `Scenario::from_str`: `Plummer`,
+    /// `file:three_way_collision` or `import:snapshot`
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    /// Particle count the scenario looks best with; for scenarios with
+    /// their own particles, how many they have when known
+    pub recommended_particles: Option<usize>,
+    /// The scenario brings its own particles and ignores `particle_count`
+    pub fixed_particles: bool,
+}
+
+/// Shape of a generated galaxy
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Morphology {
+    /// Thin rotating disk with two spiral arms
+    #[default]
+    Spiral,
+    /// Pressure-supported Hernquist sphere with isotropic random velocities
+    Elliptical,
+    /// Exponential disk with a central bulge and a dark halo, close to
+    /// equilibrium so it holds together for many rotations
+    Composite,
+}
+
+/// Sense of a galaxy's rotation about its spin axis
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Spin {
+    /// Counter-clockwise seen from the tip of the spin axis
+    #[default]
+    Prograde,
+    /// Clockwise seen from the tip of the spin axis; the stars orbit
+    /// against the direction a prograde disk would turn
+    Retrograde,
+}
+
+/// How the masses of a galaxy's particles are drawn
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MassFunction {
+    /// The generator's own masses, e.g. the spiral's ramp from heavy
+    /// particles in the centre to light ones at the rim
+    #[default]
+    Ramp,
+    /// Salpeter (1955) power law dN/dm ∝ m^-2.35 from 0.1 to 100 M☉
+    Salpeter,
+    /// Kroupa (2001) broken power law with slopes 0.3, 1.3 and 2.3 breaking
+    /// at 0.08 and 0.5 M☉, from 0.01 to 100 M☉
+    Kroupa,
+}
+
+/// One galaxy of a custom collision setup. Vectors are `[x, y, z]`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GalaxySpec {
+    /// Number of particles
+    pub count: usize,
+    #[serde(default)]
+    pub center: [f32; 3],
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    #[serde(default = "default_galaxy_radius")]
+    pub radius: f32,
+    #[serde(default = "default_galaxy_color")]
+    pub color: [f32; 4],
+    /// Axis the disk rotates about (right-handed); tilt it for off-axis
+    /// encounters
+    #[serde(default = "default_spin_axis")]
+    pub spin_axis: [f32; 3],
+    /// Further rotation of the whole galaxy as roll, pitch and yaw in
+    /// degrees, about the x, then y, then z axis, applied after the spin
+    /// axis; e.g. `[60, 0, 0]` inclines a face-on disk by 60°
+    #[serde(default)]
+    pub euler_angles: [f32; 3],
+    #[serde(default)]
+    pub spin: Spin,
+    #[serde(default)]
+    pub morphology: Morphology,
+    /// Random velocities added on top of the orbits, as a fraction of each
+    /// star's orbital speed; 0 keeps the disks perfectly cold
+    #[serde(default)]
+    pub dispersion: f32,
+    /// Stellar masses drawn from an initial mass function, which also colors
+    /// each particle like a star of its mass
+    #[serde(default)]
+    pub mass_function: MassFunction,
+}
+
+fn default_galaxy_radius() -> f32 {
+    2.0
+}
+
+fn default_galaxy_color() -> [f32; 4] {
+    [0.8, 0.8, 1.0, 1.0]
+}
+
+fn default_spin_axis() -> [f32; 3] {
+    [0.0, 0.0, 1.0]
+}
+
+/// Default Barnes-Hut opening angle, a common speed/accuracy compromise
+pub const DEFAULT_THETA: f32 = 0.5;
+
+fn default_theta() -> f32 {
+    DEFAULT_THETA
+}
+
+/// Most physics steps the server integrates per published frame
+pub const MAX_STEPS_PER_UPDATE: u32 = 64;
+
+/// Most physics steps one `ClientMessage::Step` may ask for
+pub const MAX_SINGLE_STEPS: u32 = 1000;
+
+fn default_steps_per_update() -> u32 {
+    1
+}
+
+/// Settings of the shared simulation. The server is authoritative: changes
+/// from a controller apply to every client and are echoed back to confirm.
 #[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct SimulationConfig {
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PhysicsConfig {
     pub particle_count: usize,
     pub time_step: f32,
+    /// Physics steps integrated per published frame, to fast-forward slow
+    /// evolution without sending more frames
+    #[serde(default = "default_steps_per_update")]
+    pub steps_per_update: u32,
     pub gravity_strength: f32,
-    pub visual_fps: u32,
-    pub zoom_level: f32,
     #[serde(default)]
     pub debug: bool,
+    /// Side length of a cubic periodic box centred on the origin; `None` means
+    /// open boundaries
+    #[serde(default)]
+    pub periodic_box: Option<f32>,
+    #[serde(default)]
+    pub solver: ForceSolver,
+    /// Barnes-Hut opening angle: lower is more accurate, higher is faster
+    #[serde(default = "default_theta")]
+    pub theta: f32,
+    #[serde(default)]
+    pub theta_tuning: ThetaTuning,
+    #[serde(default)]
+    pub integrator: Integrator,
+    /// Speed of light in simulation units; when set, a first post-Newtonian
+    /// correction is added to gravity so orbits precess
+    #[serde(default)]
+    pub speed_of_light: Option<f32>,
+    /// Initial conditions; changing the scenario resets the run
+    #[serde(default)]
+    pub scenario: Scenario,
+    /// Shapes of the two colliding galaxies; changing them resets the run
+    #[serde(default)]
+    pub morphologies: [Morphology; 2],
+    /// Galaxies for the galaxy collision scenario in place of the default
+    /// pair; their counts replace `particle_count`
+    #[serde(default)]
+    pub galaxies: Vec<GalaxySpec>,
+    /// Seed for the random initial conditions; the same seed and settings
+    /// always generate the same particles
+    #[serde(default)]
+    pub seed: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Version of the `SimulationStats` layout. Raise it whenever a metric is
+/// added, renamed or removed, and describe the change in `STATS_METRICS`.
+pub const STATS_SCHEMA_VERSION: u32 = 3;
+
+/// Name, unit and description of every numeric field of `SimulationStats`
+const STATS_METRICS: [(&str, &str, &str); 14] = [
+    (
+        "fps",
+        "1/s",
+        "Physics frames the last step time would allow per second",
+    ),
+    (
+        "computation_time_ms",
+        "ms",
+        "Wall-clock time of the last physics step",
+    ),
+    ("particle_count", "", "Particles in the simulation"),
+    ("sim_time", "sim", "Simulation time in simulation units"),
+    (
+        "cpu_usage",
+        "%",
+        "Share of a 60 FPS frame budget the last step used",
+    ),
+    ("frame_number", "", "Physics steps since the last reset"),
+    (
+        "substeps",
+        "",
+        "Sub-steps the last frame was split into for close encounters",
+    ),
+    (
+        "theta",
+        "",
+        "Barnes-Hut opening angle in use, changing while it is tuned automatically",
+    ),
+    (
+        "force_error",
+        "",
+        "Mean relative force error of Barnes-Hut against the exact sum on a sample (error tuning only)",
+    ),
+    (
+        "tree.build_time_ms",
+        "ms",
+        "Time spent building Barnes-Hut trees in the last frame (debug only)",
+    ),
+    (
+        "tree.traversal_time_ms",
+        "ms",
+        "Time spent walking Barnes-Hut trees for forces in the last frame (debug only)",
+    ),
+    (
+        "tree.nodes",
+        "",
+        "Nodes of the last Barnes-Hut tree (debug only)",
+    ),
+    (
+        "tree.max_depth",
+        "",
+        "Levels below the root of the last Barnes-Hut tree (debug only)",
+    ),
+    (
+        "tree.interactions_per_particle",
+        "",
+        "Nodes and particles each particle summed forces from, on average (debug only)",
+    ),
+];
+
+/// One chartable metric of `SimulationStats`
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricInfo {
+    /// Field name in the stats JSON
+    pub name: String,
+    /// Unit symbol; empty for counts, `sim` for simulation units
+    pub unit: String,
+    pub description: String,
+}
+
+/// Description of the metrics in `SimulationStats`, so dashboards can build
+/// their charts from it instead of hard-coding the fields
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StatsSchema {
+    pub version: u32,
+    pub metrics: Vec<MetricInfo>,
+}
+
+impl StatsSchema {
+    /// The schema of this build's `SimulationStats`
+    pub fn current() -> Self {
+        StatsSchema {
+            version: STATS_SCHEMA_VERSION,
+            metrics: STATS_METRICS
+                .iter()
+                .map(|&(name, unit, description)| MetricInfo {
+                    name: name.to_string(),
+                    unit: unit.to_string(),
+                    description: description.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SimulationStats {
+    /// `STATS_SCHEMA_VERSION` of the server that sent it
+    #[serde(default)]
+    pub schema_version: u32,
     pub fps: f32,
     pub computation_time_ms: f32,
     pub particle_count: usize,
     pub sim_time: f32,
     pub cpu_usage: f32,
     pub frame_number: u64,
+    /// Sub-steps the last frame was split into because of close encounters
+    #[serde(default = "default_substeps")]
+    pub substeps: u32,
+    /// Simulation time formatted in physical units, e.g. "t = 245.0 Myr"
+    #[serde(default)]
+    pub sim_time_display: String,
+    /// Seed the current initial conditions were generated from
+    #[serde(default)]
+    pub seed: u64,
+    /// Barnes-Hut opening angle in use
+    #[serde(default = "default_theta")]
+    pub theta: f32,
+    /// Latest sampled force error while tuning theta to an error budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_error: Option<f32>,
+    /// Cost of the Barnes-Hut solver, reported by servers in debug mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<TreeStats>,
+}
+
+/// What the Barnes-Hut force evaluations of one frame cost, to see how
+/// theta and the particle distribution affect performance
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TreeStats {
+    /// Summed over the frame's force evaluations, which sub-steps and
+    /// higher-order integrators multiply
+    pub build_time_ms: f32,
+    pub traversal_time_ms: f32,
+    /// Shape of the most recent tree
+    pub nodes: usize,
+    pub max_depth: u32,
+    /// Leaves and accepted cells per particle in the most recent evaluation;
+    /// the direct sum would need one less than the particle count
+    pub interactions_per_particle: f32,
+}
+
+/// Kinetic and potential energy of each particle group (galaxy or scenario
+/// component) at one moment, to plot how energy moves between them
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EnergySample {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    /// Kinetic energy per group
+    pub kinetic: Vec<f32>,
+    /// Potential energy of the pairs within each group
+    pub potential: Vec<f32>,
+    /// Potential energy of the pairs from different groups; with the two
+    /// above it adds up to the total energy
+    pub interaction: f32,
+}
+
+/// Gravitational constant in kpc³ / (M☉ · Myr²)
+pub const G_KPC3_PER_MSUN_MYR2: f64 = 4.498_502e-12;
+
+/// One kpc/Myr in km/s
+const KM_S_PER_KPC_MYR: f64 = 977.792;
+
+/// Physical meaning of one simulation unit of length, mass and time. The time
+/// unit follows from the other two and the value of G in simulation units.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UnitSystem {
+    pub length_kpc: f64,
+    pub mass_msun: f64,
+    pub time_myr: f64,
+}
+
+impl UnitSystem {
+    /// Units in which the gravitational constant equals `gravity`
+    pub fn from_scales(length_kpc: f64, mass_msun: f64, gravity: f64) -> Self {
+        let time_myr = (gravity * length_kpc.powi(3) / (G_KPC3_PER_MSUN_MYR2 * mass_msun)).sqrt();
+        UnitSystem {
+            length_kpc,
+            mass_msun,
+            time_myr,
+        }
+    }
+
+    pub fn length_to_kpc(&self, length: f32) -> f64 {
+        length as f64 * self.length_kpc
+    }
+
+    pub fn mass_to_msun(&self, mass: f32) -> f64 {
+        mass as f64 * self.mass_msun
+    }
+
+    pub fn time_to_myr(&self, time: f32) -> f64 {
+        time as f64 * self.time_myr
+    }
+
+    pub fn velocity_to_km_s(&self, speed: f32) -> f64 {
+        speed as f64 * self.length_kpc / self.time_myr * KM_S_PER_KPC_MYR
+    }
+
+    /// Human-readable simulation time such as "t = 245.0 Myr", picking yr,
+    /// kyr, Myr or Gyr to suit the magnitude
+    pub fn format_time(&self, time: f32, precision: usize) -> String {
+        let myr = self.time_to_myr(time);
+        let (value, unit) = if myr.abs() >= 1000.0 {
+            (myr / 1000.0, "Gyr")
+        } else if myr.abs() >= 1.0 || myr == 0.0 {
+            (myr, "Myr")
+        } else if myr.abs() >= 1.0e-3 {
+            (myr * 1.0e3, "kyr")
+        } else {
+            (myr * 1.0e6, "yr")
+        };
+        format!("t = {:.*} {}", precision, value, unit)
+    }
+}
+
+impl Default for UnitSystem {
+    /// 1 kpc and 10¹⁰ M☉ with G = 1, a common galactic-dynamics choice
+    /// giving a time unit of about 4.7 Myr
+    fn default() -> Self {
+        UnitSystem::from_scales(1.0, 1.0e10, 1.0)
+    }
+}
+
+fn default_substeps() -> u32 {
+    1
+}
+
+/// Most frames per second the server streams to one client
+pub const MAX_VISUAL_FPS: u32 = 120;
+
+/// Presentation settings of one client. The server only uses them to pace
+/// that client's frames and remembers them in its session so a reconnecting
+/// client picks up where it left off; they never touch the simulation or
+/// other clients. The camera (zoom, panning, orbiting) stays in the browser.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ViewConfig {
+    pub visual_fps: u32,
+    #[serde(default)]
+    pub accessibility: Accessibility,
+    /// Stream a representative subset of at most about this many particles
+    /// while the simulation has more; `None` streams them all
+    #[serde(default)]
+    pub max_streamed_particles: Option<usize>,
+}
+
+impl Default for ViewConfig {
+    fn default() -> Self {
+        ViewConfig {
+            visual_fps: 30,
+            accessibility: Accessibility::default(),
+            max_streamed_particles: None,
+        }
+    }
+}
+
+/// Colors particles are drawn in
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Palette {
+    /// The colors the scenario gives its particles
+    #[default]
+    Natural,
+    /// One Okabe-Ito hue per group, distinguishable with any color vision;
+    /// brightness still follows the particle's own color
+    ColorblindSafe,
+    /// Saturated group colors at full brightness
+    HighContrast,
+}
+
+/// A client's accessibility options, kept with its other view settings
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Accessibility {
+    #[serde(default)]
+    pub palette: Palette,
+    /// Draw particles without the additive glow and the brightening of
+    /// merged particles, which flicker in dense regions
+    #[serde(default)]
+    pub reduced_motion: bool,
+    /// Larger text in the stats and controls
+    #[serde(default)]
+    pub large_text: bool,
+}
+
+/// A change of the particle count the server made on its own to keep the
+/// physics within its frame-time budget
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ParticleScaling {
+    pub from: usize,
+    pub to: usize,
+    /// Mean physics time per frame that led to the change
+    pub computation_time_ms: f32,
+    pub target_ms: f32,
+    /// Particles were removed from the running simulation; otherwise `to`
+    /// takes effect at the next reset
+    pub immediate: bool,
+}
+
+/// Whether a simulation is being recorded to disk, sent in reply to
+/// `StartRecording` and `StopRecording`
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RecordingStatus {
+    /// File being written in the server's recordings directory; `None`
+    /// while not recording
+    pub file: Option<String>,
+    /// Every this many published frames are written
+    pub interval: u64,
+    /// Frames written so far, or in total once stopped
+    pub frames: u64,
+}
+
+/// A saved snapshot of a simulation, sent in reply to `SaveSnapshot` and
+/// `LoadSnapshot`
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SnapshotInfo {
+    /// Name in the server's snapshots directory
+    pub name: String,
+    /// Unix timestamp in seconds
+    pub saved_at: u64,
+    pub sim_time: f32,
+    pub frame_number: u64,
+    pub particle_count: usize,
+}
+
+/// What a connection is allowed to do; viewers only receive the stream
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Role {
+    Viewer,
+    Controller,
+}
+
+/// Handshake sent once when a websocket connection is established
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Welcome {
+    pub session_token: String,
+    pub resumed: bool,
+    pub settings: ViewConfig,
+    pub role: Role,
+    /// Websocket URL clients should use when reconnecting, when the server
+    /// knows its public address (e.g. behind a TLS-terminating proxy)
+    #[serde(default)]
+    pub websocket_url: Option<String>,
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub server_version: String,
+    /// Physical scale of simulation units, for labelling in real units
+    #[serde(default)]
+    pub units: UnitSystem,
+    /// Optional server subsystems compiled into this build
+    #[serde(default)]
+    pub features: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type")]
 pub enum ClientMessage {
-    UpdateConfig(SimulationConfig),
+    /// Change the shared simulation's settings. With an `id`, the server
+    /// answers with an `Ack` of the same id rather than `Config` or `Error`.
+    UpdateConfig {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        #[serde(flatten)]
+        config: PhysicsConfig,
+    },
+    /// Change this client's own view settings; allowed for viewers too
+    UpdateView(ViewConfig),
+    /// Switch to a scenario by name (see `Scenario::from_str`) and rebuild
+    /// the particles from it, even when it is already selected
+    SetScenario {
+        scenario: String,
+    },
+    /// Ask for the current scenario list, e.g. after importing particles;
+    /// allowed for viewers too
+    ListScenarios,
+    /// Ask for the current frame in full straight away rather than waiting
+    /// for the next one, e.g. after reconnecting, while paused or when a
+    /// delta no longer fits; allowed for viewers too
+    RequestState,
     Reset,
     Pause,
     Resume,
+    /// Advance a paused simulation by exactly `count` physics steps, at
+    /// most `MAX_SINGLE_STEPS`, and send the resulting state
+    Step {
+        count: u32,
+    },
+    /// Write every `interval`th published frame, with the settings and seed,
+    /// to a file in the server's recordings directory; the configured
+    /// interval when omitted. Answered with `Recording`, also when already
+    /// recording.
+    StartRecording {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        interval: Option<u64>,
+    },
+    /// Finish the recording in progress; answered with `Recording`
+    StopRecording,
+    /// Jump back to the latest buffered keyframe at or before `frame` and
+    /// continue the run from there with the settings it had then; a replay
+    /// jumps to recorded frame `frame`, or the first recorded after it.
+    /// Answered with `Config` and the state there.
+    Seek {
+        frame: u64,
+    },
+    /// Negate every velocity so the run retraces its steps, e.g. to watch a
+    /// collision un-happen; the clock keeps counting forward
+    ReverseTime,
+    /// Save the particles, settings, seed and clock to the server's
+    /// snapshots directory as `name`, or a name made from the time when
+    /// omitted; answered with `Snapshot`
+    SaveSnapshot {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// Continue from snapshot `name` where it was saved; answered with
+    /// `Snapshot`, `Config` and the restored state
+    LoadSnapshot {
+        name: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type")]
 pub enum ServerMessage {
+    Welcome(Welcome),
     State(SimulationState),
     Stats(SimulationStats),
-    Config(SimulationConfig),
-    Error { message: String },
+    /// The metrics `Stats` messages carry; sent once after the welcome
+    StatsSchema(StatsSchema),
+    /// Scenarios the server can run; sent after the welcome and on request
+    Scenarios {
+        scenarios: Vec<ScenarioInfo>,
+    },
+    /// Energy partition samples: the retained history after the welcome,
+    /// then each new sample. A frame number lower than the last one received
+    /// means the simulation was reset and the series starts over.
+    Energy {
+        samples: Vec<EnergySample>,
+    },
+    Config(PhysicsConfig),
+    /// A frame that only lists the particles that moved noticeably since
+    /// the last frame; apply the new positions to the last state received
+    StateDelta(StateDelta),
+    /// A frame as quantized changes since the previous one, between full
+    /// `State` keyframes; apply it to the last state received
+    StateDiff(StateDiff),
+    /// Sent about once a second in place of state frames while nothing
+    /// moves, i.e. while paused; the last state received is still current
+    Paused {
+        frame_number: u64,
+        sim_time: f32,
+    },
+    Error {
+        message: String,
+    },
+    /// The answer to an `UpdateConfig` with an `id`: the settings in effect
+    /// afterwards, which show any value the server adjusted, and if the
+    /// update was rejected, why, the settings then being unchanged
+    Ack {
+        id: u64,
+        applied_config: PhysicsConfig,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Recording to disk started or stopped
+    Recording(RecordingStatus),
+    /// A snapshot was saved or loaded
+    Snapshot(SnapshotInfo),
+    /// The server trimmed particles because frames took too long, or will
+    /// add some back at the next reset because they are fast again
+    ParticlesScaled(ParticleScaling),
+    /// The client speaks a different protocol version than the server and
+    /// must reload to fetch the matching build. The shape of this message
+    /// must never change so that any client version can recognise it.
+    Reload {
+        protocol_version: u32,
+        server_version: String,
+    },
 }
@@ -0,0 +1,203 @@
+// Binary encodings of state frames, for clients that pick one at connect
+// time. Other messages stay in the session's message format either way.
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::{SimulationState, StateDiff};
+
+/// A binary websocket message in the bincode encoding. bincode writes the
+/// variant index first as a little-endian u32, leaving room for more kinds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum BinaryMessage {
+    State(BinaryState),
+    StateDiff(StateDiff),
+    QuantizedState(QuantizedState),
+}
+
+/// `SimulationState` with every field always present, since bincode has no
+/// field names to tell a skipped field by
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BinaryState {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    pub interpolated: bool,
+    pub positions: Vec<f32>,
+    pub velocities: Vec<f32>,
+    pub masses: Vec<f32>,
+    pub colors: Vec<u8>,
+    pub fixed: Vec<bool>,
+    pub groups: Vec<u16>,
+}
+
+/// `state` as a bincode `BinaryMessage::State`
+pub fn encode_bincode(state: &SimulationState) -> Result<Vec<u8>, String> {
+    let message = BinaryMessage::State(BinaryState {
+        frame_number: state.frame_number,
+        sim_time: state.sim_time,
+        interpolated: state.interpolated,
+        positions: state.positions.clone(),
+        velocities: state.velocities.clone(),
+        masses: state.masses.clone(),
+        colors: state.colors.clone(),
+        fixed: state.fixed.clone(),
+        groups: state.groups.clone(),
+    });
+    bincode::serialize(&message).map_err(|e| format!("Failed to encode state: {}", e))
+}
+
+/// `diff` as a bincode `BinaryMessage::StateDiff`
+pub fn encode_bincode_diff(diff: &StateDiff) -> Result<Vec<u8>, String> {
+    bincode::serialize(&BinaryMessage::StateDiff(diff.clone()))
+        .map_err(|e| format!("Failed to encode state diff: {}", e))
+}
+
+/// A bincode message from `encode_bincode`, `encode_bincode_diff` or
+/// `encode_quantized`
+pub fn decode_bincode(bytes: &[u8]) -> Result<BinaryMessage, String> {
+    bincode::deserialize(bytes).map_err(|e| format!("Failed to decode binary message: {}", e))
+}
+
+impl From<BinaryState> for SimulationState {
+    fn from(state: BinaryState) -> Self {
+        SimulationState {
+            positions: state.positions,
+            velocities: state.velocities,
+            masses: state.masses,
+            colors: state.colors,
+            fixed: state.fixed,
+            groups: state.groups,
+            sim_time: state.sim_time,
+            frame_number: state.frame_number,
+            interpolated: state.interpolated,
+        }
+    }
+}
+
+/// A state frame at reduced precision, two thirds the size of `BinaryState`:
+/// positions as 16-bit fixed point within the frame's bounding box, the
+/// other values as IEEE half floats or bytes. Enough for rendering.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QuantizedState {
+    pub frame_number: u64,
+    pub sim_time: f32,
+    pub interpolated: bool,
+    /// Low corner of the bounding box
+    pub origin: [f32; 3],
+    /// Size of one position step along each axis
+    pub step: [f32; 3],
+    pub particles: Vec<QuantizedParticle>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct QuantizedParticle {
+    /// Steps from `origin`
+    pub position: [u16; 3],
+    /// Half-float bits
+    pub velocity: [u16; 3],
+    /// Half-float bits
+    pub mass: u16,
+    /// RGBA, 0-255
+    pub color: [u8; 4],
+    pub fixed: bool,
+    pub group: u16,
+}
+
+/// Largest position step count
+const POSITION_STEPS: f32 = u16::MAX as f32;
+
+impl QuantizedState {
+    pub fn new(state: &SimulationState) -> Self {
+        let mut low = Vector3::repeat(f32::INFINITY);
+        let mut high = Vector3::repeat(f32::NEG_INFINITY);
+        for position in state.positions.chunks_exact(3) {
+            let position = Vector3::from_column_slice(position);
+            low = low.inf(&position);
+            high = high.sup(&position);
+        }
+        if state.is_empty() {
+            low = Vector3::zeros();
+            high = Vector3::zeros();
+        }
+        let step = (high - low) / POSITION_STEPS;
+
+        let half = |value: f32| half::f16::from_f32(value).to_bits();
+        let particles = (0..state.len())
+            .map(|index| {
+                let offset = state.position(index).coords - low;
+                let position = |axis: usize| {
+                    if step[axis] > 0.0 {
+                        (offset[axis] / step[axis]).round() as u16
+                    } else {
+                        0
+                    }
+                };
+                QuantizedParticle {
+                    position: [position(0), position(1), position(2)],
+                    velocity: <[f32; 3]>::from(state.velocity(index)).map(half),
+                    mass: half(state.masses[index]),
+                    color: state.color(index),
+                    fixed: state.is_fixed(index),
+                    group: state.group(index),
+                }
+            })
+            .collect();
+
+        QuantizedState {
+            frame_number: state.frame_number,
+            sim_time: state.sim_time,
+            interpolated: state.interpolated,
+            origin: low.into(),
+            step: step.into(),
+            particles,
+        }
+    }
+}
+
+impl From<QuantizedState> for SimulationState {
+    fn from(state: QuantizedState) -> Self {
+        let origin = Vector3::from(state.origin);
+        let step = Vector3::from(state.step);
+        let float = |bits: u16| half::f16::from_bits(bits).to_f32();
+        let count = state.particles.len();
+        let mut decoded = SimulationState {
+            positions: Vec::with_capacity(count * 3),
+            velocities: Vec::with_capacity(count * 3),
+            masses: Vec::with_capacity(count),
+            colors: Vec::with_capacity(count * 4),
+            sim_time: state.sim_time,
+            frame_number: state.frame_number,
+            interpolated: state.interpolated,
+            ..Default::default()
+        };
+        for particle in &state.particles {
+            let steps = Vector3::from(particle.position.map(f32::from));
+            decoded
+                .positions
+                .extend((origin + steps.component_mul(&step)).iter());
+            decoded.velocities.extend(particle.velocity.map(float));
+            decoded.masses.push(float(particle.mass));
+            decoded.colors.extend(particle.color);
+        }
+        if state.particles.iter().any(|particle| particle.fixed) {
+            decoded.fixed = state
+                .particles
+                .iter()
+                .map(|particle| particle.fixed)
+                .collect();
+        }
+        if state.particles.iter().any(|particle| particle.group != 0) {
+            decoded.groups = state
+                .particles
+                .iter()
+                .map(|particle| particle.group)
+                .collect();
+        }
+        decoded
+    }
+}
+
+/// `state` as a bincode `BinaryMessage::QuantizedState`
+pub fn encode_quantized(state: &SimulationState) -> Result<Vec<u8>, String> {
+    bincode::serialize(&BinaryMessage::QuantizedState(QuantizedState::new(state)))
+        .map_err(|e| format!("Failed to encode quantized state: {}", e))
+}